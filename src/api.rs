@@ -0,0 +1,736 @@
+//! JSON endpoints for scripts and other clients.
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::db::{self, EntryFilter, FeedSort, Ordering, Page};
+use crate::extract::{self, ExtractMode};
+use crate::{
+    parse, sanitize, user, AppError, AppState, CombinedView, Entry, EntryCounts, Feed, FetchStatus,
+    FilterRule, SavedFilter, UtcTime,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/entries", get(list_entries))
+        .route("/export/entries.ndjson", get(export_entries))
+        .route("/api/v1/feeds", get(list_feeds).post(add_feed))
+        .route("/api/v1/feeds/:feed_id", get(feed).delete(remove_feed))
+        .route("/api/feeds/:feed_id/entries", post(push_entries))
+        .route("/api/v1/entries", get(list_entries))
+        .route("/api/v1/entries/:entry_id/read", post(toggle_read))
+        .route("/api/v1/entries/:entry_id/starred", post(toggle_starred))
+        .route("/api/v1/entries/:entry_id/hidden", post(toggle_hidden))
+        .route(
+            "/api/v1/entries/:entry_id/position",
+            get(playback_position).put(set_playback_position),
+        )
+        .route("/api/v1/filters/export", get(export_filters))
+        .route("/api/v1/filters/import", post(import_filters))
+}
+
+#[derive(Debug, Deserialize)]
+struct EntriesQuery {
+    /// Same as the entry_filter header, `unread` when missing
+    filter: Option<String>,
+    /// `asc` or `desc` by published date, newest first when missing
+    order: Option<String>,
+    /// Comma separated entry fields to include, all of them when missing
+    fields: Option<String>,
+    /// At most this many entries, all of them when missing
+    limit: Option<u32>,
+    /// How many entries to skip before the first one returned
+    #[serde(default)]
+    offset: u32,
+}
+
+async fn list_entries(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<EntriesQuery>,
+) -> Result<Json<Vec<Value>>, AppError> {
+    Ok(Json(query_entries(&db, query).await?))
+}
+
+/// How many entries the NDJSON export reads from the database at a time.
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// The same entries as `/api/entries`, one JSON object per line, read a page at a
+/// time as the body goes out so exporting all of them doesn't have to fit in memory.
+async fn export_entries(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<EntriesQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(entry_lines(db, query, EXPORT_PAGE_SIZE)?),
+    ))
+}
+
+/// The lines of an NDJSON export in chunks, one for each page of `page_size` entries.
+fn entry_lines(
+    db: db::DB,
+    query: EntriesQuery,
+    page_size: u32,
+) -> anyhow::Result<impl stream::Stream<Item = anyhow::Result<Vec<u8>>>> {
+    let (filter, ordering) = entries_order(&query)?;
+    let fields = query.fields;
+    // the body is read after the handler returns, outside the request's user scope
+    let user = user::current()?;
+    let start = Some((query.offset, query.limit));
+    Ok(stream::unfold(start, move |next| {
+        let (db, filter, ordering, fields) =
+            (db.clone(), filter.clone(), ordering.clone(), fields.clone());
+        async move {
+            let (offset, left) = next?;
+            let size = left.map_or(page_size, |l| l.min(page_size));
+            if size == 0 {
+                return None;
+            }
+            let page = Page::new(offset, size);
+            let entries = match user::scope(user, db.get_entries_page(filter, ordering, page)).await
+            {
+                Ok(entries) if entries.is_empty() => return None,
+                Ok(entries) => entries,
+                Err(e) => return Some((Err(e), None)),
+            };
+            let fields = split_fields(fields.as_deref());
+            let mut chunk = vec![];
+            for e in &entries {
+                let written = entry_value(e, fields.as_deref())
+                    .and_then(|value| serde_json::to_writer(&mut chunk, &value));
+                if let Err(e) = written {
+                    return Some((Err(e.into()), None));
+                }
+                chunk.push(b'\n');
+            }
+            // a short page is the last one
+            let next =
+                (entries.len() as u32 == size).then(|| (offset + size, left.map(|l| l - size)));
+            Some((Ok(chunk), next))
+        }
+    }))
+}
+
+async fn query_entries(db: &db::DB, query: EntriesQuery) -> Result<Vec<Value>, AppError> {
+    let (filter, ordering) = entries_order(&query)?;
+    let fields = split_fields(query.fields.as_deref());
+    let page = Page {
+        offset: query.offset,
+        limit: query.limit,
+    };
+    let entries = db.get_entries_page(filter, ordering, page).await?;
+    let entries = entries
+        .iter()
+        .map(|e| entry_value(e, fields.as_deref()))
+        .collect::<Result<Vec<Value>, serde_json::Error>>()?;
+    Ok(entries)
+}
+
+/// Which entries a query is for and in what order.
+fn entries_order(query: &EntriesQuery) -> anyhow::Result<(EntryFilter, Ordering)> {
+    let filter: EntryFilter = query.filter.as_deref().unwrap_or("unread").parse()?;
+    let ordering: Ordering = query
+        .order
+        .as_deref()
+        .unwrap_or("desc")
+        .to_uppercase()
+        .parse()?;
+    Ok((filter, ordering))
+}
+
+fn split_fields(fields: Option<&str>) -> Option<Vec<&str>> {
+    fields.map(|f| f.split(',').map(str::trim).collect())
+}
+
+/// An entry as JSON, with just `fields` when they're given.
+fn entry_value(entry: &Entry, fields: Option<&[&str]>) -> serde_json::Result<Value> {
+    let value = serde_json::to_value(entry)?;
+    Ok(match fields {
+        Some(fields) => select_fields(value, fields),
+        None => value,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedsQuery {
+    /// `name`, `category`, `last_fetched` or `unread`, by name when missing
+    sort: Option<String>,
+    /// At most this many feeds, all of them when missing
+    limit: Option<u32>,
+    /// How many feeds to skip before the first one returned
+    #[serde(default)]
+    offset: u32,
+}
+
+async fn list_feeds(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<FeedsQuery>,
+) -> Result<Json<Vec<Feed>>, AppError> {
+    let sort: FeedSort = match &query.sort {
+        Some(sort) => sort.parse()?,
+        None => FeedSort::default(),
+    };
+    let page = Page {
+        offset: query.offset,
+        limit: query.limit,
+    };
+    Ok(Json(db.get_feeds_page(sort, page).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewFeed {
+    name: String,
+    site_url: String,
+    feed_url: String,
+    #[serde(default)]
+    category: String,
+    /// Take entries pushed to `/api/feeds/:feed_id/entries` instead of fetching `feed_url`
+    #[serde(default)]
+    push: bool,
+}
+
+/// Adds a feed, or updates the one with the same feed url, queued for a first fetch.
+async fn add_feed(
+    State(AppState { db, jobs, .. }): State<AppState>,
+    Json(body): Json<NewFeed>,
+) -> Result<Response, AppError> {
+    let mut feed = Feed::new(body.name, body.site_url, body.feed_url, body.category);
+    feed.push = body.push;
+    let id = feed.id.clone();
+    db.add_feeds(std::iter::once(feed)).await?;
+    jobs.first_fetch(&id);
+    match db.get_feed(&id).await? {
+        Some(feed) => Ok((StatusCode::CREATED, Json(feed)).into_response()),
+        None => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+/// Everything about a feed a management screen needs, in one response.
+#[derive(Debug, Serialize)]
+struct FeedDetail {
+    #[serde(flatten)]
+    feed: Feed,
+    /// The recent fetches that failed, oldest first
+    errors: Vec<FetchStatus>,
+    entries: EntryCounts,
+}
+
+async fn feed_detail(db: &db::DB, mut feed: Feed) -> anyhow::Result<FeedDetail> {
+    feed.statuses = db.get_feed_history(&feed.id).await?;
+    if let Some(last) = feed.statuses.last() {
+        feed.last_fetched = Some(last.created_at.clone());
+        feed.fetch_error.clone_from(&last.fetch_error);
+    }
+    let errors = feed.statuses.iter().filter(|s| !s.ok()).cloned().collect();
+    let entries = db.get_feed_entry_counts(&feed.name).await?;
+    Ok(FeedDetail {
+        feed,
+        errors,
+        entries,
+    })
+}
+
+async fn feed(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(feed) = db.get_feed(&feed_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    Ok(Json(feed_detail(&db, feed).await?).into_response())
+}
+
+async fn remove_feed(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    if db.get_feed(&feed_id).await?.is_none() {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    db.remove_feed(feed_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// An entry pushed into a feed, with the same fields as entries are listed with.
+#[derive(Debug, Deserialize)]
+struct PushedEntry {
+    /// Identifies the entry within the feed, pushing it again updates it. The
+    /// content link when missing.
+    id: Option<String>,
+    title: String,
+    content_link: String,
+    #[serde(default)]
+    comments_link: String,
+    published: Option<UtcTime>,
+    author: Option<String>,
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PushedEntries {
+    new_entries: usize,
+}
+
+/// Stores entries sent by a script into a push feed, just as if a refresh had found them.
+async fn push_entries(
+    Path(feed_id): Path<String>,
+    State(AppState {
+        db,
+        client,
+        excerpt_length,
+        ..
+    }): State<AppState>,
+    Json(body): Json<Vec<PushedEntry>>,
+) -> Result<Response, AppError> {
+    let Some(feed) = db.get_feed(&feed_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if !feed.push {
+        return Ok((StatusCode::CONFLICT, "feed isn't a push feed").into_response());
+    }
+    let entries: Vec<Entry> = body
+        .into_iter()
+        .map(|p| pushed_entry(&feed, p, excerpt_length))
+        .collect();
+    let new_entries = db.add_entries(entries.clone().into_iter()).await?;
+    if feed.extract_mode() == ExtractMode::OnRefresh {
+        extract::extract_missing(&db, &client, &entries).await;
+    }
+    Ok((StatusCode::CREATED, Json(PushedEntries { new_entries })).into_response())
+}
+
+fn pushed_entry(feed: &Feed, pushed: PushedEntry, excerpt_length: usize) -> Entry {
+    // scope ids to the feed so two scripts can't clobber each other's entries
+    let id = format!(
+        "{}/{}",
+        feed.id,
+        pushed.id.as_deref().unwrap_or(&pushed.content_link)
+    );
+    let mut entry = Entry::new(
+        &id,
+        pushed.title,
+        pushed.content_link,
+        pushed.comments_link,
+        pushed.published,
+    );
+    entry.feed.clone_from(&feed.name);
+    entry.author = pushed.author;
+    entry.excerpt = pushed
+        .content
+        .as_deref()
+        .map(|c| parse::excerpt(c, excerpt_length))
+        .filter(|e| !e.is_empty());
+    entry.content = pushed
+        .content
+        .as_deref()
+        .map(|c| sanitize::sanitize(c, Some(&entry.content_link)));
+    entry
+}
+
+/// Flips an entry between read and unread, returning it as it is now.
+async fn toggle_read(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    db.toggle_entry_read(&entry_id).await?;
+    entry_response(&db, &entry_id).await
+}
+
+/// Flips an entry between starred and not, returning it as it is now.
+async fn toggle_starred(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    db.toggle_entry_starred(&entry_id).await?;
+    entry_response(&db, &entry_id).await
+}
+
+async fn toggle_hidden(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    db.toggle_entry_hidden(&entry_id).await?;
+    entry_response(&db, &entry_id).await
+}
+
+/// How far into an entry's enclosure someone got, in seconds.
+#[derive(Deserialize, Serialize)]
+struct Playback {
+    position: f64,
+}
+
+async fn playback_position(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    Ok(match db.get_entry(&entry_id).await? {
+        Some(entry) => Json(Playback {
+            position: entry.playback_position,
+        })
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Saves where playback got to, so another device can resume from there.
+async fn set_playback_position(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Json(playback): Json<Playback>,
+) -> Result<Response, AppError> {
+    if !playback.position.is_finite() || playback.position < 0.0 {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "position must be seconds from the start",
+        )
+            .into_response());
+    }
+    if !db
+        .set_playback_position(&entry_id, playback.position)
+        .await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    Ok(Json(playback).into_response())
+}
+
+/// The current user's saved filters, the combined views and the filter rules,
+/// to share with another instance or user.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct FilterSet {
+    #[serde(default)]
+    saved_filters: Vec<SavedFilter>,
+    #[serde(default)]
+    views: Vec<CombinedView>,
+    #[serde(default)]
+    rules: Vec<FilterRule>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct FilterImport {
+    added: usize,
+    /// Ones with the same name as one already here, or rules that are already here
+    skipped: usize,
+}
+
+async fn export_filters(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Json<FilterSet>, AppError> {
+    Ok(Json(FilterSet {
+        saved_filters: db.get_saved_filters().await?,
+        views: db.get_combined_views().await?,
+        rules: db.get_filter_rules().await?,
+    }))
+}
+
+async fn import_filters(
+    State(AppState { db, .. }): State<AppState>,
+    Json(set): Json<FilterSet>,
+) -> Result<Json<FilterImport>, AppError> {
+    Ok(Json(import_filter_set(&db, set).await?))
+}
+
+/// Adds every filter and view from an export under new ids, so one set can be
+/// imported by several users, skipping names that are already taken and rules
+/// that would do the same as one already here.
+async fn import_filter_set(db: &db::DB, set: FilterSet) -> anyhow::Result<FilterImport> {
+    let filters = db.get_saved_filters().await?;
+    let views = db.get_combined_views().await?;
+    let mut rules = db.get_filter_rules().await?;
+    let taken = |names: &[&String], name: &str| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+    let filter_names: Vec<&String> = filters.iter().map(|f| &f.name).collect();
+    let view_names: Vec<&String> = views.iter().map(|v| &v.name).collect();
+    let mut import = FilterImport {
+        added: 0,
+        skipped: 0,
+    };
+    for filter in set.saved_filters {
+        if taken(&filter_names, &filter.name) {
+            import.skipped += 1;
+            continue;
+        }
+        db.add_saved_filter(&SavedFilter {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            ..filter
+        })
+        .await?;
+        import.added += 1;
+    }
+    for view in set.views {
+        if taken(&view_names, &view.name) {
+            import.skipped += 1;
+            continue;
+        }
+        db.add_combined_view(&CombinedView {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            ..view
+        })
+        .await?;
+        import.added += 1;
+    }
+    for rule in set.rules {
+        let same = |r: &FilterRule| {
+            r.pattern.eq_ignore_ascii_case(&rule.pattern)
+                && r.field == rule.field
+                && r.feed_id == rule.feed_id
+                && r.action == rule.action
+        };
+        if rules.iter().any(same) {
+            import.skipped += 1;
+            continue;
+        }
+        let rule = FilterRule {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            ..rule
+        };
+        db.add_filter_rule(&rule).await?;
+        rules.push(rule);
+        import.added += 1;
+    }
+    Ok(import)
+}
+
+async fn entry_response(db: &db::DB, entry_id: &str) -> Result<Response, AppError> {
+    Ok(match db.get_entry(entry_id).await? {
+        Some(entry) => Json::<Entry>(entry).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Drops every key of a JSON object that isn't in `fields`.
+fn select_fields(value: Value, fields: &[&str]) -> Value {
+    match value {
+        Value::Object(mut map) => {
+            map.retain(|k, _| fields.contains(&k.as_str()));
+            Value::Object(map)
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    use crate::Entry;
+
+    #[test]
+    fn select_entry_fields() {
+        let entry = Entry::new(
+            "my-entry",
+            "Cool Post".to_string(),
+            "https://content.com/1".to_string(),
+            "".to_string(),
+            None,
+        );
+        let value = serde_json::to_value(entry).unwrap();
+        let selected = select_fields(value, &["id", "title", "content_link", "nope"]);
+        let keys: Vec<&String> = selected.as_object().unwrap().keys().collect();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(selected["title"], "Cool Post");
+        assert!(selected.get("read").is_none());
+    }
+
+    #[tokio::test]
+    async fn import_exported_filters() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            db.add_saved_filter(&SavedFilter {
+                id: "mine".to_string(),
+                name: "Today".to_string(),
+                unread: true,
+                since_hours: Some(24),
+                ..Default::default()
+            })
+            .await?;
+            let set: FilterSet = serde_json::from_value(serde_json::json!({
+                "saved_filters": [
+                    {"id": "a", "name": "today", "unread": false, "starred": true,
+                        "category": null, "feed_id": null, "since_hours": null},
+                    {"id": "b", "name": "Starred tech", "unread": false, "starred": true,
+                        "category": "Tech", "feed_id": null, "since_hours": null}
+                ],
+                "views": [{"id": "c", "name": "Work", "categories": ["Tech"], "excluded": []}],
+                "rules": [
+                    {"id": "d", "pattern": "Sponsored", "field": "title", "feed_id": null, "action": "drop"},
+                    {"id": "e", "pattern": "sponsored", "field": "title", "feed_id": null, "action": "drop"}
+                ]
+            }))?;
+            let import = import_filter_set(&db, set).await?;
+            assert_eq!(
+                import,
+                FilterImport {
+                    added: 3,
+                    skipped: 2
+                }
+            );
+            let filters = db.get_saved_filters().await?;
+            assert_eq!(filters.len(), 2);
+            assert!(filters.iter().all(|f| f.id != "b"));
+            let views = db.get_combined_views().await?;
+            assert_eq!(views[0].categories, ["Tech"]);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn page_through_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries = (1..=5).map(|i| {
+                Entry::new(
+                    &format!("entry-{}", i),
+                    format!("Post {}", i),
+                    format!("https://content.com/{}", i),
+                    "".to_string(),
+                    Some(chrono::DateTime::from_timestamp(i * 60, 0).unwrap().into()),
+                )
+            });
+            db.add_entries(entries).await?;
+
+            let query = EntriesQuery {
+                filter: Some("all".to_string()),
+                order: Some("asc".to_string()),
+                fields: Some("title".to_string()),
+                limit: Some(2),
+                offset: 1,
+            };
+            let page = query_entries(&db, query).await.map_err(|e| e.0)?;
+            assert_eq!(page.len(), 2);
+            assert_eq!(page[0]["title"], "Post 2");
+            assert_eq!(page[1]["title"], "Post 3");
+
+            let query = EntriesQuery {
+                filter: Some("all".to_string()),
+                order: Some("asc".to_string()),
+                fields: Some("title".to_string()),
+                limit: Some(3),
+                offset: 1,
+            };
+            let chunks: Vec<Vec<u8>> = entry_lines(db.clone(), query, 2)?
+                .map(|c| c.unwrap())
+                .collect()
+                .await;
+            // read two entries at a time, up to the limit
+            assert_eq!(chunks.len(), 2);
+            assert_eq!(
+                String::from_utf8(chunks.concat())?,
+                "{\"title\":\"Post 2\"}\n{\"title\":\"Post 3\"}\n{\"title\":\"Post 4\"}\n"
+            );
+            let everything = EntriesQuery {
+                filter: Some("all".to_string()),
+                order: None,
+                fields: None,
+                limit: None,
+                offset: 0,
+            };
+            let lines: Vec<Vec<u8>> = entry_lines(db, everything, 2)?
+                .map(|c| c.unwrap())
+                .collect()
+                .await;
+            assert_eq!(lines.concat().iter().filter(|&&b| b == b'\n').count(), 5);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn feed_detail_counts_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/feed".to_string(),
+                "".to_string(),
+            );
+            db.add_feeds(std::iter::once(feed.clone())).await?;
+            let entries: Vec<Entry> = (1..=3)
+                .map(|i| Entry {
+                    feed: "Blog".to_string(),
+                    ..Entry::new(
+                        &format!("entry-{}", i),
+                        format!("Post {}", i),
+                        format!("https://blog.com/{}", i),
+                        "".to_string(),
+                        None,
+                    )
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+            db.toggle_entry_read(&entries[0].id).await?;
+            db.toggle_entry_starred(&entries[1].id).await?;
+            db.toggle_entry_hidden(&entries[2].id).await?;
+            db.update_feed_status(feed.id.clone(), Some(200), None)
+                .await?;
+            db.update_feed_status(feed.id.clone(), Some(500), Some("boom".to_string()))
+                .await?;
+
+            let detail = feed_detail(&db, feed).await?;
+            let expected = EntryCounts {
+                total: 3,
+                unread: 1,
+                starred: 1,
+                hidden: 1,
+            };
+            assert_eq!(detail.entries, expected);
+            assert_eq!(detail.feed.statuses.len(), 2);
+            assert_eq!(detail.errors.len(), 1);
+            assert_eq!(detail.feed.fetch_error.as_deref(), Some("boom"));
+            let json = serde_json::to_value(&detail)?;
+            assert_eq!(json["name"], "Blog");
+            assert_eq!(json["entries"]["unread"], 1);
+            Ok(())
+        })
+        .await
+    }
+
+    #[test]
+    fn entries_pushed_into_feed() {
+        let mut feed = Feed::new(
+            "Inbox".to_string(),
+            "https://mail.com".to_string(),
+            "https://mail.com/inbox".to_string(),
+            "".to_string(),
+        );
+        feed.push = true;
+        let pushed: Vec<PushedEntry> = serde_json::from_str(
+            r#"[
+                {"title": "Newsletter", "content_link": "https://mail.com/1", "content": "<p>Hello <b>there</b></p>"},
+                {"id": "msg-2", "title": "Receipt", "content_link": "https://mail.com/2"}
+            ]"#,
+        )
+        .unwrap();
+        let entries: Vec<Entry> = pushed
+            .into_iter()
+            .map(|p| pushed_entry(&feed, p, 200))
+            .collect();
+        assert!(entries.iter().all(|e| e.feed == "Inbox"));
+        assert_eq!(entries[0].excerpt.as_deref(), Some("Hello there"));
+        assert!(entries[1].excerpt.is_none());
+        assert_eq!(
+            entries[1].id,
+            Entry::new(
+                &format!("{}/msg-2", feed.id),
+                "".to_string(),
+                "".to_string(),
+                "".to_string(),
+                None
+            )
+            .id
+        );
+    }
+}
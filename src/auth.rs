@@ -0,0 +1,906 @@
+//! Bearer tokens and the scopes they grant, and logging in as the owner or
+//! another user.
+//!
+//! Tokens come from `FEED_API_TOKENS` or are created on the tokens page, which
+//! only keeps a hash of each.
+//!
+//! A token's scope limits what that token can do, not what the server lets
+//! through: requests without one aren't scoped at all. Scopes only keep anyone
+//! out once `FEED_USERNAME` and `FEED_PASSWORD` are set, which makes everything
+//! past reading ask for a login or a token. Without them, anyone who can reach
+//! the server can do anything the owner can.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use askama_axum::Template;
+use axum::extract::{MatchedPath, Path, Query, Request, State};
+use axum::http::header::{
+    ACCEPT, AUTHORIZATION, COOKIE, RETRY_AFTER, SET_COOKIE, WWW_AUTHENTICATE,
+};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{delete, get, post};
+use axum::{Extension, Form, Router};
+use chrono::Utc;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{base_path, db, filters, proxy, secrets, user, ApiToken, AppError, AppState, UtcTime};
+
+const SESSION_COOKIE: &str = "feedreader_session";
+
+/// How long a login lasts.
+const SESSION_DAYS: i64 = 30;
+
+/// Failed logins for one username before it's locked out, within `FAILURE_WINDOW`.
+const ACCOUNT_FAILURES: u32 = 5;
+
+/// Failed logins from one address before it's locked out, within `FAILURE_WINDOW`,
+/// more than for a username as several people can share one.
+const IP_FAILURES: u32 = 20;
+
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// How long a locked out username or address has to wait to try again.
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route("/tokens.html", get(tokens_page))
+        .route("/tokens", post(create_token))
+        .route("/tokens/:token_id", delete(revoke_token))
+}
+
+/// What a token is allowed to do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Read,
+    MarkState,
+    ManageFeeds,
+    Admin,
+}
+
+impl Scope {
+    /// The scope needed to call a route, by method and matched path.
+    pub fn required(method: &Method, path: &str) -> Scope {
+        let reading = method == Method::GET || method == Method::HEAD;
+        match path {
+            "/settings" | "/dump" | "/tokens.html" | "/users.html" | "/webhooks.html" => {
+                Scope::Admin
+            }
+            // read, but fetch whatever site they're given
+            "/discover" | "/suggestions/feeds" => Scope::ManageFeeds,
+            _ if reading => Scope::Read,
+            // posted, but only to log in or ask for more than fits in a query string
+            "/login"
+            | "/logout"
+            | "/greader/accounts/ClientLogin"
+            | "/greader/reader/api/0/stream/items/contents" => Scope::Read,
+            "/read"
+            | "/read_all"
+            | "/read/:entry_id"
+            | "/starred/:entry_id"
+            | "/hidden/:entry_id"
+            | "/api/v1/entries/:entry_id/read"
+            | "/api/v1/entries/:entry_id/starred"
+            | "/api/v1/entries/:entry_id/hidden"
+            | "/api/v1/entries/:entry_id/position"
+            | "/preferences"
+            | "/greader/reader/api/0/edit-tag"
+            | "/greader/reader/api/0/mark-all-as-read" => Scope::MarkState,
+            "/feeds"
+            | "/feeds/:feed_url"
+            | "/feeds/:feed_url/icon"
+            | "/feeds/:feed_url/compact"
+            | "/feeds/:feed_url/enabled"
+            | "/categories"
+            | "/categories/:category_id"
+            | "/categories/:category_id/digest"
+            | "/rules"
+            | "/rules/:rule_id"
+            | "/feeds/:feed_url/extract"
+            | "/feeds/:feed_url/refresh"
+            | "/api/v1/feeds"
+            | "/api/v1/feeds/:feed_id"
+            | "/api/feeds/:feed_id/entries"
+            | "/opml"
+            | "/imports"
+            | "/views"
+            | "/views/:view_id"
+            | "/filters"
+            | "/filters/:filter_id"
+            | "/api/v1/filters/import"
+            | "/refresh" => Scope::ManageFeeds,
+            _ => Scope::Admin,
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Scope> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "mark-state" => Ok(Scope::MarkState),
+            "manage-feeds" => Ok(Scope::ManageFeeds),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(anyhow!("unknown scope {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::MarkState => write!(f, "mark-state"),
+            Scope::ManageFeeds => write!(f, "manage-feeds"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Tokens configured as `token=scope` pairs separated by commas.
+#[derive(Debug, Clone, Default)]
+pub struct Tokens(Arc<HashMap<String, Scope>>);
+
+impl FromStr for Tokens {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Tokens> {
+        let tokens = s
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                let (token, scope) = t
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("token is missing a scope"))?;
+                Ok((token.to_string(), scope.parse()?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(Tokens(Arc::new(tokens)))
+    }
+}
+
+impl Tokens {
+    fn scope(&self, token: &str) -> Option<Scope> {
+        self.0.get(token).copied()
+    }
+}
+
+/// What's stored for a created token, so a leaked database doesn't leak them.
+fn hash_token(token: &str) -> String {
+    secrets::hex(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+fn generate_token() -> Result<String> {
+    let mut secret = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| anyhow!("couldn't generate a token"))?;
+    Ok(format!(
+        "fr_{}",
+        base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// The scope a token grants, whether it's configured or was created. When it was
+/// last used is noted for created ones, unless `read_only`.
+async fn token_scope(
+    tokens: &Tokens,
+    db: &db::DB,
+    token: &str,
+    read_only: bool,
+) -> Result<Option<Scope>> {
+    if let Some(scope) = tokens.scope(token) {
+        return Ok(Some(scope));
+    }
+    match db.use_api_token(&hash_token(token), !read_only).await? {
+        Some(scope) => Ok(Some(scope.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether there are any tokens, configured or created.
+async fn any_tokens(tokens: &Tokens, db: &db::DB) -> Result<bool> {
+    Ok(!tokens.0.is_empty() || db.has_api_tokens().await?)
+}
+
+/// Whether a token would be let in, any is while there are none.
+pub async fn accepts(tokens: &Tokens, db: &db::DB, token: &str, read_only: bool) -> Result<bool> {
+    Ok(
+        !any_tokens(tokens, db).await?
+            || token_scope(tokens, db, token, read_only).await?.is_some(),
+    )
+}
+
+/// The owner's credentials, from `FEED_USERNAME` and `FEED_PASSWORD`.
+#[derive(Debug)]
+pub struct Login {
+    username: String,
+    password: String,
+}
+
+impl Login {
+    pub fn new(username: String, password: String) -> Login {
+        Login { username, password }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    fn matches(&self, username: &str, password: &str) -> bool {
+        // both compared in full, so timing doesn't give away which was wrong
+        let username_ok = constant_time_eq(self.username.as_bytes(), username.as_bytes());
+        let password_ok = constant_time_eq(self.password.as_bytes(), password.as_bytes());
+        username_ok & password_ok
+    }
+}
+
+/// Failed logins by username and by client address, to lock out whoever's
+/// guessing passwords for a while.
+#[derive(Debug, Clone, Default)]
+pub struct LoginThrottle(Arc<Mutex<HashMap<String, Failures>>>);
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// What came of trying a username and password.
+enum Attempt {
+    User(i64),
+    Wrong,
+    /// Not tried, as the username or address is locked out for this long
+    Locked(Duration),
+}
+
+impl LoginThrottle {
+    fn keys(ip: Option<IpAddr>, username: &str) -> Vec<(String, u32)> {
+        let mut keys = vec![(
+            format!("user:{}", username.to_lowercase()),
+            ACCOUNT_FAILURES,
+        )];
+        if let Some(ip) = ip {
+            keys.push((format!("ip:{}", ip), IP_FAILURES));
+        }
+        keys
+    }
+
+    /// How long until the username and address can both try again, if either is locked out.
+    fn locked(&self, ip: Option<IpAddr>, username: &str, now: Instant) -> Option<Duration> {
+        let failures = self.0.lock().unwrap();
+        Self::keys(ip, username)
+            .iter()
+            .filter_map(|(key, _)| failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Notes a failed login, returning whether it locked out the username or address.
+    fn fail(&self, ip: Option<IpAddr>, username: &str, now: Instant) -> bool {
+        let mut failures = self.0.lock().unwrap();
+        // forget anything old enough not to count, so this doesn't grow forever
+        failures.retain(|_, f| {
+            now.duration_since(f.since) < FAILURE_WINDOW
+                || f.locked_until.is_some_and(|until| until > now)
+        });
+        let mut locked = false;
+        for (key, limit) in Self::keys(ip, username) {
+            let f = failures.entry(key).or_insert(Failures {
+                count: 0,
+                since: now,
+                locked_until: None,
+            });
+            if now.duration_since(f.since) >= FAILURE_WINDOW {
+                *f = Failures {
+                    count: 0,
+                    since: now,
+                    locked_until: None,
+                };
+            }
+            f.count += 1;
+            if f.count >= limit && f.locked_until.is_none() {
+                f.locked_until = Some(now + LOCKOUT);
+                locked = true;
+            }
+        }
+        locked
+    }
+
+    /// Clears a username's failures once it logs in. Its address's are left to
+    /// expire, so logging into one account doesn't allow more guesses at another.
+    fn succeeded(&self, username: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&format!("user:{}", username.to_lowercase()));
+    }
+}
+
+/// The address a request came from, when the server knows it.
+fn client_ip(client: Option<&proxy::Client>) -> Option<IpAddr> {
+    client.and_then(|c| c.ip)
+}
+
+/// Checks a username and password unless either the username or the client is
+/// locked out, recording failures and lockouts in the audit log.
+async fn attempt_login(
+    login: &Login,
+    db: &db::DB,
+    throttle: &LoginThrottle,
+    ip: Option<IpAddr>,
+    username: &str,
+    password: &str,
+) -> Result<Attempt> {
+    if let Some(wait) = throttle.locked(ip, username, Instant::now()) {
+        return Ok(Attempt::Locked(wait));
+    }
+    let ip_text = ip.map(|ip| ip.to_string());
+    if let Some(user) = authenticate(login, db, username, password).await? {
+        throttle.succeeded(username);
+        return Ok(Attempt::User(user));
+    }
+    warn!("failed login for {:?} from {:?}", username, ip_text);
+    db.add_audit_event("login_failed", Some(username), ip_text.clone())
+        .await?;
+    if throttle.fail(ip, username, Instant::now()) {
+        warn!("locked out {:?} from {:?}", username, ip_text);
+        db.add_audit_event("lockout", Some(username), ip_text)
+            .await?;
+    }
+    Ok(Attempt::Wrong)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The route a request matched, with placeholders, or its path if none did.
+fn route_path(request: &Request) -> &str {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| request.uri().path())
+}
+
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Works out who a request is from, by login session, HTTP Basic credentials or a
+/// known token, and handles it as them. Anything that only reads is let through as
+/// a visitor to the owner's reader without any, everything else asks for a login,
+/// and only the owner gets past admin routes. Unless `FEED_USERNAME` and
+/// `FEED_PASSWORD` are set, every request is the owner's.
+pub async fn require_login(
+    State(AppState {
+        db,
+        login,
+        tokens,
+        throttle,
+        read_only,
+        ..
+    }): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // without a login there's no one else to be
+    let Some(login) = login else {
+        return user::scope(user::OWNER, next.run(request)).await;
+    };
+    let required = Scope::required(request.method(), route_path(&request));
+    let ip = client_ip(request.extensions().get());
+    let identified = identify(
+        &login,
+        &tokens,
+        &db,
+        &throttle,
+        ip,
+        request.headers(),
+        read_only.enabled(),
+    );
+    let user = match identified.await {
+        Ok(user) => user,
+        Err(e) => {
+            error!("couldn't check credentials: {:#}", e);
+            None
+        }
+    };
+    match user {
+        Some(user) if user != user::OWNER && required == Scope::Admin => {
+            (StatusCode::FORBIDDEN, "only the owner can do that").into_response()
+        }
+        Some(user) => user::scope(user, next.run(request)).await,
+        // anyone can read, and what they see is the owner's reader, left as it was
+        None if required == Scope::Read => user::visit(next.run(request)).await,
+        None => ask_for_login(request.headers()),
+    }
+}
+
+/// The user a request's credentials belong to, if it has any that are good.
+async fn identify(
+    login: &Login,
+    tokens: &Tokens,
+    db: &db::DB,
+    throttle: &LoginThrottle,
+    ip: Option<IpAddr>,
+    headers: &HeaderMap,
+    read_only: bool,
+) -> Result<Option<i64>> {
+    let authorization = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if let Some(basic) = authorization.strip_prefix("Basic ") {
+        let decoded = base64::decode(basic.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        match decoded.as_deref().and_then(|d| d.split_once(':')) {
+            Some((username, password)) => {
+                match attempt_login(login, db, throttle, ip, username, password).await? {
+                    Attempt::User(user) => Ok(Some(user)),
+                    Attempt::Wrong | Attempt::Locked(_) => Ok(None),
+                }
+            }
+            None => Ok(None),
+        }
+    } else if let Some(token) = authorization
+        .strip_prefix("Bearer ")
+        .or_else(|| authorization.strip_prefix("GoogleLogin auth="))
+    {
+        // tokens act for the owner, whether one's enough for the route is up to `require_scope`
+        Ok(token_scope(tokens, db, token.trim(), read_only)
+            .await?
+            .map(|_| user::OWNER))
+    } else if let Some(id) = session_id(headers) {
+        db.session_user(id, chrono::Duration::days(SESSION_DAYS))
+            .await
+    } else {
+        Ok(None)
+    }
+}
+
+/// The owner, by `FEED_USERNAME` and `FEED_PASSWORD`, or a user added on the users page.
+async fn authenticate(
+    login: &Login,
+    db: &db::DB,
+    username: &str,
+    password: &str,
+) -> Result<Option<i64>> {
+    if login.matches(username, password) {
+        return Ok(Some(user::OWNER));
+    }
+    Ok(match db.get_user_login(username).await? {
+        Some((id, Some(hash))) if user::verify_password(password, &hash) => Some(id),
+        _ => None,
+    })
+}
+
+fn ask_for_login(headers: &HeaderMap) -> Response {
+    if headers.contains_key("HX-Request") {
+        (
+            StatusCode::UNAUTHORIZED,
+            [("HX-Redirect", base_path::to("/login"))],
+        )
+            .into_response()
+    } else if headers
+        .get(ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+    {
+        Redirect::to(&base_path::to("/login")).into_response()
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, r#"Basic realm="feedreader""#)],
+            "log in first",
+        )
+            .into_response()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    next: String,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+/// Where to go after logging in, as long as it's somewhere on this site.
+fn local_path(next: Option<String>) -> String {
+    next.filter(|n| n.starts_with('/') && !n.starts_with("//"))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+async fn login_page(Query(query): Query<LoginQuery>) -> LoginTemplate {
+    LoginTemplate {
+        next: local_path(query.next),
+        error: None,
+    }
+}
+
+async fn login(
+    State(AppState {
+        db,
+        login,
+        throttle,
+        ..
+    }): State<AppState>,
+    client: Option<Extension<proxy::Client>>,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    let next = local_path(form.next);
+    let Some(login) = login else {
+        return Ok(Redirect::to(&base_path::to(&next)).into_response());
+    };
+    let ip = client_ip(client.as_deref());
+    let user =
+        match attempt_login(&login, &db, &throttle, ip, &form.username, &form.password).await? {
+            Attempt::User(user) => user,
+            Attempt::Wrong => {
+                let page = LoginTemplate {
+                    next,
+                    error: Some("Wrong username or password".to_string()),
+                };
+                return Ok((StatusCode::UNAUTHORIZED, page).into_response());
+            }
+            Attempt::Locked(wait) => {
+                let minutes = wait.as_secs().div_ceil(60);
+                let page = LoginTemplate {
+                    next,
+                    error: Some(format!(
+                        "Too many failed logins, try again in {} minutes",
+                        minutes
+                    )),
+                };
+                return Ok((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, wait.as_secs().to_string())],
+                    page,
+                )
+                    .into_response());
+            }
+        };
+    db.add_audit_event("login", Some(&form.username), ip.map(|ip| ip.to_string()))
+        .await?;
+    let id = Uuid::new_v4().to_string();
+    db.add_session(&id, user, chrono::Duration::days(SESSION_DAYS))
+        .await?;
+    // only when the proxy in front says it's over https, as plain http would drop it
+    let secure = match client {
+        Some(Extension(client)) if client.scheme == "https" => "; Secure",
+        _ => "",
+    };
+    let cookie = format!(
+        "{}={}; Path={}/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        SESSION_COOKIE,
+        id,
+        base_path::get(),
+        SESSION_DAYS * 24 * 60 * 60,
+        secure
+    );
+    Ok(([(SET_COOKIE, cookie)], Redirect::to(&base_path::to(&next))).into_response())
+}
+
+async fn logout(
+    State(AppState { db, .. }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(id) = session_id(&headers) {
+        db.remove_session(id).await?;
+    }
+    let cookie = format!(
+        "{}=; Path={}/; HttpOnly; SameSite=Lax; Max-Age=0",
+        SESSION_COOKIE,
+        base_path::get()
+    );
+    Ok((
+        [(SET_COOKIE, cookie)],
+        Redirect::to(&base_path::to("/login")),
+    )
+        .into_response())
+}
+
+/// Checks a bearer token against the scope the route needs.
+///
+/// Requests without a token are let through as before; only tokens are scoped,
+/// and it's `require_login` that turns away anyone with neither.
+pub async fn require_scope(
+    State(AppState {
+        tokens,
+        db,
+        read_only,
+        ..
+    }): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let bearer = match authorization.strip_prefix("Bearer ") {
+        Some(token) => Some(token),
+        // Google Reader clients send back whatever ClientLogin accepted,
+        // which is anything while there are no tokens
+        None => match authorization.strip_prefix("GoogleLogin auth=") {
+            Some(token) => match any_tokens(&tokens, &db).await {
+                Ok(true) => Some(token),
+                Ok(false) => None,
+                Err(e) => return AppError(e).into_response(),
+            },
+            None => None,
+        },
+    };
+    if let Some(token) = bearer {
+        let scope = match token_scope(&tokens, &db, token.trim(), read_only.enabled()).await {
+            Ok(Some(scope)) => scope,
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "unknown token").into_response(),
+            Err(e) => return AppError(e).into_response(),
+        };
+        let required = Scope::required(request.method(), route_path(&request));
+        if scope < required {
+            return (
+                StatusCode::FORBIDDEN,
+                format!("token needs the {} scope", required),
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    tokens: Vec<ApiToken>,
+    /// A token just created, shown this once
+    created: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    name: String,
+    scope: String,
+}
+
+async fn tokens_page(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<TokensTemplate, AppError> {
+    Ok(TokensTemplate {
+        tokens: db.get_api_tokens().await?,
+        created: None,
+    })
+}
+
+async fn create_token(
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<TokenForm>,
+) -> Result<TokensTemplate, AppError> {
+    let scope: Scope = form.scope.parse()?;
+    let secret = generate_token()?;
+    let token = ApiToken {
+        id: Uuid::new_v4().to_string(),
+        name: form.name,
+        scope: scope.to_string(),
+        created_at: UtcTime(Utc::now()),
+        last_used_at: None,
+    };
+    db.add_api_token(&token, &hash_token(&secret)).await?;
+    info!("created {} token {}", token.scope, token.name);
+    Ok(TokensTemplate {
+        tokens: db.get_api_tokens().await?,
+        created: Some(secret),
+    })
+}
+
+async fn revoke_token(
+    Path(token_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    if db.remove_api_token(&token_id).await? {
+        Ok(StatusCode::OK)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn route_scopes() {
+        assert_eq!(Scope::required(&Method::GET, "/"), Scope::Read);
+        assert_eq!(Scope::required(&Method::GET, "/starred.json"), Scope::Read);
+        assert_eq!(
+            Scope::required(&Method::POST, "/read/:entry_id"),
+            Scope::MarkState
+        );
+        assert_eq!(Scope::required(&Method::POST, "/read_only"), Scope::Admin);
+        assert_eq!(
+            Scope::required(&Method::DELETE, "/feeds/:feed_url"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(
+            Scope::required(&Method::GET, "/feeds/:feed_url/icon"),
+            Scope::Read
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/api/v1/entries/:entry_id/starred"),
+            Scope::MarkState
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/api/v1/feeds"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/greader/reader/api/0/edit-tag"),
+            Scope::MarkState
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/greader/accounts/ClientLogin"),
+            Scope::Read
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/preferences"),
+            Scope::MarkState
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/categories/:category_id/digest"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(
+            Scope::required(&Method::POST, "/imports"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(Scope::required(&Method::GET, "/imports"), Scope::Read);
+        assert_eq!(
+            Scope::required(&Method::GET, "/discover"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(
+            Scope::required(&Method::GET, "/suggestions/feeds"),
+            Scope::ManageFeeds
+        );
+        assert_eq!(Scope::required(&Method::GET, "/dump"), Scope::Admin);
+        assert_eq!(Scope::required(&Method::GET, "/tokens.html"), Scope::Admin);
+        assert_eq!(Scope::required(&Method::POST, "/unknown"), Scope::Admin);
+    }
+
+    #[test]
+    fn lock_out_repeated_failures() {
+        let throttle = LoginThrottle::default();
+        let ip: Option<IpAddr> = Some("10.0.0.1".parse().unwrap());
+        let start = Instant::now();
+        for _ in 1..ACCOUNT_FAILURES {
+            assert!(!throttle.fail(ip, "Owner", start));
+        }
+        assert!(throttle.locked(ip, "owner", start).is_none());
+        assert!(throttle.fail(ip, "owner", start));
+        assert_eq!(throttle.locked(None, "OWNER", start), Some(LOCKOUT));
+        // the address isn't locked out yet, so another username can still try
+        assert!(throttle.locked(ip, "someone", start).is_none());
+        assert!(throttle.locked(ip, "owner", start + LOCKOUT).is_none());
+
+        for _ in 0..IP_FAILURES {
+            throttle.fail(ip, "guess", start);
+        }
+        assert!(throttle.locked(ip, "someone", start).is_some());
+        throttle.succeeded("guess");
+        assert!(throttle.locked(None, "guess", start).is_none());
+    }
+
+    #[test]
+    fn login_credentials() {
+        let login = Login::new("me".to_string(), "hunter2".to_string());
+        assert!(login.matches("me", "hunter2"));
+        assert!(!login.matches("me", "hunter"));
+        assert!(!login.matches("you", "hunter2"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            "theme=dark; feedreader_session=abc".parse().unwrap(),
+        );
+        assert_eq!(session_id(&headers), Some("abc"));
+        assert_eq!(local_path(Some("/feeds.html".to_string())), "/feeds.html");
+        assert_eq!(local_path(Some("//evil.example".to_string())), "/");
+        assert_eq!(local_path(None), "/");
+    }
+
+    #[tokio::test]
+    async fn created_tokens() -> Result<()> {
+        let db = db::connect(db::ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let tokens: Tokens = "phone=mark-state".parse()?;
+        assert!(accepts(&Tokens::default(), &db, "anything", false).await?);
+        assert!(accepts(&tokens, &db, "phone", false).await?);
+        assert!(!accepts(&tokens, &db, "other", false).await?);
+
+        let secret = generate_token()?;
+        let token = ApiToken {
+            id: "1".to_string(),
+            name: "script".to_string(),
+            scope: Scope::ManageFeeds.to_string(),
+            created_at: UtcTime(Utc::now()),
+            last_used_at: None,
+        };
+        db.add_api_token(&token, &hash_token(&secret)).await?;
+        // read-only, so the use isn't written down
+        assert_eq!(
+            token_scope(&tokens, &db, &secret, true).await?,
+            Some(Scope::ManageFeeds)
+        );
+        assert!(db.get_api_tokens().await?[0].last_used_at.is_none());
+        assert_eq!(
+            token_scope(&tokens, &db, &secret, false).await?,
+            Some(Scope::ManageFeeds)
+        );
+        assert!(!accepts(&Tokens::default(), &db, "anything", false).await?);
+        let used = db.get_api_tokens().await?[0]
+            .last_used_at
+            .as_ref()
+            .map(|t| t.0);
+        assert!(used.is_some());
+        // noted at most once a minute
+        token_scope(&tokens, &db, &secret, false).await?;
+        let again = db.get_api_tokens().await?[0]
+            .last_used_at
+            .as_ref()
+            .map(|t| t.0);
+        assert_eq!(again, used);
+
+        assert!(db.remove_api_token("1").await?);
+        assert_eq!(token_scope(&tokens, &db, &secret, false).await?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_tokens() {
+        let tokens: Tokens = "widget=read, phone=mark-state,me=admin".parse().unwrap();
+        assert_eq!(tokens.scope("widget"), Some(Scope::Read));
+        assert_eq!(tokens.scope("phone"), Some(Scope::MarkState));
+        assert_eq!(tokens.scope("me"), Some(Scope::Admin));
+        assert_eq!(tokens.scope("other"), None);
+        assert!(Scope::Read < Scope::ManageFeeds);
+        assert!("widget=everything".parse::<Tokens>().is_err());
+    }
+}
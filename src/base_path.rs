@@ -0,0 +1,84 @@
+//! Serving under a URL prefix like `/reader`, set with `FEED_BASE_PATH`, to sit
+//! alongside other things on a domain rather than needing one to itself.
+//!
+//! Requests have the prefix taken off before they're routed, so routes and
+//! everything looking at them are written as if there were none. Links and
+//! redirects put it back with `to`, or the `url` filter in templates.
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use axum::extract::Request;
+use axum::http::{StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// Sets the prefix for the rest of the process, once on startup.
+pub fn set(base: String) {
+    // a second call, like from a test, keeps the first
+    let _ = BASE_PATH.set(base);
+}
+
+/// The prefix, empty when serving from the root.
+pub fn get() -> &'static str {
+    BASE_PATH.get().map(String::as_str).unwrap_or_default()
+}
+
+/// The path as it's reached from outside, `path` starting with `/`.
+pub fn to(path: &str) -> String {
+    format!("{}{}", get(), path)
+}
+
+/// A prefix as it's configured, with or without slashes around it, as it's
+/// used: starting with a slash and without one at the end.
+pub fn normalize(base: &str) -> Result<String> {
+    let base = base.trim().trim_matches('/');
+    if base
+        .chars()
+        .any(|c| c.is_whitespace() || "?#%\\".contains(c))
+    {
+        return Err(anyhow!("{} isn't a plain path", base));
+    }
+    if base.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!("/{}", base))
+}
+
+/// Takes the prefix off requests, turning away any without it.
+pub async fn strip(mut request: Request, next: Next) -> Response {
+    let base = get();
+    let Some(rest) = request.uri().path().strip_prefix(base) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let rest = match rest {
+        "" => "/",
+        rest if rest.starts_with('/') => rest,
+        // just sharing the start, like /readers for /reader
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", rest, query),
+        None => rest.to_string(),
+    };
+    let Ok(uri) = path_and_query.parse::<Uri>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    *request.uri_mut() = uri;
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_base_paths() {
+        assert_eq!(normalize("reader").unwrap(), "/reader");
+        assert_eq!(normalize("/apps/reader/").unwrap(), "/apps/reader");
+        assert_eq!(normalize("/").unwrap(), "");
+        assert_eq!(normalize("").unwrap(), "");
+        assert!(normalize("/reader?x=1").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Managing categories in one place, where renaming one renames it for every
+//! feed, combined view and saved filter that uses it.
+use askama_axum::Template;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Form, Router};
+use serde::Deserialize;
+
+use crate::filters;
+use crate::view::category_path;
+use crate::{db, AppError, AppState, Category};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/categories.html", get(categories_page))
+        .route("/categories", post(add_category))
+        .route(
+            "/categories/:category_id",
+            put(rename_category).delete(remove_category),
+        )
+        .route("/categories/:category_id/digest", post(set_category_digest))
+}
+
+#[derive(Template)]
+#[template(path = "categories.html")]
+struct CategoriesTemplate {
+    categories: Vec<Category>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CategoryForm {
+    name: String,
+}
+
+/// The page again after a change, with why it wasn't made if it wasn't. It's a
+/// success either way so htmx swaps the error in.
+async fn categories(db: &db::DB, error: Option<&str>) -> Result<Response, AppError> {
+    Ok(CategoriesTemplate {
+        categories: db.get_category_list().await?,
+        error: error.map(String::from),
+    }
+    .into_response())
+}
+
+async fn categories_page(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    categories(&db, None).await
+}
+
+async fn add_category(
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<CategoryForm>,
+) -> Result<Response, AppError> {
+    match db.add_category(&form.name).await? {
+        Some(_) => categories(&db, None).await,
+        None => categories(&db, Some("A category needs a name")).await,
+    }
+}
+
+async fn rename_category(
+    Path(category_id): Path<i64>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<CategoryForm>,
+) -> Result<Response, AppError> {
+    if db::normalize_category(&form.name).is_empty() {
+        return categories(&db, Some("A category needs a name")).await;
+    }
+    if !db.rename_category(category_id, &form.name).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    categories(&db, None).await
+}
+
+#[derive(Deserialize)]
+struct DigestForm {
+    digest: Option<String>,
+}
+
+async fn set_category_digest(
+    Path(category_id): Path<i64>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<DigestForm>,
+) -> Result<Response, AppError> {
+    if !db
+        .set_category_digest(category_id, form.digest.is_some())
+        .await?
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    categories(&db, None).await
+}
+
+async fn remove_category(
+    Path(category_id): Path<i64>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    if !db.remove_category(category_id).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    categories(&db, None).await
+}
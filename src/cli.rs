@@ -0,0 +1,242 @@
+//! The command line: the server, and administration that works on the database
+//! directly so it doesn't need the server running.
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
+use reqwest::Url;
+use tokio::time;
+
+use crate::config::Config;
+use crate::secrets::SecretKey;
+use crate::{
+    db, feeds_to_opml, parse_opml_outlines, read_opml, refresher, request_id, sanitize, user, Dump,
+    Feed,
+};
+
+#[derive(Parser)]
+#[command(version, about = "A minimal feed reader")]
+pub struct Cli {
+    /// TOML file of settings, which environment variables override
+    #[arg(long, global = true, env = "FEED_CONFIG")]
+    pub config: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the server, what happens without a command
+    Serve,
+    /// Check the configuration, database and network the server needs
+    Doctor,
+    /// Add the feeds in an OPML file
+    Import {
+        /// The OPML file
+        path: String,
+    },
+    /// Print every feed as OPML
+    Export,
+    /// Load a dump from /dump, with the owner's read and starred state
+    Restore {
+        /// The dump's JSON file
+        path: String,
+    },
+    /// Add a feed, fetched on the next refresh
+    AddFeed {
+        /// Where the feed is fetched from
+        url: String,
+        /// Shown for the feed, its host when missing
+        #[arg(long)]
+        name: Option<String>,
+        /// The site the feed is for, the feed url's origin when missing
+        #[arg(long)]
+        site_url: Option<String>,
+        #[arg(long, default_value = "")]
+        category: String,
+    },
+    /// Store a credential, like a feed's login, encrypted with FEED_SECRET_KEY.
+    /// The secret is read from the first line of stdin.
+    SetCredential {
+        /// What the credential is stored under
+        name: String,
+    },
+    /// Remove a stored credential
+    RemoveCredential {
+        /// What the credential is stored under
+        name: String,
+    },
+    /// Re-encrypt stored credentials from FEED_SECRET_KEY to a new key, to then
+    /// replace FEED_SECRET_KEY with
+    RotateKey {
+        /// The new key, 32 random bytes in base64
+        #[arg(long, env = "FEED_NEW_SECRET_KEY", hide_env_values = true)]
+        new_key: String,
+    },
+    /// Fetch feeds on the refresh interval without serving anything
+    Refresh {
+        /// Fetch every feed once and exit
+        #[arg(long)]
+        once: bool,
+    },
+}
+
+/// Runs one of the administration commands, the ones besides `serve` and `doctor`.
+pub async fn run(command: Command, config_path: Option<&str>) -> Result<()> {
+    let config = Config::load(config_path)?;
+    sanitize::configure(config.html_allow.clone());
+    let backing = config.db.to_string();
+    let db = db::connect(config.db.clone())
+        .await
+        .with_context(|| format!("couldn't open {}", backing))?;
+    db.init()
+        .await
+        .with_context(|| format!("couldn't set up {}", backing))?;
+    // there's no one logged in at the command line, it's the owner running it
+    user::scope(user::OWNER, dispatch(command, config, db)).await
+}
+
+async fn dispatch(command: Command, config: Config, db: db::DB) -> Result<()> {
+    match command {
+        Command::Serve | Command::Doctor => unreachable!("handled by main"),
+        Command::Import { path } => import(&db, &path).await,
+        Command::Export => {
+            println!("{}", feeds_to_opml(&db.get_feeds().await?).to_string()?);
+            Ok(())
+        }
+        Command::Restore { path } => {
+            let file = File::open(&path).with_context(|| format!("couldn't open {}", path))?;
+            let dump: Dump = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("couldn't read a dump from {}", path))?;
+            let (feeds, new_entries) = db.restore(dump).await?;
+            println!(
+                "restored {} feeds and {} new entries from {}",
+                feeds, new_entries, path
+            );
+            Ok(())
+        }
+        Command::AddFeed {
+            url,
+            name,
+            site_url,
+            category,
+        } => {
+            let feed = new_feed(url, name, site_url, category)?;
+            let name = feed.name.clone();
+            db.add_feeds(std::iter::once(feed)).await?;
+            println!("added {}", name);
+            Ok(())
+        }
+        Command::SetCredential { name } => {
+            let key = config
+                .secret_key
+                .as_ref()
+                .ok_or_else(|| anyhow!("set FEED_SECRET_KEY to store credentials"))?;
+            let mut secret = String::new();
+            std::io::stdin()
+                .read_line(&mut secret)
+                .context("couldn't read the secret from stdin")?;
+            let secret = secret.trim_end_matches(['\r', '\n']);
+            if secret.is_empty() {
+                return Err(anyhow!("no secret given on stdin"));
+            }
+            db.set_credential(key, &name, secret).await?;
+            println!("stored {}", name);
+            Ok(())
+        }
+        Command::RemoveCredential { name } => {
+            if !db.remove_credential(&name).await? {
+                return Err(anyhow!("no credential called {}", name));
+            }
+            println!("removed {}", name);
+            Ok(())
+        }
+        Command::RotateKey { new_key } => {
+            let old = config.secret_key.as_ref().ok_or_else(|| {
+                anyhow!("set FEED_SECRET_KEY to the key credentials are encrypted with now")
+            })?;
+            let new: SecretKey = new_key.parse().context("invalid new key")?;
+            let rotated = db.rotate_credentials(old, &new).await?;
+            println!(
+                "re-encrypted {} credentials, set FEED_SECRET_KEY to the new key",
+                rotated
+            );
+            Ok(())
+        }
+        Command::Refresh { once } => refresh(&config, db, once).await,
+    }
+}
+
+/// Adds feeds one at a time like the server's imports, reporting the ones that fail.
+async fn import(db: &db::DB, path: &str) -> Result<()> {
+    let document = read_opml(path)?;
+    let outlines = parse_opml_outlines(&document);
+    let total = outlines.len();
+    let mut added = 0;
+    for (name, feed) in outlines {
+        let result = match feed {
+            Ok(feed) => db.add_feeds(std::iter::once(feed)).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => added += 1,
+            Err(e) => eprintln!("couldn't import {}: {:#}", name, e),
+        }
+    }
+    println!("imported {} of {} feeds from {}", added, total, path);
+    Ok(())
+}
+
+fn new_feed(
+    url: String,
+    name: Option<String>,
+    site_url: Option<String>,
+    category: String,
+) -> Result<Feed> {
+    let parsed = Url::parse(&url).with_context(|| format!("invalid feed url {}", url))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow!("feed url {} has no host", url))?;
+    let name = name.unwrap_or_else(|| host.to_string());
+    let site_url = site_url.unwrap_or_else(|| parsed.origin().ascii_serialization());
+    Ok(Feed::new(name, site_url, url, category))
+}
+
+async fn refresh(config: &Config, db: db::DB, once: bool) -> Result<()> {
+    let (refresher, _, _) = refresher(config, db).await?;
+    if once {
+        // run as though asked for, so a staggered schedule doesn't spread it out
+        refresher.refresh_all(Some(request_id::generate())).await;
+        return Ok(());
+    }
+    let mut interval = time::interval(config.refresh_interval);
+    loop {
+        interval.tick().await;
+        refresher.refresh_all(None).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_feed_defaults() {
+        let feed = new_feed(
+            "https://blog.example.com/feed.xml".to_string(),
+            None,
+            None,
+            String::new(),
+        )
+        .unwrap();
+        assert_eq!(feed.name, "blog.example.com");
+        assert_eq!(feed.site_url, "https://blog.example.com");
+
+        let cli =
+            Cli::try_parse_from(["feedreader", "refresh", "--once", "--config", "a.toml"]).unwrap();
+        assert_eq!(cli.config.as_deref(), Some("a.toml"));
+        assert!(matches!(cli.command, Some(Command::Refresh { once: true })));
+        assert!(new_feed("not a url".to_string(), None, None, String::new()).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Settings read when the server starts, from a TOML file given with `--config`
+//! and the environment, which overrides it.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::db::{ConnectionBacking, TursoCreds};
+use crate::{
+    auth, base_path, debug_dump, digest, filters, notify, parse, proxy, public_url, refresh,
+    sanitize, secrets,
+};
+
+/// Everything read from the config file and environment on startup.
+pub struct Config {
+    pub db: ConnectionBacking,
+    pub opml_file: Option<String>,
+    pub refresh_interval: Duration,
+    pub schedule: refresh::Schedule,
+    pub failure_webhook: Option<String>,
+    pub notifiers: notify::NotifierSpecs,
+    /// Names of the notifiers told when a feed starts or stops failing
+    pub failure_notifiers: Vec<String>,
+    pub head_probe: bool,
+    pub excerpt_length: usize,
+    /// Characters of entry titles shown in lists, 0 for all of them
+    pub title_length: usize,
+    pub link_check_interval: Duration,
+    /// Time between the first fetches of newly added feeds
+    pub first_fetch_spacing: Duration,
+    pub client: ClientConfig,
+    pub retention: Option<chrono::Duration>,
+    pub resurface_count: Option<u32>,
+    /// How often to save starred entries' pages to the Wayback Machine, never when missing
+    pub archive_interval: Option<Duration>,
+    pub tokens: auth::Tokens,
+    pub login: Option<auth::Login>,
+    /// What stored credentials are encrypted with, none can be stored without it
+    pub secret_key: Option<secrets::SecretKey>,
+    /// Proxies trusted to say who they're forwarding requests for
+    pub trusted_proxies: proxy::TrustedProxies,
+    /// What every path is served under, empty for the root
+    pub base_path: String,
+    /// Where the server is reached from outside, for links read elsewhere
+    pub public_url: Option<reqwest::Url>,
+    /// The elements and attributes kept in feed HTML
+    pub html_allow: sanitize::Allowlist,
+    /// Where and how often unread entries are emailed, never when missing
+    pub digest: Option<digest::DigestConfig>,
+    /// Where bodies of feeds that couldn't be parsed are kept, nowhere when missing
+    pub body_dumps: Option<debug_dump::BodyDumps>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, if there is one, with the environment
+    /// overriding anything it sets.
+    pub fn load(path: Option<&str>) -> Result<Config> {
+        let mut source = match path {
+            Some(path) => Source::read(path)?,
+            None => Source::default(),
+        };
+        let creds = match (source.get("TURSO_URL")?, source.get("TURSO_TOKEN")?) {
+            (Some(url), Some(token)) => Some(TursoCreds { url, token }),
+            _ => None,
+        };
+        let db = match (creds, source.get("FEED_DB_PATH")?) {
+            (Some(creds), Some(path)) => ConnectionBacking::RemoteReplica(creds, path),
+            (Some(creds), None) => ConnectionBacking::Remote(creds),
+            (None, Some(path)) => ConnectionBacking::File(path),
+            (None, None) => {
+                return Err(anyhow!(
+                    "no database configured, set FEED_DB_PATH or both TURSO_URL and TURSO_TOKEN"
+                ))
+            }
+        };
+        let login = match (source.get("FEED_USERNAME")?, source.get("FEED_PASSWORD")?) {
+            (Some(username), Some(password)) => Some(auth::Login::new(username, password)),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "FEED_USERNAME and FEED_PASSWORD have to be set together"
+                ))
+            }
+        };
+        // parsed here rather than with `var` so a bad key isn't repeated in the error
+        let secret_key = match source.get("FEED_SECRET_KEY")? {
+            Some(key) => Some(key.parse().context("invalid FEED_SECRET_KEY")?),
+            None => None,
+        };
+        let public_url = match source.get("FEED_PUBLIC_URL")? {
+            Some(url) => Some(public_url::parse(&url).context("invalid FEED_PUBLIC_URL")?),
+            None => None,
+        };
+        // the public URL's path is the base path unless it's set too, when they have to agree
+        let public_base = match &public_url {
+            Some(url) => Some(base_path::normalize(url.path()).context("invalid FEED_PUBLIC_URL")?),
+            None => None,
+        };
+        let base_path = match (source.get("FEED_BASE_PATH")?, public_base) {
+            (Some(base), public_base) => {
+                let base = base_path::normalize(&base).context("invalid FEED_BASE_PATH")?;
+                if public_base.as_ref().is_some_and(|p| *p != base) {
+                    return Err(anyhow!(
+                        "FEED_PUBLIC_URL has to be under FEED_BASE_PATH={}",
+                        base
+                    ));
+                }
+                base
+            }
+            (None, public_base) => public_base.unwrap_or_default(),
+        };
+        let digest = Self::digest(&mut source)?;
+        let body_dumps = match (
+            source.get("FEED_DEBUG_DUMP_DIR")?,
+            source.get("FEED_DEBUG_FEEDS")?,
+        ) {
+            (Some(dir), feeds) => Some(debug_dump::BodyDumps::new(
+                dir,
+                feeds
+                    .map(|feeds| {
+                        feeds
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|f| !f.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            )),
+            (None, Some(_)) => {
+                return Err(anyhow!(
+                    "FEED_DEBUG_FEEDS needs FEED_DEBUG_DUMP_DIR to keep their bodies in"
+                ))
+            }
+            (None, None) => None,
+        };
+        let config = Config {
+            db,
+            opml_file: source.get("FEED_OPML_FILE")?,
+            refresh_interval: Duration::from_secs(
+                source.var("FEED_REFRESH_INTERVAL")?.unwrap_or(3 * 60),
+            ),
+            schedule: source.var("FEED_REFRESH_SCHEDULE")?.unwrap_or_default(),
+            failure_webhook: source.get("FEED_FAILURE_WEBHOOK_URL")?,
+            notifiers: source.var("FEED_NOTIFIERS")?.unwrap_or_default(),
+            failure_notifiers: source
+                .get("FEED_FAILURE_NOTIFIERS")?
+                .map(|names| {
+                    names
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|n| !n.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            head_probe: source.get("FEED_HEAD_PROBE")?.is_some_and(|p| p == "true"),
+            excerpt_length: source
+                .var("FEED_EXCERPT_LENGTH")?
+                .unwrap_or(parse::DEFAULT_EXCERPT_LENGTH),
+            title_length: source
+                .var("FEED_TITLE_LENGTH")?
+                .unwrap_or(filters::DEFAULT_TITLE_LENGTH),
+            link_check_interval: Duration::from_secs(
+                source
+                    .var("FEED_LINK_CHECK_INTERVAL")?
+                    .unwrap_or(24 * 60 * 60),
+            ),
+            first_fetch_spacing: Duration::from_secs(
+                source.var("FEED_FIRST_FETCH_SPACING")?.unwrap_or(2),
+            ),
+            client: ClientConfig {
+                max_idle_per_host: source.var("FEED_POOL_MAX_IDLE_PER_HOST")?,
+                idle_timeout: source
+                    .var("FEED_POOL_IDLE_TIMEOUT")?
+                    .map(Duration::from_secs),
+                http2: source.var("FEED_HTTP2")?.unwrap_or(true),
+                contact: source.get("FEED_CONTACT_URL")?,
+            },
+            retention: source
+                .var("FEED_RETENTION_DAYS")?
+                .map(chrono::Duration::days),
+            resurface_count: source.var("FEED_RESURFACE_COUNT")?,
+            archive_interval: source
+                .var("FEED_ARCHIVE_INTERVAL")?
+                .map(Duration::from_secs),
+            tokens: source.var("FEED_API_TOKENS")?.unwrap_or_default(),
+            login,
+            secret_key,
+            trusted_proxies: source.var("FEED_TRUSTED_PROXIES")?.unwrap_or_default(),
+            base_path,
+            public_url,
+            html_allow: source.var("FEED_HTML_ALLOW")?.unwrap_or_default(),
+            digest,
+            body_dumps,
+        };
+        // a misspelled setting would otherwise be quietly left at its default
+        if let Some(key) = source.unknown().next() {
+            return Err(anyhow!("unknown setting {} in the config file", key));
+        }
+        Ok(config)
+    }
+
+    /// The email digest's settings, which all have to be there when it's on.
+    fn digest(source: &mut Source) -> Result<Option<digest::DigestConfig>> {
+        // all looked up either way, so none are taken for unknown settings
+        let frequency = source.var("FEED_DIGEST")?;
+        let smtp_host = source.get("FEED_SMTP_HOST")?;
+        let smtp_tls: digest::SmtpTls = source.var("FEED_SMTP_TLS")?.unwrap_or_default();
+        let smtp_port = source.var("FEED_SMTP_PORT")?;
+        let username = source.get("FEED_SMTP_USERNAME")?;
+        let password = source.get("FEED_SMTP_PASSWORD")?;
+        let from = source.get("FEED_DIGEST_FROM")?;
+        let to = source.get("FEED_DIGEST_TO")?;
+        let Some(frequency) = frequency else {
+            return Ok(None);
+        };
+        let missing = |name| anyhow!("FEED_DIGEST needs {} to be set", name);
+        let smtp_login = match (username, password) {
+            (Some(username), Some(password)) => Some(digest::SmtpLogin { username, password }),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "FEED_SMTP_USERNAME and FEED_SMTP_PASSWORD go together"
+                ))
+            }
+        };
+        // a password shouldn't go over the network in the clear
+        if smtp_login.is_some() && smtp_tls == digest::SmtpTls::None {
+            return Err(anyhow!(
+                "FEED_SMTP_PASSWORD needs FEED_SMTP_TLS to be starttls or tls"
+            ));
+        }
+        Ok(Some(digest::DigestConfig {
+            frequency,
+            smtp_host: smtp_host.ok_or_else(|| missing("FEED_SMTP_HOST"))?,
+            smtp_port: smtp_port.unwrap_or(smtp_tls.default_port()),
+            smtp_tls,
+            smtp_login,
+            from: digest::parse_address(&from.ok_or_else(|| missing("FEED_DIGEST_FROM"))?)
+                .context("invalid FEED_DIGEST_FROM")?,
+            to: digest::parse_addresses(&to.ok_or_else(|| missing("FEED_DIGEST_TO"))?)
+                .context("invalid FEED_DIGEST_TO")?,
+        }))
+    }
+
+    /// The local database file, if there is one.
+    pub fn db_path(&self) -> Option<&str> {
+        match &self.db {
+            ConnectionBacking::File(p) | ConnectionBacking::RemoteReplica(_, p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+/// Connection pooling for the client feeds are fetched with, to tune for lots of
+/// small fetches or a few big ones. Unset values keep reqwest's defaults.
+pub struct ClientConfig {
+    /// Idle connections kept open to each host
+    pub max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept before it's closed
+    pub idle_timeout: Option<Duration>,
+    /// Off to only ever speak HTTP/1.1
+    pub http2: bool,
+    /// Where publishers can find out about this instance or reach whoever runs it
+    pub contact: Option<String>,
+}
+
+/// What a client's requests are for, told apart in their user agent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Purpose {
+    /// Polling feeds
+    Fetch,
+    /// Pages fetched to show, like full articles and feeds found on a site
+    Render,
+    /// Checking on links that are being kept
+    Archive,
+}
+
+impl ClientConfig {
+    /// The user agent for a purpose, with the contact URL as feed fetchers
+    /// conventionally include it.
+    pub fn user_agent(&self, purpose: Purpose) -> String {
+        let role = match purpose {
+            Purpose::Fetch => "feed fetcher",
+            Purpose::Render => "page renderer",
+            Purpose::Archive => "link archiver",
+        };
+        match &self.contact {
+            Some(contact) => format!(
+                "feedreader/{} ({}; +{})",
+                env!("CARGO_PKG_VERSION"),
+                role,
+                contact
+            ),
+            None => format!("feedreader/{} ({})", env!("CARGO_PKG_VERSION"), role),
+        }
+    }
+
+    /// A client builder with these settings, identifying itself for the purpose.
+    pub fn builder(&self, purpose: Purpose) -> reqwest::ClientBuilder {
+        let mut builder = reqwest::Client::builder().user_agent(self.user_agent(purpose));
+        if let Some(max) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if !self.http2 {
+            builder = builder.http1_only();
+        }
+        builder
+    }
+}
+
+/// Where settings are read from: the environment, then the config file.
+#[derive(Default)]
+struct Source {
+    /// The file's settings, named like the environment variables in lower case
+    /// and without `FEED_`, so `FEED_DB_PATH` is `db_path`
+    file: toml::Table,
+    /// Settings looked up so far, by their name in the file
+    known: HashSet<String>,
+}
+
+impl Source {
+    fn read(path: &str) -> Result<Source> {
+        let text = fs::read_to_string(path).with_context(|| format!("couldn't read {}", path))?;
+        let file = text
+            .parse()
+            .with_context(|| format!("couldn't parse {}", path))?;
+        Ok(Source {
+            file,
+            known: HashSet::new(),
+        })
+    }
+
+    /// A setting as text, the environment variable overriding the file.
+    fn get(&mut self, name: &str) -> Result<Option<String>> {
+        let key = name.strip_prefix("FEED_").unwrap_or(name).to_lowercase();
+        self.known.insert(key.clone());
+        if let Ok(value) = env::var(name) {
+            return Ok(Some(value));
+        }
+        let value = match self.file.get(&key) {
+            None => return Ok(None),
+            Some(toml::Value::String(s)) => s.clone(),
+            Some(toml::Value::Integer(i)) => i.to_string(),
+            Some(toml::Value::Boolean(b)) => b.to_string(),
+            // lists are comma separated, like api_tokens is in FEED_API_TOKENS
+            Some(toml::Value::Array(values)) => values
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow!("{} in the config file has to be a list of strings", key))?
+                .join(","),
+            Some(_) => {
+                return Err(anyhow!(
+                    "{} in the config file has to be a string, integer, boolean or list",
+                    key
+                ))
+            }
+        };
+        Ok(Some(value))
+    }
+
+    /// Parses an optional setting, naming it and its value if that fails.
+    fn var<T>(&mut self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.get(name)? {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| anyhow!("{}", e))
+                .with_context(|| format!("invalid {}={:?}", name, value)),
+            None => Ok(None),
+        }
+    }
+
+    /// Settings in the file that nothing looked up.
+    fn unknown(&self) -> impl Iterator<Item = &String> {
+        self.file.keys().filter(|key| !self.known.contains(*key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn user_agents_by_purpose() {
+        let mut client = ClientConfig {
+            max_idle_per_host: None,
+            idle_timeout: None,
+            http2: true,
+            contact: None,
+        };
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            client.user_agent(Purpose::Fetch),
+            format!("feedreader/{} (feed fetcher)", version)
+        );
+        client.contact = Some("https://reader.example.com/about".to_string());
+        assert_eq!(
+            client.user_agent(Purpose::Render),
+            format!(
+                "feedreader/{} (page renderer; +https://reader.example.com/about)",
+                version
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_values_name_the_variable() {
+        let mut source = Source::default();
+        env::set_var("FEED_TEST_NUMBER", "soon");
+        let err = source.var::<u64>("FEED_TEST_NUMBER").unwrap_err();
+        assert!(format!("{:#}", err).starts_with("invalid FEED_TEST_NUMBER=\"soon\""));
+
+        env::set_var("FEED_TEST_NUMBER", "30");
+        assert_eq!(source.var::<u64>("FEED_TEST_NUMBER").unwrap(), Some(30));
+        assert_eq!(source.var::<u64>("FEED_TEST_UNSET").unwrap(), None);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut source = Source {
+            file: r#"
+                test_interval = 60
+                test_name = "from the file"
+                api_tokens = ["abc=read", "def=admin"]
+                test_typo = true
+            "#
+            .parse()
+            .unwrap(),
+            known: HashSet::new(),
+        };
+        env::set_var("FEED_TEST_NAME", "from the environment");
+        assert_eq!(source.var::<u64>("FEED_TEST_INTERVAL").unwrap(), Some(60));
+        assert_eq!(
+            source.get("FEED_TEST_NAME").unwrap().as_deref(),
+            Some("from the environment")
+        );
+        assert_eq!(
+            source.get("FEED_API_TOKENS").unwrap().as_deref(),
+            Some("abc=read,def=admin")
+        );
+        assert_eq!(source.unknown().collect::<Vec<_>>(), vec!["test_typo"]);
+    }
+}
@@ -1,21 +1,34 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::{env, str::FromStr};
+use std::{fmt, str::FromStr};
 
-use anyhow::{Context, Result};
-use chrono::Utc;
+use anyhow::{anyhow, Context, Result};
+use chrono::{TimeZone, Utc};
+use tokio::sync::watch;
+use tracing::info;
 
-use crate::UtcTime;
+use crate::extract::{Article, ExtractMode};
+use crate::preview::LinkPreview;
+use crate::secrets::SecretKey;
+use crate::{rules, user, UtcTime};
 
-use super::{Entry, Feed};
+use super::{
+    ApiToken, ArchiveMonth, Category, CombinedView, DayCount, Dump, Entry, EntryCounts,
+    EntryRevision, Feed, FeedStats, FetchStatus, FilterRule, Icon, SavedFilter, User, Webhook,
+};
 
 #[derive(Clone)]
 pub struct DB {
     main_conn: libsql::Connection,
-    update_conn: libsql::Connection,
     #[allow(dead_code)] // someday
     db: Arc<libsql::Database>,
+    /// The row id of the last entry `add_entries` stored, for whatever wants to
+    /// know about new entries as they come in
+    inserted: Arc<watch::Sender<i64>>,
 }
 
+#[derive(Clone)]
 pub enum ConnectionBacking {
     #[allow(dead_code)] // used in tests...
     Memory,
@@ -24,20 +37,25 @@ pub enum ConnectionBacking {
     RemoteReplica(TursoCreds, String),
 }
 
+impl fmt::Display for ConnectionBacking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionBacking::Memory => write!(f, "in-memory database"),
+            ConnectionBacking::File(p) => write!(f, "database file {}", p),
+            ConnectionBacking::Remote(creds) => write!(f, "turso database {}", creds.url),
+            ConnectionBacking::RemoteReplica(creds, p) => {
+                write!(f, "turso database {} replicated to {}", creds.url, p)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct TursoCreds {
     pub url: String,
     pub token: String,
 }
 
-impl TursoCreds {
-    pub fn from_env() -> Option<Self> {
-        Some(Self {
-            url: env::var("TURSO_URL").ok()?,
-            token: env::var("TURSO_TOKEN").ok()?,
-        })
-    }
-}
-
 pub async fn connect(conn_back: ConnectionBacking) -> Result<DB> {
     let db = match conn_back {
         ConnectionBacking::Remote(creds) => {
@@ -54,23 +72,394 @@ pub async fn connect(conn_back: ConnectionBacking) -> Result<DB> {
         ConnectionBacking::Memory => libsql::Builder::new_local(":memory:").build().await?,
     };
     let main_conn = db.connect()?;
-    // update is always in memory for now
-    let update_conn = libsql::Builder::new_local(":memory:")
-        .build()
-        .await?
-        .connect()?;
+    let (inserted, _) = watch::channel(0);
     Ok(DB {
         main_conn,
-        update_conn,
         db: db.into(),
+        inserted: Arc::new(inserted),
     })
 }
 
+/// Columns added after the initial schema and before migrations, applied by the
+/// baseline to databases created before them.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("entries", "title_key", "TEXT"),
+    ("entries", "duplicate_of", "TEXT"),
+    ("entries", "read_at", "DATETIME"),
+    ("entries", "author", "TEXT"),
+    ("entries", "enclosure_url", "TEXT"),
+    ("entries", "enclosure_type", "TEXT"),
+    ("feeds", "icon_url", "TEXT"),
+    ("feeds", "etag", "TEXT"),
+    ("feeds", "last_modified", "TEXT"),
+    (
+        "feeds",
+        "consecutive_failures",
+        "INTEGER NOT NULL DEFAULT 0",
+    ),
+    ("feeds", "next_retry_at", "DATETIME"),
+    ("feeds", "last_entry_published", "DATETIME"),
+    ("feeds", "last_entry_id", "TEXT"),
+    ("feeds", "content_length", "INTEGER"),
+    ("entries", "content", "TEXT"),
+    ("feeds", "compact", "BOOLEAN NOT NULL DEFAULT false"),
+    ("entries", "link_status", "INTEGER"),
+    ("entries", "link_checked_at", "DATETIME"),
+    ("entries", "link_dead", "BOOLEAN NOT NULL DEFAULT false"),
+    ("entries", "excerpt", "TEXT"),
+    ("feeds", "extract_mode", "TEXT NOT NULL DEFAULT 'never'"),
+    ("entries", "extracted_content", "TEXT"),
+    ("feeds", "push", "BOOLEAN NOT NULL DEFAULT false"),
+    ("entries", "resurfaced_at", "DATETIME"),
+];
+
+/// The version the baseline schema is recorded as.
+const BASELINE_VERSION: i64 = 1;
+
+/// A schema change applied once per database, in a transaction with recording it.
+pub(crate) struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Changes to the schema since the baseline, in order. Append new ones after
+/// the last version; never edit or reorder one that's been released.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        name: "sessions",
+        sql: r#"
+CREATE TABLE sessions
+(
+    id         TEXT PRIMARY KEY NOT NULL,
+    created_at DATETIME NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 3,
+        name: "api_tokens",
+        sql: r#"
+CREATE TABLE api_tokens
+(
+    id           TEXT PRIMARY KEY NOT NULL,
+    name         TEXT NOT NULL,
+    token_hash   TEXT NOT NULL UNIQUE,
+    scope        TEXT NOT NULL,
+    created_at   DATETIME NOT NULL,
+    last_used_at DATETIME
+);
+"#,
+    },
+    Migration {
+        version: 4,
+        name: "users",
+        sql: r#"
+CREATE TABLE users
+(
+    id            INTEGER PRIMARY KEY NOT NULL,
+    username      TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT
+);
+INSERT INTO users (id, username) VALUES (1, 'owner');
+
+CREATE TABLE entry_state
+(
+    user_id       INTEGER NOT NULL,
+    entry_id      TEXT NOT NULL,
+    read          BOOLEAN NOT NULL DEFAULT false,
+    starred       BOOLEAN NOT NULL DEFAULT false,
+    read_at       DATETIME,
+    resurfaced_at DATETIME,
+    PRIMARY KEY (user_id, entry_id)
+);
+CREATE INDEX idx_entry_state_read ON entry_state(user_id, read);
+CREATE INDEX idx_entry_state_entry ON entry_state(entry_id);
+INSERT INTO entry_state (user_id, entry_id, read, starred, read_at, resurfaced_at)
+    SELECT 1, id, COALESCE(read, false), COALESCE(starred, false), read_at, resurfaced_at FROM entries;
+
+DROP INDEX IF EXISTS idx_entries_read;
+ALTER TABLE entries DROP COLUMN read;
+ALTER TABLE entries DROP COLUMN starred;
+ALTER TABLE entries DROP COLUMN read_at;
+ALTER TABLE entries DROP COLUMN resurfaced_at;
+
+CREATE TRIGGER entry_state_insert AFTER INSERT ON entries BEGIN
+    INSERT OR IGNORE INTO entry_state (user_id, entry_id) SELECT id, new.id FROM users;
+END;
+CREATE TRIGGER entry_state_delete AFTER DELETE ON entries BEGIN
+    DELETE FROM entry_state WHERE entry_id = old.id;
+END;
+
+ALTER TABLE sessions ADD COLUMN user_id INTEGER NOT NULL DEFAULT 1;
+"#,
+    },
+    Migration {
+        version: 5,
+        name: "link_previews",
+        sql: r#"
+CREATE TABLE link_previews
+(
+    url         TEXT PRIMARY KEY NOT NULL,
+    title       TEXT,
+    description TEXT,
+    image       TEXT,
+    fetched_at  DATETIME NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 6,
+        name: "saved_filters",
+        sql: r#"
+CREATE TABLE saved_filters
+(
+    id          TEXT PRIMARY KEY NOT NULL,
+    user_id     INTEGER NOT NULL,
+    name        TEXT NOT NULL,
+    unread      BOOLEAN NOT NULL DEFAULT false,
+    starred     BOOLEAN NOT NULL DEFAULT false,
+    category    TEXT,
+    feed_id     TEXT,
+    since_hours INTEGER
+);
+"#,
+    },
+    Migration {
+        version: 7,
+        name: "feed_enabled",
+        sql: r#"
+ALTER TABLE feeds ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT true;
+"#,
+    },
+    Migration {
+        version: 8,
+        name: "hidden_entries",
+        sql: r#"
+ALTER TABLE entry_state ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT false;
+"#,
+    },
+    Migration {
+        version: 9,
+        name: "categories",
+        sql: r#"
+-- spellings that only differ by case or surrounding whitespace, from before
+-- categories were normalized on write, go into their most used one
+UPDATE feeds SET category = (
+    SELECT trim(other.category) FROM feeds AS other
+    WHERE lower(trim(other.category)) = lower(trim(feeds.category))
+    GROUP BY other.category
+    ORDER BY COUNT(*) DESC, other.category
+    LIMIT 1
+);
+
+CREATE TABLE categories
+(
+    id   INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+INSERT OR IGNORE INTO categories (name)
+    SELECT DISTINCT category FROM feeds WHERE category != '' ORDER BY category;
+
+ALTER TABLE feeds ADD COLUMN category_id INTEGER REFERENCES categories (id);
+UPDATE feeds SET category_id = (SELECT id FROM categories WHERE name = feeds.category);
+ALTER TABLE feeds DROP COLUMN category;
+"#,
+    },
+    Migration {
+        version: 10,
+        name: "playback_positions",
+        sql: r#"
+ALTER TABLE entry_state ADD COLUMN playback_position REAL NOT NULL DEFAULT 0;
+"#,
+    },
+    Migration {
+        version: 11,
+        name: "entry_keys",
+        sql: r#"
+-- filled in for existing entries by init, empty when an entry has no key
+ALTER TABLE entries ADD COLUMN link_key TEXT;
+ALTER TABLE entries ADD COLUMN content_key TEXT;
+CREATE INDEX idx_entries_link_key ON entries(link_key);
+CREATE INDEX idx_entries_content_key ON entries(content_key);
+"#,
+    },
+    Migration {
+        version: 12,
+        name: "credentials",
+        sql: r#"
+-- values are encrypted with the instance's secret key, see secrets.rs
+CREATE TABLE credentials
+(
+    name       TEXT PRIMARY KEY NOT NULL,
+    value      TEXT NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 13,
+        name: "filter_rules",
+        sql: r#"
+CREATE TABLE filter_rules
+(
+    id      TEXT PRIMARY KEY NOT NULL,
+    pattern TEXT NOT NULL,
+    field   TEXT NOT NULL,
+    feed_id TEXT,
+    action  TEXT NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 14,
+        name: "audit_log",
+        sql: r#"
+CREATE TABLE audit_log
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    event      TEXT NOT NULL,
+    username   TEXT,
+    ip         TEXT,
+    created_at DATETIME NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 15,
+        name: "icons",
+        sql: r#"
+-- no data when the feed's site didn't have an icon
+CREATE TABLE icons
+(
+    feed_id      TEXT PRIMARY KEY NOT NULL,
+    content_type TEXT,
+    data         BLOB,
+    fetched_at   DATETIME NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 16,
+        name: "feed_redirects",
+        sql: r#"
+-- where a feed's URL has been permanently redirecting to, and how many fetches in a row
+CREATE TABLE feed_redirects
+(
+    feed_id  TEXT PRIMARY KEY NOT NULL,
+    location TEXT NOT NULL,
+    count    INTEGER NOT NULL
+);
+"#,
+    },
+    Migration {
+        version: 17,
+        name: "extracted_at",
+        sql: r#"
+-- articles extracted before this are treated as stale and fetched again when next opened
+ALTER TABLE entries ADD COLUMN extracted_at DATETIME;
+"#,
+    },
+    Migration {
+        version: 18,
+        name: "category_digest",
+        sql: r#"
+-- whether the category's unread entries go in the email digest
+ALTER TABLE categories ADD COLUMN digest BOOLEAN NOT NULL DEFAULT false;
+"#,
+    },
+    Migration {
+        version: 19,
+        name: "webhooks",
+        sql: r#"
+-- where new entries are posted, for every feed or just one feed or category
+CREATE TABLE webhooks
+(
+    id          TEXT PRIMARY KEY NOT NULL,
+    url         TEXT NOT NULL,
+    secret      TEXT NOT NULL,
+    feed_id     TEXT,
+    category_id INTEGER
+);
+"#,
+    },
+    Migration {
+        version: 20,
+        name: "user_settings",
+        sql: r#"
+-- preferences each user sets for themselves, where settings are the whole server's
+CREATE TABLE user_settings
+(
+    user_id INTEGER NOT NULL,
+    key     TEXT NOT NULL,
+    value   TEXT NOT NULL,
+    PRIMARY KEY (user_id, key)
+);
+INSERT INTO user_settings (user_id, key, value)
+    SELECT 1, key, value FROM settings WHERE key = 'new_tab_links';
+DELETE FROM settings WHERE key = 'new_tab_links';
+"#,
+    },
+];
+
+/// The schema version this build expects.
+pub(crate) fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(BASELINE_VERSION, |m| m.version)
+}
+
+/// Everything selected to build an `Entry`.
+const ENTRY_COLUMNS: &str = "entries.rowid AS item_id, id, title, content_link, comments_link, robust_link, published, read, starred, hidden, playback_position, feed, duplicate_of, author, enclosure_url, enclosure_type, content, excerpt, link_dead,
+    (SELECT categories.name FROM feeds JOIN categories ON categories.id = feeds.category_id
+        WHERE feeds.name = entries.feed LIMIT 1) AS category,
+    COALESCE((SELECT compact FROM feeds WHERE feeds.name = entries.feed LIMIT 1), false) AS compact,
+    (SELECT extract_mode FROM feeds WHERE feeds.name = entries.feed LIMIT 1) AS extract_mode,
+    (SELECT feeds.id FROM feeds WHERE feeds.name = entries.feed LIMIT 1) AS feed_id,
+    (SELECT COUNT(*) FROM entry_revisions WHERE entry_revisions.entry_id = entries.id) AS revisions";
+
+/// Entries with the current user's read and starred state on them, to select
+/// `ENTRY_COLUMNS` from.
+fn entries_with_state() -> Result<String> {
+    Ok(format!(
+        "entries JOIN entry_state ON entry_state.entry_id = entries.id AND entry_state.user_id = {}",
+        user::current()?
+    ))
+}
+
+/// Everything selected to build a `Feed`.
+const FEED_COLUMNS: &str = "id, name, site_url, feed_url, category_id,
+    COALESCE((SELECT name FROM categories WHERE categories.id = feeds.category_id), '') AS category,
+    icon_url, etag, last_modified,
+    consecutive_failures, next_retry_at, last_entry_published, last_entry_id, content_length, compact, extract_mode, push, enabled";
+
+/// Adds the category named by the first parameter unless it's blank or there is one.
+const ADD_CATEGORY: &str = "INSERT OR IGNORE INTO categories (name) SELECT ?1 WHERE ?1 != ''";
+
+/// The names of the feeds in the category named by the parameter `param`, for
+/// matching entries' `feed` against.
+fn feeds_in_category(param: &str) -> String {
+    format!(
+        "SELECT feeds.name FROM feeds JOIN categories ON categories.id = feeds.category_id
+        WHERE categories.name = {}",
+        param
+    )
+}
+
+#[derive(Clone)]
 pub enum Ordering {
     Ascending,
     Descending,
 }
 
+impl fmt::Display for Ordering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ordering::Ascending => write!(f, "ASC"),
+            Ordering::Descending => write!(f, "DESC"),
+        }
+    }
+}
+
 impl FromStr for Ordering {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Ordering> {
@@ -82,28 +471,356 @@ impl FromStr for Ordering {
     }
 }
 
+/// A slice of a query's results, everything by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page {
+    pub offset: u32,
+    pub limit: Option<u32>,
+}
+
+impl Page {
+    pub fn new(offset: u32, limit: u32) -> Page {
+        Page {
+            offset,
+            limit: Some(limit),
+        }
+    }
+
+    /// Where the page after this one starts, if `shown` filled this one.
+    pub fn next_offset(&self, shown: usize) -> Option<u32> {
+        match self.limit {
+            Some(limit) if shown as u32 >= limit => Some(self.offset + limit),
+            _ => None,
+        }
+    }
+}
+
+/// How to order a list of feeds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FeedSort {
+    #[default]
+    Name,
+    /// By category, then name
+    Category,
+    /// Most recently fetched first
+    LastFetched,
+    /// Most unread entries first
+    Unread,
+}
+
+impl FeedSort {
+    fn order_clause(&self) -> Result<String> {
+        Ok(match self {
+            FeedSort::Name => "ORDER BY name COLLATE NOCASE, id".to_string(),
+            FeedSort::Category => {
+                "ORDER BY category COLLATE NOCASE, name COLLATE NOCASE, id".to_string()
+            }
+            FeedSort::LastFetched => {
+                "ORDER BY (SELECT MAX(id) FROM feed_updates WHERE feed_updates.feed = feeds.id) DESC, id".to_string()
+            }
+            FeedSort::Unread => format!(
+                "ORDER BY (SELECT COUNT(*) FROM {} WHERE entries.feed = feeds.name AND read = false AND hidden = false) DESC, id",
+                entries_with_state()?
+            ),
+        })
+    }
+}
+
+impl fmt::Display for FeedSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedSort::Name => write!(f, "name"),
+            FeedSort::Category => write!(f, "category"),
+            FeedSort::LastFetched => write!(f, "last_fetched"),
+            FeedSort::Unread => write!(f, "unread"),
+        }
+    }
+}
+
+impl FromStr for FeedSort {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<FeedSort> {
+        match s {
+            "name" => Ok(FeedSort::Name),
+            "category" => Ok(FeedSort::Category),
+            "last_fetched" => Ok(FeedSort::LastFetched),
+            "unread" => Ok(FeedSort::Unread),
+            _ => Err(anyhow!("unknown feed sort {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum EntryFilter {
     Unread,
+    /// Unread entries published in the last given number of days, or without a date
+    UnreadWithin(u32),
     Starred,
     All,
+    /// Entries published in the given year and month
+    Month(i32, u32),
+    /// Entries from the feed with the given id
+    Feed(String),
+    /// Entries from feeds in the given category
+    Category(String),
+    /// Entries matching a full text search, best matches first
+    Search(String),
+    /// Entries from the categories of the combined view with the given id
+    Combined(String),
+    /// Starred entries picked to be read again this week
+    Resurfaced,
+    /// Entries matching the current user's saved filter with the given id
+    Saved(String),
+    /// Entries the current user hid, which every other filter leaves out
+    Hidden,
+    /// Entries with an audio or video enclosure
+    Podcasts,
+}
+
+impl fmt::Display for EntryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryFilter::Unread => write!(f, "unread"),
+            EntryFilter::UnreadWithin(days) => write!(f, "unread_within:{}", days),
+            EntryFilter::Starred => write!(f, "starred"),
+            EntryFilter::All => write!(f, "all"),
+            EntryFilter::Month(year, month) => write!(f, "month:{}-{}", year, month),
+            EntryFilter::Feed(id) => write!(f, "feed:{}", id),
+            EntryFilter::Category(c) => write!(f, "category:{}", c),
+            EntryFilter::Search(q) => write!(f, "search:{}", q),
+            EntryFilter::Combined(id) => write!(f, "view:{}", id),
+            EntryFilter::Resurfaced => write!(f, "resurfaced"),
+            EntryFilter::Saved(id) => write!(f, "saved:{}", id),
+            EntryFilter::Hidden => write!(f, "hidden"),
+            EntryFilter::Podcasts => write!(f, "podcasts"),
+        }
+    }
 }
 
 impl FromStr for EntryFilter {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<EntryFilter> {
+        if let Some(month) = s.strip_prefix("month:") {
+            let (year, month) = month
+                .split_once('-')
+                .ok_or_else(|| anyhow!("month filter should look like month:2024-01"))?;
+            return Ok(EntryFilter::Month(year.parse()?, month.parse()?));
+        }
+        if let Some(days) = s.strip_prefix("unread_within:") {
+            return Ok(EntryFilter::UnreadWithin(days.parse()?));
+        }
+        if let Some(id) = s.strip_prefix("feed:") {
+            return Ok(EntryFilter::Feed(id.to_string()));
+        }
+        if let Some(category) = s.strip_prefix("category:") {
+            return Ok(EntryFilter::Category(category.to_string()));
+        }
+        if let Some(id) = s.strip_prefix("view:") {
+            return Ok(EntryFilter::Combined(id.to_string()));
+        }
+        if let Some(id) = s.strip_prefix("saved:") {
+            return Ok(EntryFilter::Saved(id.to_string()));
+        }
+        if let Some(query) = s.strip_prefix("search:") {
+            return Ok(EntryFilter::Search(query.to_string()));
+        }
         Ok(match s {
             "unread" => EntryFilter::Unread,
             "starred" => EntryFilter::Starred,
+            "resurfaced" => EntryFilter::Resurfaced,
+            "hidden" => EntryFilter::Hidden,
+            "podcasts" => EntryFilter::Podcasts,
             _ => EntryFilter::All,
         })
     }
 }
 
+/// What a saved filter asks of entries, as a WHERE clause over `entries_with_state`
+/// and its parameters. Every part that's set has to hold.
+fn saved_filter_conditions(filter: &SavedFilter) -> (String, Vec<libsql::Value>) {
+    let in_category = format!("feed IN ({})", feeds_in_category("?"));
+    let mut conditions = vec![];
+    let mut params: Vec<libsql::Value> = vec![];
+    if filter.unread {
+        conditions.push("read = false AND duplicate_of IS NULL");
+    }
+    if filter.starred {
+        conditions.push("starred = true");
+    }
+    if let Some(category) = &filter.category {
+        conditions.push(in_category.as_str());
+        params.push(category.clone().into());
+    }
+    if let Some(feed_id) = &filter.feed_id {
+        conditions.push("feed IN (SELECT name FROM feeds WHERE id = ?)");
+        params.push(feed_id.clone().into());
+    }
+    if let Some(hours) = filter.since_hours {
+        conditions.push("published >= ?");
+        params.push(UtcTime(Utc::now() - chrono::Duration::hours(hours.into())).into());
+    }
+    if conditions.is_empty() {
+        return (String::new(), params);
+    }
+    (format!("WHERE {}", conditions.join(" AND ")), params)
+}
+
+/// The first instant of the given month and of the month after it.
+fn month_bounds(year: i32, month: u32) -> Result<(UtcTime, UtcTime)> {
+    let start = Utc
+        .with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .ok_or_else(|| anyhow!("invalid month {}-{}", year, month))?;
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let end = Utc
+        .with_ymd_and_hms(next_year, next_month, 1, 0, 0, 0)
+        .single()
+        .ok_or_else(|| anyhow!("invalid month {}-{}", next_year, next_month))?;
+    Ok((start.into(), end.into()))
+}
+
+async fn record_migration(conn: &libsql::Connection, version: i64, name: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+        (version, name, UtcTime(Utc::now())),
+    )
+    .await
+    .with_context(|| format!("couldn't record migration {}", version))?;
+    Ok(())
+}
+
 impl DB {
+    /// Brings the schema up to date: the baseline for a database from before
+    /// migrations, then each migration it hasn't had yet.
     pub(crate) async fn init(&self) -> Result<()> {
         self.main_conn
             .execute_batch(
                 r#"
+CREATE TABLE IF NOT EXISTS schema_migrations
+(
+    version    INTEGER PRIMARY KEY NOT NULL,
+    name       TEXT NOT NULL,
+    applied_at DATETIME NOT NULL
+);
+"#,
+            )
+            .await
+            .context("couldn't create schema_migrations")?;
+        let version = self.schema_version().await?;
+        if version > latest_version() {
+            return Err(anyhow!(
+                "database schema is at version {}, newer than this build's {}",
+                version,
+                latest_version()
+            ));
+        }
+        if version < BASELINE_VERSION {
+            self.baseline().await?;
+            record_migration(&self.main_conn, BASELINE_VERSION, "baseline").await?;
+        }
+        self.migrate(MIGRATIONS).await?;
+        self.fill_entry_keys().await?;
+        Ok(())
+    }
+
+    /// Works out the keys duplicates are found by for entries stored before they were.
+    async fn fill_entry_keys(&self) -> Result<()> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT id, content_link, content FROM entries WHERE link_key IS NULL",
+                (),
+            )
+            .await?;
+        let mut keys = vec![];
+        while let Some(row) = rows.next().await? {
+            let id: String = row.get(0)?;
+            let link: String = row.get(1)?;
+            let content: Option<String> = row.get(2)?;
+            keys.push((
+                id,
+                link_key(&link),
+                content.as_deref().and_then(content_key),
+            ));
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare("UPDATE entries SET link_key = ?, content_key = ? WHERE id = ?")
+                .await?;
+            for (id, link, content) in &keys {
+                stmt.execute((
+                    link.clone().unwrap_or_default(),
+                    content.clone().unwrap_or_default(),
+                    id.clone(),
+                ))
+                .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        info!("worked out duplicate keys for {} entries", keys.len());
+        Ok(())
+    }
+
+    /// The latest migration applied, or 0 for a database from before them.
+    pub(crate) async fn schema_version(&self) -> Result<i64> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+                (),
+            )
+            .await?;
+        if rows.next().await?.is_none() {
+            return Ok(0);
+        }
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+                (),
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(0),
+        }
+    }
+
+    async fn migrate(&self, migrations: &[Migration]) -> Result<()> {
+        let current = self.schema_version().await?;
+        for migration in migrations.iter().filter(|m| m.version > current) {
+            let tx = self.main_conn.transaction().await?;
+            tx.execute_batch(migration.sql).await.with_context(|| {
+                format!(
+                    "couldn't apply migration {} ({})",
+                    migration.version, migration.name
+                )
+            })?;
+            record_migration(&tx, migration.version, migration.name).await?;
+            tx.commit().await?;
+            info!(
+                "applied migration {} ({})",
+                migration.version, migration.name
+            );
+        }
+        Ok(())
+    }
+
+    /// The schema as it was built before migrations. Every step is idempotent, so
+    /// it brings a database from any point before them up to the baseline.
+    async fn baseline(&self) -> Result<()> {
+        self.main_conn
+            .execute_batch(
+                r#"
 CREATE TABLE IF NOT EXISTS feeds
 (
     id           TEXT PRIMARY KEY NOT NULL,
@@ -126,275 +843,4416 @@ CREATE TABLE IF NOT EXISTS entries
     feed          TEXT
 );
 CREATE INDEX IF NOT EXISTS idx_entries_read ON entries(read, published ASC);
+
+CREATE TABLE IF NOT EXISTS settings
+(
+    key   TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
 "#,
             )
             .await
             .context("couldn't init db")?;
-        self.update_conn
+        for (table, column, definition) in ADDED_COLUMNS {
+            self.add_column(table, column, definition).await?;
+        }
+        self.main_conn
+            .execute_batch(
+                "CREATE INDEX IF NOT EXISTS idx_entries_title_key ON entries(title_key, published);",
+            )
+            .await
+            .context("couldn't create title index")?;
+        self.main_conn
             .execute_batch(
                 r#"
 CREATE TABLE IF NOT EXISTS feed_updates
 (
     id          INTEGER PRIMARY KEY NOT NULL,
     feed        TEXT NOT NULL,
+    status      INTEGER,
     fetch_error TEXT,
     created_at  DATETIME
 );
+CREATE INDEX IF NOT EXISTS idx_feed_updates_feed ON feed_updates(feed, id);
+
+CREATE TABLE IF NOT EXISTS entry_revisions
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    entry_id   TEXT NOT NULL,
+    content    TEXT NOT NULL,
+    created_at DATETIME
+);
+CREATE INDEX IF NOT EXISTS idx_entry_revisions_entry ON entry_revisions(entry_id, id);
+
+CREATE TABLE IF NOT EXISTS combined_views
+(
+    id   TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS combined_view_categories
+(
+    view_id  TEXT NOT NULL,
+    category TEXT NOT NULL COLLATE NOCASE,
+    excluded BOOLEAN NOT NULL DEFAULT false,
+    PRIMARY KEY (view_id, category)
+);
 "#,
             )
             .await
-            .context("couldn't init update db")?;
+            .context("couldn't init feed_updates")?;
+        self.init_search().await?;
         Ok(())
     }
 
-    pub(crate) async fn add_feeds<T>(&self, feeds: T) -> Result<()>
-    where
-        T: Iterator<Item = Feed>,
-    {
-        let tx = self.main_conn.transaction().await?;
-        {
-            let mut stmt = tx
-                .prepare(
-                    r#"
-    INSERT OR REPLACE INTO feeds (id, name, site_url, feed_url, category)
-    VALUES (?, ?, ?, ?, ?);
-                    "#,
-                )
-                .await
-                .context("couldn't prepare statement")?;
-
-            for f in feeds {
-                let _ = stmt
-                    .execute((f.id, f.name, f.site_url, f.feed_url, f.category))
-                    .await?;
-                stmt.reset();
-            }
-        }
-        tx.commit().await?;
-
+    /// Starts a login session for a user, clearing out any that have expired.
+    pub(crate) async fn add_session(
+        &self,
+        id: &str,
+        user_id: i64,
+        max_age: chrono::Duration,
+    ) -> Result<()> {
+        self.main_conn
+            .execute(
+                "DELETE FROM sessions WHERE created_at < ?1",
+                vec![libsql::Value::from(UtcTime(Utc::now() - max_age))],
+            )
+            .await?;
+        self.main_conn
+            .execute(
+                "INSERT INTO sessions (id, user_id, created_at) VALUES (?1, ?2, ?3)",
+                (id, user_id, UtcTime(Utc::now())),
+            )
+            .await?;
         Ok(())
     }
 
-    pub(crate) async fn get_feeds(&self) -> Result<Vec<Feed>> {
-        // TODO: Probably still want update info
-        let mut stmt = self
+    /// The user logged in by a session, if it exists and started less than `max_age` ago.
+    pub(crate) async fn session_user(
+        &self,
+        id: &str,
+        max_age: chrono::Duration,
+    ) -> Result<Option<i64>> {
+        let mut rows = self
             .main_conn
-            .prepare("SELECT id, name, site_url, feed_url, category FROM feeds")
-            .await
-            .context("couldn't prepare statement")?;
-        let mut rows = stmt.query(()).await?;
-        let mut feeds: Vec<Feed> = vec![];
-        // TODO: Use .into_stream
-        while let Some(row) = rows.next().await.unwrap() {
-            let feed = libsql::de::from_row(&row)?;
-            feeds.push(feed);
+            .query(
+                "SELECT user_id FROM sessions WHERE id = ?1 AND created_at >= ?2",
+                (id, UtcTime(Utc::now() - max_age)),
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
         }
-
-        Ok(feeds)
     }
 
-    pub(crate) async fn remove_feed(&self, id: String) -> Result<()> {
-        let mut stmt = self
-            .main_conn
-            .prepare("DELETE FROM feeds WHERE id = ?")
+    pub(crate) async fn remove_session(&self, id: &str) -> Result<()> {
+        self.main_conn
+            .execute("DELETE FROM sessions WHERE id = ?1", [id])
             .await?;
-        stmt.execute([id]).await?;
-
         Ok(())
     }
 
-    pub(crate) async fn update_feed_status(&self, id: String, error: Option<String>) -> Result<()> {
-        let mut stmt = self
-            .update_conn
-            .prepare(
-                "INSERT INTO feed_updates (feed, fetch_error, created_at)
-                      VALUES (?, ?, ?)",
-            )
-            .await?;
+    /// Adds a user, starting them with every entry unread and unstarred.
+    pub(crate) async fn add_user(&self, username: &str, password_hash: &str) -> Result<i64> {
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(
+            "INSERT INTO users (username, password_hash) VALUES (?1, ?2)",
+            (username, password_hash),
+        )
+        .await
+        .context("couldn't add user")?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO entry_state (user_id, entry_id) SELECT ?1, id FROM entries",
+            vec![libsql::Value::from(id)],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub(crate) async fn get_users(&self) -> Result<Vec<User>> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT id, username FROM users ORDER BY id", ())
+            .await?;
+        let mut users = vec![];
+        while let Some(row) = rows.next().await? {
+            users.push(libsql::de::from_row(&row)?);
+        }
+        Ok(users)
+    }
+
+    /// A user's id and password hash by username. The owner has no hash, they log
+    /// in with `FEED_PASSWORD`.
+    pub(crate) async fn get_user_login(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, Option<String>)>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT id, password_hash FROM users WHERE username = ?1",
+                [username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes a user with their sessions, saved filters and read and starred state,
+    /// returning whether they existed.
+    pub(crate) async fn remove_user(&self, id: i64) -> Result<bool> {
+        let tx = self.main_conn.transaction().await?;
+        for table in ["entry_state", "sessions", "saved_filters", "user_settings"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE user_id = ?1", table),
+                vec![libsql::Value::from(id)],
+            )
+            .await?;
+        }
+        let removed = tx
+            .execute(
+                "DELETE FROM users WHERE id = ?1",
+                vec![libsql::Value::from(id)],
+            )
+            .await?;
+        tx.commit().await?;
+        Ok(removed > 0)
+    }
+
+    /// Names the owner after `FEED_USERNAME`, so no one else can be added as them.
+    pub(crate) async fn rename_owner(&self, username: &str) -> Result<()> {
+        self.main_conn
+            .execute(
+                "UPDATE users SET username = ?1 WHERE id = ?2",
+                (username, user::OWNER),
+            )
+            .await
+            .context("couldn't rename the owner")?;
+        Ok(())
+    }
+
+    pub(crate) async fn add_api_token(&self, token: &ApiToken, token_hash: &str) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO api_tokens (id, name, token_hash, scope, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    token.id.as_str(),
+                    token.name.as_str(),
+                    token_hash,
+                    token.scope.as_str(),
+                    token.created_at.clone(),
+                ),
+            )
+            .await
+            .context("couldn't add api token")?;
+        Ok(())
+    }
+
+    pub(crate) async fn get_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT id, name, scope, created_at, last_used_at FROM api_tokens ORDER BY created_at",
+                (),
+            )
+            .await?;
+        let mut tokens = vec![];
+        while let Some(row) = rows.next().await? {
+            tokens.push(libsql::de::from_row(&row)?);
+        }
+        Ok(tokens)
+    }
+
+    /// Revokes a token, returning whether it existed.
+    pub(crate) async fn remove_api_token(&self, id: &str) -> Result<bool> {
+        let removed = self
+            .main_conn
+            .execute("DELETE FROM api_tokens WHERE id = ?1", [id])
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// The scope of the token with this hash, noting that it was used when `record`
+    /// is. The note is only rewritten once it's `TOKEN_USE_RESOLUTION_SECS` old,
+    /// so a busy client doesn't write on every request.
+    pub(crate) async fn use_api_token(
+        &self,
+        token_hash: &str,
+        record: bool,
+    ) -> Result<Option<String>> {
+        if record {
+            let now = Utc::now();
+            let stale = now - chrono::Duration::seconds(TOKEN_USE_RESOLUTION_SECS);
+            self.main_conn
+                .execute(
+                    "UPDATE api_tokens SET last_used_at = ?1
+                    WHERE token_hash = ?2 AND (last_used_at IS NULL OR last_used_at < ?3)",
+                    (UtcTime(now), token_hash, UtcTime(stale)),
+                )
+                .await?;
+        }
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT scope FROM api_tokens WHERE token_hash = ?1",
+                [token_hash],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn has_api_tokens(&self) -> Result<bool> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT 1 FROM api_tokens LIMIT 1", ())
+            .await?;
+        Ok(rows.next().await?.is_some())
+    }
+
+    /// Stores a credential encrypted with `key`, replacing any under the same name.
+    pub(crate) async fn set_credential(
+        &self,
+        key: &SecretKey,
+        name: &str,
+        secret: &str,
+    ) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO credentials (name, value, updated_at) VALUES (?1, ?2, ?3)
+                ON CONFLICT (name) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+                (name, key.encrypt(name, secret)?, UtcTime(Utc::now())),
+            )
+            .await
+            .with_context(|| format!("couldn't store credential {}", name))?;
+        Ok(())
+    }
+
+    /// The credential stored under `name`, decrypted with `key`.
+    pub(crate) async fn get_credential(
+        &self,
+        key: &SecretKey,
+        name: &str,
+    ) -> Result<Option<String>> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT value FROM credentials WHERE name = ?1", [name])
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(key.decrypt(name, &row.get::<String>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn get_credential_names(&self) -> Result<Vec<String>> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT name FROM credentials ORDER BY name", ())
+            .await?;
+        let mut names = vec![];
+        while let Some(row) = rows.next().await? {
+            names.push(row.get(0)?);
+        }
+        Ok(names)
+    }
+
+    /// Removes a credential, returning whether there was one.
+    pub(crate) async fn remove_credential(&self, name: &str) -> Result<bool> {
+        let removed = self
+            .main_conn
+            .execute("DELETE FROM credentials WHERE name = ?1", [name])
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Re-encrypts every stored credential from `old` to `new` in one transaction,
+    /// so a key that can't open one of them changes nothing. Returns how many there were.
+    pub(crate) async fn rotate_credentials(
+        &self,
+        old: &SecretKey,
+        new: &SecretKey,
+    ) -> Result<usize> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT name, value FROM credentials", ())
+            .await?;
+        let mut rotated = vec![];
+        while let Some(row) = rows.next().await? {
+            let name: String = row.get(0)?;
+            let secret = old.decrypt(&name, &row.get::<String>(1)?)?;
+            let value = new.encrypt(&name, &secret)?;
+            rotated.push((name, value));
+        }
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare("UPDATE credentials SET value = ?, updated_at = ? WHERE name = ?")
+                .await?;
+            for (name, value) in &rotated {
+                stmt.execute((value.clone(), UtcTime(Utc::now()), name.clone()))
+                    .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(rotated.len())
+    }
+
+    /// Records something security related that happened, like a failed login.
+    pub(crate) async fn add_audit_event(
+        &self,
+        event: &str,
+        username: Option<&str>,
+        ip: Option<String>,
+    ) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO audit_log (event, username, ip, created_at) VALUES (?1, ?2, ?3, ?4)",
+                (event, username, ip, UtcTime(Utc::now())),
+            )
+            .await
+            .context("couldn't record audit event")?;
+        Ok(())
+    }
 
-        stmt.execute((id, error, UtcTime(Utc::now()))).await?;
+    /// Sets up the full text index over entries, kept in sync by triggers, and fills
+    /// it from the entries already there when it's new.
+    async fn init_search(&self) -> Result<()> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'entries_fts'",
+                (),
+            )
+            .await?;
+        let exists = rows.next().await?.is_some();
+        self.main_conn
+            .execute_batch(
+                r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS entries_fts USING fts5
+(
+    title, feed, content,
+    content = 'entries', content_rowid = 'rowid'
+);
 
+CREATE TRIGGER IF NOT EXISTS entries_fts_insert AFTER INSERT ON entries BEGIN
+    INSERT INTO entries_fts (rowid, title, feed, content)
+    VALUES (new.rowid, new.title, new.feed, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS entries_fts_delete AFTER DELETE ON entries BEGIN
+    INSERT INTO entries_fts (entries_fts, rowid, title, feed, content)
+    VALUES ('delete', old.rowid, old.title, old.feed, old.content);
+END;
+CREATE TRIGGER IF NOT EXISTS entries_fts_update AFTER UPDATE OF title, feed, content ON entries BEGIN
+    INSERT INTO entries_fts (entries_fts, rowid, title, feed, content)
+    VALUES ('delete', old.rowid, old.title, old.feed, old.content);
+    INSERT INTO entries_fts (rowid, title, feed, content)
+    VALUES (new.rowid, new.title, new.feed, new.content);
+END;
+"#,
+            )
+            .await
+            .context("couldn't init search index")?;
+        if !exists {
+            self.main_conn
+                .execute(
+                    "INSERT INTO entries_fts (entries_fts) VALUES ('rebuild')",
+                    (),
+                )
+                .await
+                .context("couldn't build search index")?;
+        }
         Ok(())
     }
 
-    pub(crate) async fn add_entries<T>(&self, entries: T) -> Result<()>
+    async fn add_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let statement = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+        match self.main_conn.execute(&statement, ()).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.to_string().contains("duplicate column name") => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("couldn't add column {}.{}", table, column)),
+        }
+    }
+
+    /// Adds feeds, or updates the name, urls and category of ones that already exist
+    /// while leaving any other state on them alone, like whether they take pushes.
+    /// Categories are normalized and added when they're new, one differing from an
+    /// existing category only by case is taken to be that one.
+    pub(crate) async fn add_feeds<T>(&self, feeds: T) -> Result<()>
     where
-        T: Iterator<Item = Entry>,
+        T: Iterator<Item = Feed>,
     {
         let tx = self.main_conn.transaction().await?;
         {
-            let mut stmt = tx.prepare(
-                    "INSERT OR IGNORE INTO entries (id, title, content_link, comments_link, robust_link, published, read, starred, feed)
-                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
-                ).await?;
-            for e in entries {
+            let mut add_category = tx
+                .prepare(ADD_CATEGORY)
+                .await
+                .context("couldn't prepare statement")?;
+            let mut stmt = tx
+                .prepare(
+                    r#"
+    INSERT INTO feeds (id, name, site_url, feed_url, category_id, push)
+    VALUES (?1, ?2, ?3, ?4, (SELECT id FROM categories WHERE name = ?5), ?6)
+    ON CONFLICT (id) DO UPDATE SET
+        name = excluded.name,
+        site_url = excluded.site_url,
+        feed_url = excluded.feed_url,
+        category_id = excluded.category_id;
+                    "#,
+                )
+                .await
+                .context("couldn't prepare statement")?;
+
+            for f in feeds {
+                add_category
+                    .execute([normalize_category(&f.category)])
+                    .await?;
+                add_category.reset();
                 let _ = stmt
+                    .execute((
+                        f.id,
+                        f.name,
+                        f.site_url,
+                        f.feed_url,
+                        normalize_category(&f.category),
+                        f.push,
+                    ))
+                    .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Loads a dump from `/dump` in one transaction, so a failed restore leaves
+    /// nothing behind. Feeds are added or updated with their settings, entries
+    /// missing here are added, and every dumped entry gets the current user's
+    /// state from the dump. Returns how many feeds and new entries there were.
+    pub(crate) async fn restore(&self, dump: Dump) -> Result<(usize, usize)> {
+        let feeds = dump.feeds.len();
+        let mut added = 0;
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut add_category = tx
+                .prepare(ADD_CATEGORY)
+                .await
+                .context("couldn't prepare statement")?;
+            let mut feed_stmt = tx
+                .prepare(
+                    "INSERT INTO feeds (id, name, site_url, feed_url, category_id, icon_url,
+                        compact, extract_mode, push, enabled, last_entry_published, last_entry_id)
+                    VALUES (?1, ?2, ?3, ?4, (SELECT id FROM categories WHERE name = ?5), ?6,
+                        ?7, COALESCE(NULLIF(?8, ''), 'never'), ?9, ?10, ?11, ?12)
+                    ON CONFLICT (id) DO UPDATE SET
+                        name = excluded.name,
+                        site_url = excluded.site_url,
+                        feed_url = excluded.feed_url,
+                        category_id = excluded.category_id,
+                        icon_url = excluded.icon_url,
+                        compact = excluded.compact,
+                        extract_mode = excluded.extract_mode,
+                        push = excluded.push,
+                        enabled = excluded.enabled",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for f in dump.feeds {
+                let category = normalize_category(&f.category);
+                add_category.execute([category.as_str()]).await?;
+                add_category.reset();
+                feed_stmt
+                    .execute((
+                        f.id,
+                        f.name,
+                        f.site_url,
+                        f.feed_url,
+                        category,
+                        f.icon_url,
+                        f.compact,
+                        f.extract_mode,
+                        f.push,
+                        f.enabled,
+                        f.last_entry_published,
+                        f.last_entry_id,
+                    ))
+                    .await?;
+                feed_stmt.reset();
+            }
+
+            let mut entry_stmt = tx
+                .prepare(
+                    "INSERT OR IGNORE INTO entries (id, title, content_link, comments_link, robust_link, published, feed, title_key, duplicate_of, author, enclosure_url, enclosure_type, content, excerpt, link_key, content_key)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            // when something was read isn't dumped, so it's left unknown rather
+            // than piling every read entry onto the day of the restore
+            let mut state_stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = ?, starred = ?, hidden = ?, playback_position = ?
+                    WHERE entry_id = ? AND user_id = ?",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for e in dump.entries {
+                let id = e.id.clone();
+                let state = (e.read, e.starred, e.hidden, e.playback_position);
+                let link = link_key(&e.content_link).unwrap_or_default();
+                let content = e.content.as_deref().and_then(content_key);
+                added += entry_stmt
                     .execute((
                         e.id,
-                        e.title,
+                        e.title.clone(),
                         e.content_link,
                         e.comments_link,
                         e.robust_link,
                         e.published,
-                        e.read,
-                        e.starred,
                         e.feed,
+                        title_key(&e.title),
+                        e.duplicate_of,
+                        e.author,
+                        e.enclosure_url,
+                        e.enclosure_type,
+                        e.content,
+                        e.excerpt,
+                        link,
+                        content.unwrap_or_default(),
                     ))
                     .await?;
-                stmt.reset();
+                entry_stmt.reset();
+                state_stmt
+                    .execute((state.0, state.1, state.2, state.3, id, user::current()?))
+                    .await?;
+                state_stmt.reset();
             }
         }
         tx.commit().await?;
-
-        Ok(())
+        Ok((feeds, added))
     }
 
-    pub(crate) async fn get_entries(
-        &self,
-        filter: EntryFilter,
-        ordering: Ordering,
-    ) -> Result<Vec<Entry>> {
-        let order_clause = match ordering {
-            Ordering::Ascending => "ORDER BY published ASC",
-            Ordering::Descending => "ORDER BY published DESC",
+    /// Changes a feed's name, urls and category, keeping its id and any other state.
+    /// Its entries follow it to a new name, and a new feed url is fetched in full
+    /// rather than checked against the old one's validators. Returns whether the
+    /// feed existed.
+    pub(crate) async fn update_feed(&self, feed: &Feed) -> Result<bool> {
+        let Some(old) = self.get_feed(&feed.id).await? else {
+            return Ok(false);
         };
+        let category = normalize_category(&feed.category);
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(ADD_CATEGORY, [category.as_str()]).await?;
+        tx.execute(
+            r#"
+    UPDATE feeds SET
+        name = ?2,
+        site_url = ?3,
+        category_id = (SELECT id FROM categories WHERE name = ?5),
+        etag = CASE WHEN feed_url = ?4 THEN etag END,
+        last_modified = CASE WHEN feed_url = ?4 THEN last_modified END,
+        content_length = CASE WHEN feed_url = ?4 THEN content_length END,
+        feed_url = ?4
+    WHERE id = ?1;
+            "#,
+            (
+                feed.id.as_str(),
+                feed.name.as_str(),
+                feed.site_url.as_str(),
+                feed.feed_url.as_str(),
+                category.as_str(),
+            ),
+        )
+        .await
+        .context("couldn't update feed")?;
+        tx.execute(
+            "UPDATE entries SET feed = ?1 WHERE feed = ?2",
+            (feed.name.as_str(), old.name.as_str()),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
 
-        let where_clause = match filter {
-            EntryFilter::Starred => "WHERE starred = true",
-            EntryFilter::Unread => "WHERE read = false",
-            EntryFilter::All => "",
-        };
-        let statement_string = format!("SELECT id, title, content_link, comments_link, robust_link, published, read, starred, feed FROM entries {} {}", where_clause, order_clause);
+    pub(crate) async fn get_feeds(&self) -> Result<Vec<Feed>> {
+        self.get_feeds_page(FeedSort::default(), Page::default())
+            .await
+    }
+
+    /// Like `get_feeds`, but sorted and only the feeds in `page`.
+    pub(crate) async fn get_feeds_page(&self, sort: FeedSort, page: Page) -> Result<Vec<Feed>> {
+        // TODO: Probably still want update info
         let mut stmt = self
             .main_conn
-            .prepare(&statement_string)
+            .prepare(&format!(
+                "SELECT {} FROM feeds {} LIMIT ? OFFSET ?",
+                FEED_COLUMNS,
+                sort.order_clause()?
+            ))
             .await
             .context("couldn't prepare statement")?;
-        let mut rows = stmt.query(()).await?;
-        let mut entries: Vec<Entry> = vec![];
+        let mut rows = stmt
+            .query((page.limit.map_or(-1, i64::from), i64::from(page.offset)))
+            .await?;
+        let mut feeds: Vec<Feed> = vec![];
         // TODO: Use .into_stream
-        while let Some(row) = rows.next().await? {
-            let entry = libsql::de::from_row(&row)?;
-            entries.push(entry);
+        while let Some(row) = rows.next().await.unwrap() {
+            let feed = libsql::de::from_row(&row)?;
+            feeds.push(feed);
         }
-        Ok(entries)
-    }
 
-    pub(crate) async fn get_starred_entries(&self) -> Result<Vec<Entry>> {
-        self.get_entries(EntryFilter::Starred, Ordering::Ascending)
-            .await
+        Ok(feeds)
     }
 
-    pub(crate) async fn get_unread_entries(&self) -> Result<Vec<Entry>> {
-        self.get_entries(EntryFilter::Unread, Ordering::Ascending)
+    pub(crate) async fn get_feed(&self, id: &str) -> Result<Option<Feed>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!("SELECT {} FROM feeds WHERE id = ?", FEED_COLUMNS))
             .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
+        }
     }
 
-    pub(crate) async fn mark_entry_read(
-        &self,
-        entry_id: String,
-        filter: EntryFilter,
-        ordering: Ordering,
-    ) -> Result<Vec<Entry>> {
-        {
-            let mut stmt = self
-                .main_conn
-                .prepare("UPDATE entries SET read = NOT read WHERE id = ?")
-                .await
-                .context("couldn't prepare statement")?;
-            stmt.execute([entry_id]).await?;
+    /// The feed fetched from `feed_url`, which after a move or an edit is no
+    /// longer the one its id was made from.
+    pub(crate) async fn get_feed_by_url(&self, feed_url: &str) -> Result<Option<Feed>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM feeds WHERE feed_url = ?",
+                FEED_COLUMNS
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([feed_url]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
         }
-        self.get_entries(filter, ordering).await
     }
 
-    pub(crate) async fn mark_entry_starred(
-        &self,
-        entry_id: String,
-        filter: EntryFilter,
-        ordering: Ordering,
-    ) -> Result<Vec<Entry>> {
-        {
-            let mut stmt = self
-                .main_conn
-                .prepare("UPDATE entries SET starred = NOT starred WHERE id = ?")
-                .await
-                .context("couldn't prepare statement")?;
-            stmt.execute([entry_id]).await?;
-        }
-        self.get_entries(filter, ordering).await
+    /// A feed's cached icon, if it's been fetched.
+    pub(crate) async fn get_icon(&self, feed_id: &str) -> Result<Option<Icon>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT content_type, data FROM icons WHERE feed_id = ?1",
+                [feed_id],
+            )
+            .await?;
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let data = match row.get_value(1)? {
+            libsql::Value::Blob(data) => Some(data),
+            _ => None,
+        };
+        Ok(Some(Icon {
+            content_type: row.get(0)?,
+            data,
+        }))
     }
-}
 
-impl From<UtcTime> for libsql::Value {
-    fn from(t: UtcTime) -> libsql::Value {
-        libsql::Value::Text(t.0.to_rfc3339())
+    /// Whether a feed's icon was fetched, or found missing, since `since`.
+    pub(crate) async fn icon_fetched_since(&self, feed_id: &str, since: UtcTime) -> Result<bool> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT 1 FROM icons WHERE feed_id = ?1 AND fetched_at >= ?2",
+                (feed_id, since),
+            )
+            .await?;
+        Ok(rows.next().await?.is_some())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Caches a feed's icon, an empty one when its site doesn't have one.
+    pub(crate) async fn set_icon(&self, feed_id: &str, icon: Icon) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO icons (feed_id, content_type, data, fetched_at) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (feed_id) DO UPDATE SET
+                    content_type = excluded.content_type,
+                    data = excluded.data,
+                    fetched_at = excluded.fetched_at",
+                (
+                    feed_id,
+                    icon.content_type,
+                    icon.data.map_or(libsql::Value::Null, libsql::Value::Blob),
+                    UtcTime(Utc::now()),
+                ),
+            )
+            .await
+            .context("couldn't store icon")?;
+        Ok(())
+    }
+
+    /// Sets a custom icon for a feed, or goes back to the site's favicon with `None`.
+    /// The cached icon is dropped so the next refresh fetches the new one.
+    pub(crate) async fn set_feed_icon(&self, id: &str, icon_url: Option<String>) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET icon_url = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute(vec![
+            icon_url.map_or(libsql::Value::Null, libsql::Value::Text),
+            libsql::Value::Text(id.to_string()),
+        ])
+        .await?;
+        self.main_conn
+            .execute("DELETE FROM icons WHERE feed_id = ?", [id])
+            .await?;
+        Ok(())
+    }
+
+    /// Switches a feed between the compact and full entry display.
+    pub(crate) async fn set_feed_compact(&self, id: &str, compact: bool) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET compact = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute((compact, id)).await?;
+        Ok(())
+    }
+
+    /// Pauses or resumes fetching a feed, keeping its entries either way.
+    pub(crate) async fn set_feed_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET enabled = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute((enabled, id)).await?;
+        Ok(())
+    }
+
+    /// Sets when to fetch full articles for a feed's entries.
+    pub(crate) async fn set_feed_extract_mode(&self, id: &str, mode: ExtractMode) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET extract_mode = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute((mode.to_string(), id)).await?;
+        Ok(())
+    }
+
+    /// Remembers the validators from a feed's last full response for the next conditional request.
+    pub(crate) async fn set_feed_validators(
+        &self,
+        id: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        content_length: Option<u64>,
+    ) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE feeds SET etag = ?, last_modified = ?, content_length = ? WHERE id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute(vec![
+            etag.map_or(libsql::Value::Null, libsql::Value::Text),
+            last_modified.map_or(libsql::Value::Null, libsql::Value::Text),
+            content_length.map_or(libsql::Value::Null, |l| libsql::Value::Integer(l as i64)),
+            libsql::Value::Text(id.to_string()),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Records how many fetches in a row have failed and when to try again,
+    /// with no retry time meaning the next pass.
+    pub(crate) async fn set_feed_backoff(
+        &self,
+        id: &str,
+        consecutive_failures: u32,
+        next_retry_at: Option<UtcTime>,
+    ) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET consecutive_failures = ?, next_retry_at = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute(vec![
+            libsql::Value::Integer(consecutive_failures.into()),
+            next_retry_at.map_or(libsql::Value::Null, |t| t.into()),
+            libsql::Value::Text(id.to_string()),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Moves a feed's cursor to the newest entry stored from it.
+    pub(crate) async fn set_feed_cursor(
+        &self,
+        id: &str,
+        published: UtcTime,
+        entry_id: &str,
+    ) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE feeds SET last_entry_published = ?, last_entry_id = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute(vec![
+            published.into(),
+            libsql::Value::Text(entry_id.to_string()),
+            libsql::Value::Text(id.to_string()),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Every category's name, in order.
+    pub(crate) async fn get_categories(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .main_conn
+            .prepare("SELECT name FROM categories ORDER BY name")
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut categories = vec![];
+        while let Some(row) = rows.next().await? {
+            categories.push(row.get(0)?);
+        }
+        Ok(categories)
+    }
+
+    /// Every category with how many feeds are in it, in order.
+    pub(crate) async fn get_category_list(&self) -> Result<Vec<Category>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT id, name, (SELECT COUNT(*) FROM feeds WHERE category_id = categories.id) AS feeds, digest
+                FROM categories ORDER BY name",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut categories = vec![];
+        while let Some(row) = rows.next().await? {
+            categories.push(libsql::de::from_row(&row)?);
+        }
+        Ok(categories)
+    }
+
+    async fn get_category_id(&self, name: &str) -> Result<Option<i64>> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT id FROM categories WHERE name = ?", [name])
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds an empty category, returning its id or the id of the one already called
+    /// that. Blank names aren't categories.
+    pub(crate) async fn add_category(&self, name: &str) -> Result<Option<i64>> {
+        let name = normalize_category(name);
+        self.main_conn
+            .execute(ADD_CATEGORY, [name.as_str()])
+            .await?;
+        self.get_category_id(&name).await
+    }
+
+    /// Renames a category along with the combined views and saved filters that name
+    /// it, returning whether it existed. Taking the name of another category merges
+    /// the two.
+    pub(crate) async fn rename_category(&self, id: i64, name: &str) -> Result<bool> {
+        let name = normalize_category(name);
+        if name.is_empty() {
+            return Err(anyhow!("a category needs a name"));
+        }
+        let mut rows = self
+            .main_conn
+            .query("SELECT name FROM categories WHERE id = ?", [id])
+            .await?;
+        let old: String = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => return Ok(false),
+        };
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT id, name FROM categories WHERE name = ?1 AND id != ?2",
+                (name.as_str(), id),
+            )
+            .await?;
+        // merging takes the other category's spelling
+        let (other, name) = match rows.next().await? {
+            Some(row) => (Some(row.get::<i64>(0)?), row.get(1)?),
+            None => (None, name),
+        };
+        let tx = self.main_conn.transaction().await?;
+        match other {
+            Some(other) => {
+                tx.execute(
+                    "UPDATE feeds SET category_id = ?1 WHERE category_id = ?2",
+                    [other, id],
+                )
+                .await?;
+                tx.execute("DELETE FROM categories WHERE id = ?", [id])
+                    .await?;
+            }
+            None => {
+                tx.execute(
+                    "UPDATE categories SET name = ?1 WHERE id = ?2",
+                    (name.as_str(), id),
+                )
+                .await?;
+            }
+        }
+        // views that had both keep one
+        tx.execute(
+            "UPDATE OR IGNORE combined_view_categories SET category = ?1 WHERE category = ?2",
+            (name.as_str(), old.as_str()),
+        )
+        .await?;
+        if other.is_some() {
+            tx.execute(
+                "DELETE FROM combined_view_categories WHERE category = ?",
+                [old.as_str()],
+            )
+            .await?;
+        }
+        tx.execute(
+            "UPDATE saved_filters SET category = ?1 WHERE category = ?2 COLLATE NOCASE",
+            (name.as_str(), old.as_str()),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Sets whether a category is in the email digest, returning whether it existed.
+    pub(crate) async fn set_category_digest(&self, id: i64, digest: bool) -> Result<bool> {
+        let updated = self
+            .main_conn
+            .execute(
+                "UPDATE categories SET digest = ?1 WHERE id = ?2",
+                (digest, id),
+            )
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Removes a category and drops it from combined views, leaving its feeds
+    /// without one. Returns whether it existed.
+    pub(crate) async fn remove_category(&self, id: i64) -> Result<bool> {
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(
+            "UPDATE feeds SET category_id = NULL WHERE category_id = ?",
+            [id],
+        )
+        .await?;
+        tx.execute(
+            "DELETE FROM combined_view_categories
+            WHERE category = (SELECT name FROM categories WHERE id = ?)",
+            [id],
+        )
+        .await?;
+        tx.execute("DELETE FROM webhooks WHERE category_id = ?", [id])
+            .await?;
+        let removed = tx
+            .execute("DELETE FROM categories WHERE id = ?", [id])
+            .await?;
+        tx.commit().await?;
+        Ok(removed > 0)
+    }
+
+    /// Adds a combined view, or replaces the categories of the one with the same id.
+    pub(crate) async fn add_combined_view(&self, view: &CombinedView) -> Result<()> {
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(
+            "INSERT INTO combined_views (id, name) VALUES (?1, ?2)
+            ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+            (view.id.as_str(), view.name.as_str()),
+        )
+        .await?;
+        tx.execute(
+            "DELETE FROM combined_view_categories WHERE view_id = ?",
+            [view.id.as_str()],
+        )
+        .await?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT OR IGNORE INTO combined_view_categories (view_id, category, excluded)
+                    VALUES (?, ?, ?)",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            let categories = view.categories.iter().map(|c| (c, false));
+            let excluded = view.excluded.iter().map(|c| (c, true));
+            for (category, excluded) in categories.chain(excluded) {
+                stmt.execute((view.id.as_str(), normalize_category(category), excluded))
+                    .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub(crate) async fn get_combined_views(&self) -> Result<Vec<CombinedView>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT v.id, v.name, c.category, c.excluded FROM combined_views v
+                LEFT JOIN combined_view_categories c ON c.view_id = v.id
+                ORDER BY v.name COLLATE NOCASE, v.id, c.category",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut views: Vec<CombinedView> = vec![];
+        while let Some(row) = rows.next().await? {
+            let id: String = row.get(0)?;
+            if views.last().map(|v| &v.id) != Some(&id) {
+                views.push(CombinedView {
+                    id,
+                    name: row.get(1)?,
+                    ..Default::default()
+                });
+            }
+            let view = views.last_mut().unwrap();
+            match row.get::<Option<String>>(2)? {
+                Some(category) if row.get::<bool>(3)? => view.excluded.push(category),
+                Some(category) => view.categories.push(category),
+                None => {}
+            }
+        }
+        Ok(views)
+    }
+
+    pub(crate) async fn get_combined_view(&self, id: &str) -> Result<Option<CombinedView>> {
+        Ok(self
+            .get_combined_views()
+            .await?
+            .into_iter()
+            .find(|v| v.id == id))
+    }
+
+    pub(crate) async fn remove_combined_view(&self, id: &str) -> Result<bool> {
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(
+            "DELETE FROM combined_view_categories WHERE view_id = ?",
+            [id],
+        )
+        .await?;
+        let removed = tx
+            .execute("DELETE FROM combined_views WHERE id = ?", [id])
+            .await?;
+        tx.commit().await?;
+        Ok(removed > 0)
+    }
+
+    pub(crate) async fn add_saved_filter(&self, filter: &SavedFilter) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO saved_filters (id, user_id, name, unread, starred, category, feed_id, since_hours)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                (
+                    filter.id.as_str(),
+                    user::current()?,
+                    filter.name.as_str(),
+                    filter.unread,
+                    filter.starred,
+                    filter.category.clone(),
+                    filter.feed_id.clone(),
+                    filter.since_hours,
+                ),
+            )
+            .await
+            .context("couldn't add saved filter")?;
+        Ok(())
+    }
+
+    /// The current user's saved filters, by name.
+    pub(crate) async fn get_saved_filters(&self) -> Result<Vec<SavedFilter>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT id, name, unread, starred, category, feed_id, since_hours FROM saved_filters
+                WHERE user_id = ? ORDER BY name COLLATE NOCASE, id",
+                [user::current()?],
+            )
+            .await?;
+        let mut filters = vec![];
+        while let Some(row) = rows.next().await? {
+            filters.push(libsql::de::from_row(&row)?);
+        }
+        Ok(filters)
+    }
+
+    pub(crate) async fn get_saved_filter(&self, id: &str) -> Result<Option<SavedFilter>> {
+        Ok(self
+            .get_saved_filters()
+            .await?
+            .into_iter()
+            .find(|f| f.id == id))
+    }
+
+    pub(crate) async fn remove_saved_filter(&self, id: &str) -> Result<bool> {
+        let removed = self
+            .main_conn
+            .execute(
+                "DELETE FROM saved_filters WHERE id = ?1 AND user_id = ?2",
+                (id, user::current()?),
+            )
+            .await?;
+        Ok(removed > 0)
+    }
+
+    pub(crate) async fn add_filter_rule(&self, rule: &FilterRule) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT INTO filter_rules (id, pattern, field, feed_id, action) VALUES (?, ?, ?, ?, ?)",
+                (
+                    rule.id.as_str(),
+                    rule.pattern.as_str(),
+                    rule.field.as_str(),
+                    rule.feed_id.clone(),
+                    rule.action.as_str(),
+                ),
+            )
+            .await
+            .context("couldn't add filter rule")?;
+        Ok(())
+    }
+
+    /// Every filter rule with the name of the feed it's for, by pattern.
+    pub(crate) async fn get_filter_rules(&self) -> Result<Vec<FilterRule>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT filter_rules.id, pattern, field, feed_id, feeds.name AS feed, action
+                FROM filter_rules LEFT JOIN feeds ON feeds.id = filter_rules.feed_id
+                ORDER BY pattern COLLATE NOCASE, filter_rules.id",
+                (),
+            )
+            .await?;
+        let mut rules = vec![];
+        while let Some(row) = rows.next().await? {
+            rules.push(libsql::de::from_row(&row)?);
+        }
+        Ok(rules)
+    }
+
+    pub(crate) async fn remove_filter_rule(&self, id: &str) -> Result<bool> {
+        let removed = self
+            .main_conn
+            .execute("DELETE FROM filter_rules WHERE id = ?1", [id])
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Adds a webhook, keeping its secret as a credential encrypted with `key`
+    /// rather than alongside it.
+    pub(crate) async fn add_webhook(
+        &self,
+        key: &SecretKey,
+        webhook: &Webhook,
+        secret: &str,
+    ) -> Result<()> {
+        let name = webhook_credential(&webhook.id);
+        let tx = self.main_conn.transaction().await?;
+        tx.execute(
+            "INSERT INTO webhooks (id, url, secret, feed_id, category_id) VALUES (?, ?, '', ?, ?)",
+            (
+                webhook.id.as_str(),
+                webhook.url.as_str(),
+                webhook.feed_id.clone(),
+                webhook.category_id,
+            ),
+        )
+        .await
+        .context("couldn't add webhook")?;
+        tx.execute(
+            "INSERT INTO credentials (name, value, updated_at) VALUES (?1, ?2, ?3)",
+            (
+                name.as_str(),
+                key.encrypt(&name, secret)?,
+                UtcTime(Utc::now()),
+            ),
+        )
+        .await
+        .context("couldn't store webhook secret")?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The secret a webhook's requests are signed with, decrypted with `key`.
+    pub(crate) async fn get_webhook_secret(&self, key: &SecretKey, id: &str) -> Result<String> {
+        self.get_credential(key, &webhook_credential(id))
+            .await?
+            .ok_or_else(|| anyhow!("webhook {} has no stored secret", id))
+    }
+
+    /// Moves secrets of webhooks added before they were encrypted into credentials,
+    /// returning how many there were.
+    pub(crate) async fn seal_webhook_secrets(&self, key: &SecretKey) -> Result<usize> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT id, secret FROM webhooks WHERE secret != ''", ())
+            .await?;
+        let mut plain = vec![];
+        while let Some(row) = rows.next().await? {
+            plain.push((row.get::<String>(0)?, row.get::<String>(1)?));
+        }
+        for (id, secret) in &plain {
+            self.set_credential(key, &webhook_credential(id), secret)
+                .await?;
+            self.main_conn
+                .execute(
+                    "UPDATE webhooks SET secret = '' WHERE id = ?1",
+                    [id.as_str()],
+                )
+                .await?;
+        }
+        Ok(plain.len())
+    }
+
+    /// Every webhook with the names of the feed or category it's for, by URL.
+    pub(crate) async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT webhooks.id, url, feed_id, feeds.name AS feed,
+                    webhooks.category_id, categories.name AS category
+                FROM webhooks
+                LEFT JOIN feeds ON feeds.id = webhooks.feed_id
+                LEFT JOIN categories ON categories.id = webhooks.category_id
+                ORDER BY url, webhooks.id",
+                (),
+            )
+            .await?;
+        let mut webhooks = vec![];
+        while let Some(row) = rows.next().await? {
+            webhooks.push(libsql::de::from_row(&row)?);
+        }
+        Ok(webhooks)
+    }
+
+    pub(crate) async fn remove_webhook(&self, id: &str) -> Result<bool> {
+        let removed = self
+            .main_conn
+            .execute("DELETE FROM webhooks WHERE id = ?1", [id])
+            .await?;
+        self.remove_credential(&webhook_credential(id)).await?;
+        Ok(removed > 0)
+    }
+
+    pub(crate) async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .main_conn
+            .prepare("SELECT value FROM settings WHERE key = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([key]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute([key, value]).await?;
+        Ok(())
+    }
+
+    /// One of the current user's own preferences.
+    pub(crate) async fn get_user_setting(&self, key: &str) -> Result<Option<String>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT value FROM user_settings WHERE user_id = ?1 AND key = ?2",
+                (user::current()?, key),
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn set_user_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.main_conn
+            .execute(
+                "INSERT OR REPLACE INTO user_settings (user_id, key, value) VALUES (?1, ?2, ?3)",
+                (user::current()?, key, value),
+            )
+            .await
+            .context("couldn't save user setting")?;
+        Ok(())
+    }
+
+    pub(crate) async fn remove_feed(&self, id: String) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("DELETE FROM feeds WHERE id = ?")
+            .await?;
+        stmt.execute([id.clone()]).await?;
+        let mut stmt = self
+            .main_conn
+            .prepare("DELETE FROM feed_updates WHERE feed = ?")
+            .await?;
+        stmt.execute([id.clone()]).await?;
+        self.main_conn
+            .execute("DELETE FROM filter_rules WHERE feed_id = ?", [id.clone()])
+            .await?;
+        self.main_conn
+            .execute("DELETE FROM webhooks WHERE feed_id = ?", [id.clone()])
+            .await?;
+        self.main_conn
+            .execute("DELETE FROM icons WHERE feed_id = ?", [id.clone()])
+            .await?;
+        self.main_conn
+            .execute("DELETE FROM feed_redirects WHERE feed_id = ?", [id])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records where a fetch of a feed was permanently redirected to, or that it
+    /// wasn't with `None`, returning how many fetches in a row have been sent there.
+    pub(crate) async fn record_feed_redirect(
+        &self,
+        feed_id: &str,
+        location: Option<&str>,
+    ) -> Result<u32> {
+        let Some(location) = location else {
+            self.main_conn
+                .execute("DELETE FROM feed_redirects WHERE feed_id = ?1", [feed_id])
+                .await?;
+            return Ok(0);
+        };
+        let mut rows = self
+            .main_conn
+            .query(
+                "INSERT INTO feed_redirects (feed_id, location, count) VALUES (?1, ?2, 1)
+                ON CONFLICT (feed_id) DO UPDATE SET
+                    count = CASE WHEN location = excluded.location THEN count + 1 ELSE 1 END,
+                    location = excluded.location
+                RETURNING count",
+                (feed_id, location),
+            )
+            .await
+            .context("couldn't record redirect")?;
+        let row = rows
+            .next()
+            .await?
+            .ok_or_else(|| anyhow!("recording a redirect didn't return a count"))?;
+        Ok(row.get(0)?)
+    }
+
+    /// Points a feed at the URL it's moved to, keeping its id and entries. Returns
+    /// false without changing anything when another feed already has that URL.
+    pub(crate) async fn move_feed(&self, feed_id: &str, feed_url: &str) -> Result<bool> {
+        let tx = self.main_conn.transaction().await?;
+        let mut rows = tx
+            .query(
+                "SELECT 1 FROM feeds WHERE feed_url = ?1 AND id != ?2",
+                (feed_url, feed_id),
+            )
+            .await?;
+        if rows.next().await?.is_some() {
+            return Ok(false);
+        }
+        drop(rows);
+        // validators from the old URL mean nothing to the new one
+        tx.execute(
+            "UPDATE feeds SET feed_url = ?1, etag = NULL, last_modified = NULL, content_length = NULL
+            WHERE id = ?2",
+            (feed_url, feed_id),
+        )
+        .await
+        .context("couldn't move feed")?;
+        tx.execute("DELETE FROM feed_redirects WHERE feed_id = ?1", [feed_id])
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub(crate) async fn update_feed_status(
+        &self,
+        id: String,
+        status: Option<u16>,
+        error: Option<String>,
+    ) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "INSERT INTO feed_updates (feed, status, fetch_error, created_at)
+                      VALUES (?, ?, ?, ?)",
+            )
+            .await?;
+        stmt.execute((
+            id.clone(),
+            status.map(u32::from),
+            error,
+            UtcTime(Utc::now()),
+        ))
+        .await?;
+
+        // only keep enough history to draw the sparkline
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "DELETE FROM feed_updates WHERE feed = ? AND id NOT IN
+                    (SELECT id FROM feed_updates WHERE feed = ? ORDER BY id DESC LIMIT ?)",
+            )
+            .await?;
+        stmt.execute((id.clone(), id, FETCH_STATUS_HISTORY)).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn get_last_feed_status(&self, id: &str) -> Result<Option<FetchStatus>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT status, fetch_error, created_at FROM feed_updates
+                WHERE feed = ? ORDER BY id DESC LIMIT 1",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The most recent fetch results per feed id, oldest first.
+    pub(crate) async fn get_feed_statuses(&self) -> Result<HashMap<String, Vec<FetchStatus>>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT feed, status, fetch_error, created_at FROM feed_updates ORDER BY id ASC",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut statuses: HashMap<String, Vec<FetchStatus>> = HashMap::new();
+        while let Some(row) = rows.next().await? {
+            let feed: String = row.get(0)?;
+            let status = libsql::de::from_row(&row)?;
+            statuses.entry(feed).or_default().push(status);
+        }
+
+        Ok(statuses)
+    }
+
+    /// The most recent fetch results for one feed, oldest first.
+    pub(crate) async fn get_feed_history(&self, id: &str) -> Result<Vec<FetchStatus>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT status, fetch_error, created_at FROM feed_updates
+                WHERE feed = ? ORDER BY id ASC",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        let mut statuses = vec![];
+        while let Some(row) = rows.next().await? {
+            statuses.push(libsql::de::from_row(&row)?);
+        }
+        Ok(statuses)
+    }
+
+    /// How many entries the feed called `feed` has, and how many of those the
+    /// current user hasn't read, has starred or has hidden.
+    pub(crate) async fn get_feed_entry_counts(&self, feed: &str) -> Result<EntryCounts> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT COUNT(*) AS total,
+                    COALESCE(SUM(read = false AND duplicate_of IS NULL AND hidden = false), 0) AS unread,
+                    COALESCE(SUM(starred), 0) AS starred,
+                    COALESCE(SUM(hidden), 0) AS hidden
+                FROM {} WHERE feed = ?",
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([feed]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(libsql::de::from_row(&row)?),
+            None => Ok(EntryCounts::default()),
+        }
+    }
+
+    /// Links of the current user's starred and read entries, most recently read first,
+    /// with whether each is starred.
+    pub(crate) async fn get_engaged_links(&self, limit: u32) -> Result<Vec<(String, bool)>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT content_link, starred FROM {}
+                WHERE (starred = true OR read = true) AND content_link != ''
+                ORDER BY read_at DESC, published DESC LIMIT ?",
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([limit]).await?;
+        let mut links = vec![];
+        while let Some(row) = rows.next().await? {
+            links.push((row.get(0)?, row.get(1)?));
+        }
+        Ok(links)
+    }
+
+    /// Every feed's unread count for the current user, including feeds with none.
+    pub(crate) async fn get_feed_stats(&self) -> Result<Vec<FeedStats>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT feeds.id AS feed_id, COALESCE(categories.name, '') AS category,
+                    COUNT(unread.id) AS unread
+                FROM feeds
+                LEFT JOIN categories ON categories.id = feeds.category_id
+                LEFT JOIN (SELECT entries.id, feed FROM {}
+                    WHERE read = false AND duplicate_of IS NULL AND hidden = false) AS unread
+                    ON unread.feed = feeds.name
+                GROUP BY feeds.id
+                ORDER BY feeds.id",
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut stats = vec![];
+        while let Some(row) = rows.next().await? {
+            stats.push(libsql::de::from_row(&row)?);
+        }
+        Ok(stats)
+    }
+
+    /// Stores entries that haven't been seen before, returning how many were new.
+    /// One that's another copy of a stored entry, found by its link or content,
+    /// isn't stored again; whether it was read or starred goes onto the stored one.
+    /// Filter rules are applied to new entries, leaving out ones they drop.
+    pub(crate) async fn add_entries<T>(&self, entries: T) -> Result<usize>
+    where
+        T: Iterator<Item = Entry>,
+    {
+        let rules = self.get_filter_rules().await?;
+        let mut inserted = 0;
+        let mut last_row = None;
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx.prepare(
+                    "INSERT OR IGNORE INTO entries (id, title, content_link, comments_link, robust_link, published, feed, title_key, duplicate_of, author, enclosure_url, enclosure_type, content, excerpt, link_key, content_key)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                ).await?;
+            // every user gets unread, unstarred state from a trigger, this is for
+            // entries coming in already read or starred
+            let mut state_stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = ?, starred = ? WHERE entry_id = ? AND user_id = ?",
+                )
+                .await?;
+            let mut current_stmt = tx
+                .prepare("SELECT content FROM entries WHERE id = ?")
+                .await?;
+            let mut revision_stmt = tx
+                .prepare(
+                    "INSERT INTO entry_revisions (entry_id, content, created_at) VALUES (?, ?, ?)",
+                )
+                .await?;
+            let mut update_stmt = tx
+                .prepare(
+                    "UPDATE entries SET content = ?, excerpt = ?, content_key = ? WHERE id = ?",
+                )
+                .await?;
+            let mut original_stmt = tx
+                .prepare(
+                    "SELECT id FROM entries
+                    WHERE title_key = ? AND id != ? AND duplicate_of IS NULL
+                    AND published >= ? AND published <= ?
+                    ORDER BY published ASC LIMIT 1",
+                )
+                .await?;
+            let mut same_stmt = tx
+                .prepare(
+                    "SELECT id FROM entries
+                    WHERE id != ?1 AND NOT EXISTS (SELECT 1 FROM entries WHERE id = ?1)
+                    AND ((link_key = ?2 AND (feed != ?3 OR title_key = ?4)) OR content_key = ?5)
+                    ORDER BY published ASC LIMIT 1",
+                )
+                .await?;
+            let mut merge_stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = read OR ?, starred = starred OR ?
+                    WHERE entry_id = ? AND user_id = ?",
+                )
+                .await?;
+            // rules are for everyone, unlike state an entry comes in with
+            let mut rule_stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = read OR ?, starred = starred OR ? WHERE entry_id = ?",
+                )
+                .await?;
+            for e in entries {
+                let outcome = rules::apply(&rules, &e);
+                if outcome.drop {
+                    continue;
+                }
+                let key = title_key(&e.title);
+                let link = link_key(&e.content_link);
+                let content_hash = e.content.as_deref().and_then(content_key);
+
+                // the same article under a new id, from a feed that changed its
+                // guids or another feed syndicating it, adds to the one we have
+                let mut same = same_stmt
+                    .query((
+                        e.id.clone(),
+                        link.clone(),
+                        e.feed.clone(),
+                        key.clone(),
+                        content_hash.clone(),
+                    ))
+                    .await?;
+                let existing: Option<String> = match same.next().await? {
+                    Some(row) => Some(row.get(0)?),
+                    None => None,
+                };
+                same_stmt.reset();
+                if let Some(existing) = existing {
+                    if e.read || e.starred {
+                        merge_stmt
+                            .execute((e.read, e.starred, existing, user::current()?))
+                            .await?;
+                        merge_stmt.reset();
+                    }
+                    continue;
+                }
+
+                let published = e.published.as_ref().map(|p| p.0).unwrap_or_else(Utc::now);
+                let window = chrono::Duration::hours(DUPLICATE_WINDOW_HOURS);
+                let mut originals = original_stmt
+                    .query((
+                        key.clone(),
+                        e.id.clone(),
+                        UtcTime(published - window),
+                        UtcTime(published + window),
+                    ))
+                    .await?;
+                let duplicate_of: Option<String> = match originals.next().await? {
+                    Some(row) => Some(row.get(0)?),
+                    None => None,
+                };
+                original_stmt.reset();
+
+                let id = e.id.clone();
+                let content = e.content.clone();
+                let excerpt = e.excerpt.clone();
+                let (read, starred) = (e.read, e.starred);
+                let added = stmt
+                    .execute((
+                        e.id,
+                        e.title,
+                        e.content_link,
+                        e.comments_link,
+                        e.robust_link,
+                        e.published,
+                        e.feed,
+                        key,
+                        duplicate_of,
+                        e.author,
+                        e.enclosure_url,
+                        e.enclosure_type,
+                        e.content,
+                        e.excerpt,
+                        link.unwrap_or_default(),
+                        content_hash.clone().unwrap_or_default(),
+                    ))
+                    .await?;
+                stmt.reset();
+                inserted += added;
+                if added > 0 {
+                    last_row = Some(tx.last_insert_rowid());
+                }
+                if added > 0 && (read || starred) {
+                    state_stmt
+                        .execute((read, starred, id.clone(), user::current()?))
+                        .await?;
+                    state_stmt.reset();
+                }
+                if added > 0 && (outcome.read || outcome.star) {
+                    rule_stmt
+                        .execute((outcome.read, outcome.star, id.clone()))
+                        .await?;
+                    rule_stmt.reset();
+                }
+
+                // an entry we already have might have been edited since
+                let Some(content) = content.filter(|_| added == 0) else {
+                    continue;
+                };
+                let mut rows = current_stmt.query([id.clone()]).await?;
+                let current = match rows.next().await? {
+                    Some(row) => match row.get_value(0)? {
+                        libsql::Value::Text(current) => Some(current),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                current_stmt.reset();
+                if current.as_deref() == Some(content.as_str()) {
+                    continue;
+                }
+                if let Some(previous) = current {
+                    revision_stmt
+                        .execute((id.clone(), previous, UtcTime(Utc::now())))
+                        .await?;
+                    revision_stmt.reset();
+                }
+                update_stmt
+                    .execute((content, excerpt, content_hash.unwrap_or_default(), id))
+                    .await?;
+                update_stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        if let Some(row) = last_row {
+            self.inserted.send_replace(row);
+        }
+
+        Ok(inserted)
+    }
+
+    /// Follows the row id of the last entry stored, which changes whenever
+    /// `add_entries` stores new ones.
+    pub(crate) fn watch_inserted(&self) -> watch::Receiver<i64> {
+        self.inserted.subscribe()
+    }
+
+    pub(crate) async fn get_entries(
+        &self,
+        filter: EntryFilter,
+        ordering: Ordering,
+    ) -> Result<Vec<Entry>> {
+        self.get_entries_page(filter, ordering, Page::default())
+            .await
+    }
+
+    /// Like `get_entries`, but only the entries in `page`.
+    pub(crate) async fn get_entries_page(
+        &self,
+        filter: EntryFilter,
+        ordering: Ordering,
+        page: Page,
+    ) -> Result<Vec<Entry>> {
+        self.get_stream_entries(filter, false, ordering, page).await
+    }
+
+    /// Like `get_entries_page`, optionally narrowed to unread entries, for clients
+    /// that can ask for any list without what they've read.
+    pub(crate) async fn get_stream_entries(
+        &self,
+        filter: EntryFilter,
+        unread_only: bool,
+        ordering: Ordering,
+        page: Page,
+    ) -> Result<Vec<Entry>> {
+        // ties broken by id so pages don't overlap
+        let order_clause = match ordering {
+            Ordering::Ascending => "ORDER BY published ASC, id ASC",
+            Ordering::Descending => "ORDER BY published DESC, id DESC",
+        };
+
+        let hidden = matches!(filter, EntryFilter::Hidden);
+        let (where_clause, mut params): (Cow<str>, Vec<libsql::Value>) = match filter {
+            EntryFilter::Starred => ("WHERE starred = true".into(), vec![]),
+            EntryFilter::Hidden => ("WHERE hidden = true".into(), vec![]),
+            EntryFilter::Podcasts => (
+                format!("WHERE enclosure_url IS NOT NULL AND ({})", PLAYABLE_ENCLOSURE).into(),
+                vec![],
+            ),
+            EntryFilter::Unread => (
+                "WHERE read = false AND duplicate_of IS NULL".into(),
+                vec![],
+            ),
+            EntryFilter::UnreadWithin(days) => (
+                "WHERE read = false AND duplicate_of IS NULL AND (published IS NULL OR published >= ?)"
+                    .into(),
+                vec![UtcTime(Utc::now() - chrono::Duration::days(days.into())).into()],
+            ),
+            EntryFilter::All => ("".into(), vec![]),
+            EntryFilter::Month(year, month) => {
+                let (start, end) = month_bounds(year, month)?;
+                (
+                    "WHERE published >= ? AND published < ?".into(),
+                    vec![start.into(), end.into()],
+                )
+            }
+            EntryFilter::Feed(id) => (
+                "WHERE feed IN (SELECT name FROM feeds WHERE id = ?)".into(),
+                vec![id.into()],
+            ),
+            EntryFilter::Category(category) => (
+                format!("WHERE feed IN ({})", feeds_in_category("?")).into(),
+                vec![category.into()],
+            ),
+            // a view without included categories takes every one it doesn't exclude
+            EntryFilter::Combined(id) => (
+                "WHERE feed IN (SELECT feeds.name FROM feeds LEFT JOIN categories ON categories.id = feeds.category_id
+                    WHERE (NOT EXISTS (SELECT 1 FROM combined_view_categories WHERE view_id = ?1 AND NOT excluded)
+                        OR categories.name COLLATE NOCASE IN (SELECT category FROM combined_view_categories WHERE view_id = ?1 AND NOT excluded))
+                    AND COALESCE(categories.name, '') COLLATE NOCASE NOT IN (SELECT category FROM combined_view_categories WHERE view_id = ?1 AND excluded))".into(),
+                vec![id.into()],
+            ),
+            EntryFilter::Resurfaced => (
+                "WHERE starred = true AND resurfaced_at >= ?".into(),
+                vec![UtcTime(Utc::now() - chrono::Duration::days(RESURFACE_DAYS)).into()],
+            ),
+            EntryFilter::Saved(id) => match self.get_saved_filter(&id).await? {
+                Some(saved) => {
+                    let (clause, params) = saved_filter_conditions(&saved);
+                    (clause.into(), params)
+                }
+                None => return Ok(vec![]),
+            },
+            // ranked rather than ordered by date
+            EntryFilter::Search(query) => return self.search_entries(&query, page).await,
+        };
+        let mut conditions = vec![];
+        if unread_only {
+            conditions.push("read = false AND duplicate_of IS NULL");
+        }
+        // hidden entries only show up when they're asked for
+        if !hidden {
+            conditions.push("hidden = false");
+        }
+        let where_clause = match where_clause.as_ref() {
+            "" => format!("WHERE {}", conditions.join(" AND ")),
+            clause if conditions.is_empty() => clause.to_string(),
+            clause => format!("{} AND {}", clause, conditions.join(" AND ")),
+        };
+        params.push(page.limit.map_or(-1, i64::from).into());
+        params.push(i64::from(page.offset).into());
+        let statement_string = format!(
+            "SELECT {} FROM {} {} {} LIMIT ? OFFSET ?",
+            ENTRY_COLUMNS,
+            entries_with_state()?,
+            where_clause,
+            order_clause
+        );
+        let mut stmt = self
+            .main_conn
+            .prepare(&statement_string)
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(params).await?;
+        let mut entries: Vec<Entry> = vec![];
+        // TODO: Use .into_stream
+        while let Some(row) = rows.next().await? {
+            let entry = libsql::de::from_row(&row)?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Up to `limit` entries of every kind, hidden ones too, in the order they were
+    /// stored after the one with row id `after`, to go through all of them a page
+    /// at a time.
+    pub(crate) async fn get_entries_after(&self, after: i64, limit: u32) -> Result<Vec<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {} WHERE entries.rowid > ? ORDER BY entries.rowid LIMIT ?",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query((after, limit)).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// The row id of the last entry stored, 0 before there are any.
+    pub(crate) async fn last_item_id(&self) -> Result<i64> {
+        let mut rows = self
+            .main_conn
+            .query("SELECT COALESCE(MAX(rowid), 0) FROM entries", ())
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Unread entries stored after row id `after` and up to `through`, from the
+    /// categories in the email digest, by category and then oldest first.
+    pub(crate) async fn get_digest_entries(&self, after: i64, through: i64) -> Result<Vec<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {}
+                WHERE entries.rowid > ?1 AND entries.rowid <= ?2
+                AND read = false AND hidden = false AND duplicate_of IS NULL
+                AND feed IN (SELECT feeds.name FROM feeds JOIN categories ON categories.id = feeds.category_id
+                    WHERE categories.digest)
+                ORDER BY category, published ASC, id ASC",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query((after, through)).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Entries stored after row id `after` and up to `through` that aren't
+    /// duplicates of ones already stored, oldest first.
+    pub(crate) async fn get_new_entries(&self, after: i64, through: i64) -> Result<Vec<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {}
+                WHERE entries.rowid > ?1 AND entries.rowid <= ?2 AND duplicate_of IS NULL
+                ORDER BY entries.rowid ASC",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query((after, through)).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Entries matching every word of `query` in their title, feed or content, best matches first.
+    pub(crate) async fn search_entries(&self, query: &str, page: Page) -> Result<Vec<Entry>> {
+        let Some(query) = fts_query(query) else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {}
+                JOIN (SELECT rowid AS hit, rank FROM entries_fts WHERE entries_fts MATCH ?) AS hits
+                    ON entries.rowid = hits.hit
+                WHERE hidden = false
+                ORDER BY hits.rank LIMIT ? OFFSET ?",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt
+            .query((
+                query,
+                page.limit.map_or(-1, i64::from),
+                i64::from(page.offset),
+            ))
+            .await?;
+        let mut entries: Vec<Entry> = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    pub(crate) async fn get_entry(&self, id: &str) -> Result<Option<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {} WHERE id = ?",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The article fetched from an entry's page, if it's been extracted.
+    pub(crate) async fn get_extracted_content(&self, id: &str) -> Result<Option<Article>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT extracted_content AS content, extracted_at FROM entries
+                WHERE id = ? AND extracted_content IS NOT NULL",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn set_extracted_content(&self, id: &str, content: &str) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare("UPDATE entries SET extracted_content = ?, extracted_at = ? WHERE id = ?")
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute((content, UtcTime(Utc::now()), id)).await?;
+        Ok(())
+    }
+
+    /// Whether any stored entry links to `url`.
+    pub(crate) async fn has_entry_link(&self, url: &str) -> Result<bool> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT 1 FROM entries WHERE content_link = ?1 AND content_link != '' LIMIT 1",
+                [url],
+            )
+            .await?;
+        Ok(rows.next().await?.is_some())
+    }
+
+    pub(crate) async fn get_link_preview(&self, url: &str) -> Result<Option<LinkPreview>> {
+        let mut rows = self
+            .main_conn
+            .query(
+                "SELECT url, title, description, image, fetched_at FROM link_previews WHERE url = ?1",
+                [url],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(libsql::de::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores a link preview, clearing out ones older than `max_age`.
+    pub(crate) async fn set_link_preview(
+        &self,
+        preview: &LinkPreview,
+        max_age: chrono::Duration,
+    ) -> Result<()> {
+        self.main_conn
+            .execute(
+                "DELETE FROM link_previews WHERE fetched_at < ?1",
+                vec![libsql::Value::from(UtcTime(Utc::now() - max_age))],
+            )
+            .await?;
+        self.main_conn
+            .execute(
+                "INSERT OR REPLACE INTO link_previews (url, title, description, image, fetched_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    preview.url.as_str(),
+                    preview.title.clone(),
+                    preview.description.clone(),
+                    preview.image.clone(),
+                    preview.fetched_at.clone(),
+                ),
+            )
+            .await
+            .context("couldn't store link preview")?;
+        Ok(())
+    }
+
+    /// Earlier versions of an entry's content, oldest first.
+    pub(crate) async fn get_entry_revisions(&self, id: &str) -> Result<Vec<EntryRevision>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT content, created_at FROM entry_revisions WHERE entry_id = ? ORDER BY id ASC",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([id]).await?;
+        let mut revisions = vec![];
+        while let Some(row) = rows.next().await? {
+            revisions.push(libsql::de::from_row(&row)?);
+        }
+        Ok(revisions)
+    }
+
+    /// Entry counts for every month that has published entries, newest first.
+    pub(crate) async fn get_archive_months(&self) -> Result<Vec<ArchiveMonth>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT CAST(substr(published, 1, 4) AS INTEGER) AS year,
+                        CAST(substr(published, 6, 2) AS INTEGER) AS month,
+                        COUNT(*) AS count
+                FROM entries WHERE published IS NOT NULL
+                GROUP BY year, month ORDER BY year DESC, month DESC",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut months: Vec<ArchiveMonth> = vec![];
+        while let Some(row) = rows.next().await? {
+            let month = libsql::de::from_row(&row)?;
+            months.push(month);
+        }
+        Ok(months)
+    }
+
+    /// Number of entries the current user marked read per day (as YYYY-MM-DD) since the given time.
+    pub(crate) async fn get_read_counts_by_day(&self, since: UtcTime) -> Result<Vec<DayCount>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "SELECT substr(read_at, 1, 10) AS date, COUNT(*) AS count
+                FROM entry_state WHERE user_id = ? AND read = true AND read_at >= ?
+                GROUP BY date ORDER BY date ASC",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query((user::current()?, since)).await?;
+        let mut days: Vec<DayCount> = vec![];
+        while let Some(row) = rows.next().await? {
+            let day = libsql::de::from_row(&row)?;
+            days.push(day);
+        }
+        Ok(days)
+    }
+
+    pub(crate) async fn get_starred_entries(&self) -> Result<Vec<Entry>> {
+        self.get_entries(EntryFilter::Starred, Ordering::Ascending)
+            .await
+    }
+
+    /// Entries any user has starred, oldest first.
+    pub(crate) async fn get_entries_starred_by_anyone(&self) -> Result<Vec<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {}
+                WHERE id IN (SELECT entry_id FROM entry_state WHERE starred = true)
+                ORDER BY published ASC, id ASC",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Up to `limit` entries someone starred that link somewhere but don't have a
+    /// robust link yet, oldest first.
+    pub(crate) async fn get_entries_to_archive(&self, limit: u32) -> Result<Vec<Entry>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {}
+                WHERE id IN (SELECT entry_id FROM entry_state WHERE starred = true)
+                AND content_link != '' AND COALESCE(robust_link, '') = ''
+                ORDER BY published ASC, id ASC LIMIT ?",
+                ENTRY_COLUMNS,
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query([limit]).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Stores where an archived copy of an entry's page is.
+    pub(crate) async fn set_robust_link(&self, id: &str, link: &str) -> Result<()> {
+        self.main_conn
+            .execute(
+                "UPDATE entries SET robust_link = ? WHERE id = ?",
+                [link, id],
+            )
+            .await
+            .context("couldn't store robust link")?;
+        Ok(())
+    }
+
+    /// Picks up to `count` random entries the current user starred, published before
+    /// `published_before`, to show them again, skipping ones shown again since
+    /// `not_since`. Returns how many were picked.
+    pub(crate) async fn resurface_starred(
+        &self,
+        count: u32,
+        published_before: UtcTime,
+        not_since: UtcTime,
+    ) -> Result<usize> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entry_state SET resurfaced_at = ?1 WHERE user_id = ?5 AND entry_id IN (
+                    SELECT entry_id FROM entry_state JOIN entries ON entries.id = entry_state.entry_id
+                    WHERE user_id = ?5 AND starred = true AND published < ?2
+                        AND (resurfaced_at IS NULL OR resurfaced_at < ?3)
+                    ORDER BY RANDOM() LIMIT ?4
+                )",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt
+            .execute((
+                UtcTime(Utc::now()),
+                published_before,
+                not_since,
+                i64::from(count),
+                user::current()?,
+            ))
+            .await?)
+    }
+
+    /// Records the result of checking an entry's content link, `None` if it couldn't be reached.
+    /// Whether it's dead is left as it was when `dead` is `None`, for checks that couldn't tell.
+    pub(crate) async fn set_link_status(
+        &self,
+        id: &str,
+        status: Option<u16>,
+        dead: Option<bool>,
+    ) -> Result<()> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entries SET link_status = ?, link_checked_at = ?, link_dead = COALESCE(?, link_dead) WHERE id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        stmt.execute(vec![
+            status.map_or(libsql::Value::Null, |s| libsql::Value::Integer(s.into())),
+            UtcTime(Utc::now()).into(),
+            dead.map_or(libsql::Value::Null, |d| d.into()),
+            libsql::Value::Text(id.to_string()),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Which of `ids` belong to entries that are already stored.
+    pub(crate) async fn stored_entry_ids(&self, ids: &[String]) -> Result<HashSet<String>> {
+        let mut stored = HashSet::new();
+        // a few hundred at a time, under SQLite's limit on parameters
+        for ids in ids.chunks(500) {
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            let mut stmt = self
+                .main_conn
+                .prepare(&format!(
+                    "SELECT id FROM entries WHERE id IN ({})",
+                    placeholders
+                ))
+                .await
+                .context("couldn't prepare statement")?;
+            let params: Vec<libsql::Value> = ids.iter().map(|id| id.as_str().into()).collect();
+            let mut rows = stmt.query(params).await?;
+            while let Some(row) = rows.next().await? {
+                stored.insert(row.get(0)?);
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Entries by their row ids, the numeric ids handed to Google Reader clients.
+    pub(crate) async fn get_entries_by_item_ids(&self, item_ids: &[i64]) -> Result<Vec<Entry>> {
+        if item_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let placeholders = vec!["?"; item_ids.len()].join(", ");
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT {} FROM {} WHERE entries.rowid IN ({}) ORDER BY published DESC, id DESC",
+                ENTRY_COLUMNS,
+                entries_with_state()?,
+                placeholders
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let params: Vec<libsql::Value> = item_ids.iter().map(|&id| id.into()).collect();
+        let mut rows = stmt.query(params).await?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next().await? {
+            entries.push(libsql::de::from_row(&row)?);
+        }
+        Ok(entries)
+    }
+
+    /// Sets entries read or unread by row id, returning how many changed.
+    pub(crate) async fn set_items_read(&self, item_ids: &[i64], read: bool) -> Result<usize> {
+        let mut updated = 0;
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = ?1,
+                        read_at = CASE WHEN ?1 THEN COALESCE(read_at, ?2) END
+                    WHERE user_id = ?4 AND read IS NOT ?1
+                        AND entry_id = (SELECT id FROM entries WHERE rowid = ?3)",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for &id in item_ids {
+                updated += stmt
+                    .execute((read, UtcTime(Utc::now()), id, user::current()?))
+                    .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// Stars or unstars entries by row id, returning how many changed.
+    pub(crate) async fn set_items_starred(&self, item_ids: &[i64], starred: bool) -> Result<usize> {
+        let mut updated = 0;
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET starred = ?1
+                    WHERE user_id = ?3 AND starred IS NOT ?1
+                        AND entry_id = (SELECT id FROM entries WHERE rowid = ?2)",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for &id in item_ids {
+                updated += stmt.execute((starred, id, user::current()?)).await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// How many unread entries each feed has, by feed name, leaving out feeds with none.
+    pub(crate) async fn get_unread_counts(&self) -> Result<Vec<(String, u32)>> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "SELECT feed, COUNT(*) FROM {}
+                WHERE read = false AND duplicate_of IS NULL AND hidden = false AND feed IS NOT NULL
+                GROUP BY feed",
+                entries_with_state()?
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        let mut rows = stmt.query(()).await?;
+        let mut counts = vec![];
+        while let Some(row) = rows.next().await? {
+            counts.push((row.get(0)?, row.get(1)?));
+        }
+        Ok(counts)
+    }
+
+    /// Flips an entry between read and unread, returning whether it existed.
+    pub(crate) async fn toggle_entry_read(&self, entry_id: &str) -> Result<bool> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entry_state SET read = NOT read,
+                    read_at = CASE WHEN read THEN NULL ELSE ? END
+                WHERE entry_id = ? AND user_id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt
+            .execute((UtcTime(Utc::now()), entry_id, user::current()?))
+            .await?
+            > 0)
+    }
+
+    /// Marks every given entry read (without toggling), returning how many changed.
+    pub(crate) async fn mark_entries_read(&self, entry_ids: Vec<String>) -> Result<usize> {
+        let mut updated = 0;
+        let now = UtcTime(Utc::now());
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET read = true, read_at = ?
+                    WHERE entry_id = ? AND user_id = ? AND read = false",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for id in entry_ids {
+                updated += stmt.execute((now.clone(), id, user::current()?)).await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// Marks every unread entry read, or only those of one feed (by name) or
+    /// category, returning how many changed.
+    pub(crate) async fn mark_all_read(
+        &self,
+        feed: Option<&str>,
+        category: Option<&str>,
+    ) -> Result<usize> {
+        let mut stmt = self
+            .main_conn
+            .prepare(&format!(
+                "UPDATE entry_state SET read = true, read_at = ?1
+                WHERE user_id = ?4 AND read = false AND entry_id IN (
+                    SELECT id FROM entries
+                    WHERE (?2 IS NULL OR feed = ?2)
+                        AND (?3 IS NULL OR feed IN ({})))",
+                feeds_in_category("?3")
+            ))
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt
+            .execute((UtcTime(Utc::now()), feed, category, user::current()?))
+            .await?)
+    }
+
+    /// Deletes entries everyone has read and no one has starred published before
+    /// `cutoff`, along with their revisions, returning how many went. Entries
+    /// without a date are kept, the feed cursor can't tell they were seen so
+    /// they'd come back unread.
+    pub(crate) async fn prune_entries(&self, cutoff: UtcTime) -> Result<usize> {
+        let tx = self.main_conn.transaction().await?;
+        let pruned = tx
+            .execute(
+                "DELETE FROM entries
+                WHERE id NOT IN (SELECT entry_id FROM entry_state WHERE read = false OR starred = true)
+                    AND published IS NOT NULL AND published < ?",
+                [cutoff],
+            )
+            .await?;
+        tx.execute(
+            "DELETE FROM entry_revisions WHERE entry_id NOT IN (SELECT id FROM entries)",
+            (),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(pruned as usize)
+    }
+
+    /// Merges read/starred flags from entries of another instance onto the current
+    /// user's matching entries here, by id or content link. Nothing is inserted and flags are only
+    /// ever set, so merging an older dump can't unread or unstar anything.
+    pub(crate) async fn merge_entry_state(&self, entries: Vec<Entry>) -> Result<usize> {
+        let mut matched = 0;
+        let now = UtcTime(Utc::now());
+        let tx = self.main_conn.transaction().await?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE entry_state SET
+                        read = read OR ?1,
+                        read_at = CASE WHEN ?1 AND NOT read THEN ?2 ELSE read_at END,
+                        starred = starred OR ?3
+                    WHERE user_id = ?6 AND entry_id IN (SELECT id FROM entries
+                        WHERE id = ?4 OR (content_link = ?5 AND content_link != ''))",
+                )
+                .await
+                .context("couldn't prepare statement")?;
+            for e in entries.into_iter().filter(|e| e.read || e.starred) {
+                matched += stmt
+                    .execute((
+                        e.read,
+                        now.clone(),
+                        e.starred,
+                        e.id,
+                        e.content_link,
+                        user::current()?,
+                    ))
+                    .await?;
+                stmt.reset();
+            }
+        }
+        tx.commit().await?;
+        Ok(matched)
+    }
+
+    /// Flips an entry between starred and not, returning whether it existed.
+    pub(crate) async fn toggle_entry_starred(&self, entry_id: &str) -> Result<bool> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entry_state SET starred = NOT starred WHERE entry_id = ? AND user_id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt.execute((entry_id, user::current()?)).await? > 0)
+    }
+
+    /// Hides an entry from the current user's lists, or brings it back, returning
+    /// whether it existed. Hidden entries stay stored, so they're still matched
+    /// against when spotting duplicates and aren't added again on the next fetch.
+    pub(crate) async fn toggle_entry_hidden(&self, entry_id: &str) -> Result<bool> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entry_state SET hidden = NOT hidden WHERE entry_id = ? AND user_id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt.execute((entry_id, user::current()?)).await? > 0)
+    }
+
+    /// Records how many seconds into an entry's enclosure the current user got,
+    /// false when there's no such entry.
+    pub(crate) async fn set_playback_position(
+        &self,
+        entry_id: &str,
+        position: f64,
+    ) -> Result<bool> {
+        let mut stmt = self
+            .main_conn
+            .prepare(
+                "UPDATE entry_state SET playback_position = ? WHERE entry_id = ? AND user_id = ?",
+            )
+            .await
+            .context("couldn't prepare statement")?;
+        Ok(stmt.execute((position, entry_id, user::current()?)).await? > 0)
+    }
+}
+
+/// Enclosures a browser can play, rather than the images some feeds attach.
+const PLAYABLE_ENCLOSURE: &str = "enclosure_type LIKE 'audio/%' OR enclosure_type LIKE 'video/%'";
+
+/// Turns what someone typed into an FTS5 query matching all of its words, quoting
+/// each so operators and stray punctuation are searched for rather than parsed.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.join(" "))
+}
+
+/// Trims a category and collapses runs of whitespace inside it.
+pub(crate) fn normalize_category(category: &str) -> String {
+    category.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// How long resurfaced starred entries stay on the rediscover page.
+pub(crate) const RESURFACE_DAYS: i64 = 7;
+
+/// How many seconds a token's last use can be off by, rather than noting every use.
+const TOKEN_USE_RESOLUTION_SECS: i64 = 60;
+
+/// How many fetch results to keep per feed.
+const FETCH_STATUS_HISTORY: u32 = 20;
+
+/// Near-duplicate titles published within this many hours of each other are collapsed.
+const DUPLICATE_WINDOW_HOURS: i64 = 24;
+
+/// Words aggregators tack onto a repeated headline that shouldn't make it look new.
+const TITLE_NOISE_WORDS: &[&str] = &["breaking", "exclusive", "live", "update", "updated"];
+
+/// FNV-1a, for hashes that are persisted or otherwise must not change between builds.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Normalizes a title into a stable hash so that minor edits (case, punctuation,
+/// word order, "UPDATED:" prefixes) produce the same key.
+fn title_key(title: &str) -> String {
+    let lowered = title.to_lowercase();
+    let mut words: Vec<&str> = lowered
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && !TITLE_NOISE_WORDS.contains(w))
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+
+    let hash = stable_hash(words.join(" ").as_bytes());
+    format!("{:016x}", hash)
+}
+
+/// Query parameters that only track where a click came from.
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "igshid", "mc_cid", "mc_eid", "ref", "ref_src",
+];
+
+/// Content shorter than this is too likely to be boilerplate to find duplicates by.
+const MIN_CONTENT_KEY_LEN: usize = 200;
+
+/// Normalizes a link so the same page gets the same key however it's linked:
+/// scheme, `www.`, a trailing slash, the fragment, tracking parameters and the
+/// order of the rest don't matter.
+fn link_key(link: &str) -> Option<String> {
+    let url = reqwest::Url::parse(link.trim()).ok()?;
+    let host = url.host_str()?;
+    let mut key = host.strip_prefix("www.").unwrap_or(host).to_lowercase();
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push_str(url.path().trim_end_matches('/'));
+    let mut query: Vec<String> = url
+        .query_pairs()
+        .filter(|(name, _)| !name.starts_with("utm_") && !TRACKING_PARAMS.contains(&name.as_ref()))
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if !query.is_empty() {
+        query.sort_unstable();
+        key.push('?');
+        key.push_str(&query.join("&"));
+    }
+    Some(key)
+}
+
+/// The credential a webhook's secret is stored under.
+fn webhook_credential(id: &str) -> String {
+    format!("webhook:{}", id)
+}
+
+/// A hash of content long enough to identify an article, ignoring case and whitespace.
+fn content_key(content: &str) -> Option<String> {
+    let normalized = content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    if normalized.len() < MIN_CONTENT_KEY_LEN {
+        return None;
+    }
+    Some(format!("{:016x}", stable_hash(normalized.as_bytes())))
+}
+
+impl From<UtcTime> for libsql::Value {
+    fn from(t: UtcTime) -> libsql::Value {
+        libsql::Value::Text(t.0.to_rfc3339())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn add_list_feeds() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feeds = vec![
+            Feed {
+                id: base64::encode_config("HackerNews", base64::URL_SAFE),
+                name: "HackerNews".to_string(),
+                site_url: "https://news.ycombinator.com".to_string(),
+                feed_url: "https://news.ycombinator.com/rss".to_string(),
+                last_fetched: Some(Utc::now().into()),
+                fetch_error: None,
+                category: "tech".to_string(),
+                ..Default::default()
+            },
+            Feed {
+                id: base64::encode_config("Product Hunt", base64::URL_SAFE),
+                name: "Product Hunt".to_string(),
+                site_url: "https://www.producthunt.com".to_string(),
+                feed_url: "https://www.producthunt.com/feed".to_string(),
+                last_fetched: None,
+                fetch_error: None,
+                category: "tech".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        db.add_feeds(feeds.into_iter()).await?;
+        let f = db.get_feeds().await?;
+        assert_eq!(f.len(), 2);
+        assert_eq!(f[0].name, "HackerNews");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sort_and_page_feeds() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = |name: &str, category: &str| {
+                Feed::new(
+                    name.to_string(),
+                    format!("https://{}.com", name),
+                    format!("https://{}.com/rss", name),
+                    category.to_string(),
+                )
+            };
+            db.add_feeds(
+                vec![
+                    feed("zebra", "animals"),
+                    feed("Apple", "tech"),
+                    feed("mango", "fruit"),
+                ]
+                .into_iter(),
+            )
+            .await?;
+            let mut entry = Entry::new(
+                "e",
+                "Post".to_string(),
+                "https://mango.com/1".to_string(),
+                "".to_string(),
+                None,
+            );
+            entry.feed = "mango".to_string();
+            db.add_entries(std::iter::once(entry)).await?;
+            let zebra = base64::encode_config("https://zebra.com/rss", base64::URL_SAFE);
+            db.update_feed_status(zebra, Some(200), None).await?;
+
+            let names = |feeds: Vec<Feed>| feeds.into_iter().map(|f| f.name).collect::<Vec<_>>();
+            let by = |sort: &str| db.get_feeds_page(sort.parse().unwrap(), Page::default());
+            assert_eq!(names(by("name").await?), ["Apple", "mango", "zebra"]);
+            assert_eq!(names(by("category").await?), ["zebra", "mango", "Apple"]);
+            assert_eq!(by("last_fetched").await?[0].name, "zebra");
+            assert_eq!(by("unread").await?[0].name, "mango");
+            assert!("size".parse::<FeedSort>().is_err());
+
+            let page = db.get_feeds_page(FeedSort::Name, Page::new(1, 1)).await?;
+            assert_eq!(names(page), ["mango"]);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn normalize_categories() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feed = |name: &str, category: &str| {
+            Feed::new(
+                name.to_string(),
+                format!("https://{}.com", name),
+                format!("https://{}.com/rss", name),
+                category.to_string(),
+            )
+        };
+        db.add_feeds(
+            vec![
+                feed("a", "Tech"),
+                feed("b", "tech "),
+                feed("c", " TECH"),
+                feed("d", "Home  Lab"),
+            ]
+            .into_iter(),
+        )
+        .await?;
+        assert_eq!(db.get_categories().await?, ["Home Lab", "Tech"]);
+
+        // the spelling is the category's, changed by renaming it
+        db.add_feeds(std::iter::once(feed("d", "home lab"))).await?;
+        assert_eq!(db.get_categories().await?, ["Home Lab", "Tech"]);
+        let categories = db.get_category_list().await?;
+        assert_eq!(categories[1].feeds, 3);
+        let (home_lab, tech) = (categories[0].id, categories[1].id);
+        db.add_combined_view(&CombinedView {
+            id: "v".to_string(),
+            name: "Hobbies".to_string(),
+            categories: vec!["home lab".to_string()],
+            excluded: vec![],
+        })
+        .await?;
+
+        assert!(db.rename_category(home_lab, "Homelab").await?);
+        let d = feed("d", "");
+        assert_eq!(db.get_feed(&d.id).await?.unwrap().category, "Homelab");
+        assert_eq!(db.get_combined_views().await?[0].categories, ["Homelab"]);
+
+        // taking another category's name merges them
+        assert!(db.rename_category(home_lab, "tech").await?);
+        assert_eq!(db.get_categories().await?, ["Tech"]);
+        assert_eq!(db.get_category_list().await?[0].feeds, 4);
+        assert_eq!(db.get_combined_views().await?[0].categories, ["Tech"]);
+        assert!(!db.rename_category(home_lab, "Gone").await?);
+
+        assert!(db.remove_category(tech).await?);
+        assert!(db.get_categories().await?.is_empty());
+        assert_eq!(db.get_feed(&d.id).await?.unwrap().category, "");
+        assert!(db.get_combined_views().await?[0].categories.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_feed_moves_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let mut feed = Feed::new(
+                "Blgo".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/rss".to_string(),
+                "Tech".to_string(),
+            );
+            db.add_feeds(std::iter::once(feed.clone())).await?;
+            db.set_feed_validators(&feed.id, Some("\"v1\"".to_string()), None, None)
+                .await?;
+            let mut entry = Entry::new(
+                "post",
+                "Post".to_string(),
+                "https://blog.com/post".to_string(),
+                "".to_string(),
+                None,
+            );
+            entry.feed = "Blgo".to_string();
+            db.add_entries(std::iter::once(entry)).await?;
+
+            feed.name = "Blog".to_string();
+            feed.feed_url = "https://blog.com/atom".to_string();
+            feed.category = "tech ".to_string();
+            assert!(db.update_feed(&feed).await?);
+            let updated = db.get_feed(&feed.id).await?.unwrap();
+            assert_eq!(updated.name, "Blog");
+            assert_eq!(updated.feed_url, "https://blog.com/atom");
+            assert_eq!(updated.category, "Tech");
+            assert!(updated.etag.is_none());
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es[0].feed, "Blog");
+
+            feed.id = "missing".to_string();
+            assert!(!db.update_feed(&feed).await?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn migrations_apply_once() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        assert_eq!(db.schema_version().await?, latest_version());
+        // a rerun shouldn't redo the baseline
+        db.init().await?;
+
+        let migrations = [Migration {
+            version: latest_version() + 1,
+            name: "notes",
+            sql: "CREATE TABLE notes (entry_id TEXT NOT NULL, body TEXT NOT NULL);",
+        }];
+        db.migrate(&migrations).await?;
+        // applying it again would fail on the existing table
+        db.migrate(&migrations).await?;
+        assert_eq!(db.schema_version().await?, latest_version() + 1);
+
+        let err = db.init().await.unwrap_err();
+        assert!(err.to_string().contains("newer than this build"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readd_feed_keeps_state() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feed = || {
+            Feed::new(
+                "HackerNews".to_string(),
+                "https://news.ycombinator.com".to_string(),
+                "https://news.ycombinator.com/rss".to_string(),
+                "tech".to_string(),
+            )
+        };
+        db.add_feeds(std::iter::once(feed())).await?;
+        // stands in for state that an import doesn't know about
+        db.main_conn
+            .execute("ALTER TABLE feeds ADD COLUMN note TEXT", ())
+            .await?;
+        db.main_conn
+            .execute("UPDATE feeds SET note = 'keep me'", ())
+            .await?;
+
+        let mut renamed = feed();
+        renamed.name = "HN".to_string();
+        db.add_feeds(std::iter::once(renamed)).await?;
+
+        let f = db.get_feeds().await?;
+        assert_eq!(f.len(), 1);
+        assert_eq!(f[0].name, "HN");
+        let mut rows = db.main_conn.query("SELECT note FROM feeds", ()).await?;
+        let note: String = rows.next().await?.unwrap().get(0)?;
+        assert_eq!(note, "keep me");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readd_feed_keeps_push() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feed = || {
+            Feed::new(
+                "Pushed".to_string(),
+                "https://example.com".to_string(),
+                "https://example.com/feed".to_string(),
+                "".to_string(),
+            )
+        };
+        let mut pushed = feed();
+        pushed.push = true;
+        db.add_feeds(std::iter::once(pushed)).await?;
+        // an OPML import of the same feed doesn't know it takes pushes
+        db.add_feeds(std::iter::once(feed())).await?;
+        let f = db.get_feeds().await?;
+        assert_eq!(f.len(), 1);
+        assert!(f[0].push);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn feed_icon_override() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feed = Feed::new(
+            "HackerNews".to_string(),
+            "https://news.ycombinator.com/".to_string(),
+            "https://news.ycombinator.com/rss".to_string(),
+            "tech".to_string(),
+        );
+        let id = feed.id.clone();
+        db.add_feeds(std::iter::once(feed)).await?;
+        let f = db.get_feed(&id).await?.unwrap();
+        assert_eq!(f.icon(), "https://news.ycombinator.com/favicon.ico");
+
+        db.set_feed_icon(&id, Some("https://example.com/hn.png".to_string()))
+            .await?;
+        let f = db.get_feed(&id).await?.unwrap();
+        assert_eq!(f.icon(), "https://example.com/hn.png");
+
+        db.set_feed_icon(&id, None).await?;
+        assert!(db.get_feed(&id).await?.unwrap().icon_url.is_none());
+
+        db.set_feed_validators(&id, Some("\"abc\"".to_string()), None, Some(512))
+            .await?;
+        let f = db.get_feed(&id).await?.unwrap();
+        assert_eq!(f.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(f.content_length, Some(512));
+        assert_eq!(f.consecutive_failures, 0);
+
+        db.set_feed_backoff(&id, 3, Some(Utc::now().into())).await?;
+        let f = db.get_feed(&id).await?.unwrap();
+        assert_eq!(f.consecutive_failures, 3);
+        assert!(f.next_retry_at.is_some());
+
+        db.set_feed_cursor(&id, Utc::now().into(), "entry").await?;
+        let f = db.get_feed(&id).await?.unwrap();
+        assert!(f.last_entry_published.is_some());
+        assert_eq!(f.last_entry_id.as_deref(), Some("entry"));
+        assert!(f.last_modified.is_none());
+        assert!(f.icon_url.is_none());
+        assert!(db.get_feed("missing").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_list_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries = vec![
+                Entry::new(
+                    "my-entry",
+                    "Cool Post".to_string(),
+                    "https://content.com/1".to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                ),
+                Entry::new(
+                    "your-entry",
+                    "Gross Post".to_string(),
+                    "https://content.com/2".to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                ),
+            ];
+
+            db.add_entries(entries.into_iter()).await?;
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es.len(), 2);
+            assert_eq!(es[0].title, "Cool Post");
+            assert_ne!(es[0].id, "my-entry");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn entries_include_category() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            db.add_feeds(std::iter::once(Feed::new(
+                "HackerNews".to_string(),
+                "https://news.ycombinator.com".to_string(),
+                "https://news.ycombinator.com/rss".to_string(),
+                "tech".to_string(),
+            )))
+            .await?;
+            let entry = |id: &str, feed: &str| {
+                let mut e = Entry::new(
+                    id,
+                    format!("Post {}", id),
+                    format!("https://content.com/{}", id),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                );
+                e.feed = feed.to_string();
+                e
+            };
+            db.add_entries(vec![entry("one", "HackerNews"), entry("two", "Removed")].into_iter())
+                .await?;
+
+            let es = db
+                .get_entries(
+                    EntryFilter::Category("tech".to_string()),
+                    Ordering::Ascending,
+                )
+                .await?;
+            assert_eq!(es.len(), 1);
+            assert_eq!(es[0].category.as_deref(), Some("tech"));
+
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            let removed = es.iter().find(|e| e.feed == "Removed").unwrap();
+            assert!(removed.category.is_none());
+            assert!(!removed.compact);
+            assert!(es.iter().all(|e| !e.compact));
+
+            let feed_id =
+                base64::encode_config("https://news.ycombinator.com/rss", base64::URL_SAFE);
+            let filter: EntryFilter = format!("feed:{}", feed_id).parse()?;
+            let es = db.get_entries(filter, Ordering::Ascending).await?;
+            assert_eq!(es.len(), 1);
+            assert_eq!(es[0].feed, "HackerNews");
+
+            db.set_feed_compact(&feed_id, true).await?;
+            assert!(db.get_feed(&feed_id).await?.unwrap().compact);
+            assert!(db.get_feed(&feed_id).await?.unwrap().enabled);
+            db.set_feed_enabled(&feed_id, false).await?;
+            assert!(!db.get_feed(&feed_id).await?.unwrap().enabled);
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            let hn = es.iter().find(|e| e.feed == "HackerNews").unwrap();
+            assert!(hn.compact);
+            assert_eq!(hn.extract_mode(), ExtractMode::Never);
+
+            db.set_feed_extract_mode(&feed_id, ExtractMode::OnView)
+                .await?;
+            let hn = db.get_entry(&hn.id).await?.unwrap();
+            assert_eq!(hn.extract_mode(), ExtractMode::OnView);
+            assert!(db.get_extracted_content(&hn.id).await?.is_none());
+            db.set_extracted_content(&hn.id, "<p>Full article</p>")
+                .await?;
+            let article = db.get_extracted_content(&hn.id).await?.unwrap();
+            assert_eq!(article.content, "<p>Full article</p>");
+            assert!(article.is_fresh());
+            Ok(())
+        })
+        .await
+    }
 
     #[tokio::test]
-    async fn add_list_feeds() -> Result<(), anyhow::Error> {
+    async fn page_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let published = Some(Utc::now().into());
+            let entries = (0..5).map(|i| {
+                Entry::new(
+                    &format!("entry-{}", i),
+                    format!("Post {}", i),
+                    format!("https://content.com/{}", i),
+                    "".to_string(),
+                    published.clone(),
+                )
+            });
+            db.add_entries(entries).await?;
+
+            let page = Page::new(0, 2);
+            let first = db
+                .get_entries_page(EntryFilter::All, Ordering::Descending, page)
+                .await?;
+            assert_eq!(first.len(), 2);
+            assert_eq!(page.next_offset(first.len()), Some(2));
+
+            let page = Page::new(4, 2);
+            let last = db
+                .get_entries_page(EntryFilter::All, Ordering::Descending, page)
+                .await?;
+            assert_eq!(last.len(), 1);
+            assert_eq!(page.next_offset(last.len()), None);
+
+            // same published time everywhere, so pages only stay apart by id
+            let all = db
+                .get_entries(EntryFilter::All, Ordering::Descending)
+                .await?;
+            assert_eq!(all.len(), 5);
+            assert_eq!(first[0].id, all[0].id);
+            assert_eq!(last[0].id, all[4].id);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn flag_dead_links() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = Entry::new(
+                "my-entry",
+                "Cool Post".to_string(),
+                "https://content.com/1".to_string(),
+                "".to_string(),
+                None,
+            );
+            let id = entry.id.clone();
+            db.add_entries(std::iter::once(entry)).await?;
+            assert!(!db.get_entry(&id).await?.unwrap().link_dead);
+
+            db.set_link_status(&id, Some(404), Some(true)).await?;
+            let e = db.get_entry(&id).await?.unwrap();
+            assert!(e.link_dead);
+            assert_eq!(
+                e.archived_link(),
+                "https://web.archive.org/web/https://content.com/1"
+            );
+
+            // a check that couldn't reach it doesn't change the verdict
+            db.set_link_status(&id, None, None).await?;
+            assert!(db.get_entry(&id).await?.unwrap().link_dead);
+
+            db.set_link_status(&id, Some(200), Some(false)).await?;
+            assert!(!db.get_entry(&id).await?.unwrap().link_dead);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn mark_all_entries_read() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            db.add_feeds(
+                vec![
+                    Feed::new(
+                        "Blog".to_string(),
+                        "https://blog.com".to_string(),
+                        "https://blog.com/feed".to_string(),
+                        "Tech".to_string(),
+                    ),
+                    Feed::new(
+                        "News".to_string(),
+                        "https://news.com".to_string(),
+                        "https://news.com/feed".to_string(),
+                        "".to_string(),
+                    ),
+                ]
+                .into_iter(),
+            )
+            .await?;
+            let entries = ["Blog", "Blog", "News"]
+                .iter()
+                .enumerate()
+                .map(|(i, feed)| {
+                    let mut e = Entry::new(
+                        &format!("entry-{}", i),
+                        format!("Post {}", i),
+                        format!("https://content.com/{}", i),
+                        "".to_string(),
+                        None,
+                    );
+                    e.feed = feed.to_string();
+                    e
+                });
+            db.add_entries(entries).await?;
+
+            assert_eq!(db.mark_all_read(None, Some("tech")).await?, 2);
+            assert_eq!(db.mark_all_read(Some("Blog"), None).await?, 0);
+            let unread = db
+                .get_entries(EntryFilter::Unread, Ordering::Ascending)
+                .await?;
+            assert_eq!(unread.len(), 1);
+            assert_eq!(unread[0].feed, "News");
+
+            assert_eq!(db.mark_all_read(None, None).await?, 1);
+            assert!(db
+                .get_entries(EntryFilter::Unread, Ordering::Ascending)
+                .await?
+                .is_empty());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn entry_state_is_per_user() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = |id: &str| {
+                Entry::new(
+                    id,
+                    id.to_string(),
+                    format!("https://content.com/{}", id),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                )
+            };
+            db.add_entries(std::iter::once(entry("before"))).await?;
+            let partner = db.add_user("partner", "hash").await?;
+            db.add_entries(std::iter::once(entry("after"))).await?;
+            let ids: Vec<String> = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?
+                .into_iter()
+                .map(|e| e.id)
+                .collect();
+
+            user::scope(partner, async {
+                // both entries start unread for someone new, whenever they came in
+                assert_eq!(
+                    db.get_entries(EntryFilter::Unread, Ordering::Ascending)
+                        .await?
+                        .len(),
+                    2
+                );
+                db.mark_entries_read(ids.clone()).await?;
+                db.toggle_entry_starred(&ids[0]).await?;
+                anyhow::Ok(())
+            })
+            .await?;
+            let unread = db
+                .get_entries(EntryFilter::Unread, Ordering::Ascending)
+                .await?;
+            assert_eq!(unread.len(), 2);
+            assert!(db.get_starred_entries().await?.is_empty());
+            assert_eq!(db.get_entries_starred_by_anyone().await?.len(), 1);
+
+            // kept until the owner has read them too, and while anyone has one starred
+            let later = UtcTime(Utc::now() + chrono::Duration::days(1));
+            assert_eq!(db.prune_entries(later.clone()).await?, 0);
+            db.mark_entries_read(ids.clone()).await?;
+            assert_eq!(db.prune_entries(later).await?, 1);
+
+            assert!(db.remove_user(partner).await?);
+            assert_eq!(db.get_users().await?.len(), 1);
+            assert!(db.get_entries_starred_by_anyone().await?.is_empty());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn filter_combined_views() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = |name: &str, category: &str| {
+                Feed::new(
+                    name.to_string(),
+                    format!("https://{}.com", name),
+                    format!("https://{}.com/rss", name),
+                    category.to_string(),
+                )
+            };
+            db.add_feeds(
+                vec![
+                    feed("blog", "blogs"),
+                    feed("letter", "Newsletters"),
+                    feed("paper", "news"),
+                ]
+                .into_iter(),
+            )
+            .await?;
+            let entries = ["blog", "letter", "paper"].map(|name| {
+                let mut e = Entry::new(
+                    name,
+                    format!("{} post", name),
+                    format!("https://{}.com/1", name),
+                    "".to_string(),
+                    None,
+                );
+                e.feed = name.to_string();
+                e
+            });
+            db.add_entries(entries.into_iter()).await?;
+
+            let feeds_in = |view: &str| {
+                let db = db.clone();
+                let filter: EntryFilter = format!("view:{}", view).parse().unwrap();
+                async move {
+                    let entries = db.get_entries(filter, Ordering::Ascending).await?;
+                    let mut feeds: Vec<String> = entries.into_iter().map(|e| e.feed).collect();
+                    feeds.sort();
+                    Ok::<_, anyhow::Error>(feeds)
+                }
+            };
+            db.add_combined_view(&CombinedView {
+                id: "morning".to_string(),
+                name: "Morning read".to_string(),
+                categories: vec!["blogs".to_string(), "newsletters".to_string()],
+                excluded: vec![],
+            })
+            .await?;
+            db.add_combined_view(&CombinedView {
+                id: "quiet".to_string(),
+                name: "No news".to_string(),
+                categories: vec![],
+                excluded: vec!["news".to_string()],
+            })
+            .await?;
+            assert_eq!(feeds_in("morning").await?, ["blog", "letter"]);
+            assert_eq!(feeds_in("quiet").await?, ["blog", "letter"]);
+
+            let views = db.get_combined_views().await?;
+            assert_eq!(views.len(), 2);
+            assert_eq!(views[0].name, "Morning read");
+            assert_eq!(views[0].categories, ["blogs", "newsletters"]);
+            assert_eq!(views[1].excluded, ["news"]);
+
+            assert!(db.remove_combined_view("quiet").await?);
+            assert!(db.get_combined_view("quiet").await?.is_none());
+            assert!(!db.remove_combined_view("quiet").await?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn filter_saved_filters() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = |name: &str, category: &str| {
+                Feed::new(
+                    name.to_string(),
+                    format!("https://{}.com", name),
+                    format!("https://{}.com/rss", name),
+                    category.to_string(),
+                )
+            };
+            db.add_feeds(vec![feed("blog", "tech"), feed("paper", "news")].into_iter())
+                .await?;
+            let old: UtcTime = (Utc::now() - chrono::Duration::days(3)).into();
+            let entries = [
+                ("blog-new", "blog", UtcTime(Utc::now())),
+                ("blog-old", "blog", old.clone()),
+                ("paper-new", "paper", UtcTime(Utc::now())),
+            ]
+            .map(|(id, feed, published)| {
+                let mut e = Entry::new(
+                    id,
+                    id.to_string(),
+                    format!("https://{}.com/{}", feed, id),
+                    "".to_string(),
+                    Some(published),
+                );
+                e.feed = feed.to_string();
+                e
+            });
+            db.add_entries(entries.into_iter()).await?;
+            for e in db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?
+            {
+                db.toggle_entry_starred(&e.id).await?;
+            }
+
+            db.add_saved_filter(&SavedFilter {
+                id: "today".to_string(),
+                name: "Today in tech".to_string(),
+                unread: true,
+                starred: true,
+                category: Some("Tech".to_string()),
+                since_hours: Some(24),
+                ..Default::default()
+            })
+            .await?;
+            let filter: EntryFilter = "saved:today".parse()?;
+            let titles: Vec<String> = db
+                .get_entries(filter, Ordering::Ascending)
+                .await?
+                .into_iter()
+                .map(|e| e.title)
+                .collect();
+            assert_eq!(titles, ["blog-new"]);
+
+            // someone else's filters aren't theirs to use
+            let partner = db.add_user("partner", "hash").await?;
+            user::scope(partner, async {
+                assert!(db.get_saved_filters().await?.is_empty());
+                assert!(db
+                    .get_entries(EntryFilter::Saved("today".to_string()), Ordering::Ascending)
+                    .await?
+                    .is_empty());
+                assert!(!db.remove_saved_filter("today").await?);
+                anyhow::Ok(())
+            })
+            .await?;
+            assert!(db.remove_saved_filter("today").await?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn prune_old_read_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let old: UtcTime = (Utc::now() - chrono::Duration::days(90)).into();
+            let entry = |id: &str, published: Option<UtcTime>| {
+                Entry::new(
+                    id,
+                    id.to_string(),
+                    format!("https://content.com/{}", id),
+                    "".to_string(),
+                    published,
+                )
+            };
+            let entries = vec![
+                entry("old-read", Some(old.clone())),
+                entry("old-starred", Some(old.clone())),
+                entry("old-unread", Some(old.clone())),
+                entry("new-read", Some(Utc::now().into())),
+                entry("undated-read", None),
+            ];
+            let ids: HashMap<String, String> = entries
+                .iter()
+                .map(|e| (e.title.clone(), e.id.clone()))
+                .collect();
+            db.add_entries(entries.into_iter()).await?;
+            for title in ["old-read", "old-starred", "new-read", "undated-read"] {
+                db.toggle_entry_read(&ids[title]).await?;
+            }
+            db.toggle_entry_starred(&ids["old-starred"]).await?;
+
+            let cutoff = (Utc::now() - chrono::Duration::days(30)).into();
+            assert_eq!(db.prune_entries(cutoff).await?, 1);
+            assert!(db.get_entry(&ids["old-read"]).await?.is_none());
+            for title in ["old-starred", "old-unread", "new-read", "undated-read"] {
+                assert!(
+                    db.get_entry(&ids[title]).await?.is_some(),
+                    "{} was pruned",
+                    title
+                );
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn resurface_old_starred() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let old: UtcTime = (Utc::now() - chrono::Duration::days(60)).into();
+            let entry = |id: &str, published: UtcTime| {
+                Entry::new(
+                    id,
+                    id.to_string(),
+                    format!("https://content.com/{}", id),
+                    "".to_string(),
+                    Some(published),
+                )
+            };
+            let entries = vec![
+                entry("old-starred", old.clone()),
+                entry("old-unstarred", old.clone()),
+                entry("new-starred", Utc::now().into()),
+            ];
+            let ids: HashMap<String, String> = entries
+                .iter()
+                .map(|e| (e.title.clone(), e.id.clone()))
+                .collect();
+            db.add_entries(entries.into_iter()).await?;
+            db.toggle_entry_starred(&ids["old-starred"]).await?;
+            db.toggle_entry_starred(&ids["new-starred"]).await?;
+
+            let month_ago: UtcTime = (Utc::now() - chrono::Duration::days(30)).into();
+            assert_eq!(
+                db.resurface_starred(5, month_ago.clone(), month_ago.clone())
+                    .await?,
+                1
+            );
+            let es = db
+                .get_entries(EntryFilter::Resurfaced, Ordering::Ascending)
+                .await?;
+            assert_eq!(es.len(), 1);
+            assert_eq!(es[0].title, "old-starred");
+            // shown recently, so not picked again
+            assert_eq!(
+                db.resurface_starred(5, month_ago.clone(), month_ago)
+                    .await?,
+                0
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn search_entries_by_text() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = |id: &str, title: &str, content: &str| {
+                let mut e = Entry::new(
+                    id,
+                    title.to_string(),
+                    format!("https://content.com/{}", id),
+                    "".to_string(),
+                    None,
+                );
+                e.feed = "Blog".to_string();
+                e.content = Some(content.to_string());
+                e
+            };
+            db.add_entries(
+                vec![
+                    entry("one", "Rust async traits", "<p>Finally stable</p>"),
+                    entry(
+                        "two",
+                        "Gardening",
+                        "<p>Tomatoes, and some rust on the leaves</p>",
+                    ),
+                    entry("three", "Cooking", "<p>Nothing to see</p>"),
+                ]
+                .into_iter(),
+            )
+            .await?;
+
+            let found = db.search_entries("rust", Page::default()).await?;
+            assert_eq!(found.len(), 2);
+            let found = db
+                .get_entries("search:rust stable".parse()?, Ordering::Descending)
+                .await?;
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].title, "Rust async traits");
+            // operators and quotes are just text
+            assert!(db
+                .search_entries("\"rust OR", Page::default())
+                .await?
+                .is_empty());
+            assert!(db.search_entries("  ", Page::default()).await?.is_empty());
+
+            // edits are picked up by the index
+            db.add_entries(std::iter::once(entry(
+                "three",
+                "Cooking",
+                "<p>Rust-free pans</p>",
+            )))
+            .await?;
+            let found = db.search_entries("pans", Page::default()).await?;
+            assert_eq!(found.len(), 1);
+            assert!(db
+                .search_entries("nothing", Page::default())
+                .await?
+                .is_empty());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn keep_edited_content() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = |content: &str| {
+                let mut e = Entry::new(
+                    "story",
+                    "Breaking".to_string(),
+                    "https://news.com/story".to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                );
+                e.content = Some(content.to_string());
+                e
+            };
+            db.add_entries(std::iter::once(entry("first draft")))
+                .await?;
+            db.add_entries(std::iter::once(entry("first draft")))
+                .await?;
+            assert_eq!(
+                db.add_entries(std::iter::once(entry("corrected"))).await?,
+                0
+            );
+
+            let id = entry("").id;
+            let e = db.get_entry(&id).await?.unwrap();
+            assert_eq!(e.content.as_deref(), Some("corrected"));
+            assert_eq!(e.revisions, 1);
+            let revisions = db.get_entry_revisions(&id).await?;
+            assert_eq!(revisions.len(), 1);
+            assert_eq!(revisions[0].content, "first draft");
+            Ok(())
+        })
+        .await
+    }
+
+    #[test]
+    fn title_key_ignores_minor_edits() {
+        assert_eq!(
+            title_key("Rust 2.0 Released!"),
+            title_key("UPDATED: rust 2.0 released")
+        );
+        assert_eq!(
+            title_key("Released: Rust 2.0"),
+            title_key("Rust 2.0 released")
+        );
+        assert_ne!(
+            title_key("Rust 2.0 released"),
+            title_key("Rust 2.1 released")
+        );
+    }
+
+    #[tokio::test]
+    async fn collapse_duplicate_titles() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let now = Utc::now();
+            let entries = vec![
+                Entry::new(
+                    "original",
+                    "Big News Today".to_string(),
+                    "https://content.com/1".to_string(),
+                    "".to_string(),
+                    Some(now.into()),
+                ),
+                Entry::new(
+                    "repost",
+                    "UPDATE: big news today".to_string(),
+                    "https://content.com/2".to_string(),
+                    "".to_string(),
+                    Some((now + chrono::Duration::hours(2)).into()),
+                ),
+                Entry::new(
+                    "much-later",
+                    "Big news today".to_string(),
+                    "https://content.com/3".to_string(),
+                    "".to_string(),
+                    Some((now + chrono::Duration::days(7)).into()),
+                ),
+            ];
+
+            db.add_entries(entries.into_iter()).await?;
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es.len(), 3);
+            assert_eq!(es[0].duplicate_of, None);
+            assert_eq!(es[1].duplicate_of.as_ref(), Some(&es[0].id));
+            assert_eq!(es[2].duplicate_of, None);
+
+            let unread = db
+                .get_entries(EntryFilter::Unread, Ordering::Ascending)
+                .await?;
+            assert_eq!(unread.len(), 2);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn merge_copies_of_stored_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = |id: &str, feed: &str, title: &str, link: &str| {
+                let mut entry = Entry::new(
+                    id,
+                    title.to_string(),
+                    link.to_string(),
+                    "".to_string(),
+                    None,
+                );
+                entry.feed = feed.to_string();
+                entry
+            };
+            db.add_entries(
+                vec![
+                    entry(
+                        "a",
+                        "Blog",
+                        "Big News",
+                        "https://blog.com/news/?utm_source=rss",
+                    ),
+                    entry("b", "Blog", "Small News", "https://blog.com/other"),
+                ]
+                .into_iter(),
+            )
+            .await?;
+            let mut syndicated = entry("c", "Planet", "Big News", "http://www.blog.com/news#top");
+            syndicated.starred = true;
+            let new_guid = entry("d", "Blog", "Big News", "https://blog.com/news");
+            // the same page, but a different entry from the same feed
+            let same_page = entry("e", "Blog", "Bigger News", "https://blog.com/news");
+            assert_eq!(
+                db.add_entries(vec![syndicated, new_guid, same_page].into_iter())
+                    .await?,
+                1
+            );
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es.len(), 3);
+            assert!(es.iter().all(|e| e.feed == "Blog"));
+            let big: Vec<&Entry> = es.iter().filter(|e| e.title == "Big News").collect();
+            assert_eq!(big.len(), 1);
+            assert!(big[0].starred);
+
+            assert_eq!(
+                link_key("HTTPS://WWW.Blog.com/a/?b=2&utm_medium=x&a=1#c").as_deref(),
+                Some("blog.com/a?a=1&b=2")
+            );
+            assert!(content_key("too short").is_none());
+            let long = "word ".repeat(50);
+            assert_eq!(
+                content_key(&long),
+                content_key(&long.to_uppercase().replace(' ', "\n "))
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn rotate_credentials() -> Result<(), anyhow::Error> {
         let db: DB = connect(ConnectionBacking::Memory).await?;
         db.init().await?;
-        let feeds = vec![
-            Feed {
-                id: base64::encode_config("HackerNews", base64::URL_SAFE),
-                name: "HackerNews".to_string(),
-                site_url: "https://news.ycombinator.com".to_string(),
-                feed_url: "https://news.ycombinator.com/rss".to_string(),
-                last_fetched: Some(Utc::now().into()),
-                fetch_error: None,
-                category: "tech".to_string(),
-            },
-            Feed {
-                id: base64::encode_config("Product Hunt", base64::URL_SAFE),
-                name: "Product Hunt".to_string(),
-                site_url: "https://www.producthunt.com".to_string(),
-                feed_url: "https://www.producthunt.com/feed".to_string(),
-                last_fetched: None,
-                fetch_error: None,
-                category: "tech".to_string(),
-            },
-        ];
+        let old: SecretKey = base64::encode([1u8; 32]).parse()?;
+        let new: SecretKey = base64::encode([2u8; 32]).parse()?;
+        db.set_credential(&old, "feed:blog", "hunter2").await?;
+        db.set_credential(&old, "pinboard", "user:token").await?;
 
-        db.add_feeds(feeds.into_iter()).await?;
-        let f = db.get_feeds().await?;
-        assert_eq!(f.len(), 2);
-        assert_eq!(f[0].name, "HackerNews");
+        assert!(db.rotate_credentials(&new, &old).await.is_err());
+        assert_eq!(db.rotate_credentials(&old, &new).await?, 2);
+        assert!(db.get_credential(&old, "feed:blog").await.is_err());
+        assert_eq!(
+            db.get_credential(&new, "feed:blog").await?.as_deref(),
+            Some("hunter2")
+        );
+        assert!(db.remove_credential("pinboard").await?);
+        assert_eq!(db.get_credential(&new, "pinboard").await?, None);
         Ok(())
     }
 
     #[tokio::test]
-    async fn add_list_entries() -> Result<(), anyhow::Error> {
+    async fn encrypt_webhook_secrets() -> Result<(), anyhow::Error> {
         let db: DB = connect(ConnectionBacking::Memory).await?;
         db.init().await?;
-        let entries = vec![
-            Entry::new(
-                "my-entry",
-                "Cool Post".to_string(),
-                "https://content.com/1".to_string(),
+        let key: SecretKey = base64::encode([4u8; 32]).parse()?;
+        let webhook = Webhook {
+            id: "hook".to_string(),
+            url: "https://hooks.example.com/a".to_string(),
+            ..Default::default()
+        };
+        db.add_webhook(&key, &webhook, "s3cret").await?;
+
+        let mut rows = db
+            .main_conn
+            .query(
+                "SELECT webhooks.secret, credentials.value FROM webhooks, credentials
+                WHERE credentials.name = 'webhook:' || webhooks.id",
+                (),
+            )
+            .await?;
+        let row = rows.next().await?.unwrap();
+        assert_eq!(row.get::<String>(0)?, "");
+        assert!(!row.get::<String>(1)?.contains("s3cret"));
+        assert_eq!(db.get_webhook_secret(&key, "hook").await?, "s3cret");
+
+        db.main_conn
+            .execute(
+                "INSERT INTO webhooks (id, url, secret) VALUES ('old', 'https://old.com', 'plain')",
+                (),
+            )
+            .await?;
+        assert_eq!(db.seal_webhook_secrets(&key).await?, 1);
+        assert_eq!(db.get_webhook_secret(&key, "old").await?, "plain");
+        assert_eq!(db.seal_webhook_secrets(&key).await?, 0);
+
+        assert!(db.remove_webhook("hook").await?);
+        assert!(db.get_webhook_secret(&key, "hook").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn filter_rules_on_new_entries() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/feed.xml".to_string(),
                 "".to_string(),
-                Some(Utc::now().into()),
-            ),
-            Entry::new(
-                "your-entry",
-                "Gross Post".to_string(),
-                "https://content.com/2".to_string(),
+            );
+            let feed_id = feed.id.clone();
+            db.add_feeds(std::iter::once(feed)).await?;
+            let rule = |pattern: &str, feed_id: Option<String>, action: &str| FilterRule {
+                id: pattern.to_string(),
+                pattern: pattern.to_string(),
+                field: "title".to_string(),
+                feed_id,
+                action: action.to_string(),
+                ..Default::default()
+            };
+            db.add_filter_rule(&rule("Sponsored", Some(feed_id.clone()), "drop"))
+                .await?;
+            db.add_filter_rule(&rule("rust", None, "star")).await?;
+            assert_eq!(
+                db.get_filter_rules().await?[1].feed.as_deref(),
+                Some("Blog")
+            );
+
+            let entries = [
+                ("Blog", "SPONSORED: buy things"),
+                ("Other", "Sponsored by nobody"),
+                ("Blog", "Rust 2.0"),
+            ]
+            .iter()
+            .map(|(feed, title)| {
+                let mut entry = Entry::new(
+                    title,
+                    title.to_string(),
+                    format!("https://content.com/{}", title),
+                    "".to_string(),
+                    None,
+                );
+                entry.feed = feed.to_string();
+                entry
+            });
+            assert_eq!(db.add_entries(entries).await?, 2);
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es[0].title, "Sponsored by nobody");
+            assert!(!es[0].starred);
+            assert!(es[1].starred);
+
+            db.remove_feed(feed_id).await?;
+            assert_eq!(db.get_filter_rules().await?.len(), 1);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn cache_feed_icons() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feed = Feed::new(
+            "Blog".to_string(),
+            "https://blog.com".to_string(),
+            "https://blog.com/feed.xml".to_string(),
+            "".to_string(),
+        );
+        let id = feed.id.clone();
+        db.add_feeds(std::iter::once(feed)).await?;
+        let hour_ago = UtcTime(Utc::now() - chrono::Duration::hours(1));
+        assert!(!db.icon_fetched_since(&id, hour_ago.clone()).await?);
+
+        let icon = Icon {
+            content_type: Some("image/png".to_string()),
+            data: Some(vec![0x89, b'P', b'N', b'G']),
+        };
+        db.set_icon(&id, icon).await?;
+        assert!(db.icon_fetched_since(&id, hour_ago.clone()).await?);
+        let cached = db.get_icon(&id).await?.unwrap();
+        assert_eq!(cached.data.as_deref(), Some(&[0x89, b'P', b'N', b'G'][..]));
+
+        db.set_feed_icon(&id, Some("https://blog.com/logo.png".to_string()))
+            .await?;
+        assert!(db.get_icon(&id).await?.is_none());
+        db.set_icon(&id, Icon::default()).await?;
+        assert!(db.get_icon(&id).await?.unwrap().data.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn move_redirected_feeds() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let feeds = ["old", "taken"].map(|name| {
+            Feed::new(
+                name.to_string(),
+                "https://blog.com".to_string(),
+                format!("https://blog.com/{}.xml", name),
                 "".to_string(),
-                Some(Utc::now().into()),
-            ),
-        ];
+            )
+        });
+        let id = feeds[0].id.clone();
+        db.add_feeds(feeds.into_iter()).await?;
+        db.set_feed_validators(&id, Some("\"v1\"".to_string()), None, None)
+            .await?;
+
+        let new = Some("https://blog.com/new.xml");
+        assert_eq!(db.record_feed_redirect(&id, new).await?, 1);
+        assert_eq!(db.record_feed_redirect(&id, new).await?, 2);
+        assert_eq!(
+            db.record_feed_redirect(&id, Some("https://elsewhere.com"))
+                .await?,
+            1
+        );
+        assert_eq!(db.record_feed_redirect(&id, None).await?, 0);
+        assert_eq!(db.record_feed_redirect(&id, new).await?, 1);
+
+        assert!(!db.move_feed(&id, "https://blog.com/taken.xml").await?);
+        assert!(db.move_feed(&id, "https://blog.com/new.xml").await?);
+        let moved = db.get_feed(&id).await?.unwrap();
+        assert_eq!(moved.feed_url, "https://blog.com/new.xml");
+        assert!(moved.etag.is_none());
+        assert_eq!(db.record_feed_redirect(&id, new).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hidden_entries_left_out() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries: Vec<Entry> = ["first", "second"]
+                .iter()
+                .map(|title| {
+                    Entry::new(
+                        title,
+                        title.to_string(),
+                        format!("https://content.com/{}", title),
+                        "".to_string(),
+                        None,
+                    )
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+            assert!(db.toggle_entry_hidden(&entries[0].id).await?);
+
+            for filter in [EntryFilter::All, EntryFilter::Unread] {
+                let es = db.get_entries(filter, Ordering::Ascending).await?;
+                assert_eq!(es.len(), 1);
+                assert_eq!(es[0].title, "second");
+            }
+            assert_eq!(db.search_entries("first", Page::default()).await?.len(), 0);
+            let hidden = db
+                .get_entries(EntryFilter::Hidden, Ordering::Ascending)
+                .await?;
+            assert_eq!(hidden.len(), 1);
+            assert!(hidden[0].hidden);
+
+            // still there, so fetching it again doesn't bring it back
+            assert_eq!(db.add_entries(entries.into_iter()).await?, 0);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn restore_dump() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/feed.xml".to_string(),
+                "Tech".to_string(),
+            );
+            db.add_feeds(std::iter::once(feed)).await?;
+            db.set_feed_compact(&db.get_feeds().await?[0].id, true)
+                .await?;
+            let entries: Vec<Entry> = ["first", "second"]
+                .iter()
+                .map(|title| {
+                    let mut entry = Entry::new(
+                        title,
+                        title.to_string(),
+                        format!("https://content.com/{}", title),
+                        "".to_string(),
+                        None,
+                    );
+                    entry.feed = "Blog".to_string();
+                    entry
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+            db.toggle_entry_read(&entries[0].id).await?;
+            db.toggle_entry_starred(&entries[1].id).await?;
+            let dump = Dump {
+                feeds: db.get_feeds().await?,
+                entries: db.get_entries_after(0, 10).await?,
+            };
+            let json = serde_json::to_string(&dump)?;
+
+            let fresh: DB = connect(ConnectionBacking::Memory).await?;
+            fresh.init().await?;
+            assert_eq!(fresh.restore(serde_json::from_str(&json)?).await?, (1, 2));
+            let feeds = fresh.get_feeds().await?;
+            assert_eq!(feeds[0].category, "Tech");
+            assert!(feeds[0].compact);
+            let restored = fresh.get_entries_after(0, 10).await?;
+            assert!(restored[0].read && !restored[0].starred);
+            assert!(!restored[1].read && restored[1].starred);
+
+            // restoring again adds nothing new
+            assert_eq!(fresh.restore(serde_json::from_str(&json)?).await?, (1, 0));
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn feed_stats_count_unread() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feeds = [("busy", "News"), ("quiet", "News"), ("loose", "")];
+            db.add_feeds(feeds.iter().map(|(name, category)| {
+                Feed::new(
+                    name.to_string(),
+                    format!("https://{}.com", name),
+                    format!("https://{}.com/feed.xml", name),
+                    category.to_string(),
+                )
+            }))
+            .await?;
+            let entries: Vec<Entry> = ["first", "second", "third"]
+                .iter()
+                .map(|title| {
+                    let mut entry = Entry::new(
+                        title,
+                        title.to_string(),
+                        format!("https://content.com/{}", title),
+                        "".to_string(),
+                        None,
+                    );
+                    entry.feed = "busy".to_string();
+                    entry
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+            db.toggle_entry_read(&entries[0].id).await?;
+            db.toggle_entry_hidden(&entries[1].id).await?;
+
+            let stats = db.get_feed_stats().await?;
+            assert_eq!(stats.len(), 3);
+            let feeds = db.get_feeds().await?;
+            let unread = |name: &str| {
+                let feed = feeds.iter().find(|f| f.name == name).unwrap();
+                let stat = stats.iter().find(|s| s.feed_id == feed.id).unwrap();
+                (stat.category.as_str(), stat.unread)
+            };
+            assert_eq!(unread("busy"), ("News", 1));
+            assert_eq!(unread("quiet"), ("News", 0));
+            assert_eq!(unread("loose"), ("", 0));
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn podcast_positions() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let enclosures = [
+                ("episode", Some("audio/mpeg")),
+                ("photo", Some("image/jpeg")),
+                ("article", None),
+            ];
+            let entries: Vec<Entry> = enclosures
+                .iter()
+                .map(|(title, kind)| {
+                    let mut entry = Entry::new(
+                        title,
+                        title.to_string(),
+                        format!("https://content.com/{}", title),
+                        "".to_string(),
+                        None,
+                    );
+                    entry.enclosure_type = kind.map(String::from);
+                    entry.enclosure_url =
+                        kind.map(|_| format!("https://content.com/{}.bin", title));
+                    entry
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+
+            let podcasts = db
+                .get_entries(EntryFilter::Podcasts, Ordering::Ascending)
+                .await?;
+            assert_eq!(podcasts.len(), 1);
+            assert_eq!(podcasts[0].title, "episode");
+            assert_eq!(podcasts[0].playback_position, 0.0);
+
+            assert!(db.set_playback_position(&entries[0].id, 754.5).await?);
+            let episode = db.get_entry(&entries[0].id).await?.unwrap();
+            assert_eq!(episode.playback_position, 754.5);
+            assert!(!db.set_playback_position("missing", 1.0).await?);
+            Ok(())
+        })
+        .await
+    }
 
-        db.add_entries(entries.into_iter()).await?;
-        let es = db
-            .get_entries(EntryFilter::All, Ordering::Ascending)
+    #[tokio::test]
+    async fn feed_status_history() -> Result<(), anyhow::Error> {
+        let db: DB = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        for i in 0..FETCH_STATUS_HISTORY + 5 {
+            let error = (i % 2 == 0).then(|| "response code not ok".to_string());
+            db.update_feed_status("feed".to_string(), Some(200), error)
+                .await?;
+        }
+        db.update_feed_status("other".to_string(), None, None)
             .await?;
-        assert_eq!(es.len(), 2);
-        assert_eq!(es[0].title, "Cool Post");
-        assert_ne!(es[0].id, "my-entry");
+
+        let statuses = db.get_feed_statuses().await?;
+        let history = &statuses["feed"];
+        assert_eq!(history.len(), FETCH_STATUS_HISTORY as usize);
+        assert!(!history.last().unwrap().ok());
+        assert_eq!(history[0].status, Some(200));
+        assert_eq!(statuses["other"].len(), 1);
+        assert_eq!(db.get_feed_history("feed").await?.len(), history.len());
+
+        let last = db.get_last_feed_status("feed").await?.unwrap();
+        assert!(!last.ok());
+        assert!(db.get_last_feed_status("missing").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn feed_status_history_persists() -> Result<(), anyhow::Error> {
+        let path =
+            std::env::temp_dir().join(format!("feedreader-history-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        {
+            let db: DB = connect(ConnectionBacking::File(path.clone())).await?;
+            db.init().await?;
+            db.update_feed_status("feed".to_string(), Some(500), Some("boom".to_string()))
+                .await?;
+        }
+
+        let db: DB = connect(ConnectionBacking::File(path.clone())).await?;
+        db.init().await?;
+        let last = db.get_last_feed_status("feed").await?.unwrap();
+        assert_eq!(last.status, Some(500));
+
+        db.remove_feed("feed".to_string()).await?;
+        assert!(db.get_last_feed_status("feed").await?.is_none());
+        let _ = std::fs::remove_file(&path);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn archive_months() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let published = |y, m, d| Some(Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap().into());
+            let entries = vec![
+                Entry::new(
+                    "one",
+                    "January".to_string(),
+                    "https://content.com/1".to_string(),
+                    "".to_string(),
+                    published(2024, 1, 31),
+                ),
+                Entry::new(
+                    "two",
+                    "December".to_string(),
+                    "https://content.com/2".to_string(),
+                    "".to_string(),
+                    published(2023, 12, 1),
+                ),
+                Entry::new(
+                    "three",
+                    "Also December".to_string(),
+                    "https://content.com/3".to_string(),
+                    "".to_string(),
+                    published(2023, 12, 31),
+                ),
+            ];
+            db.add_entries(entries.into_iter()).await?;
+
+            let months = db.get_archive_months().await?;
+            assert_eq!(months.len(), 2);
+            assert_eq!(
+                (months[0].year, months[0].month, months[0].count),
+                (2024, 1, 1)
+            );
+            assert_eq!(
+                (months[1].year, months[1].month, months[1].count),
+                (2023, 12, 2)
+            );
+
+            let december = db
+                .get_entries("month:2023-12".parse()?, Ordering::Ascending)
+                .await?;
+            assert_eq!(december.len(), 2);
+            assert_eq!(december[0].title, "December");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn read_counts_by_day() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries = vec![
+                Entry::new(
+                    "one",
+                    "One".to_string(),
+                    "https://content.com/1".to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                ),
+                Entry::new(
+                    "two",
+                    "Two".to_string(),
+                    "https://content.com/2".to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                ),
+            ];
+            db.add_entries(entries.into_iter()).await?;
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            for e in es.iter() {
+                db.toggle_entry_read(&e.id).await?;
+            }
+            // toggling back to unread shouldn't count
+            db.toggle_entry_read(&es[1].id).await?;
+
+            let since = (Utc::now() - chrono::Duration::days(1)).into();
+            let days = db.get_read_counts_by_day(since).await?;
+            assert_eq!(days.len(), 1);
+            assert_eq!(days[0].date, Utc::now().format("%Y-%m-%d").to_string());
+            assert_eq!(days[0].count, 1);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn mark_entries_read_batch() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries = (0..3).map(|i| {
+                Entry::new(
+                    &format!("entry-{}", i),
+                    format!("Post {}", i),
+                    format!("https://content.com/{}", i),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                )
+            });
+            db.add_entries(entries).await?;
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+
+            let ids = vec![es[0].id.clone(), es[1].id.clone(), "missing".to_string()];
+            assert_eq!(db.mark_entries_read(ids.clone()).await?, 2);
+            // already read entries stay read
+            assert_eq!(db.mark_entries_read(ids).await?, 0);
+
+            let unread = db
+                .get_entries(EntryFilter::Unread, Ordering::Ascending)
+                .await?;
+            assert_eq!(unread.len(), 1);
+            assert_eq!(unread[0].id, es[2].id);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn merge_entry_state_from_dump() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db: DB = connect(ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entry = |id: &str, link: &str| {
+                Entry::new(
+                    id,
+                    format!("Post {}", id),
+                    link.to_string(),
+                    "".to_string(),
+                    Some(Utc::now().into()),
+                )
+            };
+            db.add_entries(
+                vec![entry("one", "https://content.com/1"), entry("two", "")].into_iter(),
+            )
+            .await?;
+
+            // the other instance saw the same post under a different guid
+            let mut by_link = entry("other-guid", "https://content.com/1");
+            by_link.read = true;
+            let mut by_id = entry("two", "");
+            by_id.starred = true;
+            let mut missing = entry("three", "");
+            missing.read = true;
+            assert_eq!(
+                db.merge_entry_state(vec![by_link, by_id, missing]).await?,
+                2
+            );
+
+            let es = db
+                .get_entries(EntryFilter::All, Ordering::Ascending)
+                .await?;
+            assert_eq!(es.len(), 2);
+            let one = es.iter().find(|e| e.title == "Post one").unwrap();
+            let two = es.iter().find(|e| e.title == "Post two").unwrap();
+            assert!(one.read && !one.starred);
+            assert!(!two.read && two.starred);
+            Ok(())
+        })
+        .await
+    }
 }
@@ -0,0 +1,134 @@
+//! Keeping what feeds sent when it couldn't be parsed, to see why and pass on
+//! to whoever publishes the feed or maintains the parser. Turned on by setting
+//! `FEED_DEBUG_DUMP_DIR`, for every feed or just the ones named or given by id
+//! in `FEED_DEBUG_FEEDS`.
+//!
+//! Each feed gets `<hash>.body`, the response exactly as it came, and
+//! `<hash>.txt`, saying which feed it was, where and when it came from and what
+//! went wrong. The hash is of the feed's id, which can be too long for a file name.
+//! Both are replaced on the feed's next failure, so they only take as much room
+//! as the feeds that are failing.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use tokio::fs;
+use tracing::{error, info};
+
+use crate::{db, Feed};
+
+/// Where bodies that couldn't be parsed are kept, and for which feeds.
+#[derive(Debug, Clone)]
+pub struct BodyDumps {
+    dir: PathBuf,
+    /// Names or ids, every feed when empty
+    feeds: Vec<String>,
+}
+
+/// What came back with a body that couldn't be parsed.
+pub struct Failure<'a> {
+    pub status: Option<u16>,
+    pub content_type: Option<&'a str>,
+    pub error: &'a str,
+}
+
+impl BodyDumps {
+    pub fn new(dir: impl Into<PathBuf>, feeds: Vec<String>) -> BodyDumps {
+        BodyDumps {
+            dir: dir.into(),
+            feeds,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn wants(&self, f: &Feed) -> bool {
+        self.feeds.is_empty() || self.feeds.iter().any(|n| *n == f.name || *n == f.id)
+    }
+
+    /// Keeps `body` if it's from a feed being debugged, logging rather than
+    /// failing when it can't be written, as the fetch has already failed.
+    pub async fn keep(&self, f: &Feed, body: &[u8], failure: Failure<'_>) {
+        if !self.wants(f) {
+            return;
+        }
+        match self.write(f, body, failure).await {
+            Ok(path) => info!("kept what {} sent in {}", f.name, path.display()),
+            Err(e) => error!("couldn't keep what {} sent: {:#}", f.name, e),
+        }
+    }
+
+    async fn write(&self, f: &Feed, body: &[u8], failure: Failure<'_>) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("couldn't create {}", self.dir.display()))?;
+        let name = file_name(f);
+        let body_path = self.dir.join(format!("{}.body", name));
+        fs::write(&body_path, body)
+            .await
+            .with_context(|| format!("couldn't write {}", body_path.display()))?;
+        let details = format!(
+            "feed: {}\nid: {}\nurl: {}\nfetched: {}\nstatus: {}\ncontent type: {}\nbytes: {}\nerror: {}\n",
+            f.name,
+            f.id,
+            f.feed_url,
+            Utc::now().to_rfc3339(),
+            failure.status.map(|s| s.to_string()).unwrap_or_default(),
+            failure.content_type.unwrap_or_default(),
+            body.len(),
+            failure.error
+        );
+        let details_path = self.dir.join(format!("{}.txt", name));
+        fs::write(&details_path, details)
+            .await
+            .with_context(|| format!("couldn't write {}", details_path.display()))?;
+        Ok(body_path)
+    }
+}
+
+/// What a feed's files are called, the same every time so a failure replaces the last.
+fn file_name(f: &Feed) -> String {
+    format!("{:016x}", db::stable_hash(f.id.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn keep_bodies_of_debugged_feeds() {
+        let dir = std::env::temp_dir().join(format!("feedreader-dumps-{}", std::process::id()));
+        let dumps = BodyDumps::new(&dir, vec!["Broken".to_string()]);
+        let broken = Feed::new(
+            "Broken".to_string(),
+            "https://broken.example.com".to_string(),
+            "https://broken.example.com/feed".to_string(),
+            String::new(),
+        );
+        let other = Feed::new(
+            "Other".to_string(),
+            "https://other.example.com".to_string(),
+            "https://other.example.com/feed".to_string(),
+            String::new(),
+        );
+        let failure = || Failure {
+            status: Some(200),
+            content_type: Some("text/html"),
+            error: "no root element",
+        };
+        dumps.keep(&broken, b"<html>not a feed", failure()).await;
+        dumps.keep(&other, b"<html>not one either", failure()).await;
+
+        let name = file_name(&broken);
+        let body = std::fs::read(dir.join(format!("{}.body", name))).unwrap();
+        assert_eq!(body, b"<html>not a feed");
+        let details = std::fs::read_to_string(dir.join(format!("{}.txt", name))).unwrap();
+        assert!(details.contains(&format!("id: {}\n", broken.id)));
+        assert!(details.contains("url: https://broken.example.com/feed\n"));
+        assert!(details.contains("error: no root element\n"));
+        assert!(!dir.join(format!("{}.body", file_name(&other))).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,570 @@
+//! A daily or weekly email of what's come in unread since the last one, from
+//! the categories that opt into it on the categories page. It's turned on with
+//! `FEED_DIGEST=daily` or `weekly` and goes to the addresses in `FEED_DIGEST_TO`.
+//!
+//! Mail is sent over SMTP to `FEED_SMTP_HOST`, upgraded with STARTTLS unless
+//! `FEED_SMTP_TLS` says `tls` for a port that starts out encrypted, or `none` for
+//! a relay on the same host. With `FEED_SMTP_USERNAME` and `FEED_SMTP_PASSWORD`
+//! it logs in with AUTH PLAIN or LOGIN, whichever the server offers.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use askama_axum::Template;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{error, info};
+
+use crate::maintenance::ReadOnly;
+use crate::{db, public_url, Entry};
+
+/// How often to check whether a digest is due.
+const CHECK_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How long the SMTP server gets to take a digest.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Settings for when the last digest went out, and the row id of the last
+/// entry it covered.
+const SENT_AT: &str = "digest_sent_at";
+const SENT_THROUGH: &str = "digest_sent_through";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+impl FromStr for Frequency {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Frequency> {
+        match s {
+            "daily" => Ok(Frequency::Daily),
+            "weekly" => Ok(Frequency::Weekly),
+            _ => Err(anyhow!("{} isn't daily or weekly", s)),
+        }
+    }
+}
+
+impl Frequency {
+    fn period(self) -> chrono::Duration {
+        match self {
+            Frequency::Daily => chrono::Duration::days(1),
+            Frequency::Weekly => chrono::Duration::weeks(1),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+}
+
+/// How the connection to the SMTP server is encrypted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SmtpTls {
+    /// Upgraded with STARTTLS, refusing servers that don't offer it
+    #[default]
+    StartTls,
+    /// Encrypted from the start, like on port 465
+    Tls,
+    /// Not at all, only for a relay on the same host or network
+    None,
+}
+
+impl FromStr for SmtpTls {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<SmtpTls> {
+        match s {
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Tls),
+            "none" => Ok(SmtpTls::None),
+            _ => Err(anyhow!("{} isn't starttls, tls or none", s)),
+        }
+    }
+}
+
+impl SmtpTls {
+    /// The port usually used for each.
+    pub fn default_port(self) -> u16 {
+        match self {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Tls => 465,
+            SmtpTls::None => 25,
+        }
+    }
+}
+
+/// What the digest logs in to the SMTP server with.
+#[derive(Clone)]
+pub struct SmtpLogin {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for SmtpLogin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SmtpLogin({})", self.username)
+    }
+}
+
+/// What digests are sent through and who to.
+#[derive(Debug, Clone)]
+pub struct DigestConfig {
+    pub frequency: Frequency,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_tls: SmtpTls,
+    pub smtp_login: Option<SmtpLogin>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Email addresses separated by commas, as they're configured, at least one.
+pub fn parse_addresses(s: &str) -> Result<Vec<String>> {
+    let addresses: Vec<String> = s
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(parse_address)
+        .collect::<Result<_>>()?;
+    if addresses.is_empty() {
+        return Err(anyhow!("no addresses given"));
+    }
+    Ok(addresses)
+}
+
+/// A bare address like `me@example.com`, checked to be safe to put in headers
+/// and SMTP commands as it is.
+pub fn parse_address(s: &str) -> Result<String> {
+    let s = s.trim();
+    if !s.contains('@') || s.chars().any(|c| c.is_whitespace() || "<>,".contains(c)) {
+        return Err(anyhow!("{} isn't an email address", s));
+    }
+    Ok(s.to_string())
+}
+
+#[derive(Template)]
+#[template(path = "digest.html")]
+struct DigestTemplate {
+    subject: String,
+    sections: Vec<Section>,
+    /// Where the reader is, when there's a public URL to link to
+    reader: Option<String>,
+}
+
+/// The entries from one category.
+struct Section {
+    category: String,
+    entries: Vec<Entry>,
+}
+
+fn published_day(entry: &Entry) -> String {
+    entry
+        .published
+        .as_ref()
+        .map(|p| p.0.format("%a %b %-d").to_string())
+        .unwrap_or_default()
+}
+
+/// Sends a digest whenever one's due, checking every `CHECK_PERIOD`.
+pub async fn run(db: db::DB, config: DigestConfig, read_only: ReadOnly) {
+    let mut interval = time::interval(CHECK_PERIOD);
+    loop {
+        interval.tick().await;
+        if read_only.enabled() {
+            continue;
+        }
+        if let Err(e) = send_if_due(&db, &config).await {
+            error!(
+                "couldn't send the {} digest: {:#}",
+                config.frequency.name(),
+                e
+            );
+        }
+    }
+}
+
+/// Sends the owner's unread entries since the last digest, if it's been long
+/// enough and there are any. A digest that couldn't be sent is tried again on
+/// the next check.
+async fn send_if_due(db: &db::DB, config: &DigestConfig) -> Result<()> {
+    let now = Utc::now();
+    // read first, so entries stored while the digest's made wait for the next one
+    let through = db.last_item_id().await?;
+    let Some(sent_at) = db.get_setting(SENT_AT).await? else {
+        // the first digest has what comes in after they're turned on, not everything unread
+        return record(db, now, through).await;
+    };
+    let sent_at: DateTime<Utc> = sent_at.parse().context("invalid digest_sent_at")?;
+    if now - sent_at < config.frequency.period() {
+        return Ok(());
+    }
+    let after = match db.get_setting(SENT_THROUGH).await? {
+        Some(after) => after.parse().context("invalid digest_sent_through")?,
+        None => 0,
+    };
+    let entries = db.get_digest_entries(after, through).await?;
+    if !entries.is_empty() {
+        let count = entries.len();
+        let mail = message(config, entries, now)?;
+        time::timeout(SMTP_TIMEOUT, send(config, &mail))
+            .await
+            .map_err(|_| anyhow!("{} didn't take it in time", config.smtp_host))??;
+        info!(
+            "sent a digest of {} entries to {}",
+            count,
+            config.to.join(", ")
+        );
+    }
+    record(db, now, through).await
+}
+
+async fn record(db: &db::DB, sent_at: DateTime<Utc>, through: i64) -> Result<()> {
+    db.set_setting(SENT_THROUGH, &through.to_string()).await?;
+    db.set_setting(SENT_AT, &sent_at.to_rfc3339()).await
+}
+
+/// The digest as an email, entries coming ordered by category.
+fn message(config: &DigestConfig, entries: Vec<Entry>, now: DateTime<Utc>) -> Result<String> {
+    let subject = format!(
+        "Your {} digest: {} unread {}",
+        config.frequency.name(),
+        entries.len(),
+        if entries.len() == 1 {
+            "entry"
+        } else {
+            "entries"
+        }
+    );
+    let mut sections: Vec<Section> = vec![];
+    for entry in entries {
+        let category = entry.category.clone().unwrap_or_default();
+        match sections.last_mut() {
+            Some(section) if section.category == category => section.entries.push(entry),
+            _ => sections.push(Section {
+                category,
+                entries: vec![entry],
+            }),
+        }
+    }
+    let html = DigestTemplate {
+        subject: subject.clone(),
+        sections,
+        reader: public_url::absolute("/", None),
+    }
+    .render()?;
+    // base64 keeps lines short and 7-bit, whatever the entries have in them
+    let body = base64::encode(html);
+    let body: Vec<&str> = body
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    Ok(format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@feedreader>\r\nMIME-Version: 1.0\r\nContent-Type: text/html; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+        config.from,
+        config.to.join(", "),
+        subject,
+        now.to_rfc2822(),
+        uuid::Uuid::new_v4(),
+        body.join("\r\n")
+    ))
+}
+
+/// What an SMTP session runs over, plain or encrypted.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+type Session = BufReader<Box<dyn Stream>>;
+
+/// Hands `mail` to the SMTP server for everyone it's to.
+async fn send(config: &DigestConfig, mail: &str) -> Result<()> {
+    let stream = TcpStream::connect((config.smtp_host.as_str(), config.smtp_port))
+        .await
+        .with_context(|| format!("couldn't connect to {}", config.smtp_host))?;
+    let mut session: Session = match config.smtp_tls {
+        SmtpTls::Tls => BufReader::new(encrypt(&config.smtp_host, Box::new(stream)).await?),
+        _ => BufReader::new(Box::new(stream)),
+    };
+    expect(&mut session, 2).await?;
+    let mut extensions = command(&mut session, "EHLO localhost", 2).await?;
+    if config.smtp_tls == SmtpTls::StartTls {
+        if !offers(&extensions, "STARTTLS") {
+            return Err(anyhow!(
+                "{} doesn't offer STARTTLS, set FEED_SMTP_TLS to tls or none",
+                config.smtp_host
+            ));
+        }
+        command(&mut session, "STARTTLS", 2).await?;
+        // nothing's buffered, the server waits for the handshake
+        let stream = encrypt(&config.smtp_host, session.into_inner()).await?;
+        session = BufReader::new(stream);
+        extensions = command(&mut session, "EHLO localhost", 2).await?;
+    }
+    if let Some(login) = &config.smtp_login {
+        authenticate(&mut session, &extensions, login).await?;
+    }
+    command(&mut session, &format!("MAIL FROM:<{}>", config.from), 2).await?;
+    for to in &config.to {
+        command(&mut session, &format!("RCPT TO:<{}>", to), 2).await?;
+    }
+    command(&mut session, "DATA", 3).await?;
+    // a line starting with a dot gets another so it isn't taken for the end
+    let data = format!("{}.\r\n", mail.replace("\r\n.", "\r\n.."));
+    session.write_all(data.as_bytes()).await?;
+    session.flush().await?;
+    expect(&mut session, 2).await?;
+    command(&mut session, "QUIT", 2).await?;
+    Ok(())
+}
+
+/// Starts TLS over `stream`, checking the server's certificate is for `host`.
+async fn encrypt(host: &str, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let tls = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string())
+        .with_context(|| format!("{} isn't a host name", host))?;
+    let stream = TlsConnector::from(Arc::new(tls))
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("couldn't start TLS with {}", host))?;
+    Ok(Box::new(stream))
+}
+
+/// Whether an EHLO reply lists `extension`, like `STARTTLS` or `AUTH PLAIN`.
+fn offers(extensions: &[String], extension: &str) -> bool {
+    let mut wanted = extension.split(' ');
+    let name = wanted.next().unwrap_or_default();
+    let argument = wanted.next();
+    extensions.iter().any(|line| {
+        let mut words = line.split_whitespace();
+        words.next().is_some_and(|w| w.eq_ignore_ascii_case(name))
+            && argument.map_or(true, |a| words.any(|w| w.eq_ignore_ascii_case(a)))
+    })
+}
+
+/// Logs in with AUTH PLAIN, or LOGIN for servers that only take that.
+async fn authenticate(
+    session: &mut Session,
+    extensions: &[String],
+    login: &SmtpLogin,
+) -> Result<()> {
+    let result = if offers(extensions, "AUTH PLAIN") {
+        let token = base64::encode(format!("\0{}\0{}", login.username, login.password));
+        say(session, &format!("AUTH PLAIN {}", token), 2).await
+    } else if offers(extensions, "AUTH LOGIN") {
+        async {
+            say(session, "AUTH LOGIN", 3).await?;
+            say(session, &base64::encode(&login.username), 3).await?;
+            say(session, &base64::encode(&login.password), 2).await
+        }
+        .await
+    } else {
+        return Err(anyhow!("the server doesn't offer AUTH PLAIN or LOGIN"));
+    };
+    result.context("AUTH failed")?;
+    Ok(())
+}
+
+/// Sends an SMTP command, expecting a reply of the class `class`, like 2 for 250,
+/// and returning the reply's lines.
+async fn command<S>(session: &mut S, command: &str, class: u16) -> Result<Vec<String>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let verb = command.split([' ', ':']).next().unwrap_or_default();
+    say(session, command, class)
+        .await
+        .with_context(|| format!("{} failed", verb))
+}
+
+/// Sends a line, expecting a reply of the class `class`, without saying what was
+/// sent if it fails, for AUTH's credentials.
+async fn say<S>(session: &mut S, line: &str, class: u16) -> Result<Vec<String>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    session
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await?;
+    session.flush().await?;
+    expect(session, class).await
+}
+
+/// Reads a reply, which can go over several lines, failing unless it's of the
+/// class `class`.
+async fn expect<R: AsyncBufRead + Unpin>(read: &mut R, class: u16) -> Result<Vec<String>> {
+    let mut text = vec![];
+    loop {
+        let mut line = String::new();
+        if read.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("the server hung up"));
+        }
+        let line = line.trim_end();
+        let code: u16 = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| anyhow!("unexpected reply {:?}", line))?;
+        text.push(line.get(4..).unwrap_or_default().to_string());
+        // the last line has a space after the code, the others a dash
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if code / 100 != class {
+            return Err(anyhow!("the server said {} {}", code, text.join(" ")));
+        }
+        return Ok(text);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::UtcTime;
+
+    /// A relay answering with `ehlo` to EHLO, keeping every line it's sent.
+    async fn relay(ehlo: &'static [u8]) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut received = vec![];
+            write.write_all(b"220 relay ready\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.as_str() {
+                    "EHLO localhost" => ehlo,
+                    "DATA" => b"354 go ahead\r\n",
+                    "." => b"250 queued\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    "AUTH LOGIN" => b"334 VXNlcm5hbWU6\r\n",
+                    // the username, asked for above
+                    "cmVhZGVy" => b"334 UGFzc3dvcmQ6\r\n",
+                    l if l.starts_with("MAIL") || l.starts_with("RCPT") => b"250 ok\r\n",
+                    l if l.starts_with("AUTH PLAIN") => b"235 welcome\r\n",
+                    _ if received.last().is_some_and(|l| l == "cmVhZGVy") => b"235 welcome\r\n",
+                    _ => b"",
+                };
+                received.push(line);
+                write.write_all(reply).await.unwrap();
+            }
+            received
+        });
+        (port, server)
+    }
+
+    fn relaying_to(port: u16) -> DigestConfig {
+        DigestConfig {
+            frequency: Frequency::Daily,
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: port,
+            smtp_tls: SmtpTls::None,
+            smtp_login: None,
+            from: "reader@example.com".to_string(),
+            to: parse_addresses("me@example.com, you@example.com").unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_digest() {
+        let (port, server) = relay(b"250-relay\r\n250 8BITMIME\r\n").await;
+        let config = relaying_to(port);
+        let mut entry = Entry::new(
+            "digested",
+            "Rust & <friends>".to_string(),
+            "https://blog.com/rust".to_string(),
+            "".to_string(),
+            Some(UtcTime("2024-03-01T12:00:00Z".parse().unwrap())),
+        );
+        entry.category = Some("Tech".to_string());
+        let mail = message(&config, vec![entry], Utc::now()).unwrap();
+        send(&config, &mail).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert_eq!(
+            received[..4],
+            [
+                "EHLO localhost",
+                "MAIL FROM:<reader@example.com>",
+                "RCPT TO:<me@example.com>",
+                "RCPT TO:<you@example.com>",
+            ]
+        );
+        assert!(received.contains(&"Subject: Your daily digest: 1 unread entry".to_string()));
+        let body = received
+            .iter()
+            .skip_while(|l| !l.is_empty())
+            .take_while(|l| *l != ".")
+            .map(String::as_str)
+            .collect::<String>();
+        let html = String::from_utf8(base64::decode(body).unwrap()).unwrap();
+        assert!(html
+            .contains("<h2 style=\"font-size: 1.2em; border-bottom: 1px solid #ccc;\">Tech</h2>"));
+        assert!(html.contains("Rust &amp; &lt;friends&gt;"));
+        assert_eq!(received.last().unwrap(), "QUIT");
+
+        assert!(parse_addresses("me@example.com\r\nBcc: x@y").is_err());
+    }
+
+    #[tokio::test]
+    async fn log_in() {
+        let mail = "Subject: hi\r\n\r\nhi\r\n";
+        let login = SmtpLogin {
+            username: "reader".to_string(),
+            password: "secret".to_string(),
+        };
+
+        let (port, server) = relay(b"250-relay\r\n250 AUTH LOGIN PLAIN\r\n").await;
+        let config = DigestConfig {
+            smtp_login: Some(login.clone()),
+            ..relaying_to(port)
+        };
+        send(&config, mail).await.unwrap();
+        let received = server.await.unwrap();
+        // "\0reader\0secret"
+        assert_eq!(received[1], "AUTH PLAIN AHJlYWRlcgBzZWNyZXQ=");
+        assert_eq!(received[2], "MAIL FROM:<reader@example.com>");
+
+        let (port, server) = relay(b"250-relay\r\n250 AUTH LOGIN\r\n").await;
+        let config = DigestConfig {
+            smtp_login: Some(login),
+            ..relaying_to(port)
+        };
+        send(&config, mail).await.unwrap();
+        let received = server.await.unwrap();
+        assert_eq!(received[1..4], ["AUTH LOGIN", "cmVhZGVy", "c2VjcmV0"]);
+        assert_eq!(received[4], "MAIL FROM:<reader@example.com>");
+        assert!(!format!("{:?}", config).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn require_starttls() {
+        let (port, server) = relay(b"250-relay\r\n250 8BITMIME\r\n").await;
+        let config = DigestConfig {
+            smtp_tls: SmtpTls::StartTls,
+            ..relaying_to(port)
+        };
+        let err = send(&config, "").await.unwrap_err();
+        assert!(err.to_string().contains("doesn't offer STARTTLS"));
+        // nothing but EHLO went out in the clear
+        assert_eq!(server.await.unwrap(), ["EHLO localhost"]);
+    }
+}
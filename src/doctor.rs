@@ -0,0 +1,362 @@
+//! `feedreader doctor`: checks the configuration, database and network the server
+//! needs, saying what to change when one of them is wrong.
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future;
+use tracing::warn;
+
+use crate::config::{ClientConfig, Config, Purpose};
+use crate::secrets::SecretKey;
+use crate::{db, parse_opml_outlines, read_opml};
+
+/// How many feeds are fetched to check the network is reachable.
+const NETWORK_SAMPLE: usize = 3;
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with what to do about it if it didn't pass.
+pub struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.status == Status::Fail
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "{:<5} {:<9} {}", status, self.name, self.detail)
+    }
+}
+
+/// Runs `feedreader doctor`, printing every check. Returns whether they all passed.
+pub async fn run(config_path: Option<&str>) -> bool {
+    let checks = checks(config_path).await;
+    for check in &checks {
+        println!("{}", check);
+    }
+    !checks.iter().any(Check::failed)
+}
+
+/// The checks that don't need the database or network, run before the server starts.
+pub fn preflight(config: &Config) -> Result<()> {
+    let checks = local_checks(config);
+    for check in checks.iter().filter(|c| c.status == Status::Warn) {
+        warn!("{}", check);
+    }
+    match checks.into_iter().find(Check::failed) {
+        Some(check) => Err(anyhow!("{}: {}", check.name, check.detail)),
+        None => Ok(()),
+    }
+}
+
+async fn checks(config_path: Option<&str>) -> Vec<Check> {
+    let config = match Config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Check::fail(
+                "config",
+                format!("{:#}, fix it and run doctor again", e),
+            )]
+        }
+    };
+    let mut checks = vec![Check::ok(
+        "config",
+        match config_path {
+            Some(path) => format!("{} and environment parsed", path),
+            None => "environment parsed".to_string(),
+        },
+    )];
+    checks.extend(local_checks(&config));
+
+    let db = match db::connect(config.db).await {
+        Ok(db) => db,
+        Err(e) => {
+            checks.push(Check::fail("database", format!("couldn't open: {:#}", e)));
+            return checks;
+        }
+    };
+    match db.schema_version().await {
+        Ok(version) => {
+            checks.push(Check::ok("database", "connected"));
+            checks.push(check_schema(version, db::latest_version()));
+            if version == db::latest_version() {
+                checks.push(check_credentials(&db, config.secret_key.as_ref()).await);
+            }
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "database",
+                format!(
+                    "couldn't query: {:#}, check FEED_DB_PATH or TURSO_URL and TURSO_TOKEN",
+                    e
+                ),
+            ));
+            return checks;
+        }
+    }
+    checks.push(check_network(&db, &config.client).await);
+    checks
+}
+
+fn local_checks(config: &Config) -> Vec<Check> {
+    let mut checks = vec![];
+    if let Some(path) = config.db_path() {
+        checks.push(check_writable(path));
+    }
+    if let Some(path) = &config.opml_file {
+        checks.push(check_opml(path));
+    }
+    if let Some(dumps) = &config.body_dumps {
+        checks.push(check_dump_dir(dumps.dir()));
+    }
+    checks
+}
+
+/// SQLite writes journal files beside the database, so its directory has to be
+/// writable as well as the file.
+fn check_writable(path: &str) -> Check {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if path.exists() {
+        if let Err(e) = OpenOptions::new().append(true).open(path) {
+            return Check::fail(
+                "storage",
+                format!(
+                    "can't write to {}: {}, check its owner and mode",
+                    path.display(),
+                    e
+                ),
+            );
+        }
+    }
+    let probe = dir.join(".feedreader-doctor");
+    match fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+        Ok(()) => Check::ok("storage", format!("{} is writable", dir.display())),
+        Err(e) => Check::fail(
+            "storage",
+            format!(
+                "can't create files in {}: {}, the database needs a writable directory",
+                dir.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// Only a warning, as not being able to keep bodies doesn't stop anything else.
+fn check_dump_dir(dir: &Path) -> Check {
+    let probe = dir.join(".feedreader-doctor");
+    match fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe))
+    {
+        Ok(()) => Check::ok(
+            "dumps",
+            format!("bodies that can't be parsed go in {}", dir.display()),
+        ),
+        Err(e) => Check::warn(
+            "dumps",
+            format!(
+                "can't create files in FEED_DEBUG_DUMP_DIR={}: {}",
+                dir.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// A feed that can't be read from the file is skipped by the import, so only
+/// an unreadable file fails.
+fn check_opml(path: &str) -> Check {
+    let document = match read_opml(path) {
+        Ok(document) => document,
+        Err(e) => {
+            return Check::fail(
+                "opml",
+                format!("couldn't load FEED_OPML_FILE={}: {:#}", path, e),
+            )
+        }
+    };
+    let outlines = parse_opml_outlines(&document);
+    let broken: Vec<String> = outlines
+        .iter()
+        .filter_map(|(_, feed)| feed.as_ref().err().map(|e| e.to_string()))
+        .collect();
+    if broken.is_empty() {
+        Check::ok("opml", format!("{} lists {} feeds", path, outlines.len()))
+    } else {
+        Check::warn(
+            "opml",
+            format!(
+                "{} of {} feeds in {} will be skipped: {}",
+                broken.len(),
+                outlines.len(),
+                path,
+                broken.join("; ")
+            ),
+        )
+    }
+}
+
+fn check_schema(version: i64, latest: i64) -> Check {
+    match version.cmp(&latest) {
+        Ordering::Equal => Check::ok("schema", format!("at version {}", version)),
+        Ordering::Less => Check::warn(
+            "schema",
+            format!(
+                "at version {} of {}, migrations run when the server next starts",
+                version, latest
+            ),
+        ),
+        Ordering::Greater => Check::fail(
+            "schema",
+            format!(
+                "at version {}, newer than this build's {}, upgrade feedreader",
+                version, latest
+            ),
+        ),
+    }
+}
+
+/// Whether the configured key opens every stored credential.
+async fn check_credentials(db: &db::DB, key: Option<&SecretKey>) -> Check {
+    let names = match db.get_credential_names().await {
+        Ok(names) => names,
+        Err(e) => return Check::fail("secrets", format!("couldn't list credentials: {:#}", e)),
+    };
+    if names.is_empty() {
+        return Check::ok("secrets", "no stored credentials");
+    }
+    let Some(key) = key else {
+        return Check::fail(
+            "secrets",
+            format!(
+                "{} credentials are stored but FEED_SECRET_KEY isn't set",
+                names.len()
+            ),
+        );
+    };
+    for name in &names {
+        if let Err(e) = db.get_credential(key, name).await {
+            return Check::fail(
+                "secrets",
+                format!(
+                    "{:#}, set FEED_SECRET_KEY to the key they were encrypted with",
+                    e
+                ),
+            );
+        }
+    }
+    Check::ok(
+        "secrets",
+        format!(
+            "FEED_SECRET_KEY opens all {} stored credentials",
+            names.len()
+        ),
+    )
+}
+
+/// Fetches a few feeds; any response at all means they're reachable.
+async fn check_network(db: &db::DB, client: &ClientConfig) -> Check {
+    let feeds = match db.get_feeds().await {
+        Ok(feeds) if !feeds.is_empty() => feeds,
+        _ => return Check::warn("network", "no feeds to check against"),
+    };
+    let client = match client
+        .builder(Purpose::Fetch)
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return Check::fail("network", format!("couldn't build a client: {}", e)),
+    };
+    let sample: Vec<_> = feeds.iter().take(NETWORK_SAMPLE).collect();
+    let results =
+        future::join_all(sample.iter().map(|feed| client.head(&feed.feed_url).send())).await;
+    let failures: Vec<String> = sample
+        .iter()
+        .zip(results)
+        .filter_map(|(feed, result)| result.err().map(|e| format!("{}: {}", feed.feed_url, e)))
+        .collect();
+    if failures.is_empty() {
+        Check::ok("network", format!("reached {} feeds", sample.len()))
+    } else if failures.len() == sample.len() {
+        Check::fail(
+            "network",
+            format!(
+                "couldn't reach any feeds ({}), check DNS, firewall rules and HTTPS_PROXY",
+                failures.join("; ")
+            ),
+        )
+    } else {
+        Check::warn(
+            "network",
+            format!("some feeds unreachable: {}", failures.join("; ")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn storage_needs_a_writable_directory() {
+        let dir = std::env::temp_dir().join(format!("feedreader-doctor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let check = check_writable(dir.join("feeds.db").to_str().unwrap());
+        assert_eq!(check.status, Status::Ok);
+
+        let check = check_writable(dir.join("missing/feeds.db").to_str().unwrap());
+        assert!(check.failed());
+        assert!(check.to_string().starts_with("FAIL  storage"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
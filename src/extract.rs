@@ -0,0 +1,351 @@
+//! Pulls the article body out of a web page, for feeds that only send a stub
+//! summary and link to the real post.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use ego_tree::NodeId;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::{db, sanitize, user, Entry, UtcTime};
+
+/// Stored articles are fetched again in the background once they're this old,
+/// to pick up corrections, with the stored one shown meanwhile.
+const FRESH_FOR_HOURS: i64 = 24;
+
+/// How long opening an entry waits on its article being fetched for the first
+/// time before showing the feed's own content. The fetch carries on, so the
+/// article's there the next time it's opened.
+const FIRST_FETCH_WAIT: Duration = Duration::from_secs(5);
+
+/// When to fetch the full article for a feed's entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ExtractMode {
+    #[default]
+    Never,
+    /// Right after new entries are stored
+    OnRefresh,
+    /// The first time an entry is opened in the reader
+    OnView,
+}
+
+impl FromStr for ExtractMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<ExtractMode> {
+        match s {
+            "never" => Ok(ExtractMode::Never),
+            "refresh" => Ok(ExtractMode::OnRefresh),
+            "view" => Ok(ExtractMode::OnView),
+            _ => Err(anyhow!("unknown extract mode {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExtractMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractMode::Never => write!(f, "never"),
+            ExtractMode::OnRefresh => write!(f, "refresh"),
+            ExtractMode::OnView => write!(f, "view"),
+        }
+    }
+}
+
+/// An article pulled out of an entry's page.
+#[derive(Debug, Deserialize)]
+pub struct Article {
+    pub content: String,
+    /// Missing for ones stored before it was recorded, which count as stale
+    pub extracted_at: Option<UtcTime>,
+}
+
+impl Article {
+    pub fn is_fresh(&self) -> bool {
+        self.extracted_at
+            .as_ref()
+            .is_some_and(|at| at.0 > Utc::now() - chrono::Duration::hours(FRESH_FOR_HOURS))
+    }
+}
+
+/// Entries whose article is being fetched, so opening one again meanwhile
+/// doesn't fetch it twice.
+#[derive(Debug, Clone, Default)]
+pub struct Extractions(Arc<Mutex<HashSet<String>>>);
+
+impl Extractions {
+    /// Marks an entry's article as being fetched, false if it already was.
+    fn claim(&self, entry_id: &str) -> bool {
+        self.0.lock().unwrap().insert(entry_id.to_string())
+    }
+
+    fn release(&self, entry_id: &str) {
+        self.0.lock().unwrap().remove(entry_id);
+    }
+
+    /// Fetches an entry's article in the background, for the current user, unless
+    /// it already is being, with a handle for waiting on it when this started it.
+    fn start(
+        &self,
+        db: &db::DB,
+        client: &reqwest::Client,
+        entry: &Entry,
+    ) -> Result<Option<tokio::task::JoinHandle<Option<String>>>> {
+        let user = user::current()?;
+        if !self.claim(&entry.id) {
+            return Ok(None);
+        }
+        let (extractions, db, client, entry) =
+            (self.clone(), db.clone(), client.clone(), entry.clone());
+        Ok(Some(tokio::spawn(user::scope(user, async move {
+            let article = extract_entry(&db, &client, &entry)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("couldn't extract {}: {:?}", entry.content_link, e);
+                    None
+                });
+            extractions.release(&entry.id);
+            article
+        }))))
+    }
+}
+
+/// The article to show for an entry in the reader. A stored one is shown
+/// straight away, and fetched again in the background once it's stale, so a slow
+/// or broken site only matters the first time. Without one, it's fetched for
+/// feeds that extract on view, waiting a little while for it. Nothing's fetched
+/// while `read_only`, since that would store it.
+pub async fn article_for(
+    db: &db::DB,
+    client: &reqwest::Client,
+    extractions: &Extractions,
+    entry: &Entry,
+    read_only: bool,
+) -> Result<Option<String>> {
+    let mode = entry.extract_mode();
+    if let Some(article) = db.get_extracted_content(&entry.id).await? {
+        if !article.is_fresh() && mode != ExtractMode::Never && !read_only {
+            extractions.start(db, client, entry)?;
+        }
+        return Ok(Some(article.content));
+    }
+    if mode != ExtractMode::OnView || read_only {
+        return Ok(None);
+    }
+    let Some(extraction) = extractions.start(db, client, entry)? else {
+        return Ok(None);
+    };
+    match time::timeout(FIRST_FETCH_WAIT, extraction).await {
+        Ok(article) => Ok(article.unwrap_or_default()),
+        Err(_) => {
+            info!(
+                "still extracting {}, showing the feed's content",
+                entry.content_link
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Class and id words that suggest an element holds the article, or anything but.
+const POSITIVE: &[&str] = &[
+    "article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story",
+];
+const NEGATIVE: &[&str] = &[
+    "comment", "meta", "footer", "footnote", "sidebar", "nav", "menu", "share", "social",
+    "related", "promo", "sponsor", "advert", "banner", "masthead", "widget",
+];
+
+/// Articles with less text than this are more likely a mistake than a post.
+const MIN_ARTICLE_LENGTH: usize = 140;
+
+fn class_weight(element: ElementRef) -> f64 {
+    let e = element.value();
+    let names = format!(
+        "{} {}",
+        e.attr("class").unwrap_or_default(),
+        e.id().unwrap_or_default()
+    )
+    .to_lowercase();
+    let mut weight = 0.0;
+    if POSITIVE.iter().any(|w| names.contains(w)) {
+        weight += 25.0;
+    }
+    if NEGATIVE.iter().any(|w| names.contains(w)) {
+        weight -= 25.0;
+    }
+    weight
+}
+
+/// How much of an element's text is inside links, navigation tends to be mostly links.
+fn link_density(element: ElementRef) -> f64 {
+    let links = Selector::parse("a").unwrap();
+    let text = element.text().collect::<String>().chars().count();
+    if text == 0 {
+        return 1.0;
+    }
+    let linked: usize = element
+        .select(&links)
+        .map(|a| a.text().collect::<String>().chars().count())
+        .sum();
+    linked as f64 / text as f64
+}
+
+/// The HTML of the element most likely to be a page's article, in the spirit of
+/// Readability: paragraphs score their parent and grandparent by how much prose
+/// they hold, adjusted by class names and how link heavy the candidate is.
+pub fn readable_content(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let paragraphs = Selector::parse("p, pre, td").unwrap();
+    let mut scores: HashMap<NodeId, f64> = HashMap::new();
+    for p in document.select(&paragraphs) {
+        let text = p.text().collect::<String>();
+        let length = text.trim().chars().count();
+        if length < 25 {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (length as f64 / 100.0).min(3.0);
+        let mut ancestors = p.ancestors().filter_map(ElementRef::wrap);
+        if let Some(parent) = ancestors.next() {
+            *scores
+                .entry(parent.id())
+                .or_insert_with(|| class_weight(parent)) += score;
+        }
+        if let Some(grandparent) = ancestors.next() {
+            *scores
+                .entry(grandparent.id())
+                .or_insert_with(|| class_weight(grandparent)) += score / 2.0;
+        }
+    }
+    let (best, _) = scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let element = ElementRef::wrap(document.tree.get(id)?)?;
+            Some((element, score * (1.0 - link_density(element))))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let length = best.text().collect::<String>().trim().chars().count();
+    if length < MIN_ARTICLE_LENGTH {
+        return None;
+    }
+    Some(best.inner_html().trim().to_string())
+}
+
+/// Fetches an entry's page and stores its article, returning it if one was found.
+pub async fn extract_entry(
+    db: &db::DB,
+    client: &reqwest::Client,
+    entry: &Entry,
+) -> Result<Option<String>> {
+    if entry.content_link.is_empty() {
+        return Ok(None);
+    }
+    let page = client
+        .get(&entry.content_link)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let Some(article) = readable_content(&page) else {
+        info!("couldn't find an article at {}", entry.content_link);
+        return Ok(None);
+    };
+    let article = sanitize::sanitize(&article, Some(&entry.content_link));
+    db.set_extracted_content(&entry.id, &article).await?;
+    Ok(Some(article))
+}
+
+/// Extracts articles for entries that don't have one yet, logging rather than failing.
+pub async fn extract_missing(db: &db::DB, client: &reqwest::Client, entries: &[Entry]) {
+    for entry in entries {
+        match db.get_extracted_content(&entry.id).await {
+            Ok(Some(_)) => continue,
+            Ok(None) => {}
+            Err(e) => {
+                error!("couldn't look up article for {}: {:?}", entry.id, e);
+                continue;
+            }
+        }
+        if let Err(e) = extract_entry(db, client, entry).await {
+            error!("couldn't extract {}: {:?}", entry.content_link, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_article_body() {
+        let prose = "A sentence about something, with a few commas, that goes on for a while. ";
+        let html = format!(
+            r#"<html><body>
+            <nav class="menu"><p><a href="/">Home</a> <a href="/about">About this blog and its author</a></p></nav>
+            <div class="post-content"><h1>Title</h1><p>{0}{0}</p><p>{0}</p></div>
+            <div class="comments"><p>Nice post, thanks for writing it up!</p></div>
+            </body></html>"#,
+            prose
+        );
+        let article = readable_content(&html).unwrap();
+        assert!(article.starts_with("<h1>Title</h1>"));
+        assert!(!article.contains("Nice post"));
+        assert!(!article.contains("About"));
+
+        assert!(
+            readable_content("<html><body><p>Too short to be an article.</p></body></html>")
+                .is_none()
+        );
+        assert_eq!("view".parse::<ExtractMode>().unwrap(), ExtractMode::OnView);
+        assert_eq!(ExtractMode::OnRefresh.to_string(), "refresh");
+    }
+
+    #[test]
+    fn revalidate_stale_articles_once() {
+        let article = |hours_ago: Option<i64>| Article {
+            content: "<p>Article</p>".to_string(),
+            extracted_at: hours_ago.map(|h| UtcTime(Utc::now() - chrono::Duration::hours(h))),
+        };
+        assert!(article(Some(1)).is_fresh());
+        assert!(!article(Some(FRESH_FOR_HOURS + 1)).is_fresh());
+        assert!(!article(None).is_fresh());
+
+        let extractions = Extractions::default();
+        assert!(extractions.claim("entry"));
+        assert!(!extractions.claim("entry"));
+        extractions.release("entry");
+        assert!(extractions.claim("entry"));
+    }
+
+    #[tokio::test]
+    async fn nothing_fetched_while_read_only() -> Result<()> {
+        let db = db::connect(db::ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let mut entry = Entry::new(
+            "viewed",
+            "Viewed".to_string(),
+            "http://127.0.0.1:9/article".to_string(),
+            "".to_string(),
+            None,
+        );
+        entry.extract_mode = Some("view".to_string());
+        let extractions = Extractions::default();
+        let client = reqwest::Client::new();
+        let article = user::scope(
+            user::OWNER,
+            article_for(&db, &client, &extractions, &entry, true),
+        )
+        .await?;
+        assert_eq!(article, None);
+        // never started, so it's free to claim
+        assert!(extractions.claim("viewed"));
+        Ok(())
+    }
+}
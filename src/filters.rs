@@ -0,0 +1,133 @@
+//! Filters for the templates, so formatting lives here rather than repeated across
+//! them. Modules with templates bring them in with `use crate::filters;` and they're
+//! used like `{{ feed.last_fetched|display_some }}`.
+//!
+//! Askama passes fields by reference and method calls and literals by value,
+//! which is why most of these take any `Display`.
+use std::fmt::Display;
+
+use chrono_humanize::HumanTime;
+use reqwest::Url;
+
+use crate::{base_path, sanitize, UtcTime};
+
+/// Long enough for most titles, short enough that the odd 300 character one
+/// doesn't take over the list.
+pub const DEFAULT_TITLE_LENGTH: usize = 120;
+
+/// A path on this server as it's linked to, under the base path when there is
+/// one, like `{{ "/feeds.html"|url }}`.
+pub fn url<T: Display>(path: T) -> askama::Result<String> {
+    Ok(base_path::to(&path.to_string()))
+}
+
+/// The value, or nothing when there isn't one.
+pub fn display_some<T: Display>(value: &Option<T>) -> askama::Result<String> {
+    Ok(value.as_ref().map(T::to_string).unwrap_or_default())
+}
+
+/// How long ago or from now a time is, like "2 hours ago".
+pub fn humanize(time: &UtcTime) -> askama::Result<String> {
+    Ok(HumanTime::from(time.0).to_string())
+}
+
+/// The host a link points at without any leading `www.`, the text as it is when
+/// it isn't a link.
+pub fn domain<T: Display>(url: T) -> askama::Result<String> {
+    let url = url.to_string();
+    let host = Url::parse(&url).ok().and_then(|u| {
+        u.host_str()
+            .map(|h| h.trim_start_matches("www.").to_string())
+    });
+    Ok(host.unwrap_or(url))
+}
+
+/// Text cut to at most `length` characters with an ellipsis, at a word break when
+/// there's one near the end. A length of 0 leaves it whole.
+///
+/// Askama already has a `truncate`, which doesn't look for word breaks.
+pub fn shorten<T: Display>(text: T, length: &usize) -> askama::Result<String> {
+    let text = text.to_string();
+    let length = *length;
+    if length == 0 || text.chars().count() <= length {
+        return Ok(text);
+    }
+    // leave room for the ellipsis
+    let end = text
+        .char_indices()
+        .nth(length.saturating_sub(1))
+        .map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(space) if space >= end * 3 / 4 => &cut[..space],
+        _ => cut,
+    };
+    let cut = cut.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation());
+    Ok(format!("{}…", cut))
+}
+
+/// A count with the word for it, like "1 entry" or "3 entries".
+pub fn pluralize<T: Display>(count: T, singular: &str, plural: &str) -> askama::Result<String> {
+    let count = count.to_string();
+    let word = if count == "1" { singular } else { plural };
+    Ok(format!("{} {}", count, word))
+}
+
+/// A number of seconds as a clock reading, like "4:05" or "1:02:03".
+pub fn duration(seconds: &f64) -> askama::Result<String> {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    Ok(match hours {
+        0 => format!("{}:{:02}", minutes, seconds),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    })
+}
+
+/// A link's `href` with the attributes for leaving the reader, used like
+/// `{{ url|external_href(new_tab)|safe }}`.
+pub fn external_href<T: Display>(url: T, new_tab: &bool) -> askama::Result<String> {
+    Ok(sanitize::external_href(&url.to_string(), *new_tab))
+}
+
+/// Every link off the site in an HTML fragment, like an entry's content,
+/// with the attributes for leaving the reader.
+pub fn external_links<T: Display>(html: T, new_tab: &bool) -> askama::Result<String> {
+    Ok(sanitize::external_links(&html.to_string(), *new_tab))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+
+    #[test]
+    fn shorten_long_text() {
+        let title = "The quick brown fox jumps over the lazy dog";
+        assert_eq!(shorten(title, &20).unwrap(), "The quick brown…");
+        assert_eq!(shorten(title, &100).unwrap(), title);
+        assert_eq!(shorten(title, &0).unwrap(), title);
+        assert_eq!(shorten("ééééé", &3).unwrap(), "éé…");
+    }
+
+    #[test]
+    fn format_values() {
+        assert_eq!(display_some(&Some(404)).unwrap(), "404");
+        assert_eq!(display_some::<u16>(&None).unwrap(), "");
+        let hour_ago = UtcTime(Utc::now() - Duration::hours(1));
+        assert_eq!(humanize(&hour_ago).unwrap(), "an hour ago");
+        assert_eq!(
+            domain("https://www.example.com/feed.xml").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            domain("http://blog.example.com").unwrap(),
+            "blog.example.com"
+        );
+        assert_eq!(domain("not a link").unwrap(), "not a link");
+        assert_eq!(pluralize(1, "entry", "entries").unwrap(), "1 entry");
+        assert_eq!(pluralize(0, "entry", "entries").unwrap(), "0 entries");
+        assert_eq!(duration(&245.7).unwrap(), "4:05");
+        assert_eq!(duration(&3723.0).unwrap(), "1:02:03");
+    }
+}
@@ -0,0 +1,635 @@
+//! Enough of the Google Reader API for clients written against FreshRSS or
+//! Miniflux to use feedreader as their backend. Point them at `/greader` and log
+//! in with an API token as the password.
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, RawQuery, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{self, EntryFilter, Ordering, Page};
+use crate::{auth, sanitize, AppError, AppState, Entry, Feed};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().nest(
+        "/greader",
+        Router::new()
+            .route(
+                "/accounts/ClientLogin",
+                get(client_login).post(client_login),
+            )
+            .route("/reader/api/0/token", get(token))
+            .route("/reader/api/0/user-info", get(user_info))
+            .route("/reader/api/0/subscription/list", get(subscriptions))
+            .route("/reader/api/0/tag/list", get(tags))
+            .route("/reader/api/0/unread-count", get(unread_counts))
+            .route("/reader/api/0/stream/contents", get(stream_contents))
+            .route(
+                "/reader/api/0/stream/contents/*stream",
+                get(stream_contents),
+            )
+            .route("/reader/api/0/stream/items/ids", get(stream_item_ids))
+            .route(
+                "/reader/api/0/stream/items/contents",
+                get(item_contents).post(item_contents),
+            )
+            .route("/reader/api/0/edit-tag", post(edit_tag))
+            .route("/reader/api/0/mark-all-as-read", post(mark_all_as_read)),
+    )
+}
+
+const READING_LIST: &str = "user/-/state/com.google/reading-list";
+const STARRED: &str = "user/-/state/com.google/starred";
+const READ: &str = "user/-/state/com.google/read";
+const ITEM_PREFIX: &str = "tag:google.com,2005:reader/item/";
+
+/// How many items a stream returns when the client doesn't say.
+const DEFAULT_COUNT: u32 = 20;
+
+#[derive(Deserialize)]
+struct LoginForm {
+    #[serde(rename = "Email", default)]
+    _email: String,
+    #[serde(rename = "Passwd")]
+    passwd: String,
+}
+
+/// Hands the password back as the auth token when it's an API token, or
+/// whatever it is while there are none.
+async fn client_login(
+    State(AppState {
+        tokens,
+        db,
+        read_only,
+        ..
+    }): State<AppState>,
+    Form(login): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    if !auth::accepts(&tokens, &db, &login.passwd, read_only.enabled()).await? {
+        return Ok((StatusCode::UNAUTHORIZED, "Error=BadAuthentication\n").into_response());
+    }
+    Ok(format!("SID={0}\nLSID={0}\nAuth={0}\n", login.passwd).into_response())
+}
+
+/// The token edits are meant to send back, which is ignored since auth is by header.
+async fn token() -> &'static str {
+    "feedreader\n"
+}
+
+async fn user_info() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "userId": "1",
+        "userName": "feedreader",
+        "userProfileId": "1",
+        "userEmail": "",
+    }))
+}
+
+/// Which entries a stream id stands for. Feeds are found by the url their stream
+/// id is made from, since a moved feed keeps the id it had before.
+async fn stream_filter(db: &db::DB, stream: &str) -> Result<EntryFilter, AppError> {
+    if let Some(feed_url) = stream.strip_prefix("feed/") {
+        let id = match db.get_feed_by_url(feed_url).await? {
+            Some(feed) => feed.id,
+            None => base64::encode_config(feed_url, base64::URL_SAFE),
+        };
+        return Ok(EntryFilter::Feed(id));
+    }
+    // the user part is `-` or an id, which are the same user here
+    let state = stream
+        .strip_prefix("user/")
+        .and_then(|s| s.split_once('/'))
+        .map(|(_, state)| state);
+    match state {
+        Some("state/com.google/reading-list") => Ok(EntryFilter::All),
+        Some("state/com.google/starred") => Ok(EntryFilter::Starred),
+        Some(label) if label.starts_with("label/") => {
+            Ok(EntryFilter::Category(label["label/".len()..].to_string()))
+        }
+        _ => Err(anyhow::anyhow!("unknown stream {}", stream).into()),
+    }
+}
+
+fn label_id(category: &str) -> String {
+    format!("user/-/label/{}", category)
+}
+
+fn feed_stream_id(feed: &Feed) -> String {
+    format!("feed/{}", feed.feed_url)
+}
+
+/// An entry's id in the long form, as streams list them.
+fn item_id(entry: &Entry) -> String {
+    format!("{}{:016x}", ITEM_PREFIX, entry.item_id)
+}
+
+/// An item id in either the long hex form or as a plain number.
+fn parse_item_id(id: &str) -> Option<i64> {
+    match id.strip_prefix(ITEM_PREFIX) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|id| id as i64),
+        None => id.parse().ok(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Subscription {
+    id: String,
+    title: String,
+    categories: Vec<Label>,
+    url: String,
+    html_url: String,
+    icon_url: String,
+}
+
+#[derive(Serialize)]
+struct Label {
+    id: String,
+    label: String,
+}
+
+async fn subscriptions(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let subscriptions: Vec<Subscription> = db
+        .get_feeds()
+        .await?
+        .into_iter()
+        .map(|f| Subscription {
+            id: feed_stream_id(&f),
+            categories: (!f.category.is_empty())
+                .then(|| Label {
+                    id: label_id(&f.category),
+                    label: f.category.clone(),
+                })
+                .into_iter()
+                .collect(),
+            title: f.name,
+            url: f.feed_url,
+            html_url: f.site_url,
+            icon_url: f.icon_url.unwrap_or_default(),
+        })
+        .collect();
+    Ok(Json(serde_json::json!({ "subscriptions": subscriptions })))
+}
+
+async fn tags(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut tags = vec![serde_json::json!({ "id": STARRED })];
+    for category in db.get_categories().await? {
+        if !category.is_empty() {
+            tags.push(serde_json::json!({ "id": label_id(&category), "type": "folder" }));
+        }
+    }
+    Ok(Json(serde_json::json!({ "tags": tags })))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnreadCount {
+    id: String,
+    count: u32,
+    newest_item_timestamp_usec: String,
+}
+
+async fn unread_counts(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let feeds: HashMap<String, Feed> = db
+        .get_feeds()
+        .await?
+        .into_iter()
+        .map(|f| (f.name.clone(), f))
+        .collect();
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut total = 0;
+    let mut counts = vec![];
+    for (name, count) in db.get_unread_counts().await? {
+        total += count;
+        let Some(feed) = feeds.get(&name) else {
+            continue;
+        };
+        if !feed.category.is_empty() {
+            *labels.entry(label_id(&feed.category)).or_default() += count;
+        }
+        counts.push(UnreadCount {
+            id: feed_stream_id(feed),
+            count,
+            newest_item_timestamp_usec: "0".to_string(),
+        });
+    }
+    counts.extend(labels.into_iter().map(|(id, count)| UnreadCount {
+        id,
+        count,
+        newest_item_timestamp_usec: "0".to_string(),
+    }));
+    counts.push(UnreadCount {
+        id: READING_LIST.to_string(),
+        count: total,
+        newest_item_timestamp_usec: "0".to_string(),
+    });
+    Ok(Json(
+        serde_json::json!({ "max": total, "unreadcounts": counts }),
+    ))
+}
+
+#[derive(Default, Deserialize)]
+struct StreamQuery {
+    /// The stream, when it isn't part of the path
+    s: Option<String>,
+    /// How many items
+    n: Option<u32>,
+    /// `o` for oldest first
+    r: Option<String>,
+    /// A tag to leave out, only `read` is understood
+    xt: Option<String>,
+    /// Where the last page left off
+    c: Option<String>,
+}
+
+/// The entries of a stream page, along with where the next page starts if there may be one.
+async fn stream_page(
+    db: &db::DB,
+    stream: &str,
+    query: &StreamQuery,
+) -> Result<(Vec<Entry>, Option<String>), AppError> {
+    let filter = stream_filter(db, stream).await?;
+    let unread_only = query.xt.as_deref().is_some_and(is_read_tag);
+    let ordering = match query.r.as_deref() {
+        Some("o") => Ordering::Ascending,
+        _ => Ordering::Descending,
+    };
+    let count = query.n.unwrap_or(DEFAULT_COUNT);
+    let offset = query.c.as_deref().and_then(|c| c.parse().ok()).unwrap_or(0);
+    let page = Page::new(offset, count);
+    let entries = db
+        .get_stream_entries(filter, unread_only, ordering, page)
+        .await?;
+    let continuation = page.next_offset(entries.len()).map(|o| o.to_string());
+    Ok((entries, continuation))
+}
+
+fn is_read_tag(tag: &str) -> bool {
+    tag.ends_with("/state/com.google/read")
+}
+
+async fn stream_contents(
+    stream: Option<Path<String>>,
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let stream = match (stream, &query.s) {
+        (Some(Path(stream)), _) => stream,
+        (None, Some(stream)) => stream.clone(),
+        (None, None) => READING_LIST.to_string(),
+    };
+    let (entries, continuation) = stream_page(&db, &stream, &query).await?;
+    let items = items(&db, &entries).await?;
+    let page = serde_json::json!({
+        "id": stream,
+        "updated": Utc::now().timestamp(),
+        "items": items,
+    });
+    Ok(Json(with_continuation(page, continuation)))
+}
+
+async fn stream_item_ids(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let stream = query.s.clone().unwrap_or_else(|| READING_LIST.to_string());
+    let (entries, continuation) = stream_page(&db, &stream, &query).await?;
+    let refs: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|e| {
+            serde_json::json!({
+                "id": e.item_id.to_string(),
+                "directStreamIds": [],
+                "timestampUsec": (published_secs(e) * 1_000_000).to_string(),
+            })
+        })
+        .collect();
+    let page = serde_json::json!({ "itemRefs": refs });
+    Ok(Json(with_continuation(page, continuation)))
+}
+
+/// Adds where the next page starts to a stream page, leaving it out on the last one.
+fn with_continuation(
+    mut page: serde_json::Value,
+    continuation: Option<String>,
+) -> serde_json::Value {
+    if let (Some(page), Some(continuation)) = (page.as_object_mut(), continuation) {
+        page.insert("continuation".to_string(), continuation.into());
+    }
+    page
+}
+
+/// Every value of a form field that may be repeated, like the `i` of item ids.
+fn form_values<'a>(pairs: &'a [(String, String)], key: &str) -> Vec<&'a str> {
+    pairs
+        .iter()
+        .filter(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+        .collect()
+}
+
+/// The fields of a query string and form body together, keeping repeats.
+fn form_pairs(query: Option<&str>, body: &str) -> Result<Vec<(String, String)>, AppError> {
+    let mut pairs: Vec<(String, String)> = serde_urlencoded::from_str(query.unwrap_or_default())?;
+    pairs.extend(serde_urlencoded::from_str::<Vec<(String, String)>>(body)?);
+    Ok(pairs)
+}
+
+fn item_ids(pairs: &[(String, String)]) -> Vec<i64> {
+    form_values(pairs, "i")
+        .into_iter()
+        .filter_map(parse_item_id)
+        .collect()
+}
+
+async fn item_contents(
+    State(AppState { db, .. }): State<AppState>,
+    RawQuery(query): RawQuery,
+    body: String,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let pairs = form_pairs(query.as_deref(), &body)?;
+    let entries = db.get_entries_by_item_ids(&item_ids(&pairs)).await?;
+    let items = items(&db, &entries).await?;
+    Ok(Json(serde_json::json!({
+        "id": READING_LIST,
+        "updated": Utc::now().timestamp(),
+        "items": items,
+    })))
+}
+
+/// Marks items read or starred, or the opposite, by the state tags added and removed.
+async fn edit_tag(
+    State(AppState { db, .. }): State<AppState>,
+    RawQuery(query): RawQuery,
+    body: String,
+) -> Result<&'static str, AppError> {
+    let pairs = form_pairs(query.as_deref(), &body)?;
+    let ids = item_ids(&pairs);
+    for (key, set) in [("a", true), ("r", false)] {
+        for tag in form_values(&pairs, key) {
+            if is_read_tag(tag) {
+                db.set_items_read(&ids, set).await?;
+            } else if tag.ends_with("/state/com.google/kept-unread") {
+                db.set_items_read(&ids, !set).await?;
+            } else if tag.ends_with("/state/com.google/starred") {
+                db.set_items_starred(&ids, set).await?;
+            }
+        }
+    }
+    Ok("OK")
+}
+
+#[derive(Deserialize)]
+struct MarkAllForm {
+    s: String,
+}
+
+async fn mark_all_as_read(
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<MarkAllForm>,
+) -> Result<Response, AppError> {
+    match stream_filter(&db, &form.s).await? {
+        EntryFilter::Feed(id) => {
+            let Some(feed) = db.get_feed(&id).await? else {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            };
+            db.mark_all_read(Some(&feed.name), None).await?;
+        }
+        EntryFilter::Category(category) => {
+            db.mark_all_read(None, Some(&category)).await?;
+        }
+        EntryFilter::All => {
+            db.mark_all_read(None, None).await?;
+        }
+        _ => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    }
+    Ok("OK".into_response())
+}
+
+fn published_secs(entry: &Entry) -> i64 {
+    entry.published.as_ref().map_or(0, |p| p.0.timestamp())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    id: String,
+    crawl_time_msec: String,
+    timestamp_usec: String,
+    published: i64,
+    title: String,
+    canonical: Vec<Link>,
+    alternate: Vec<Link>,
+    summary: Content,
+    categories: Vec<String>,
+    origin: Origin,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Link {
+    href: String,
+}
+
+#[derive(Serialize)]
+struct Content {
+    content: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Origin {
+    stream_id: String,
+    title: String,
+    html_url: String,
+}
+
+/// Entries as stream items, with their feed looked up for the origin.
+async fn items(db: &db::DB, entries: &[Entry]) -> Result<Vec<Item>, AppError> {
+    let feeds: HashMap<String, Feed> = db
+        .get_feeds()
+        .await?
+        .into_iter()
+        .map(|f| (f.name.clone(), f))
+        .collect();
+    Ok(entries
+        .iter()
+        .map(|e| item(e, feeds.get(&e.feed)))
+        .collect())
+}
+
+fn item(entry: &Entry, feed: Option<&Feed>) -> Item {
+    let published = published_secs(entry);
+    let mut categories = vec![READING_LIST.to_string()];
+    if entry.read {
+        categories.push(READ.to_string());
+    }
+    if entry.starred {
+        categories.push(STARRED.to_string());
+    }
+    if let Some(category) = entry.category.as_deref().filter(|c| !c.is_empty()) {
+        categories.push(label_id(category));
+    }
+    let content = match &entry.content {
+        Some(content) => sanitize::sanitize(content, Some(&entry.content_link)),
+        None => entry.excerpt.clone().unwrap_or_default(),
+    };
+    let link = || Link {
+        href: entry.content_link.clone(),
+    };
+    Item {
+        id: item_id(entry),
+        crawl_time_msec: (published * 1000).to_string(),
+        timestamp_usec: (published * 1_000_000).to_string(),
+        published,
+        title: entry.title.clone(),
+        canonical: vec![link()],
+        alternate: vec![link()],
+        summary: Content { content },
+        categories,
+        origin: Origin {
+            stream_id: feed.map(feed_stream_id).unwrap_or_default(),
+            title: entry.feed.clone(),
+            html_url: feed.map(|f| f.site_url.clone()).unwrap_or_default(),
+        },
+        author: entry.author.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::user;
+
+    #[tokio::test]
+    async fn parse_streams_and_items() -> Result<(), anyhow::Error> {
+        let db = db::connect(db::ConnectionBacking::Memory).await?;
+        db.init().await?;
+        assert!(matches!(
+            stream_filter(&db, READING_LIST).await.unwrap(),
+            EntryFilter::All
+        ));
+        assert!(matches!(
+            stream_filter(&db, "user/1000/state/com.google/starred")
+                .await
+                .unwrap(),
+            EntryFilter::Starred
+        ));
+        assert!(matches!(
+            stream_filter(&db, "user/-/label/Tech News").await.unwrap(),
+            EntryFilter::Category(c) if c == "Tech News"
+        ));
+        assert!(matches!(
+            stream_filter(&db, "feed/https://blog.com/rss").await.unwrap(),
+            EntryFilter::Feed(id) if id == base64::encode_config("https://blog.com/rss", base64::URL_SAFE)
+        ));
+        assert!(stream_filter(&db, "user/-/state/com.google/broadcast")
+            .await
+            .is_err());
+
+        let mut entry = Entry::new(
+            "e",
+            "Post".to_string(),
+            "https://blog.com/1".to_string(),
+            "".to_string(),
+            None,
+        );
+        entry.item_id = 31;
+        entry.starred = true;
+        let id = item_id(&entry);
+        assert_eq!(id, "tag:google.com,2005:reader/item/000000000000001f");
+        assert_eq!(parse_item_id(&id), Some(31));
+        assert_eq!(parse_item_id("31"), Some(31));
+        let item = item(&entry, None);
+        assert_eq!(item.categories, [READING_LIST, STARRED]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moved_feed_keeps_its_stream() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/rss".to_string(),
+                "".to_string(),
+            );
+            let id = feed.id.clone();
+            db.add_feeds(std::iter::once(feed)).await?;
+            let mut entry = Entry::new(
+                "post",
+                "Post".to_string(),
+                "https://blog.com/post".to_string(),
+                "".to_string(),
+                None,
+            );
+            entry.feed = "Blog".to_string();
+            db.add_entries(std::iter::once(entry)).await?;
+
+            assert!(db.move_feed(&id, "https://blog.com/atom").await?);
+            let moved = db.get_feed(&id).await?.unwrap();
+            let stream = feed_stream_id(&moved);
+            assert_eq!(stream, "feed/https://blog.com/atom");
+            let (entries, _) = stream_page(&db, &stream, &StreamQuery::default())
+                .await
+                .map_err(|e| e.0)?;
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].title, "Post");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn edited_feed_keeps_its_stream() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let mut feed = Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/rss".to_string(),
+                "".to_string(),
+            );
+            db.add_feeds(std::iter::once(feed.clone())).await?;
+            let mut entry = Entry::new(
+                "post",
+                "Post".to_string(),
+                "https://blog.com/post".to_string(),
+                "".to_string(),
+                None,
+            );
+            entry.feed = "Blog".to_string();
+            db.add_entries(std::iter::once(entry)).await?;
+
+            feed.feed_url = "https://blog.com/feed.xml".to_string();
+            assert!(db.update_feed(&feed).await?);
+            let stream = feed_stream_id(&feed);
+            assert!(matches!(
+                stream_filter(&db, &stream).await.map_err(|e| e.0)?,
+                EntryFilter::Feed(id) if id == feed.id
+            ));
+            let (entries, _) = stream_page(&db, &stream, &StreamQuery::default())
+                .await
+                .map_err(|e| e.0)?;
+            assert_eq!(entries.len(), 1);
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,136 @@
+//! Feed icons, fetched from each feed's site as it's refreshed and kept in the
+//! database so lists don't send every reader off to every site for favicons.
+use anyhow::{anyhow, Result};
+use axum::extract::{Path, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use chrono::Utc;
+use tracing::{error, info};
+
+use crate::{db, AppError, AppState, Feed, Icon, UtcTime};
+
+/// Icons are fetched again after this many days, to pick up a site changing its own.
+const ICON_MAX_AGE_DAYS: i64 = 7;
+
+/// Anything bigger isn't an icon.
+const MAX_ICON_BYTES: usize = 100 * 1024;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/icons/:feed_id", get(feed_icon))
+}
+
+/// Fetches a feed's icon unless it was fetched recently. Sites without one are
+/// remembered as such, so they're only asked again once the icon's gone stale.
+pub async fn update(db: &db::DB, client: &reqwest::Client, f: &Feed) {
+    let stale = UtcTime(Utc::now() - chrono::Duration::days(ICON_MAX_AGE_DAYS));
+    match db.icon_fetched_since(&f.id, stale).await {
+        Ok(true) => return,
+        Ok(false) => {}
+        Err(e) => {
+            error!("couldn't check the icon for {}, {:?}", f.name, e);
+            return;
+        }
+    }
+    let url = f.icon();
+    let icon = match fetch(client, &url).await {
+        Ok(icon) => icon,
+        Err(e) => {
+            info!("no icon for {} at {}: {:#}", f.name, url, e);
+            Icon::default()
+        }
+    };
+    if let Err(e) = db.set_icon(&f.id, icon).await {
+        error!("couldn't store the icon for {}, {:?}", f.name, e);
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Icon> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    let content_type = image_type(
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok()),
+    )?;
+    if response
+        .content_length()
+        .is_some_and(|l| l > MAX_ICON_BYTES as u64)
+    {
+        return Err(anyhow!("it's too big"));
+    }
+    let data = response.bytes().await?;
+    if data.is_empty() || data.len() > MAX_ICON_BYTES {
+        return Err(anyhow!("it's {} bytes", data.len()));
+    }
+    Ok(Icon {
+        content_type: Some(content_type),
+        data: Some(data.to_vec()),
+    })
+}
+
+/// The type of image a response says it is. Plenty of sites answer a missing
+/// favicon with a page, which isn't one.
+fn image_type(content_type: Option<&str>) -> Result<String> {
+    let Some(content_type) = content_type else {
+        // favicon.ico is often served without saying what it is
+        return Ok("image/x-icon".to_string());
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    if essence.starts_with("image/") {
+        Ok(essence)
+    } else {
+        Err(anyhow!("it's {} rather than an image", essence))
+    }
+}
+
+/// Serves a feed's cached icon. Before it's been fetched, this sends browsers
+/// to where it'll be fetched from.
+async fn feed_icon(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    match db.get_icon(&feed_id).await? {
+        Some(Icon {
+            content_type: Some(content_type),
+            data: Some(data),
+        }) => Ok((
+            [
+                (CONTENT_TYPE, content_type),
+                (CACHE_CONTROL, "public, max-age=86400".to_string()),
+                // an svg could have scripts in it
+                (CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+            ],
+            data,
+        )
+            .into_response()),
+        Some(_) => Ok(StatusCode::NOT_FOUND.into_response()),
+        None => match db.get_feed(&feed_id).await? {
+            Some(feed) => Ok(Redirect::temporary(&feed.icon()).into_response()),
+            None => Ok(StatusCode::NOT_FOUND.into_response()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_images_are_icons() {
+        assert_eq!(image_type(Some("image/png")).unwrap(), "image/png");
+        assert_eq!(
+            image_type(Some("Image/SVG+XML; charset=utf-8")).unwrap(),
+            "image/svg+xml"
+        );
+        assert_eq!(image_type(None).unwrap(), "image/x-icon");
+        assert!(image_type(Some("text/html; charset=utf-8")).is_err());
+    }
+}
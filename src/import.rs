@@ -0,0 +1,254 @@
+//! OPML imports, added in the background one feed at a time so a big file doesn't
+//! hold up startup and a bad feed doesn't sink the rest of it.
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use askama_axum::Template;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::jobs::Jobs;
+use crate::{db, filters, user, AppState, Feed, UtcTime};
+
+/// How many imports are kept around to look at once they've finished.
+const KEPT_IMPORTS: usize = 20;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/imports", get(list_imports))
+        .route("/imports/:import_id", get(import_status))
+        .route("/import/:import_id", get(import_page))
+}
+
+/// How adding one feed from the file went.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedResult {
+    name: String,
+    feed_url: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportStatus {
+    id: String,
+    /// The file imported from
+    source: String,
+    started_at: UtcTime,
+    finished_at: Option<UtcTime>,
+    total: usize,
+    results: Vec<FeedResult>,
+}
+
+impl ImportStatus {
+    fn failed(&self) -> usize {
+        self.results.iter().filter(|r| r.error.is_some()).count()
+    }
+}
+
+/// Imports that are running or recently finished, shared with request handlers.
+#[derive(Clone)]
+pub struct Imports {
+    statuses: Arc<Mutex<Vec<ImportStatus>>>,
+    /// Where added feeds are queued for their first fetch
+    jobs: Jobs,
+}
+
+impl Imports {
+    pub fn new(jobs: Jobs) -> Imports {
+        Imports {
+            statuses: Arc::default(),
+            jobs,
+        }
+    }
+
+    /// Starts adding the parsed outlines of a file for `user`, returning the
+    /// import's id. Outlines that couldn't be parsed are reported as failures with the rest.
+    pub fn start(
+        &self,
+        db: db::DB,
+        user: i64,
+        source: String,
+        outlines: Vec<(String, Result<Feed>)>,
+    ) -> String {
+        let id = Uuid::new_v4().to_string();
+        info!("importing {} feeds from {}", outlines.len(), source);
+        {
+            let mut imports = self.statuses.lock().unwrap();
+            imports.push(ImportStatus {
+                id: id.clone(),
+                source,
+                started_at: UtcTime(Utc::now()),
+                finished_at: None,
+                total: outlines.len(),
+                results: vec![],
+            });
+            while imports.len() > KEPT_IMPORTS {
+                match imports.iter().position(|i| i.finished_at.is_some()) {
+                    Some(oldest) => imports.remove(oldest),
+                    None => break,
+                };
+            }
+        }
+        tokio::spawn(user::scope(
+            user,
+            self.clone().run(db, id.clone(), outlines),
+        ));
+        id
+    }
+
+    async fn run(self, db: db::DB, id: String, outlines: Vec<(String, Result<Feed>)>) {
+        for (name, feed) in outlines {
+            let result = match feed {
+                Ok(feed) => {
+                    let feed_url = feed.feed_url.clone();
+                    let feed_id = feed.id.clone();
+                    let added = db.add_feeds(std::iter::once(feed)).await;
+                    if added.is_ok() {
+                        self.jobs.first_fetch(&feed_id);
+                    }
+                    FeedResult {
+                        name,
+                        feed_url: Some(feed_url),
+                        error: added.err().map(|e| format!("{:#}", e)),
+                    }
+                }
+                Err(e) => FeedResult {
+                    name,
+                    feed_url: None,
+                    error: Some(format!("{:#}", e)),
+                },
+            };
+            if let Some(error) = &result.error {
+                warn!("couldn't import {}: {}", result.name, error);
+            }
+            self.update(&id, |status| status.results.push(result));
+        }
+        self.update(&id, |status| {
+            status.finished_at = Some(UtcTime(Utc::now()));
+            info!(
+                "imported {} of {} feeds from {}",
+                status.total - status.failed(),
+                status.total,
+                status.source
+            );
+        });
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut ImportStatus)) {
+        if let Some(status) = self
+            .statuses
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|i| i.id == id)
+        {
+            f(status);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<ImportStatus> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.id == id)
+            .cloned()
+    }
+
+    /// Newest first.
+    pub fn list(&self) -> Vec<ImportStatus> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
+
+async fn list_imports(State(AppState { imports, .. }): State<AppState>) -> Json<Vec<ImportStatus>> {
+    Json(imports.list())
+}
+
+async fn import_status(
+    Path(import_id): Path<String>,
+    State(AppState { imports, .. }): State<AppState>,
+) -> Response {
+    match imports.get(&import_id) {
+        Some(status) => Json(status).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "import.html")]
+struct ImportTemplate {
+    import: ImportStatus,
+}
+
+async fn import_page(
+    Path(import_id): Path<String>,
+    State(AppState { imports, .. }): State<AppState>,
+) -> Response {
+    match imports.get(&import_id) {
+        Some(import) => ImportTemplate { import }.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::db::{connect, ConnectionBacking};
+
+    #[tokio::test]
+    async fn bad_feeds_dont_stop_an_import() -> Result<()> {
+        let db = connect(ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let (jobs, _queue) = Jobs::new();
+        let imports = Imports::new(jobs.clone());
+        let feed = Feed::new(
+            "HackerNews".to_string(),
+            "https://news.ycombinator.com".to_string(),
+            "https://news.ycombinator.com/rss".to_string(),
+            "tech".to_string(),
+        );
+        let id = imports.start(
+            db.clone(),
+            user::OWNER,
+            "feeds.opml".to_string(),
+            vec![
+                (
+                    "Broken".to_string(),
+                    Err(anyhow!("Missing xml url in feed Broken")),
+                ),
+                ("HackerNews".to_string(), Ok(feed)),
+            ],
+        );
+
+        let status = loop {
+            let status = imports.get(&id).unwrap();
+            if status.finished_at.is_some() {
+                break status;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(status.total, 2);
+        assert_eq!(status.failed(), 1);
+        let feeds = db.get_feeds().await?;
+        assert_eq!(feeds.len(), 1);
+        // only the feed that was added waits for a first fetch
+        assert!(jobs.waiting(&feeds[0].id));
+        Ok(())
+    }
+}
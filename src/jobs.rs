@@ -0,0 +1,162 @@
+//! Work queued to run in the background one piece at a time, spaced out so a
+//! burst of it, like importing hundreds of feeds, doesn't all hit the network at once.
+//! Fetches someone asked for go ahead of everything else queued.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time;
+
+use crate::refresh::Refresher;
+
+#[derive(Debug, PartialEq)]
+pub enum Job {
+    /// The first fetch of the feed with this id, just after it was added
+    FirstFetch(String),
+    /// A fetch of the feed with this id that someone's waiting on
+    Fetch(String),
+}
+
+impl Job {
+    fn feed_id(&self) -> &str {
+        match self {
+            Job::FirstFetch(feed_id) | Job::Fetch(feed_id) => feed_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Priority {
+    /// Asked for by someone, run next and without spacing
+    Requested,
+    /// Queued by the server itself, spaced out
+    Background,
+}
+
+/// Queues jobs for `run` to work through.
+#[derive(Clone)]
+pub struct Jobs {
+    requested: mpsc::UnboundedSender<Job>,
+    background: mpsc::UnboundedSender<Job>,
+    /// Feeds with a fetch queued, which refresh passes leave alone
+    waiting: Arc<Mutex<HashMap<String, Priority>>>,
+}
+
+/// The receiving end of `Jobs`, requested jobs first.
+pub struct Queue {
+    requested: mpsc::UnboundedReceiver<Job>,
+    background: mpsc::UnboundedReceiver<Job>,
+}
+
+impl Queue {
+    async fn next(&mut self) -> Option<Job> {
+        tokio::select! {
+            biased;
+            Some(job) = self.requested.recv() => Some(job),
+            Some(job) = self.background.recv() => Some(job),
+            else => None,
+        }
+    }
+}
+
+impl Jobs {
+    pub fn new() -> (Jobs, Queue) {
+        let (requested, requested_queue) = mpsc::unbounded_channel();
+        let (background, background_queue) = mpsc::unbounded_channel();
+        let jobs = Jobs {
+            requested,
+            background,
+            waiting: Arc::default(),
+        };
+        let queue = Queue {
+            requested: requested_queue,
+            background: background_queue,
+        };
+        (jobs, queue)
+    }
+
+    /// Queues a feed's first fetch, unless it's already waiting for one.
+    pub fn first_fetch(&self, feed_id: &str) {
+        self.queue(Job::FirstFetch(feed_id.to_string()), Priority::Background);
+    }
+
+    /// Queues a fetch ahead of everything else, including a feed's first fetch.
+    pub fn fetch(&self, feed_id: &str) {
+        self.queue(Job::Fetch(feed_id.to_string()), Priority::Requested);
+    }
+
+    fn queue(&self, job: Job, priority: Priority) {
+        let feed_id = job.feed_id().to_string();
+        let mut waiting = self.waiting.lock().unwrap();
+        match waiting.get(&feed_id) {
+            // a requested fetch is already as soon as it gets
+            Some(Priority::Requested) => return,
+            Some(Priority::Background) if priority == Priority::Background => return,
+            _ => {}
+        }
+        let sender = match priority {
+            Priority::Requested => &self.requested,
+            Priority::Background => &self.background,
+        };
+        // when nothing's working through the queue, it's left to the next pass
+        if sender.send(job).is_ok() {
+            waiting.insert(feed_id, priority);
+        }
+    }
+
+    /// Whether a feed is still queued for a fetch.
+    pub fn waiting(&self, feed_id: &str) -> bool {
+        self.waiting.lock().unwrap().contains_key(feed_id)
+    }
+
+    /// Takes a feed off the waiting list, returning how it was queued. A
+    /// background job whose feed was fetched on request since isn't on it.
+    fn finished(&self, feed_id: &str) -> Option<Priority> {
+        self.waiting.lock().unwrap().remove(feed_id)
+    }
+}
+
+/// Works through queued jobs, requested ones first, waiting `spacing` after each
+/// background one.
+pub async fn run(jobs: Jobs, mut queue: Queue, refresher: Arc<Refresher>, spacing: Duration) {
+    while let Some(job) = queue.next().await {
+        // taken off the list before fetching, so a request made meanwhile queues again
+        let Some(priority) = jobs.finished(job.feed_id()) else {
+            continue;
+        };
+        refresher.fetch(job.feed_id()).await;
+        if priority == Priority::Background {
+            time::sleep(spacing).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn requested_fetches_go_first() {
+        let (jobs, mut queue) = Jobs::new();
+        jobs.first_fetch("a");
+        jobs.first_fetch("a");
+        jobs.first_fetch("b");
+        jobs.fetch("b");
+        jobs.fetch("b");
+        assert!(jobs.waiting("a"));
+        assert_eq!(queue.next().await, Some(Job::Fetch("b".to_string())));
+        assert_eq!(queue.next().await, Some(Job::FirstFetch("a".to_string())));
+        assert_eq!(queue.next().await, Some(Job::FirstFetch("b".to_string())));
+        assert!(queue.requested.try_recv().is_err());
+        assert!(queue.background.try_recv().is_err());
+
+        // b was fetched on request, so its first fetch is skipped
+        assert_eq!(jobs.finished("b"), Some(Priority::Requested));
+        assert_eq!(jobs.finished("b"), None);
+        assert!(!jobs.waiting("b"));
+        drop(queue);
+        jobs.first_fetch("c");
+        assert!(!jobs.waiting("c"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Types for [JSON Feed 1.1](https://jsonfeed.org/version/1.1).
+use serde::{Deserialize, Serialize};
+
+use crate::Entry;
+
+pub const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct JsonFeed {
+    pub version: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_page_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feed_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<Author>,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Item {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_html: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_published: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<Author>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Author {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Attachment {
+    pub url: String,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_in_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_in_seconds: Option<f64>,
+}
+
+impl From<&Entry> for Item {
+    fn from(e: &Entry) -> Self {
+        let mut content = format!("{}\n{}", e.title, e.content_link);
+        if !e.comments_link.is_empty() {
+            content.push_str(&format!("\nComments: {}", e.comments_link));
+        }
+        Item {
+            id: e.id.clone(),
+            url: Some(e.content_link.clone()),
+            external_url: (!e.comments_link.is_empty()).then(|| e.comments_link.clone()),
+            title: Some(e.title.clone()),
+            content_text: Some(content),
+            date_published: e.published.as_ref().map(|p| p.0.to_rfc3339()),
+            tags: vec![e.feed.clone()],
+            ..Default::default()
+        }
+    }
+}
+
+impl JsonFeed {
+    pub fn new(title: &str, entries: &[Entry]) -> Self {
+        JsonFeed {
+            version: VERSION.to_string(),
+            title: title.to_string(),
+            items: entries.iter().map(Item::from).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn serialize_starred_feed() {
+        let mut entry = Entry::new(
+            "my-entry",
+            "Cool Post".to_string(),
+            "https://content.com/1".to_string(),
+            "https://news.ycombinator.com/item?id=1".to_string(),
+            Some(Utc::now().into()),
+        );
+        entry.feed = "HackerNews".to_string();
+
+        let feed = JsonFeed::new("Starred", &[entry]);
+        let json = serde_json::to_value(feed).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["items"][0]["url"], "https://content.com/1");
+        assert_eq!(json["items"][0]["tags"][0], "HackerNews");
+        assert!(json["items"][0]["content_text"].is_string());
+        assert!(json.get("authors").is_none());
+    }
+}
@@ -0,0 +1,86 @@
+//! Periodically checks that entries anyone starred still link somewhere, flagging the
+//! ones that don't so they can be read from an archived copy instead.
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::maintenance::ReadOnly;
+use crate::{db, Entry};
+
+/// Checks every starred entry's content link once per `period`.
+pub async fn run(db: db::DB, client: reqwest::Client, period: Duration, read_only: ReadOnly) {
+    let mut interval = time::interval(period);
+    loop {
+        interval.tick().await;
+        if read_only.enabled() {
+            continue;
+        }
+        if let Err(e) = check_starred(&db, &client).await {
+            error!("couldn't check starred links: {:?}", e);
+        }
+    }
+}
+
+async fn check_starred(db: &db::DB, client: &reqwest::Client) -> anyhow::Result<()> {
+    let entries = db.get_entries_starred_by_anyone().await?;
+    let (mut dead, mut unreachable) = (0, 0);
+    for entry in entries.iter().filter(|e| !e.content_link.is_empty()) {
+        let status = link_status(client, entry).await;
+        let is_dead = link_dead(status);
+        match is_dead {
+            Some(true) => dead += 1,
+            Some(false) => {}
+            None => unreachable += 1,
+        }
+        db.set_link_status(&entry.id, status.map(|s| s.as_u16()), is_dead)
+            .await?;
+    }
+    info!(
+        "checked {} starred links, {} dead, {} unreachable",
+        entries.len(),
+        dead,
+        unreachable
+    );
+    Ok(())
+}
+
+/// The status the link answers with, trying a GET if HEAD isn't allowed.
+async fn link_status(client: &reqwest::Client, entry: &Entry) -> Option<StatusCode> {
+    let head = client.head(&entry.content_link).send().await.ok()?;
+    match head.status() {
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => client
+            .get(&entry.content_link)
+            .send()
+            .await
+            .ok()
+            .map(|r| r.status()),
+        status => Some(status),
+    }
+}
+
+/// Whether a link is gone for good. Server errors and refusals (often just bots
+/// being turned away) could pass, so only missing pages count. Not getting an
+/// answer at all could be a blip on either end, so that's `None`, unknown.
+fn link_dead(status: Option<StatusCode>) -> Option<bool> {
+    status.map(|status| status == StatusCode::NOT_FOUND || status == StatusCode::GONE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dead_link_statuses() {
+        assert_eq!(link_dead(None), None);
+        assert_eq!(link_dead(Some(StatusCode::NOT_FOUND)), Some(true));
+        assert_eq!(link_dead(Some(StatusCode::GONE)), Some(true));
+        assert_eq!(link_dead(Some(StatusCode::OK)), Some(false));
+        assert_eq!(link_dead(Some(StatusCode::FORBIDDEN)), Some(false));
+        assert_eq!(
+            link_dead(Some(StatusCode::SERVICE_UNAVAILABLE)),
+            Some(false)
+        );
+    }
+}
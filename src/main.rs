@@ -1,31 +1,33 @@
 #![warn(clippy::needless_pass_by_value)]
 
+use std::fmt;
 use std::fs::File;
 use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Duration;
-use std::{env, fmt};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 
 use axum::body::Body;
-use axum::extract::{MatchedPath, State};
+use axum::extract::{DefaultBodyLimit, MatchedPath, Query, State};
 use axum::http::header::{
-    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE, ORIGIN, REFERER,
-    USER_AGENT,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, CONTENT_DISPOSITION,
+    CONTENT_TYPE, ORIGIN, REFERER, USER_AGENT,
 };
 use axum::http::{Method, Request, Response, StatusCode};
 use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::{http, Json, Router};
+use axum::routing::{get, post};
+use axum::{http, middleware, Extension, Json, Router};
 use chrono::{DateTime, Utc};
 use chrono_humanize::HumanTime;
-use db::TursoCreds;
-use feed_rs::parser;
+use clap::Parser;
 use opml::OPML;
 use serde::{Deserialize, Serialize};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::time;
-use tokio_stream::wrappers::{IntervalStream, SignalStream};
+use tokio_stream::wrappers::{IntervalStream, ReceiverStream, SignalStream};
 
 use futures::stream::StreamExt;
 use futures::{future, stream};
@@ -34,13 +36,51 @@ use http::header::{ACCEPT, AUTHORIZATION};
 use lazy_static::lazy_static;
 use regex::Regex;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 use tracing::{error, info, info_span};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+mod api;
+mod auth;
+mod base_path;
+mod category;
+mod cli;
+mod config;
 mod db;
+mod debug_dump;
+mod digest;
+mod doctor;
+mod extract;
+mod filters;
+mod greader;
+mod icon;
+mod import;
+mod jobs;
+mod jsonfeed;
+mod linkcheck;
+mod maintenance;
+mod notify;
+mod parse;
+mod podcast;
+mod preview;
+mod proxy;
+mod prune;
+mod public_url;
+mod refresh;
+mod request_id;
+mod resurface;
+mod rules;
+mod sanitize;
+mod secrets;
+mod settings;
+mod shutdown;
+mod suggest;
+mod user;
 mod view;
+mod wayback;
+mod webhooks;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(transparent)]
@@ -58,9 +98,11 @@ impl fmt::Display for UtcTime {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Serialize)]
 struct Healthz {
     up: bool,
+    read_only: bool,
+    refresh: refresh::RefreshHealth,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -77,7 +119,142 @@ struct Feed {
     feed_url: String,
     last_fetched: Option<UtcTime>,
     fetch_error: Option<String>,
+    /// The name of the feed's category, blank when it has none
     category: String,
+    #[serde(default)]
+    category_id: Option<i64>,
+    icon_url: Option<String>,
+    /// Validators from the last full response, sent back to skip unchanged feeds
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_length: Option<u64>,
+    /// Failing feeds are skipped until `next_retry_at`, backing off as failures pile up
+    #[serde(default)]
+    consecutive_failures: u32,
+    next_retry_at: Option<UtcTime>,
+    /// The newest entry stored so far, older ones in later fetches are skipped
+    last_entry_published: Option<UtcTime>,
+    last_entry_id: Option<String>,
+    /// Entries are listed as title and time only, and marked read once shown
+    #[serde(default)]
+    compact: bool,
+    /// When to fetch full articles for entries, see `extract::ExtractMode`
+    #[serde(default)]
+    extract_mode: String,
+    /// Entries are pushed in through the API instead of fetched from `feed_url`
+    #[serde(default)]
+    push: bool,
+    /// Paused feeds keep their entries but aren't fetched
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+    #[serde(default)]
+    statuses: Vec<FetchStatus>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// A group of feeds, named in one place so renaming it renames it everywhere.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+struct Category {
+    id: i64,
+    name: String,
+    /// How many feeds are in it
+    #[serde(default)]
+    feeds: u32,
+    /// Whether its unread entries go in the email digest
+    #[serde(default)]
+    digest: bool,
+}
+
+/// Entries from several categories read as one, e.g. blogs and newsletters but not news.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+struct CombinedView {
+    id: String,
+    name: String,
+    /// Categories to take entries from, every one when empty
+    categories: Vec<String>,
+    /// Categories left out even when `categories` is empty
+    excluded: Vec<String>,
+}
+
+/// A named combination of filters kept as a shortcut in the nav, e.g. unread,
+/// starred entries in tech from the last day.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+struct SavedFilter {
+    id: String,
+    name: String,
+    unread: bool,
+    starred: bool,
+    category: Option<String>,
+    /// Entries from the feed with this id
+    feed_id: Option<String>,
+    /// Entries published within this many hours
+    since_hours: Option<u32>,
+}
+
+/// A rule applied to entries as they're stored, e.g. dropping sponsored posts
+/// from one feed. The field and action are checked by `rules`.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+struct FilterRule {
+    id: String,
+    /// Matched case insensitively anywhere in `field`
+    pattern: String,
+    field: String,
+    /// Only entries from the feed with this id, any feed's when missing
+    feed_id: Option<String>,
+    /// The name of that feed, looked up when listing rules
+    #[serde(default)]
+    feed: Option<String>,
+    action: String,
+}
+
+/// Where new entries are posted as they're stored, for every feed or just the
+/// one feed or category given. Checked and sent by `webhooks`.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+struct Webhook {
+    id: String,
+    url: String,
+    feed_id: Option<String>,
+    /// The name of that feed, looked up when listing webhooks
+    #[serde(default)]
+    feed: Option<String>,
+    category_id: Option<i64>,
+    /// The name of that category, looked up like `feed`
+    #[serde(default)]
+    category: Option<String>,
+}
+
+/// A token created on the tokens page. Only a hash of its secret is kept.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ApiToken {
+    id: String,
+    name: String,
+    scope: String,
+    created_at: UtcTime,
+    last_used_at: Option<UtcTime>,
+}
+
+/// Someone sharing the server, with their own read and starred entries.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct User {
+    id: i64,
+    username: String,
+}
+
+/// The outcome of a single fetch of a feed, oldest first when listed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct FetchStatus {
+    status: Option<u16>,
+    fetch_error: Option<String>,
+    created_at: UtcTime,
+}
+
+impl FetchStatus {
+    fn ok(&self) -> bool {
+        self.fetch_error.is_none()
+    }
 }
 
 impl Feed {
@@ -88,13 +265,101 @@ impl Feed {
             site_url,
             feed_url,
             category,
+            enabled: true,
             ..Default::default()
         }
     }
+
+    pub fn extract_mode(&self) -> extract::ExtractMode {
+        self.extract_mode.parse().unwrap_or_default()
+    }
+
+    /// Whether `mode` is the feed's extract mode, for picking it out in forms.
+    pub fn extracts(&self, mode: &str) -> bool {
+        mode.parse()
+            .is_ok_and(|m: extract::ExtractMode| m == self.extract_mode())
+    }
+
+    /// The custom icon if one is set, otherwise the site's favicon.
+    pub fn icon(&self) -> String {
+        if let Some(icon) = &self.icon_url {
+            return icon.clone();
+        }
+        reqwest::Url::parse(&self.site_url)
+            .and_then(|u| u.join("/favicon.ico"))
+            .map(|u| u.to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// A feed's site icon as it was fetched.
+#[derive(Debug, Default, Clone)]
+struct Icon {
+    content_type: Option<String>,
+    /// Missing when the site didn't have one
+    data: Option<Vec<u8>>,
+}
+
+/// How many entries were published in a given month.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ArchiveMonth {
+    year: i32,
+    month: u32,
+    count: u32,
+}
+
+impl ArchiveMonth {
+    fn label(&self) -> String {
+        let name = u8::try_from(self.month)
+            .ok()
+            .and_then(|m| chrono::Month::try_from(m).ok())
+            .map(|m| m.name())
+            .unwrap_or("Unknown");
+        format!("{} {}", name, self.year)
+    }
+
+    fn path(&self) -> String {
+        format!("/archive/{}/{:02}.html", self.year, self.month)
+    }
+}
+
+/// A count of something that happened on a given day (YYYY-MM-DD).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct DayCount {
+    date: String,
+    count: u32,
+}
+
+/// A feed's entries, counted for the current user.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+struct EntryCounts {
+    total: u32,
+    unread: u32,
+    starred: u32,
+    hidden: u32,
+}
+
+/// How many unread entries a feed has, for seeing where the backlog is.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+struct FeedStats {
+    feed_id: String,
+    /// Empty when the feed isn't in one
+    category: String,
+    unread: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Heatmap {
+    from: String,
+    to: String,
+    days: Vec<DayCount>,
 }
 
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 struct Entry {
+    /// The row id, the numeric id Google Reader clients know the entry by
+    #[serde(skip_serializing, default)]
+    item_id: i64,
     id: String,
     title: String,
     content_link: String,
@@ -103,7 +368,43 @@ struct Entry {
     published: Option<UtcTime>,
     read: bool,
     starred: bool,
+    /// Left out of every list but the hidden one, without being removed
+    #[serde(default)]
+    hidden: bool,
+    /// Seconds into the enclosure the current user got, to resume from
+    #[serde(default)]
+    playback_position: f64,
     feed: String,
+    duplicate_of: Option<String>,
+    author: Option<String>,
+    enclosure_url: Option<String>,
+    enclosure_type: Option<String>,
+    /// The category of the feed this came from, looked up when listing entries
+    category: Option<String>,
+    /// Whether the feed this came from is shown compactly, looked up like `category`
+    #[serde(default)]
+    compact: bool,
+    /// The extract mode of the feed this came from, looked up like `category`
+    extract_mode: Option<String>,
+    /// The id of the feed this came from, looked up like `category`
+    feed_id: Option<String>,
+    /// The entry's content or summary as the feed last sent it
+    content: Option<String>,
+    /// The start of the content as plain text, for lists
+    excerpt: Option<String>,
+    /// Set when a check of a starred entry found its content link gone
+    #[serde(default)]
+    link_dead: bool,
+    /// How many earlier versions of the content were kept
+    #[serde(default)]
+    revisions: u32,
+}
+
+/// A version of an entry's content from before the feed changed it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct EntryRevision {
+    content: String,
+    created_at: UtcTime,
 }
 
 impl Entry {
@@ -125,6 +426,22 @@ impl Entry {
             ..Default::default()
         }
     }
+
+    pub fn extract_mode(&self) -> extract::ExtractMode {
+        self.extract_mode
+            .as_deref()
+            .and_then(|m| m.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Where to read the entry once its link is dead, the robust link if it has one
+    /// and the Wayback Machine's latest snapshot otherwise.
+    pub fn archived_link(&self) -> String {
+        if !self.robust_link.is_empty() {
+            return self.robust_link.clone();
+        }
+        format!("https://web.archive.org/web/{}", self.content_link)
+    }
 }
 
 impl From<&feed_rs::model::Entry> for Entry {
@@ -168,26 +485,50 @@ impl From<&feed_rs::model::Entry> for Entry {
             e.updated.map(UtcTime)
         };
 
-        Entry::new(
+        let mut entry = Entry::new(
             &e.id,
             title.to_string(),
             content_link,
             comments_link,
             published,
-        )
+        );
+        entry.author = e.authors.first().map(|a| a.name.clone());
+        entry.content = e
+            .content
+            .as_ref()
+            .and_then(|c| c.body.clone())
+            .or_else(|| e.summary.as_ref().map(|s| s.content.clone()));
+        let enclosure = e.media.iter().flat_map(|m| m.content.iter()).next();
+        if let Some(enclosure) = enclosure {
+            entry.enclosure_url = enclosure.url.as_ref().map(|u| u.to_string());
+            entry.enclosure_type = enclosure.content_type.as_ref().map(|t| t.to_string());
+        }
+        entry
     }
 }
 
 #[derive(Debug)]
 struct AppError(anyhow::Error);
 
+/// Why a request failed. Sent as JSON, and kept on the response so
+/// `view::error_pages` can render it for browsers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ErrorDetails {
+    error: String,
+    request_id: String,
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response<Body> {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        error!("request failed: {:#}", self.0);
+        let details = ErrorDetails {
+            error: format!("Something went wrong: {}", self.0),
+            request_id: request_id::current().unwrap_or_default(),
+        };
+        let mut response =
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(details.clone())).into_response();
+        response.extensions_mut().insert(details);
+        response
     }
 }
 
@@ -200,165 +541,353 @@ where
     }
 }
 
+const LISTEN_ADDR: &str = "0.0.0.0:3030";
+
 #[derive(Clone)]
 pub struct AppState {
     db: db::DB,
+    refresh: refresh::RefreshHandle,
+    tokens: auth::Tokens,
+    /// For fetching pages to show, like full articles, while the refresher
+    /// polls feeds with its own
+    client: reqwest::Client,
+    /// Characters of text kept for entry excerpts, for entries pushed through the API
+    excerpt_length: usize,
+    /// Characters of entry titles shown in lists, the rest left to the entry page
+    title_length: usize,
+    read_only: maintenance::ReadOnly,
+    imports: import::Imports,
+    jobs: jobs::Jobs,
+    /// Needed for anything that changes state, when set
+    login: Option<Arc<auth::Login>>,
+    throttle: auth::LoginThrottle,
+    extractions: extract::Extractions,
+    previews: preview::Fetcher,
+    /// What stored credentials, like webhook secrets, are encrypted with
+    secret_key: Option<Arc<secrets::SecretKey>>,
+}
+
+/// Why the server couldn't start. Each kind exits with its own code from
+/// sysexits.h so supervisors can tell a bad config from a missing database.
+#[derive(Debug)]
+enum StartupError {
+    Config(anyhow::Error),
+    Database(anyhow::Error),
+    Opml(anyhow::Error),
+    Os(anyhow::Error),
+}
+
+impl StartupError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            StartupError::Config(_) => 78,
+            StartupError::Database(_) => 69,
+            StartupError::Opml(_) => 65,
+            StartupError::Os(_) => 71,
+        }
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupError::Config(e) => write!(f, "invalid configuration: {:#}", e),
+            StartupError::Database(e) => write!(f, "database unavailable: {:#}", e),
+            StartupError::Opml(e) => write!(f, "couldn't load feeds: {:#}", e),
+            StartupError::Os(e) => write!(f, "{:#}", e),
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "feedreader=info".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        // stdout is for commands' output, like export's OPML
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
-    let db = if let Some(creds) = TursoCreds::from_env() {
-        if let Ok(db_path) = env::var("FEED_DB_PATH") {
-            db::connect(db::ConnectionBacking::RemoteReplica(creds, db_path))
-        } else {
-            db::connect(db::ConnectionBacking::Remote(creds))
+    let cli = cli::Cli::parse();
+    let config_path = cli.config.as_deref();
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => match serve(config_path).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("couldn't start: {}", e);
+                ExitCode::from(e.exit_code())
+            }
+        },
+        cli::Command::Doctor => {
+            if user::scope(user::OWNER, doctor::run(config_path)).await {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
         }
-    } else if let Ok(db_path) = env::var("FEED_DB_PATH") {
-        db::connect(db::ConnectionBacking::File(db_path))
-    } else {
-        anyhow::bail!("You must specify one of turso creds or db filepath")
-    };
-
-    let db = db.await.expect("couldn't open db");
-    db.init().await.expect("couldn't init db");
-
-    if let Ok(f) = env::var("FEED_OPML_FILE") {
-        let path = f.clone();
-        let mut file = File::open(path).expect("Couldn't open opml file");
-        let document = OPML::from_reader(&mut file).expect("Couldn't parse opml file");
-
-        let feeds = parse_opml_document(&document).expect("Couldn't parse opml to feeds");
-        db.add_feeds(feeds.into_iter())
-            .await
-            .expect("couldn't add feeds");
-        info!("parsed and loaded {}", f);
+        command => match cli::run(command, config_path).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("{:#}", e);
+                ExitCode::FAILURE
+            }
+        },
     }
+}
 
-    let mut exit = stream::select_all(vec![
-        SignalStream::new(signal(SignalKind::interrupt()).unwrap()),
-        SignalStream::new(signal(SignalKind::terminate()).unwrap()),
-        SignalStream::new(signal(SignalKind::quit()).unwrap()),
-    ]);
-
-    let default_time = 3 * 60;
-    let time_interval = match env::var("FEED_REFRESH_INTERVAL") {
-        Ok(i) => i.parse().unwrap_or(default_time),
-        Err(_) => default_time,
-    };
-    let interval = time::interval(Duration::from_secs(time_interval));
+/// A client for requests made for `purpose`, with the configured pooling.
+fn http_client(
+    config: &config::ClientConfig,
+    purpose: config::Purpose,
+) -> anyhow::Result<reqwest::Client> {
+    client_builder(config, purpose)
+        .build()
+        .context("couldn't build request client")
+}
 
-    let update_db = db.clone();
-    let client = reqwest::Client::builder()
+fn client_builder(
+    config: &config::ClientConfig,
+    purpose: config::Purpose,
+) -> reqwest::ClientBuilder {
+    config
+        .builder(purpose)
         .timeout(Duration::from_secs(120))
         .gzip(true)
         .brotli(true)
+}
+
+/// A refresher set up from the config, with its handle and manual triggers.
+async fn refresher(
+    config: &config::Config,
+    db: db::DB,
+) -> anyhow::Result<(
+    refresh::Refresher,
+    refresh::RefreshHandle,
+    tokio::sync::mpsc::Receiver<Option<String>>,
+)> {
+    let client = http_client(&config.client, config::Purpose::Fetch)?;
+    // the refresher follows redirects itself, to notice feeds that have moved
+    let feeds = client_builder(&config.client, config::Purpose::Fetch)
+        .redirect(reqwest::redirect::Policy::none())
         .build()
-        .expect("couldn't build request client");
-
-    let stream = IntervalStream::new(interval)
-        .take_until(exit.next())
-        .for_each(|_| async {
-            let start = time::Instant::now();
-            let feeds = match update_db.get_feeds().await {
-                Ok(feeds) => feeds,
-                Err(err) => {
-                    error!("couldn't get feeds, {}", err);
-                    return;
-                }
-            };
+        .context("couldn't build request client")?;
+    let pages = http_client(&config.client, config::Purpose::Render)?;
+    let specs = config
+        .notifiers
+        .open(&db, config.secret_key.as_ref())
+        .await?;
+    let notifiers = notify::Notifiers::new(&specs, &client);
+    let mut failure_notifiers = notifiers.select(&config.failure_notifiers)?;
+    if let Some(url) = &config.failure_webhook {
+        failure_notifiers.push(notify::NotifierKind::Webhook(url.clone()).build(&client));
+    }
+    let (refresher, refresh, triggers) =
+        refresh::Refresher::new(db, feeds, config.refresh_interval);
+    let refresher = refresher
+        .with_failure_notifiers(failure_notifiers)
+        .with_schedule(config.schedule)
+        .with_head_probe(config.head_probe)
+        .with_excerpt_length(config.excerpt_length)
+        .with_page_client(pages)
+        .with_body_dumps(config.body_dumps.clone());
+    Ok((refresher, refresh, triggers))
+}
 
-            let mut updated = 0;
-            for f in feeds.iter() {
-                let feed_resp = client.get(&f.feed_url).send().await;
-
-                let feed_resp = match feed_resp {
-                    Ok(r) => r,
-                    Err(_) => {
-                        let _ = update_db
-                            .update_feed_status(
-                                f.id.clone(),
-                                Some("couldn't get response".to_string()),
-                            )
-                            .await;
-                        continue;
-                    }
-                };
-
-                if feed_resp.status() != reqwest::StatusCode::OK {
-                    let _ = update_db
-                        .update_feed_status(f.id.clone(), Some("response code not ok".to_string()))
-                        .await;
-                    continue;
-                }
-                // we don't actually care if this works
-                let _ = update_db.update_feed_status(f.id.clone(), None).await;
-
-                let bytes = feed_resp.bytes().await;
-
-                let body = match bytes {
-                    Ok(b) => b,
-                    Err(_) => {
-                        let _ = update_db
-                            .update_feed_status(
-                                f.id.clone(),
-                                Some("couldn't get bytes".to_string()),
-                            )
-                            .await;
-                        continue;
-                    }
-                };
-
-                let feed = match parser::parse_with_uri(body.as_ref(), Some(&f.feed_url)) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        error!("Couldn't parse feed {}: {}", &f.feed_url, e);
-                        let _ = update_db
-                            .update_feed_status(
-                                f.id.clone(),
-                                Some("couldn't parse feed".to_string()),
-                            )
-                            .await;
-                        continue;
-                    }
-                };
-                let entries: Vec<Entry> = feed
-                    .entries
-                    .iter()
-                    .map(|e| {
-                        let mut o: Entry = e.into();
-                        o.feed.clone_from(&f.name);
-                        o
-                    })
-                    .collect();
-
-                updated += entries.len();
-                if let Err(e) = update_db.add_entries(entries.into_iter()).await {
-                    error!("couldn't update entries, {:?}", e);
-                }
+async fn serve(config_path: Option<&str>) -> Result<(), StartupError> {
+    let config = config::Config::load(config_path).map_err(StartupError::Config)?;
+    doctor::preflight(&config).map_err(StartupError::Config)?;
+    base_path::set(config.base_path.clone());
+    sanitize::configure(config.html_allow.clone());
+    if let Some(url) = &config.public_url {
+        public_url::set(url);
+    }
+    let backing = config.db.to_string();
+    let db = db::connect(config.db.clone())
+        .await
+        .with_context(|| format!("couldn't open {}", backing))
+        .map_err(StartupError::Database)?;
+    db.init()
+        .await
+        .with_context(|| format!("couldn't set up {}", backing))
+        .map_err(StartupError::Database)?;
 
-                // set feed error to empty if we made it this far
-                let _ = update_db.update_feed_status(f.id.clone(), None).await;
-            }
-            info!(
-                "found {} entries in {}s",
-                updated,
-                start.elapsed().as_secs()
-            )
-        });
-    let state = AppState { db };
+    let (jobs, queue) = jobs::Jobs::new();
+    let imports = import::Imports::new(jobs.clone());
+    if let Some(f) = &config.opml_file {
+        let document = read_opml(f)
+            .with_context(|| format!("FEED_OPML_FILE={}", f))
+            .map_err(StartupError::Opml)?;
+        imports.start(
+            db.clone(),
+            user::OWNER,
+            f.clone(),
+            parse_opml_outlines(&document),
+        );
+    }
+
+    let listener = tokio::net::TcpListener::bind(LISTEN_ADDR)
+        .await
+        .with_context(|| format!("couldn't listen on {}", LISTEN_ADDR))
+        .map_err(StartupError::Os)?;
+
+    let (request_shutdown, shutdown) = shutdown::Shutdown::new();
+    let mut exit = stream::select_all(
+        [
+            SignalKind::interrupt(),
+            SignalKind::terminate(),
+            SignalKind::quit(),
+        ]
+        .into_iter()
+        .map(|kind| signal(kind).map(SignalStream::new))
+        .collect::<Result<Vec<_>, _>>()
+        .context("couldn't install signal handlers")
+        .map_err(StartupError::Os)?,
+    );
+    tokio::spawn(async move {
+        exit.next().await;
+        info!("shutting down");
+        let _ = request_shutdown.send(true);
+    });
+
+    let interval = time::interval(config.refresh_interval);
+
+    let pages = http_client(&config.client, config::Purpose::Render).map_err(StartupError::Os)?;
+    let archive =
+        http_client(&config.client, config::Purpose::Archive).map_err(StartupError::Os)?;
+    let (refresher, refresh, triggers) = refresher(&config, db.clone())
+        .await
+        .map_err(StartupError::Os)?;
+    let read_only = maintenance::ReadOnly::default();
+    tokio::spawn(maintenance::toggle_on_signal(read_only.clone()));
+    let excerpt_length = config.excerpt_length;
+    let refresher = refresher
+        .with_read_only(read_only.clone())
+        .with_jobs(jobs.clone())
+        .with_shutdown(shutdown.clone());
+    let refresher = Arc::new(refresher);
+    // background work isn't for anyone logged in, so it's explicitly the owner's
+    tokio::spawn(user::scope(
+        user::OWNER,
+        jobs::run(
+            jobs.clone(),
+            queue,
+            refresher.clone(),
+            config.first_fetch_spacing,
+        ),
+    ));
+
+    let ticks = stream::select(
+        IntervalStream::new(interval).map(|_| None),
+        ReceiverStream::new(triggers),
+    );
+    // a pass underway when shutdown is requested stops after the feed it's on
+    let refreshing = ticks
+        .take_until(shutdown.clone().wait())
+        .for_each(|request_id| user::scope(user::OWNER, refresher.refresh_all(request_id)));
+    if let Some(period) = config.archive_interval {
+        tokio::spawn(user::scope(
+            user::OWNER,
+            wayback::run(db.clone(), archive.clone(), period, read_only.clone()),
+        ));
+    }
+    tokio::spawn(user::scope(
+        user::OWNER,
+        linkcheck::run(
+            db.clone(),
+            archive,
+            config.link_check_interval,
+            read_only.clone(),
+        ),
+    ));
+    if let Some(retention) = config.retention {
+        tokio::spawn(user::scope(
+            user::OWNER,
+            prune::run(db.clone(), retention, read_only.clone()),
+        ));
+    }
+    if let Some(count) = config.resurface_count {
+        tokio::spawn(resurface::run(db.clone(), count, read_only.clone()));
+    }
+    let secret_key = config.secret_key.map(Arc::new);
+    if let Some(key) = &secret_key {
+        let sealed = db
+            .seal_webhook_secrets(key)
+            .await
+            .map_err(StartupError::Database)?;
+        if sealed > 0 {
+            info!("encrypted the secrets of {} webhooks", sealed);
+        }
+    }
+    tokio::spawn(user::scope(
+        user::OWNER,
+        webhooks::run(db.clone(), pages.clone(), secret_key.clone()),
+    ));
+    if let Some(digest) = config.digest {
+        tokio::spawn(user::scope(
+            user::OWNER,
+            digest::run(db.clone(), digest, read_only.clone()),
+        ));
+    }
+    if let Some(login) = &config.login {
+        db.rename_owner(login.username())
+            .await
+            .map_err(StartupError::Database)?;
+    }
+    let state = AppState {
+        db,
+        refresh,
+        tokens: config.tokens,
+        client: pages,
+        excerpt_length,
+        title_length: config.title_length,
+        read_only,
+        imports,
+        jobs,
+        login: config.login.map(Arc::new),
+        throttle: auth::LoginThrottle::default(),
+        extractions: extract::Extractions::default(),
+        previews: preview::Fetcher::new(config.client.user_agent(config::Purpose::Render)),
+        secret_key,
+    };
     let app = Router::new()
         .merge(view::routes())
+        .merge(refresh::routes())
+        .merge(api::routes())
+        .merge(maintenance::routes())
+        .merge(greader::routes())
+        .merge(import::routes())
+        .merge(auth::routes())
+        .merge(user::routes())
+        .merge(category::routes())
+        .merge(icon::routes())
+        .merge(rules::routes())
+        .merge(webhooks::routes())
+        .merge(podcast::routes())
+        .merge(suggest::routes())
+        .merge(preview::routes())
         .route("/healthz", get(healthz))
         .route("/dump", get(dump))
+        .route("/restore", post(restore).layer(DefaultBodyLimit::disable()))
+        .route("/restore/state", post(restore_state))
+        .route("/api/stats/heatmap", get(heatmap))
+        .route("/starred.json", get(starred_json))
+        .route("/export/opml", get(export_opml))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::reject_writes,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_login,
+        ))
         .with_state(state)
+        .layer(middleware::from_fn(view::error_pages))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -374,6 +903,7 @@ async fn main() -> anyhow::Result<()> {
                 ])
                 .allow_methods([Method::GET, Method::HEAD, Method::POST, Method::DELETE]),
         )
+        .layer(middleware::from_fn(request_id::scope))
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
                 // Log the matched route's path (with placeholders not filled in).
@@ -382,72 +912,384 @@ async fn main() -> anyhow::Result<()> {
                     .extensions()
                     .get::<MatchedPath>()
                     .map(MatchedPath::as_str);
+                let request_id = request
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|id| id.to_str().ok());
+                let client_ip = request
+                    .extensions()
+                    .get::<proxy::Client>()
+                    .and_then(|c| c.ip)
+                    .map(tracing::field::display);
 
                 info_span!(
                     "http_request",
                     method = ?request.method(),
-                    matched_path
+                    matched_path,
+                    request_id,
+                    client_ip
                 )
             }),
-        );
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3030")
-        .await
-        .expect("couldn't bind to 3030");
+        )
+        .layer(middleware::from_fn_with_state(
+            config.trusted_proxies,
+            proxy::resolve,
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+    // the prefix comes off before the app's own routing, so it never sees it
+    let app = if config.base_path.is_empty() {
+        app
+    } else {
+        info!("serving under {}", config.base_path);
+        Router::new()
+            .fallback_service(app)
+            .layer(middleware::from_fn(base_path::strip))
+    };
 
-    future::select(
-        Box::pin(stream),
-        Box::pin(axum::serve(listener, app).into_future()),
+    let serving = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .await;
+    .with_graceful_shutdown(shutdown.clone().wait())
+    .into_future();
+    let running = future::join(refreshing, serving);
+    let grace = async {
+        shutdown.wait().await;
+        time::sleep(shutdown::GRACE).await;
+    };
+    match future::select(Box::pin(running), Box::pin(grace)).await {
+        future::Either::Left(((_, Err(e)), _)) => error!("server stopped: {}", e),
+        future::Either::Left(_) => {}
+        future::Either::Right(_) => error!(
+            "requests or refreshing didn't finish within {}s, stopping anyway",
+            shutdown::GRACE.as_secs()
+        ),
+    }
+    // entries that couldn't be stored during the last pass would otherwise be lost
+    let stored = refresher.store_pending().await;
+    if stored > 0 {
+        info!("stored {} entries kept from earlier passes", stored);
+    }
     Ok(())
 }
 
-async fn healthz() -> Json<Healthz> {
-    Json(Healthz { up: true })
+/// Reads an OPML file of feeds.
+fn read_opml(path: &str) -> anyhow::Result<OPML> {
+    let mut file = File::open(path).context("couldn't open opml file")?;
+    OPML::from_reader(&mut file).context("couldn't parse opml file")
+}
+
+async fn healthz(
+    State(AppState {
+        refresh, read_only, ..
+    }): State<AppState>,
+) -> Json<Healthz> {
+    Json(Healthz {
+        up: true,
+        read_only: read_only.enabled(),
+        refresh: refresh.health(),
+    })
 }
 
-async fn dump(State(AppState { db }): State<AppState>) -> Result<Json<Dump>, AppError> {
+/// How many entries a dump reads from the database at a time.
+const DUMP_PAGE_SIZE: u32 = 500;
+
+/// Every feed and entry, hidden ones too, as the JSON `Dump` that `/restore/state`
+/// takes. Entries are streamed a page at a time so a big database doesn't have to
+/// fit in memory.
+async fn dump(State(AppState { db, .. }): State<AppState>) -> Result<impl IntoResponse, AppError> {
     let feeds = db.get_feeds().await?;
-    let entries = db
-        .get_entries(db::EntryFilter::All, db::Ordering::Descending)
-        .await?;
+    Ok((
+        [(CONTENT_TYPE, "application/json")],
+        Body::from_stream(dump_stream(db, &feeds, DUMP_PAGE_SIZE)?),
+    ))
+}
 
-    Ok(Dump { feeds, entries }.into())
+/// A `Dump` in chunks: the feeds, then each page of entries after the last row
+/// read, so pages don't shift as entries are added or removed mid-dump.
+fn dump_stream(
+    db: db::DB,
+    feeds: &[Feed],
+    page_size: u32,
+) -> anyhow::Result<impl stream::Stream<Item = anyhow::Result<Vec<u8>>>> {
+    let mut head = br#"{"feeds":"#.to_vec();
+    serde_json::to_writer(&mut head, feeds)?;
+    head.extend_from_slice(br#","entries":["#);
+    // the body is read after the handler returns, outside the request's user scope
+    let user = user::current()?;
+    let pages = stream::unfold(Some(0), move |after| {
+        let db = db.clone();
+        async move {
+            let after = after?;
+            let page = user::scope(user, db.get_entries_after(after, page_size)).await;
+            let entries = match page {
+                Ok(entries) if entries.is_empty() => return None,
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("couldn't read entries for a dump: {:?}", e);
+                    return Some((Err(e), None));
+                }
+            };
+            let mut chunk = vec![];
+            for entry in &entries {
+                if after > 0 || !chunk.is_empty() {
+                    chunk.push(b',');
+                }
+                if let Err(e) = serde_json::to_writer(&mut chunk, entry) {
+                    return Some((Err(e.into()), None));
+                }
+            }
+            let last = entries.last().map(|e| e.item_id);
+            Some((Ok(chunk), last))
+        }
+    });
+    Ok(stream::once(future::ready(Ok(head)))
+        .chain(pages)
+        .chain(stream::once(future::ready(Ok(b"]}".to_vec())))))
+}
+
+#[derive(Serialize)]
+struct RestoreResponse {
+    feeds: usize,
+    new_entries: usize,
+}
+
+/// Loads a dump from `/dump`, like one taken before moving to another database.
+/// Dumps are as big as the database, so there's no limit on the body.
+async fn restore(
+    State(AppState { db, .. }): State<AppState>,
+    Json(dump): Json<Dump>,
+) -> Result<Json<RestoreResponse>, AppError> {
+    let (feeds, new_entries) = db.restore(dump).await?;
+    info!("restored {} feeds and {} new entries", feeds, new_entries);
+    Ok(Json(RestoreResponse { feeds, new_entries }))
+}
+
+#[derive(Serialize)]
+struct RestoreStateResponse {
+    matched: usize,
+}
+
+/// Applies read/starred state from another instance's dump without adding its entries.
+async fn restore_state(
+    State(AppState { db, .. }): State<AppState>,
+    Json(dump): Json<Dump>,
+) -> Result<Json<RestoreStateResponse>, AppError> {
+    let matched = db.merge_entry_state(dump.entries).await?;
+    info!("merged read/starred state onto {} entries", matched);
+    Ok(Json(RestoreStateResponse { matched }))
+}
+
+async fn starred_json(
+    State(AppState { db, .. }): State<AppState>,
+    Extension(client): Extension<proxy::Client>,
+) -> Result<impl IntoResponse, AppError> {
+    let entries = db.get_starred_entries().await?;
+    let mut feed = jsonfeed::JsonFeed::new("Feedreader Starred", &entries);
+    feed.home_page_url = public_url::absolute("/starred.html", Some(&client));
+    feed.feed_url = public_url::absolute("/starred.json", Some(&client));
+    Ok(([(CONTENT_TYPE, "application/feed+json")], Json(feed)))
+}
+
+/// Entries read per day over the past year, including days with nothing read.
+async fn heatmap(State(AppState { db, .. }): State<AppState>) -> Result<Json<Heatmap>, AppError> {
+    let today = Utc::now().date_naive();
+    let from = today - chrono::Duration::days(364);
+    let since = from
+        .and_hms_opt(0, 0, 0)
+        .ok_or_else(|| anyhow!("couldn't build start of heatmap"))?
+        .and_utc();
+    let counts = db.get_read_counts_by_day(since.into()).await?;
+
+    let days = from
+        .iter_days()
+        .take_while(|d| *d <= today)
+        .map(|d| {
+            let date = d.format("%Y-%m-%d").to_string();
+            let count = counts
+                .iter()
+                .find(|c| c.date == date)
+                .map_or(0, |c| c.count);
+            DayCount { date, count }
+        })
+        .collect();
+
+    Ok(Json(Heatmap {
+        from: from.format("%Y-%m-%d").to_string(),
+        to: today.format("%Y-%m-%d").to_string(),
+        days,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// Comma separated categories to include, all of them when missing
+    category: Option<String>,
+}
+
+async fn export_opml(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let categories: Option<Vec<String>> = query
+        .category
+        .map(|c| c.split(',').map(|c| c.trim().to_lowercase()).collect());
+    let feeds: Vec<Feed> = db
+        .get_feeds()
+        .await?
+        .into_iter()
+        .filter(|f| match &categories {
+            Some(categories) => categories.contains(&f.category.to_lowercase()),
+            None => true,
+        })
+        .collect();
+
+    let body = feeds_to_opml(&feeds).to_string()?;
+    Ok((
+        [
+            (CONTENT_TYPE, "text/x-opml"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"feeds.opml\""),
+        ],
+        body,
+    ))
 }
 
-fn parse_opml_document(document: &opml::OPML) -> Result<Vec<Feed>, anyhow::Error> {
+/// The inverse of `parse_opml_outlines`, an outline per category holding its feeds.
+fn feeds_to_opml(feeds: &[Feed]) -> OPML {
+    let mut document = OPML {
+        head: Some(opml::Head {
+            title: Some("Feedreader".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    for f in feeds {
+        let position = document
+            .body
+            .outlines
+            .iter()
+            .position(|c| c.text == f.category);
+        let category = match position {
+            Some(i) => &mut document.body.outlines[i],
+            None => {
+                document.body.outlines.push(opml::Outline {
+                    text: f.category.clone(),
+                    ..Default::default()
+                });
+                document.body.outlines.last_mut().unwrap()
+            }
+        };
+        category.outlines.push(opml::Outline {
+            text: f.name.clone(),
+            title: Some(f.name.clone()),
+            xml_url: Some(f.feed_url.clone()),
+            html_url: Some(f.site_url.clone()),
+            ..Default::default()
+        });
+    }
+    document
+}
+
+/// Every feed outline in the document by name, with the feed or why it couldn't be read.
+fn parse_opml_outlines(document: &opml::OPML) -> Vec<(String, Result<Feed, anyhow::Error>)> {
     let mut feeds = vec![];
     for c in document.body.outlines.iter() {
         // expect outlines for each category that consists of an outline for each feed
         let category_text = c.text.clone();
         for f in c.outlines.iter() {
             let name = f.text.clone();
-            let site_url = f
-                .html_url
-                .as_ref()
-                .ok_or(anyhow!("Missing html url in feed {}", name))?;
-            let feed_url = f
-                .xml_url
-                .as_ref()
-                .ok_or(anyhow!("Missing xml url in feed {}", name))?;
-            let f = Feed::new(
-                name,
-                site_url.clone(),
-                feed_url.clone(),
-                category_text.clone(),
-            );
-            feeds.push(f);
+            let feed = match (&f.html_url, &f.xml_url) {
+                (Some(site_url), Some(feed_url)) => Ok(Feed::new(
+                    name.clone(),
+                    site_url.clone(),
+                    feed_url.clone(),
+                    category_text.clone(),
+                )),
+                (None, _) => Err(anyhow!("Missing html url in feed {}", name)),
+                (_, None) => Err(anyhow!("Missing xml url in feed {}", name)),
+            };
+            feeds.push((name, feed));
         }
     }
-
-    Ok(feeds)
+    feeds
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[tokio::test]
+    async fn dump_streams_every_entry() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let entries: Vec<Entry> = (0..5)
+                .map(|i| {
+                    Entry::new(
+                        &i.to_string(),
+                        format!("entry {}", i),
+                        format!("https://content.com/{}", i),
+                        "".to_string(),
+                        None,
+                    )
+                })
+                .collect();
+            db.add_entries(entries.clone().into_iter()).await?;
+            db.toggle_entry_hidden(&entries[3].id).await?;
+            let feeds = vec![Feed::new(
+                "Blog".to_string(),
+                "https://blog.com".to_string(),
+                "https://blog.com/feed.xml".to_string(),
+                String::new(),
+            )];
+
+            let chunks: Vec<Vec<u8>> = dump_stream(db, &feeds, 2)?
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<_, _>>()?;
+            // the feeds, three pages of entries and the end
+            assert_eq!(chunks.len(), 5);
+            let dump: Dump = serde_json::from_slice(&chunks.concat())?;
+            assert_eq!(dump.feeds.len(), 1);
+            let titles: Vec<&str> = dump.entries.iter().map(|e| e.title.as_str()).collect();
+            assert_eq!(
+                titles,
+                ["entry 0", "entry 1", "entry 2", "entry 3", "entry 4"]
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    fn parse_opml_document(document: &opml::OPML) -> Result<Vec<Feed>, anyhow::Error> {
+        parse_opml_outlines(document)
+            .into_iter()
+            .map(|(_, feed)| feed)
+            .collect()
+    }
+
+    #[test]
+    fn startup_errors_describe_the_failure() {
+        let err = StartupError::Config(anyhow!("no database configured"));
+        assert_eq!(err.exit_code(), 78);
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: no database configured"
+        );
+
+        let err = StartupError::Opml(
+            read_opml("missing.opml")
+                .context("FEED_OPML_FILE=missing.opml")
+                .unwrap_err(),
+        );
+        assert_eq!(err.exit_code(), 65);
+        assert!(err.to_string().starts_with(
+            "couldn't load feeds: FEED_OPML_FILE=missing.opml: couldn't open opml file"
+        ));
+    }
+
     #[test]
     fn parse_opml_export() {
         let mut file = File::open("feeds.opml").expect("Couldn't open feeds.opml");
@@ -460,4 +1302,18 @@ mod test {
         assert_eq!(feeds[3].category, "Austin");
         assert_eq!(feeds[3].feed_url, "http://www.austinmonitor.com/feed/");
     }
+
+    #[test]
+    fn export_opml_roundtrip() {
+        let mut file = File::open("feeds.opml").expect("Couldn't open feeds.opml");
+        let document = OPML::from_reader(&mut file).expect("Couldn't parse feeds.opml");
+        let feeds = parse_opml_document(&document).expect("Couldn't parse opml to feeds");
+
+        let exported = feeds_to_opml(&feeds).to_string().unwrap();
+        let document = OPML::from_str(&exported).expect("Couldn't parse exported opml");
+        let roundtrip = parse_opml_document(&document).expect("Couldn't parse opml to feeds");
+        assert_eq!(roundtrip.len(), feeds.len());
+        assert_eq!(roundtrip[3].category, "Austin");
+        assert_eq!(roundtrip[3].site_url, feeds[3].site_url);
+    }
 }
@@ -0,0 +1,110 @@
+//! A read-only switch for backups and migrations. While it's on, requests that
+//! would change anything are turned away and background jobs skip their runs.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+use crate::auth::Scope;
+use crate::AppState;
+
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnly(Arc<AtomicBool>);
+
+impl ReadOnly {
+    pub fn enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+        info!("read-only mode {}", if enabled { "on" } else { "off" });
+    }
+}
+
+/// The route that turns read-only mode on and off, let through while it's on.
+const TOGGLE_PATH: &str = "/read_only";
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route(TOGGLE_PATH, get(status).post(set_read_only))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ReadOnlyStatus {
+    enabled: bool,
+}
+
+async fn status(State(AppState { read_only, .. }): State<AppState>) -> Json<ReadOnlyStatus> {
+    Json(ReadOnlyStatus {
+        enabled: read_only.enabled(),
+    })
+}
+
+async fn set_read_only(
+    State(AppState { read_only, .. }): State<AppState>,
+    Json(body): Json<ReadOnlyStatus>,
+) -> Json<ReadOnlyStatus> {
+    read_only.set(body.enabled);
+    Json(body)
+}
+
+/// Answers anything but reads with a 503 while read-only mode is on.
+pub async fn reject_writes(
+    State(AppState { read_only, .. }): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| request.uri().path());
+    // GETs can need more than read scope, like /dump, but still change nothing
+    let method = request.method();
+    let reading = method == Method::GET
+        || method == Method::HEAD
+        || Scope::required(method, path) == Scope::Read;
+    let toggling = path == TOGGLE_PATH;
+    if read_only.enabled() && !reading && !toggling {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "read-only for maintenance, try again later",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Flips read-only mode every time the process gets SIGUSR1.
+pub async fn toggle_on_signal(read_only: ReadOnly) {
+    let Ok(mut signals) = signal(SignalKind::user_defined1()) else {
+        return;
+    };
+    while signals.recv().await.is_some() {
+        read_only.set(!read_only.enabled());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shared_between_clones() {
+        let read_only = ReadOnly::default();
+        let job = read_only.clone();
+        assert!(!job.enabled());
+        read_only.set(true);
+        assert!(job.enabled());
+        job.set(false);
+        assert!(!read_only.enabled());
+    }
+}
@@ -0,0 +1,376 @@
+//! Sending notifications, like a feed starting to fail, through whichever
+//! channels are configured. Each channel implements `Notifier`, and whatever
+//! sends a notification picks channels by the name they're configured under,
+//! so a new kind of channel only needs an implementation and a config prefix.
+//!
+//! Channels are configured in `FEED_NOTIFIERS` as `name=kind:target` pairs
+//! separated by commas:
+//!
+//! - `webhook:<url>` posts the notification as JSON
+//! - `ntfy:<topic url>` publishes to an [ntfy](https://ntfy.sh) topic
+//! - `telegram:<chat id>:<bot token>` messages a chat from a Telegram bot
+//! - `email:<address>` mails it with the local `sendmail`
+//!
+//! A target, or a Telegram bot token, can be `stored:<name>` instead, to use the
+//! credential stored under that name with `set-credential` rather than keep it
+//! in the config: `tg=telegram:-1001:stored:telegram-bot`.
+use std::collections::HashMap;
+use std::fmt;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::error;
+
+use crate::db;
+use crate::secrets::SecretKey;
+
+/// Something worth telling someone about.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    /// Where to go to see more
+    pub link: Option<String>,
+    /// Machine readable details, sent as they are by webhooks
+    #[serde(flatten)]
+    pub details: Value,
+}
+
+/// A channel notifications are sent through.
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>>;
+}
+
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Notifier for Webhook {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .post(&self.url)
+                .json(notification)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+struct Ntfy {
+    client: reqwest::Client,
+    topic_url: String,
+}
+
+impl Notifier for Ntfy {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .post(&self.topic_url)
+                .header("Title", &notification.title)
+                .body(notification.message.clone());
+            if let Some(link) = &notification.link {
+                request = request.header("Click", link);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+struct Telegram {
+    client: reqwest::Client,
+    chat_id: String,
+    bot_token: String,
+}
+
+impl Notifier for Telegram {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+            self.client
+                .post(url)
+                .json(&serde_json::json!({
+                    "chat_id": self.chat_id,
+                    "text": plain_text(notification),
+                }))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Hands mail to `sendmail`, which whatever mail setup the host has provides.
+struct Email {
+    to: String,
+}
+
+impl Notifier for Email {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut sendmail = Command::new("sendmail")
+                .arg("-t")
+                .stdin(Stdio::piped())
+                .spawn()
+                .context("couldn't run sendmail")?;
+            let mail = format!(
+                "To: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+                self.to,
+                notification.title.replace(['\r', '\n'], " "),
+                plain_text(notification)
+            );
+            let mut stdin = sendmail
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow!("couldn't write to sendmail"))?;
+            stdin.write_all(mail.as_bytes()).await?;
+            drop(stdin);
+            let status = sendmail.wait().await?;
+            if !status.success() {
+                return Err(anyhow!("sendmail exited with {}", status));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A notification for channels that only take text.
+fn plain_text(notification: &Notification) -> String {
+    let mut text = format!("{}\n{}", notification.title, notification.message);
+    if let Some(link) = &notification.link {
+        text.push('\n');
+        text.push_str(link);
+    }
+    text
+}
+
+/// What kind of channel a configured notifier is and where it sends to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifierKind {
+    Webhook(String),
+    Ntfy(String),
+    Telegram { chat_id: String, bot_token: String },
+    Email(String),
+}
+
+impl FromStr for NotifierKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<NotifierKind> {
+        let (kind, target) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("notifier {} is missing its kind", s))?;
+        match kind {
+            "webhook" => Ok(NotifierKind::Webhook(target.to_string())),
+            "ntfy" => Ok(NotifierKind::Ntfy(target.to_string())),
+            "telegram" => {
+                let (chat_id, bot_token) = target
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("telegram notifiers need a chat id and bot token"))?;
+                Ok(NotifierKind::Telegram {
+                    chat_id: chat_id.to_string(),
+                    bot_token: bot_token.to_string(),
+                })
+            }
+            "email" => Ok(NotifierKind::Email(target.to_string())),
+            _ => Err(anyhow!("unknown notifier kind {}", kind)),
+        }
+    }
+}
+
+impl NotifierKind {
+    pub fn build(&self, client: &reqwest::Client) -> Arc<dyn Notifier> {
+        let client = client.clone();
+        match self.clone() {
+            NotifierKind::Webhook(url) => Arc::new(Webhook { client, url }),
+            NotifierKind::Ntfy(topic_url) => Arc::new(Ntfy { client, topic_url }),
+            NotifierKind::Telegram { chat_id, bot_token } => Arc::new(Telegram {
+                client,
+                chat_id,
+                bot_token,
+            }),
+            NotifierKind::Email(to) => Arc::new(Email { to }),
+        }
+    }
+}
+
+/// Where a value names a stored credential rather than being the value itself.
+const STORED: &str = "stored:";
+
+/// `value`, or the credential it names, decrypted with `key`.
+async fn open_stored(db: &db::DB, key: Option<&SecretKey>, value: String) -> Result<String> {
+    let Some(name) = value.strip_prefix(STORED) else {
+        return Ok(value);
+    };
+    let key =
+        key.ok_or_else(|| anyhow!("set FEED_SECRET_KEY to use stored credential {}", name))?;
+    db.get_credential(key, name)
+        .await?
+        .ok_or_else(|| anyhow!("no credential called {}", name))
+}
+
+impl NotifierKind {
+    /// The kind with any stored credentials it names filled in.
+    async fn open(self, db: &db::DB, key: Option<&SecretKey>) -> Result<NotifierKind> {
+        Ok(match self {
+            NotifierKind::Webhook(url) => NotifierKind::Webhook(open_stored(db, key, url).await?),
+            NotifierKind::Ntfy(topic_url) => {
+                NotifierKind::Ntfy(open_stored(db, key, topic_url).await?)
+            }
+            NotifierKind::Telegram { chat_id, bot_token } => NotifierKind::Telegram {
+                chat_id,
+                bot_token: open_stored(db, key, bot_token).await?,
+            },
+            NotifierKind::Email(to) => NotifierKind::Email(to),
+        })
+    }
+}
+
+/// Notifiers configured as `name=kind:target` pairs separated by commas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotifierSpecs(Vec<(String, NotifierKind)>);
+
+impl FromStr for NotifierSpecs {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<NotifierSpecs> {
+        let specs = s
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|n| {
+                let (name, kind) = n
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("notifier {} is missing a name", n))?;
+                Ok((name.trim().to_string(), kind.trim().parse()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(NotifierSpecs(specs))
+    }
+}
+
+impl NotifierSpecs {
+    /// The specs with every `stored:<name>` replaced by that credential.
+    pub async fn open(&self, db: &db::DB, key: Option<&SecretKey>) -> Result<NotifierSpecs> {
+        let mut specs = vec![];
+        for (name, kind) in &self.0 {
+            let kind = kind
+                .clone()
+                .open(db, key)
+                .await
+                .with_context(|| format!("notifier {}", name))?;
+            specs.push((name.clone(), kind));
+        }
+        Ok(NotifierSpecs(specs))
+    }
+}
+
+/// The configured notifiers by name.
+#[derive(Clone, Default)]
+pub struct Notifiers(Arc<HashMap<String, Arc<dyn Notifier>>>);
+
+impl fmt::Debug for Notifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
+}
+
+impl Notifiers {
+    pub fn new(specs: &NotifierSpecs, client: &reqwest::Client) -> Notifiers {
+        let notifiers = specs
+            .0
+            .iter()
+            .map(|(name, kind)| (name.clone(), kind.build(client)))
+            .collect();
+        Notifiers(Arc::new(notifiers))
+    }
+
+    /// The notifiers with the given names, an error naming any that aren't configured.
+    pub fn select(&self, names: &[String]) -> Result<Vec<Arc<dyn Notifier>>> {
+        names
+            .iter()
+            .map(|name| {
+                self.0
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("no notifier called {}", name))
+            })
+            .collect()
+    }
+}
+
+/// Sends a notification through every one of `notifiers`, logging the ones that fail
+/// rather than letting one broken channel stop the rest.
+pub async fn send_all(notifiers: &[Arc<dyn Notifier>], notification: &Notification) {
+    for notifier in notifiers {
+        if let Err(e) = notifier.send(notification).await {
+            error!("couldn't send notification {}: {:#}", notification.title, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_notifiers() {
+        let specs: NotifierSpecs =
+            "ops=webhook:https://hooks.example.com/a, phone=ntfy:https://ntfy.sh/feeds,tg=telegram:-1001:123:ABC"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            specs.0[2],
+            (
+                "tg".to_string(),
+                NotifierKind::Telegram {
+                    chat_id: "-1001".to_string(),
+                    bot_token: "123:ABC".to_string()
+                }
+            )
+        );
+        let notifiers = Notifiers::new(&specs, &reqwest::Client::new());
+        assert_eq!(notifiers.select(&["ops".to_string()]).unwrap().len(), 1);
+        assert!(notifiers.select(&["nope".to_string()]).is_err());
+        assert!("ops=carrier-pigeon:coop".parse::<NotifierSpecs>().is_err());
+        assert!("webhook:https://example.com"
+            .parse::<NotifierSpecs>()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn open_stored_credentials() -> Result<()> {
+        let db = db::connect(db::ConnectionBacking::Memory).await?;
+        db.init().await?;
+        let key: SecretKey = base64::encode([3u8; 32]).parse()?;
+        db.set_credential(&key, "telegram-bot", "123:ABC").await?;
+        let specs: NotifierSpecs =
+            "tg=telegram:-1001:stored:telegram-bot, ops=webhook:https://hooks.example.com/a"
+                .parse()?;
+
+        let opened = specs.open(&db, Some(&key)).await?;
+        assert_eq!(
+            opened.0[0].1,
+            NotifierKind::Telegram {
+                chat_id: "-1001".to_string(),
+                bot_token: "123:ABC".to_string()
+            }
+        );
+        assert_eq!(opened.0[1], specs.0[1]);
+        assert!(specs.open(&db, None).await.is_err());
+        let missing: NotifierSpecs = "phone=ntfy:stored:nope".parse()?;
+        assert!(missing.open(&db, Some(&key)).await.is_err());
+        Ok(())
+    }
+}
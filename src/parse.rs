@@ -0,0 +1,324 @@
+//! Turns fetched feed bodies into entries.
+//!
+//! feed_rs handles RSS, Atom, and basic JSON Feed, but drops JSON Feed attachments and
+//! can't read microformats, so those are handled here first/as a fallback.
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use feed_rs::parser;
+use scraper::{ElementRef, Html, Node, Selector};
+
+use crate::jsonfeed::{self, JsonFeed};
+use crate::{Entry, UtcTime};
+
+pub fn parse_entries(body: &[u8], feed_url: &str) -> Result<Vec<Entry>> {
+    if let Some(feed) = parse_json_feed(body) {
+        return Ok(feed);
+    }
+
+    match parser::parse_with_uri(body, Some(feed_url)) {
+        Ok(feed) => Ok(feed.entries.iter().map(Entry::from).collect()),
+        Err(e) => {
+            let html = String::from_utf8_lossy(body);
+            let entries = parse_h_feed(&html);
+            if entries.is_empty() {
+                Err(anyhow!(e))
+            } else {
+                Ok(entries)
+            }
+        }
+    }
+}
+
+fn parse_json_feed(body: &[u8]) -> Option<Vec<Entry>> {
+    let feed: JsonFeed = serde_json::from_slice(body).ok()?;
+    if !feed.version.starts_with("https://jsonfeed.org/version/") {
+        return None;
+    }
+    let feed_author = feed.authors.first().and_then(|a| a.name.clone());
+    Some(
+        feed.items
+            .iter()
+            .map(|item| {
+                let mut entry = json_feed_entry(item);
+                if entry.author.is_none() {
+                    entry.author.clone_from(&feed_author);
+                }
+                entry
+            })
+            .collect(),
+    )
+}
+
+fn json_feed_entry(item: &jsonfeed::Item) -> Entry {
+    let title = item
+        .title
+        .clone()
+        .or_else(|| item.summary.clone())
+        .or_else(|| {
+            item.content_text
+                .as_ref()
+                .map(|t| t.chars().take(80).collect())
+        })
+        .unwrap_or_default();
+    let published = item
+        .date_published
+        .as_ref()
+        .or(item.date_modified.as_ref())
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+        .map(|d| UtcTime(d.into()));
+
+    let mut entry = Entry::new(
+        &item.id,
+        title,
+        item.url.clone().unwrap_or_default(),
+        item.external_url.clone().unwrap_or_default(),
+        published,
+    );
+    entry.author = item.authors.first().and_then(|a| a.name.clone());
+    entry.content = item
+        .content_html
+        .clone()
+        .or_else(|| item.content_text.clone());
+    if let Some(attachment) = item.attachments.first() {
+        entry.enclosure_url = Some(attachment.url.clone());
+        entry.enclosure_type = Some(attachment.mime_type.clone());
+    }
+    entry
+}
+
+/// How many characters of text an entry's excerpt keeps by default.
+pub const DEFAULT_EXCERPT_LENGTH: usize = 200;
+
+/// Elements whose text starts on a new line when rendered, so words on either side stay apart.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// The start of an HTML snippet's text, cut at a word boundary within `length` characters.
+/// Markup, scripts and styles are dropped and whitespace is collapsed.
+pub fn excerpt(html: &str, length: usize) -> String {
+    let fragment = Html::parse_fragment(html);
+    let mut text = String::new();
+    for node in fragment.root_element().descendants() {
+        match node.value() {
+            Node::Element(e) if BLOCK_ELEMENTS.contains(&e.name()) => text.push(' '),
+            Node::Text(t) => {
+                let hidden = node.ancestors().any(|a| {
+                    a.value()
+                        .as_element()
+                        .is_some_and(|e| e.name() == "script" || e.name() == "style")
+                });
+                if !hidden {
+                    text.push_str(t);
+                }
+            }
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= length {
+        return text;
+    }
+    let cut: String = text.chars().take(length).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) if space > cut.len() / 2 => &cut[..space],
+        _ => cut.as_str(),
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// A feed advertised by a page, found through its `<link rel="alternate">` tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredFeed {
+    pub url: String,
+    pub title: String,
+    pub kind: &'static str,
+}
+
+/// Finds RSS, Atom and JSON feeds linked from an HTML page, resolving relative links
+/// against the page's url.
+pub fn discover_feeds(html: &str, page_url: &str) -> Vec<DiscoveredFeed> {
+    let document = Html::parse_document(html);
+    let alternate = Selector::parse(r#"link[rel~="alternate"][href]"#).unwrap();
+    let base = reqwest::Url::parse(page_url).ok();
+    let mut feeds: Vec<DiscoveredFeed> = vec![];
+    for link in document.select(&alternate) {
+        let link = link.value();
+        let kind = match link.attr("type").map(str::to_lowercase).as_deref() {
+            Some("application/rss+xml") => "RSS",
+            Some("application/atom+xml") => "Atom",
+            Some("application/feed+json") | Some("application/json") => "JSON Feed",
+            _ => continue,
+        };
+        let href = link.attr("href").unwrap_or_default();
+        let url = match &base {
+            Some(base) => match base.join(href) {
+                Ok(url) => url.to_string(),
+                Err(_) => continue,
+            },
+            None => href.to_string(),
+        };
+        if feeds.iter().any(|f| f.url == url) {
+            continue;
+        }
+        let title = link.attr("title").unwrap_or(kind).to_string();
+        feeds.push(DiscoveredFeed { url, title, kind });
+    }
+    feeds
+}
+
+/// Reads `h-entry` items from a page marked up with [microformats](https://microformats.org/wiki/h-feed).
+fn parse_h_feed(html: &str) -> Vec<Entry> {
+    let document = Html::parse_document(html);
+    let h_entry = Selector::parse(".h-entry").unwrap();
+    document
+        .select(&h_entry)
+        .filter_map(h_entry_to_entry)
+        .collect()
+}
+
+fn h_entry_to_entry(element: ElementRef) -> Option<Entry> {
+    let select = |s: &str| element.select(&Selector::parse(s).unwrap()).next();
+    let text = |e: ElementRef| e.text().collect::<String>().trim().to_string();
+
+    let url = select(".u-url").and_then(|u| u.value().attr("href").map(str::to_string));
+    let uid = select(".u-uid").map(|u| {
+        u.value()
+            .attr("href")
+            .map(str::to_string)
+            .unwrap_or_else(|| text(u))
+    });
+    let title = select(".p-name").map(text).unwrap_or_else(|| text(element));
+    let published = select(".dt-published")
+        .and_then(|d| {
+            let datetime = d.value().attr("datetime").map(str::to_string);
+            datetime.or_else(|| Some(text(d)))
+        })
+        .and_then(|d| DateTime::parse_from_rfc3339(&d).ok())
+        .map(|d| UtcTime(d.into()));
+
+    let id = uid.or_else(|| url.clone())?;
+    let mut entry = Entry::new(
+        &id,
+        title,
+        url.unwrap_or_default(),
+        "".to_string(),
+        published,
+    );
+    entry.author = select(".p-author").map(text).filter(|a| !a.is_empty());
+    entry.content = select(".e-content").map(|c| c.inner_html().trim().to_string());
+    Some(entry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_json_feed_attachments() {
+        let body = r#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "Podcast",
+            "authors": [{"name": "Host"}],
+            "items": [
+                {
+                    "id": "ep1",
+                    "url": "https://example.com/ep1",
+                    "content_text": "The first episode",
+                    "date_published": "2024-01-02T03:04:05Z",
+                    "attachments": [{"url": "https://example.com/ep1.mp3", "mime_type": "audio/mpeg"}]
+                },
+                {
+                    "id": "ep2",
+                    "title": "Episode 2",
+                    "authors": [{"name": "Guest"}]
+                }
+            ]
+        }"#;
+        let entries = parse_entries(body.as_bytes(), "https://example.com/feed.json").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "The first episode");
+        assert_eq!(entries[0].author.as_deref(), Some("Host"));
+        assert_eq!(
+            entries[0].enclosure_url.as_deref(),
+            Some("https://example.com/ep1.mp3")
+        );
+        assert_eq!(entries[0].enclosure_type.as_deref(), Some("audio/mpeg"));
+        assert!(entries[0].published.is_some());
+        assert_eq!(entries[1].author.as_deref(), Some("Guest"));
+    }
+
+    #[test]
+    fn discover_alternate_links() {
+        let body = r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" title="Posts" href="/feed.xml">
+            <link rel="alternate" type="application/atom+xml" href="https://example.com/atom.xml">
+            <link rel="alternate" type="application/feed+json" href="feed.json">
+            <link rel="alternate" type="text/html" hreflang="de" href="/de/">
+            <link rel="stylesheet" href="/style.css">
+        </head></html>"#;
+        let feeds = discover_feeds(body, "https://example.com/blog/");
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[0].url, "https://example.com/feed.xml");
+        assert_eq!(feeds[0].title, "Posts");
+        assert_eq!(feeds[1].kind, "Atom");
+        assert_eq!(feeds[2].url, "https://example.com/blog/feed.json");
+    }
+
+    #[test]
+    fn parse_h_feed_page() {
+        let body = r#"<html><body><div class="h-feed">
+            <article class="h-entry">
+                <a class="u-url p-name" href="https://example.com/one">First Post</a>
+                <time class="dt-published" datetime="2024-01-02T03:04:05Z">Jan 2</time>
+                <span class="p-author">Jo</span>
+            </article>
+            <article class="h-entry">
+                <a class="u-url" href="https://example.com/two"><span class="p-name">Second Post</span></a>
+            </article>
+            <article class="h-entry"><p class="p-name">No link</p></article>
+        </div></body></html>"#;
+        let entries = parse_entries(body.as_bytes(), "https://example.com/").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "First Post");
+        assert_eq!(entries[0].content_link, "https://example.com/one");
+        assert_eq!(entries[0].author.as_deref(), Some("Jo"));
+        assert!(entries[0].published.is_some());
+        assert_eq!(entries[1].title, "Second Post");
+    }
+
+    #[test]
+    fn excerpt_from_html() {
+        let html = r#"<p>First  paragraph.</p><p>Second <b>bold</b> one.</p>
+            <script>alert("hi")</script><style>p { color: red }</style>"#;
+        assert_eq!(excerpt(html, 200), "First paragraph. Second bold one.");
+        assert_eq!(excerpt(html, 20), "First paragraph.…");
+        assert_eq!(excerpt("Plain &amp; simple", 200), "Plain & simple");
+        assert_eq!(excerpt("Überlänge", 4), "Über…");
+    }
+}
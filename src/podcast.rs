@@ -0,0 +1,52 @@
+//! Entries with something to listen to or watch, played in the page with where
+//! each one got to saved, so an episode started on one device resumes on another.
+use askama_axum::Template;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::Router;
+
+use crate::db::{EntryFilter, Ordering};
+use crate::filters;
+use crate::settings::Preferences;
+use crate::view::{more_link, PageQuery};
+use crate::{AppError, AppState, Entry};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/podcasts.html", get(podcasts))
+}
+
+#[derive(Template)]
+#[template(path = "podcasts.html")]
+struct PodcastsTemplate {
+    entries: Vec<Entry>,
+    more: Option<String>,
+    title_length: usize,
+    new_tab: bool,
+}
+
+/// Whether an entry's enclosure needs a video player rather than an audio one.
+fn is_video(entry: &Entry) -> bool {
+    entry
+        .enclosure_type
+        .as_deref()
+        .is_some_and(|t| t.starts_with("video/"))
+}
+
+async fn podcasts(
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<PodcastsTemplate, AppError> {
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    let page = query.page();
+    let entries = db
+        .get_entries_page(EntryFilter::Podcasts, Ordering::Descending, page)
+        .await?;
+    Ok(PodcastsTemplate {
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
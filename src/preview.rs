@@ -0,0 +1,348 @@
+//! Link previews from a page's OpenGraph tags, for entries whose feeds only send
+//! a bare link. Fetched when first asked for and kept for a while.
+//!
+//! Only links of stored entries are previewed, and only from hosts on the public
+//! internet, so previews can't be used to reach this machine or its network.
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use askama_axum::Template;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::Utc;
+use reqwest::header::LOCATION;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::{db, AppError, AppState, UtcTime};
+
+/// Previews are fetched again once they're this old.
+const CACHE_DAYS: i64 = 7;
+
+/// The tags are in the head, there's no need to read all of a long page.
+const MAX_PAGE_BYTES: usize = 512 * 1024;
+
+/// Redirects followed, each checked like the first url, before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/v1/preview", get(preview_json))
+        .route("/entries/:entry_id/preview", get(entry_preview))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub fetched_at: UtcTime,
+}
+
+impl LinkPreview {
+    fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.image.is_none()
+    }
+}
+
+/// OpenGraph tags, falling back to Twitter cards and then plain HTML ones.
+fn parse_preview(html: &str, url: &Url) -> LinkPreview {
+    let document = Html::parse_document(html);
+    let first = |selectors: &[&str]| {
+        selectors.iter().find_map(|s| {
+            let selector = Selector::parse(s).unwrap();
+            let element = document.select(&selector).next()?;
+            let value = match element.value().name() {
+                "meta" => element.value().attr("content")?.to_string(),
+                _ => element.text().collect(),
+            };
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            Some(value).filter(|v| !v.is_empty())
+        })
+    };
+    let title = first(&[
+        r#"meta[property="og:title"]"#,
+        r#"meta[name="twitter:title"]"#,
+        "title",
+    ]);
+    let description = first(&[
+        r#"meta[property="og:description"]"#,
+        r#"meta[name="twitter:description"]"#,
+        r#"meta[name="description"]"#,
+    ]);
+    // relative images are relative to the page
+    let image = first(&[
+        r#"meta[property="og:image"]"#,
+        r#"meta[name="twitter:image"]"#,
+    ])
+    .and_then(|image| url.join(&image).ok())
+    .filter(|image| matches!(image.scheme(), "http" | "https"))
+    .map(String::from);
+    LinkPreview {
+        url: url.to_string(),
+        title,
+        description,
+        image,
+        fetched_at: UtcTime(Utc::now()),
+    }
+}
+
+/// Only web pages are fetched for previews.
+pub(crate) fn previewable(url: &str) -> Option<Url> {
+    Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Fetches pages for previews and feed discovery, only ever connecting to public
+/// addresses.
+#[derive(Clone)]
+pub struct Fetcher {
+    user_agent: String,
+}
+
+impl Fetcher {
+    pub fn new(user_agent: String) -> Fetcher {
+        Fetcher { user_agent }
+    }
+
+    /// The start of the page at `url`, following redirects as long as each one
+    /// stays on the public internet.
+    async fn page(&self, url: &Url) -> Result<Vec<u8>> {
+        self.fetch(url, MAX_PAGE_BYTES).await
+    }
+
+    /// Up to `max_bytes` of what's at `url`, checked like a page for a preview.
+    pub(crate) async fn fetch(&self, url: &Url, max_bytes: usize) -> Result<Vec<u8>> {
+        let mut url = url.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            let address = public_address(&url).await?;
+            // connect to the address that was checked, rather than resolving it again
+            let mut builder = reqwest::Client::builder()
+                .user_agent(self.user_agent.as_str())
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(Duration::from_secs(30))
+                .gzip(true)
+                .brotli(true);
+            if let Some(host) = url.host_str() {
+                builder = builder.resolve(host, address);
+            }
+            let mut response = builder.build()?.get(url.clone()).send().await?;
+            if response.status().is_redirection() {
+                let location = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|l| l.to_str().ok())
+                    .ok_or_else(|| anyhow!("redirect from {} without a location", url))?;
+                url = previewable(url.join(location)?.as_str())
+                    .ok_or_else(|| anyhow!("{} redirects off the web", url))?;
+                continue;
+            }
+            response = response.error_for_status()?;
+            let mut page = vec![];
+            while let Some(chunk) = response.chunk().await? {
+                page.extend_from_slice(&chunk);
+                if page.len() >= max_bytes {
+                    break;
+                }
+            }
+            return Ok(page);
+        }
+        Err(anyhow!("too many redirects previewing {}", url))
+    }
+}
+
+/// Where to connect for `url`, as long as every address its host resolves to is
+/// a public one.
+async fn public_address(url: &Url) -> Result<SocketAddr> {
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("{} has no port", url))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    // addresses are already what they resolve to, v6 ones in brackets
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = match literal.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port)).await?.collect(),
+    };
+    if addresses.iter().any(|a| !is_public(a.ip())) {
+        return Err(anyhow!("{} resolves to a private address", url));
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{} doesn't resolve", url))
+}
+
+/// Whether an address is out on the internet, rather than this machine, the
+/// local network or a link-local one like a cloud metadata service.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // carrier-grade NAT, and "this network"
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// The preview for `url`, from the cache if it's recent enough. A new one isn't
+/// kept when `read_only`.
+pub async fn preview(
+    db: &db::DB,
+    fetcher: &Fetcher,
+    url: &Url,
+    read_only: bool,
+) -> Result<LinkPreview> {
+    if let Some(cached) = db.get_link_preview(url.as_str()).await? {
+        if cached.fetched_at.0 > Utc::now() - chrono::Duration::days(CACHE_DAYS) {
+            return Ok(cached);
+        }
+    }
+    let page = fetcher.page(url).await?;
+    let preview = parse_preview(&String::from_utf8_lossy(&page), url);
+    if !read_only {
+        db.set_link_preview(&preview, chrono::Duration::days(CACHE_DAYS))
+            .await?;
+    }
+    Ok(preview)
+}
+
+#[derive(Deserialize)]
+struct PreviewQuery {
+    url: String,
+}
+
+async fn preview_json(
+    State(AppState {
+        db,
+        previews,
+        read_only,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Response, AppError> {
+    let Some(url) = previewable(&query.url) else {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "url needs to be an http or https link",
+        )
+            .into_response());
+    };
+    if !db.has_entry_link(url.as_str()).await? {
+        return Ok((StatusCode::NOT_FOUND, "no entry links to that url").into_response());
+    }
+    let preview = preview(&db, &previews, &url, read_only.enabled()).await?;
+    Ok(Json(preview).into_response())
+}
+
+#[derive(Template)]
+#[template(path = "preview.html")]
+struct PreviewTemplate {
+    preview: LinkPreview,
+}
+
+/// A card for an entry without content, swapped in by the entry page.
+async fn entry_preview(
+    Path(entry_id): Path<String>,
+    State(AppState {
+        db,
+        previews,
+        read_only,
+        ..
+    }): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(entry) = db.get_entry(&entry_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(url) = previewable(&entry.content_link) else {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+    let preview = preview(&db, &previews, &url, read_only.enabled()).await?;
+    if preview.is_empty() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    Ok(PreviewTemplate { preview }.into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opengraph_tags() {
+        let url = Url::parse("https://example.com/posts/1").unwrap();
+        let preview = parse_preview(
+            r#"<html><head>
+            <title>Fallback title</title>
+            <meta property="og:title" content="  The   real title ">
+            <meta name="description" content="What it's about">
+            <meta property="og:image" content="/images/cover.png">
+            </head><body></body></html>"#,
+            &url,
+        );
+        assert_eq!(preview.title.as_deref(), Some("The real title"));
+        assert_eq!(preview.description.as_deref(), Some("What it's about"));
+        assert_eq!(
+            preview.image.as_deref(),
+            Some("https://example.com/images/cover.png")
+        );
+
+        let bare = parse_preview("<html><body><p>hi</p></body></html>", &url);
+        assert!(bare.is_empty());
+        assert!(previewable("javascript:alert(1)").is_none());
+    }
+
+    #[tokio::test]
+    async fn refuse_private_addresses() {
+        for url in [
+            "http://127.0.0.1/",
+            "http://localhost:8080/admin",
+            "http://10.0.0.1/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://100.64.0.1/",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(
+                public_address(&url).await.is_err(),
+                "{} was let through",
+                url
+            );
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1::1".parse().unwrap()));
+    }
+}
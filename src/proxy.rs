@@ -0,0 +1,215 @@
+//! Requests that come through a reverse proxy, which says who they're really
+//! from with `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`.
+//! Those are only believed from the proxies in `FEED_TRUSTED_PROXIES`, a comma
+//! separated list of addresses and CIDR ranges, as anyone can send them.
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::HOST;
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::base_path;
+
+/// Proxies whose `X-Forwarded-*` headers are believed, none by default.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<Network>>);
+
+#[derive(Debug, PartialEq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Network> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("{} isn't an address", addr))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(|| anyhow!("{} isn't a prefix length for {}", prefix, addr))?,
+            None => bits,
+        };
+        Ok(Network { addr, prefix })
+    }
+}
+
+impl Network {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<TrustedProxies> {
+        let networks = s
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(str::parse)
+            .collect::<Result<_>>()?;
+        Ok(TrustedProxies(Arc::new(networks)))
+    }
+}
+
+impl TrustedProxies {
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|n| n.contains(ip))
+    }
+}
+
+/// Who a request is from and how they reached the server, as the proxies in
+/// front of it tell it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Client {
+    /// Missing when the server wasn't told who connected
+    pub ip: Option<IpAddr>,
+    /// `http` or `https`
+    pub scheme: String,
+    /// The host, and port if there is one, the request was sent to
+    pub host: Option<String>,
+}
+
+impl Client {
+    fn new(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &TrustedProxies) -> Client {
+        let proxied = peer.is_some_and(|ip| trusted.trusts(ip));
+        let forwarded = |name: &str| proxied.then(|| first_value(headers, name)).flatten();
+        let scheme = forwarded("x-forwarded-proto")
+            .map(|s| s.to_lowercase())
+            .filter(|s| s == "http" || s == "https")
+            .unwrap_or_else(|| "http".to_string());
+        let host = forwarded("x-forwarded-host")
+            .or_else(|| first_value(headers, HOST.as_str()))
+            .filter(|h| is_host(h));
+        Client {
+            ip: peer.map(|ip| forwarded_ip(ip, headers, trusted)),
+            scheme,
+            host,
+        }
+    }
+
+    /// An absolute URL to `path` on this server, under the base path, when the
+    /// request said which host it was for. Links should go through
+    /// `public_url::absolute`, which prefers the configured public URL.
+    pub fn url(&self, path: &str) -> Option<String> {
+        let host = self.host.as_ref()?;
+        Some(format!("{}://{}{}", self.scheme, host, base_path::to(path)))
+    }
+}
+
+/// Where a request came from: starting at whoever connected, each trusted proxy
+/// is taken at its word about who it forwarded the request for.
+fn forwarded_ip(peer: IpAddr, headers: &HeaderMap, trusted: &TrustedProxies) -> IpAddr {
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .collect();
+    let mut ip = peer.to_canonical();
+    for hop in hops.iter().rev() {
+        if !trusted.trusts(ip) {
+            break;
+        }
+        // some proxies add the port
+        let Some(hop) = hop
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| hop.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        else {
+            break;
+        };
+        ip = hop.to_canonical();
+    }
+    ip
+}
+
+fn first_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let first = value.split(',').next()?.trim();
+    Some(first.to_string()).filter(|v| !v.is_empty())
+}
+
+/// Whether a `Host` could be put in a URL as it is.
+fn is_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-:[]".contains(c))
+}
+
+/// Works out who each request is from, for everything after to find as a
+/// `Client` extension.
+pub async fn resolve(
+    State(trusted): State<TrustedProxies>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = Client::new(peer, request.headers(), &trusted);
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn believe_trusted_proxies() {
+        let trusted: TrustedProxies = "10.0.0.0/8, ::1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "10.0.0.2:3030".parse().unwrap());
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.7, 203.0.113.9, 10.0.0.3".parse().unwrap(),
+        );
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "reader.example.com".parse().unwrap());
+
+        let proxied = Client::new(Some("10.0.0.2".parse().unwrap()), &headers, &trusted);
+        assert_eq!(proxied.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(
+            proxied.url("/starred.json").as_deref(),
+            Some("https://reader.example.com/starred.json")
+        );
+
+        let direct = Client::new(Some("203.0.113.9".parse().unwrap()), &headers, &trusted);
+        assert_eq!(direct.ip, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(
+            direct.url("/starred.json").as_deref(),
+            Some("http://10.0.0.2:3030/starred.json")
+        );
+
+        assert!("10.0.0.0/33".parse::<TrustedProxies>().is_err());
+    }
+}
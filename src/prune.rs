@@ -0,0 +1,29 @@
+//! Periodically deletes old entries that have been read and weren't starred, so
+//! the entries table doesn't grow forever.
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::db;
+use crate::maintenance::ReadOnly;
+
+/// How often to look for entries to prune.
+const PRUNE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Prunes entries published more than `retention` ago once a day.
+pub async fn run(db: db::DB, retention: chrono::Duration, read_only: ReadOnly) {
+    let mut interval = time::interval(PRUNE_PERIOD);
+    loop {
+        interval.tick().await;
+        if read_only.enabled() {
+            continue;
+        }
+        let cutoff = Utc::now() - retention;
+        match db.prune_entries(cutoff.into()).await {
+            Ok(pruned) => info!("pruned {} read entries published before {}", pruned, cutoff),
+            Err(e) => error!("couldn't prune entries: {:?}", e),
+        }
+    }
+}
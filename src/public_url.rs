@@ -0,0 +1,61 @@
+//! Where this instance is reached from outside, from `FEED_PUBLIC_URL` like
+//! `https://example.com/reader`, for links in things read somewhere else:
+//! output feeds, notifications and digests.
+//!
+//! Without it, links made while handling a request go to the host the request
+//! was sent to, and ones made in the background are left out, as there's no
+//! telling where the server can be reached from.
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+
+use crate::{base_path, proxy};
+
+/// The scheme, host and port, the base path being kept by `base_path`.
+static ORIGIN: OnceLock<String> = OnceLock::new();
+
+/// A public URL as it's configured, checked to be somewhere a browser can go.
+pub fn parse(url: &str) -> Result<Url> {
+    let url = Url::parse(url.trim())?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err(anyhow!("{} isn't an http or https URL", url));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(anyhow!("{} can't have a query or fragment", url));
+    }
+    Ok(url)
+}
+
+/// Sets the public URL for the rest of the process, once on startup.
+pub fn set(url: &Url) {
+    // a second call, like from a test, keeps the first
+    let _ = ORIGIN.set(url.origin().ascii_serialization());
+}
+
+/// An absolute URL to `path` on this server, under the base path. The public
+/// URL is used when it's set, otherwise the host `client` sent its request to.
+pub fn absolute(path: &str, client: Option<&proxy::Client>) -> Option<String> {
+    match ORIGIN.get() {
+        Some(origin) => Some(format!("{}{}", origin, base_path::to(path))),
+        None => client?.url(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_public_urls() {
+        let url = parse("https://example.com:8443/reader/").unwrap();
+        assert_eq!(
+            url.origin().ascii_serialization(),
+            "https://example.com:8443"
+        );
+        assert_eq!(url.path(), "/reader/");
+        assert!(parse("ftp://example.com").is_err());
+        assert!(parse("https://example.com/?x=1").is_err());
+        assert!(parse("example.com").is_err());
+    }
+}
@@ -0,0 +1,1158 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use askama_axum::Template;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use futures::stream::{Stream, StreamExt};
+use futures::FutureExt;
+use reqwest::header::{
+    HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    LOCATION,
+};
+use reqwest::Url;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time;
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::{error, info, info_span, Instrument};
+
+use crate::debug_dump::{self, BodyDumps};
+use crate::extract::{self, ExtractMode};
+use crate::filters;
+use crate::jobs::Jobs;
+use crate::maintenance::ReadOnly;
+use crate::notify::{self, Notification, Notifier};
+use crate::sanitize;
+use crate::shutdown::Shutdown;
+use crate::{db, icon, parse, request_id, AppError, AppState, Entry, Feed, UtcTime};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/refresh", post(trigger_refresh))
+        .route("/feeds/:feed_url/refresh", post(refresh_feed))
+        .route("/refresh/progress", get(refresh_progress))
+        .route("/refresh/status", get(refresh_status))
+}
+
+/// Progress of a refresh pass, published to anyone watching `/refresh/progress`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RefreshEvent {
+    Started { total: usize },
+    Fetched { feed: String },
+    Parsed { feed: String, new_entries: usize },
+    Error { feed: String, error: String },
+    Finished { new_entries: usize, seconds: u64 },
+}
+
+impl RefreshEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            RefreshEvent::Started { .. } => "started",
+            RefreshEvent::Fetched { .. } => "fetched",
+            RefreshEvent::Parsed { .. } => "parsed",
+            RefreshEvent::Error { .. } => "error",
+            RefreshEvent::Finished { .. } => "finished",
+        }
+    }
+}
+
+/// What the refresh loop has been up to, so a stalled or dead loop is noticeable.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RefreshHealth {
+    interval_secs: u64,
+    last_tick: Option<UtcTime>,
+    last_finished: Option<UtcTime>,
+    last_new_entries: usize,
+    /// Feeds done and total while a pass is running
+    progress: Option<(usize, usize)>,
+}
+
+impl RefreshHealth {
+    /// True when the loop hasn't ticked for a couple of intervals.
+    pub fn stalled(&self) -> bool {
+        let Some(last_tick) = &self.last_tick else {
+            return false;
+        };
+        let allowed = chrono::Duration::seconds(2 * self.interval_secs as i64);
+        Utc::now() - last_tick.0 > allowed
+    }
+
+    fn record(&mut self, event: &RefreshEvent) {
+        match event {
+            RefreshEvent::Started { total } => self.progress = Some((0, *total)),
+            RefreshEvent::Parsed { .. } | RefreshEvent::Error { .. } => {
+                if let Some((done, _)) = &mut self.progress {
+                    *done += 1;
+                }
+            }
+            RefreshEvent::Finished { new_entries, .. } => {
+                self.progress = None;
+                self.last_finished = Some(UtcTime(Utc::now()));
+                self.last_new_entries = *new_entries;
+            }
+            RefreshEvent::Fetched { .. } => {}
+        }
+    }
+}
+
+/// Lets request handlers kick off a refresh and watch its progress.
+#[derive(Clone)]
+pub struct RefreshHandle {
+    trigger: mpsc::Sender<Option<String>>,
+    events: broadcast::Sender<RefreshEvent>,
+    health: Arc<Mutex<RefreshHealth>>,
+}
+
+impl RefreshHandle {
+    /// Asks the background task for a pass, logged under the given request id.
+    /// Returns false if one is already queued.
+    pub fn trigger(&self, request_id: Option<String>) -> bool {
+        self.trigger.try_send(request_id).is_ok()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RefreshEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn health(&self) -> RefreshHealth {
+        self.health.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug)]
+struct FeedError {
+    status: Option<u16>,
+    message: String,
+}
+
+impl FeedError {
+    fn new(status: Option<u16>, message: &str) -> Self {
+        FeedError {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// The details of the notification sent when a feed starts or stops failing.
+#[derive(Debug, Clone, Serialize)]
+struct FeedStateChange {
+    event: &'static str,
+    feed_id: String,
+    feed_name: String,
+    feed_url: String,
+    status: Option<u16>,
+    error: Option<String>,
+    at: UtcTime,
+}
+
+pub struct Refresher {
+    db: db::DB,
+    client: reqwest::Client,
+    /// For fetching articles rather than feeds
+    page_client: reqwest::Client,
+    events: broadcast::Sender<RefreshEvent>,
+    health: Arc<Mutex<RefreshHealth>>,
+    interval: Duration,
+    schedule: Schedule,
+    head_probe: bool,
+    excerpt_length: usize,
+    /// Told when a feed starts or stops failing
+    failure_notifiers: Vec<Arc<dyn Notifier>>,
+    read_only: ReadOnly,
+    /// Where newly added feeds wait for their first fetch
+    jobs: Option<Jobs>,
+    /// Entries that couldn't be stored, by feed id, tried again next pass
+    pending: Mutex<HashMap<String, Vec<Entry>>>,
+    /// Stops a pass between feeds once requested
+    shutdown: Shutdown,
+    /// Where bodies that couldn't be parsed are kept, when they are
+    dumps: Option<BodyDumps>,
+}
+
+/// Drops entries published before the newest one already stored from this feed.
+/// Entries without a date can't be placed, so they're kept and left to the insert to dedupe.
+/// Ones in `stored` are kept too, since an edit leaves the date alone and the
+/// insert needs to see them to keep the earlier version.
+fn unseen_entries(f: &Feed, entries: Vec<Entry>, stored: &HashSet<String>) -> Vec<Entry> {
+    let Some(cursor) = &f.last_entry_published else {
+        return entries;
+    };
+    entries
+        .into_iter()
+        .filter(|e| match &e.published {
+            _ if stored.contains(&e.id) => true,
+            Some(p) if p.0 == cursor.0 => f.last_entry_id.as_ref() != Some(&e.id),
+            Some(p) => p.0 > cursor.0,
+            None => true,
+        })
+        .collect()
+}
+
+/// Whether a HEAD response matches what the last full fetch of a feed saw,
+/// by Last-Modified if the server sends it and Content-Length otherwise.
+fn unchanged_since(f: &Feed, headers: &HeaderMap) -> bool {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    if let (Some(seen), Some(now)) = (&f.last_modified, header(LAST_MODIFIED)) {
+        return seen == now;
+    }
+    match (f.content_length, header(CONTENT_LENGTH)) {
+        (Some(seen), Some(now)) => now.parse() == Ok(seen),
+        _ => false,
+    }
+}
+
+/// How many times to try a DB write before leaving it for the next pass.
+const WRITE_ATTEMPTS: u32 = 3;
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs a DB write, retrying with a doubling delay in case the error was transient.
+async fn with_retry<T, F, Fut>(mut write: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut delay = WRITE_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match write().await {
+            Ok(t) => return Ok(t),
+            Err(e) if attempt < WRITE_ATTEMPTS => {
+                error!("write failed on attempt {}, retrying: {:?}", attempt, e);
+                time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// How a scheduled pass spreads its fetches over time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Schedule {
+    /// Fetch every feed back to back as soon as the interval ticks
+    #[default]
+    Burst,
+    /// Give each feed a stable offset into the interval and fetch it then
+    Staggered,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Schedule> {
+        match s {
+            "burst" => Ok(Schedule::Burst),
+            "staggered" => Ok(Schedule::Staggered),
+            _ => Err(anyhow::anyhow!("unknown refresh schedule {}", s)),
+        }
+    }
+}
+
+/// Where in the window a feed gets fetched when staggering, the same every pass.
+fn stagger_offset(feed_id: &str, window: Duration) -> Duration {
+    let window = window.as_millis().max(1) as u64;
+    Duration::from_millis(db::stable_hash(feed_id.as_bytes()) % window)
+}
+
+/// Fetches in a row permanently redirected to the same place before a feed's URL
+/// is changed to it, so a site's mistake that's soon fixed doesn't move it.
+const MOVED_AFTER: u32 = 3;
+
+/// Redirects followed in one fetch before giving up, as browsers do.
+const MAX_REDIRECTS: usize = 10;
+
+/// The longest a failing feed waits between attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long to wait before retrying a feed that has failed `failures` times in a row,
+/// doubling from one refresh interval up to `MAX_BACKOFF`.
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    interval.saturating_mul(1 << doublings).min(MAX_BACKOFF)
+}
+
+impl Refresher {
+    /// Creates the refresher along with a handle for triggering it and the
+    /// receiving side of manual triggers, to be merged into the refresh schedule.
+    pub fn new(
+        db: db::DB,
+        client: reqwest::Client,
+        interval: Duration,
+    ) -> (Self, RefreshHandle, mpsc::Receiver<Option<String>>) {
+        let (trigger, triggers) = mpsc::channel(1);
+        let (events, _) = broadcast::channel(64);
+        let health = Arc::new(Mutex::new(RefreshHealth {
+            interval_secs: interval.as_secs(),
+            ..Default::default()
+        }));
+        let handle = RefreshHandle {
+            trigger,
+            events: events.clone(),
+            health: health.clone(),
+        };
+        let refresher = Refresher {
+            db,
+            page_client: client.clone(),
+            client,
+            events,
+            health,
+            interval,
+            schedule: Schedule::default(),
+            head_probe: false,
+            excerpt_length: parse::DEFAULT_EXCERPT_LENGTH,
+            failure_notifiers: vec![],
+            read_only: ReadOnly::default(),
+            jobs: None,
+            pending: Mutex::new(HashMap::new()),
+            shutdown: Shutdown::default(),
+            dumps: None,
+        };
+        (refresher, handle, triggers)
+    }
+
+    /// Notifies through `notifiers` whenever a feed goes from healthy to failing or back.
+    pub fn with_failure_notifiers(mut self, notifiers: Vec<Arc<dyn Notifier>>) -> Self {
+        self.failure_notifiers = notifiers;
+        self
+    }
+
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Checks feeds with a HEAD first and skips the GET if it looks unchanged.
+    pub fn with_head_probe(mut self, head_probe: bool) -> Self {
+        self.head_probe = head_probe;
+        self
+    }
+
+    /// How many characters of text to keep when making entry excerpts.
+    pub fn with_excerpt_length(mut self, length: usize) -> Self {
+        self.excerpt_length = length;
+        self
+    }
+
+    /// Skips passes while the server is read-only.
+    pub fn with_read_only(mut self, read_only: ReadOnly) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Fetches full articles with a different client than feeds, to tell them apart.
+    pub fn with_page_client(mut self, client: reqwest::Client) -> Self {
+        self.page_client = client;
+        self
+    }
+
+    /// Leaves feeds queued for their first fetch to the queue.
+    pub fn with_jobs(mut self, jobs: Jobs) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Ends a pass early, after the feed it's on, once shutdown is requested.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Keeps the bodies of feeds that couldn't be parsed, to look into why.
+    pub fn with_body_dumps(mut self, dumps: Option<BodyDumps>) -> Self {
+        self.dumps = dumps;
+        self
+    }
+
+    fn publish(&self, event: RefreshEvent) {
+        self.health.lock().unwrap().record(&event);
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Refreshes every feed, logging under the id of the request that asked for it
+    /// or a fresh one for scheduled passes.
+    pub async fn refresh_all(&self, request_id: Option<String>) {
+        if self.read_only.enabled() {
+            info!("read-only, skipping refresh");
+            return;
+        }
+        // someone asking for a refresh wants it now, not spread over the interval
+        let staggered = self.schedule == Schedule::Staggered && request_id.is_none();
+        let request_id = request_id.unwrap_or_else(request_id::generate);
+        self.health.lock().unwrap().last_tick = Some(UtcTime(Utc::now()));
+        let pass = AssertUnwindSafe(self.refresh_pass(staggered)).catch_unwind();
+        if let Err(panic) = pass.instrument(info_span!("refresh", request_id)).await {
+            // keep the loop going, the next tick gets a fresh pass
+            error!("refresh pass panicked: {}", panic_message(&*panic));
+            self.health.lock().unwrap().progress = None;
+        }
+    }
+
+    async fn refresh_pass(&self, staggered: bool) {
+        let start = time::Instant::now();
+        let feeds = match self.db.get_feeds().await {
+            Ok(feeds) => feeds,
+            Err(err) => {
+                error!("couldn't get feeds, {}", err);
+                return;
+            }
+        };
+        let now = Utc::now();
+        // pushed feeds have nothing to fetch, paused ones are left be, and new ones
+        // are fetched off the queue
+        let feeds = feeds
+            .into_iter()
+            .filter(|f| !f.push && f.enabled)
+            .filter(|f| !self.jobs.as_ref().is_some_and(|j| j.waiting(&f.id)));
+        let (feeds, waiting): (Vec<Feed>, Vec<Feed>) =
+            feeds.partition(|f| match &f.next_retry_at {
+                Some(retry) => retry.0 <= now,
+                None => true,
+            });
+        if !waiting.is_empty() {
+            info!("skipping {} failing feeds until they're due", waiting.len());
+        }
+        self.publish(RefreshEvent::Started { total: feeds.len() });
+
+        // leave some room to finish before the next tick
+        let window = self.interval * 9 / 10;
+        let mut feeds: Vec<(Duration, Feed)> = feeds
+            .into_iter()
+            .map(|f| (stagger_offset(&f.id, window), f))
+            .collect();
+        if staggered {
+            feeds.sort_by_key(|(offset, _)| *offset);
+        }
+
+        let mut updated = self.store_pending().await;
+        for (done, (offset, f)) in feeds.iter().enumerate() {
+            if staggered {
+                tokio::select! {
+                    _ = time::sleep_until(start + *offset) => {}
+                    _ = self.shutdown.clone().wait() => {}
+                }
+            }
+            if self.shutdown.requested() {
+                info!(
+                    "shutting down, leaving {} feeds for the next pass",
+                    feeds.len() - done
+                );
+                break;
+            }
+            match self.fetch_and_record(f).await {
+                Ok(new_entries) => {
+                    updated += new_entries;
+                    self.publish(RefreshEvent::Parsed {
+                        feed: f.name.clone(),
+                        new_entries,
+                    });
+                }
+                Err(error) => self.publish(RefreshEvent::Error {
+                    feed: f.name.clone(),
+                    error,
+                }),
+            }
+        }
+        let seconds = start.elapsed().as_secs();
+        info!("found {} new entries in {}s", updated, seconds);
+        self.publish(RefreshEvent::Finished {
+            new_entries: updated,
+            seconds,
+        });
+    }
+
+    /// Fetches a feed and records how it went, returning how many entries were new
+    /// or why it failed. A panic along the way, even in a notifier, only fails this
+    /// feed.
+    async fn fetch_and_record(&self, f: &Feed) -> Result<usize, String> {
+        // one bad feed shouldn't take the rest of the pass down with it
+        AssertUnwindSafe(self.record_refresh(f))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|panic| {
+                let message = panic_message(&*panic);
+                error!(
+                    "recording the refresh of {} panicked: {}",
+                    f.feed_url, message
+                );
+                Err("panicked while recording the refresh".to_string())
+            })
+    }
+
+    async fn record_refresh(&self, f: &Feed) -> Result<usize, String> {
+        let previous = self.db.get_last_feed_status(&f.id).await.ok().flatten();
+        // caught here too, so a feed that panics still gets its failure recorded
+        let outcome = AssertUnwindSafe(self.refresh_feed(f))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|panic| {
+                let message = panic_message(&*panic);
+                error!("refreshing {} panicked: {}", f.feed_url, message);
+                Err(FeedError::new(None, "panicked while refreshing"))
+            });
+        let (status, result) = match outcome {
+            Ok((status, new_entries)) => (status, Ok(new_entries)),
+            Err(e) => (e.status, Err(e.message)),
+        };
+        let error = result.as_ref().err().cloned();
+        let _ = self
+            .db
+            .update_feed_status(f.id.clone(), status, error.clone())
+            .await;
+
+        self.update_backoff(f, error.is_some()).await;
+        if error.is_none() {
+            icon::update(&self.db, &self.page_client, f).await;
+        }
+
+        // an unknown previous state isn't a transition
+        if let Some(previous) = previous {
+            if previous.ok() != error.is_none() {
+                self.notify_state_change(f, status, error).await;
+            }
+        }
+        result
+    }
+
+    /// Fetches one feed off the job queue, like one that was just added or one
+    /// someone asked for, without waiting on a pass that's underway.
+    pub async fn fetch(&self, feed_id: &str) {
+        if self.read_only.enabled() {
+            info!("read-only, leaving {} for a later pass", feed_id);
+            return;
+        }
+        let feed = match self.db.get_feed(feed_id).await {
+            Ok(Some(feed)) if !feed.push && feed.enabled => feed,
+            // removed or paused since, or nothing to fetch
+            Ok(_) => return,
+            Err(e) => {
+                error!("couldn't get feed {}, {:?}", feed_id, e);
+                return;
+            }
+        };
+        match self.fetch_and_record(&feed).await {
+            Ok(new_entries) => info!("fetched {}, {} new entries", feed.name, new_entries),
+            Err(error) => info!("fetching {} failed: {}", feed.name, error),
+        }
+    }
+
+    async fn update_backoff(&self, f: &Feed, failed: bool) {
+        let (failures, next_retry_at) = if failed {
+            let failures = f.consecutive_failures + 1;
+            let wait = chrono::Duration::from_std(backoff(self.interval, failures))
+                .unwrap_or_else(|_| chrono::Duration::days(1));
+            (failures, Some(UtcTime(Utc::now() + wait)))
+        } else if f.consecutive_failures > 0 {
+            (0, None)
+        } else {
+            return;
+        };
+        if let Err(e) = self
+            .db
+            .set_feed_backoff(&f.id, failures, next_retry_at)
+            .await
+        {
+            error!("couldn't store backoff for {}, {:?}", f.name, e);
+        }
+    }
+
+    async fn notify_state_change(&self, f: &Feed, status: Option<u16>, error: Option<String>) {
+        if self.failure_notifiers.is_empty() {
+            return;
+        }
+        let change = FeedStateChange {
+            event: if error.is_some() {
+                "feed_failing"
+            } else {
+                "feed_recovered"
+            },
+            feed_id: f.id.clone(),
+            feed_name: f.name.clone(),
+            feed_url: f.feed_url.clone(),
+            status,
+            error,
+            at: UtcTime(Utc::now()),
+        };
+        info!("feed {} changed state: {}", f.name, change.event);
+        let notification = Notification {
+            title: match change.error {
+                Some(_) => format!("{} is failing", f.name),
+                None => format!("{} recovered", f.name),
+            },
+            message: change
+                .error
+                .clone()
+                .unwrap_or_else(|| "fetching it works again".to_string()),
+            link: Some(f.feed_url.clone()),
+            details: serde_json::to_value(&change).unwrap_or_default(),
+        };
+        notify::send_all(&self.failure_notifiers, &notification).await;
+    }
+
+    fn keep_pending(&self, feed_id: &str, entries: Vec<Entry>) {
+        let mut pending = self.pending.lock().unwrap();
+        let kept = pending.entry(feed_id.to_string()).or_default();
+        kept.extend(entries);
+        // the same entries come back every pass while the DB is down
+        let mut seen = HashSet::new();
+        kept.retain(|e| seen.insert(e.id.clone()));
+    }
+
+    /// Tries again to store entries from earlier passes, returning how many were new.
+    pub async fn store_pending(&self) -> usize {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut stored = 0;
+        for (feed_id, entries) in pending {
+            match with_retry(|| self.db.add_entries(entries.clone().into_iter())).await {
+                Ok(new_entries) => stored += new_entries,
+                Err(e) => {
+                    error!("still couldn't store entries for {}, {:?}", feed_id, e);
+                    self.keep_pending(&feed_id, entries);
+                }
+            }
+        }
+        stored
+    }
+
+    /// Fetches and stores a single feed, returning the response status and how many entries were new.
+    async fn refresh_feed(&self, f: &Feed) -> Result<(Option<u16>, usize), FeedError> {
+        if self.head_probe && (f.last_modified.is_some() || f.content_length.is_some()) {
+            // a failed probe just means doing the GET
+            if let Ok(head) = self.client.head(&f.feed_url).send().await {
+                if head.status() == reqwest::StatusCode::OK && unchanged_since(f, head.headers()) {
+                    return Ok((Some(head.status().as_u16()), 0));
+                }
+            }
+        }
+
+        let (feed_resp, moved) = self.get_following(f).await?;
+
+        let status = Some(feed_resp.status().as_u16());
+        if feed_resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+            self.record_move(f, moved).await;
+            return Ok((status, 0));
+        }
+        if feed_resp.status() != reqwest::StatusCode::OK {
+            return Err(FeedError::new(status, "response code not ok"));
+        }
+        let validator = |name| {
+            feed_resp
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let (etag, last_modified) = (validator(ETAG), validator(LAST_MODIFIED));
+        let content_type = validator(CONTENT_TYPE);
+        let content_length = validator(CONTENT_LENGTH).and_then(|l| l.parse().ok());
+
+        let body = feed_resp
+            .bytes()
+            .await
+            .map_err(|_| FeedError::new(status, "couldn't get bytes"))?;
+        self.publish(RefreshEvent::Fetched {
+            feed: f.name.clone(),
+        });
+
+        let entries = match parse::parse_entries(body.as_ref(), &f.feed_url) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Couldn't parse feed {}: {}", &f.feed_url, e);
+                if let Some(dumps) = &self.dumps {
+                    let failure = debug_dump::Failure {
+                        status,
+                        content_type: content_type.as_deref(),
+                        error: &e.to_string(),
+                    };
+                    dumps.keep(f, &body, failure).await;
+                }
+                return Err(FeedError::new(status, "couldn't parse feed"));
+            }
+        };
+        let ids: Vec<String> = entries.iter().map(|e| e.id.clone()).collect();
+        let stored = match self.db.stored_entry_ids(&ids).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                error!("couldn't look up stored entries for {}, {:?}", f.name, e);
+                HashSet::new()
+            }
+        };
+        let entries: Vec<Entry> = unseen_entries(f, entries, &stored)
+            .into_iter()
+            .map(|mut o| {
+                o.feed.clone_from(&f.name);
+                o.content = o
+                    .content
+                    .as_deref()
+                    .map(|c| sanitize::sanitize(c, Some(&o.content_link)));
+                o.excerpt = o
+                    .content
+                    .as_deref()
+                    .map(|c| parse::excerpt(c, self.excerpt_length))
+                    .filter(|e| !e.is_empty());
+                o
+            })
+            .collect();
+        let newest = entries
+            .iter()
+            .filter_map(|e| e.published.as_ref().map(|p| (p.clone(), e.id.clone())))
+            .max_by_key(|(p, _)| p.0);
+
+        let stored = with_retry(|| self.db.add_entries(entries.clone().into_iter())).await;
+        let new_entries = match stored {
+            Ok(new_entries) => new_entries,
+            Err(e) => {
+                error!(
+                    "couldn't update entries, keeping them for next pass, {:?}",
+                    e
+                );
+                self.keep_pending(&f.id, entries);
+                return Err(FeedError::new(status, "couldn't store entries"));
+            }
+        };
+        if f.extract_mode() == ExtractMode::OnRefresh {
+            extract::extract_missing(&self.db, &self.page_client, &entries).await;
+        }
+        if let Some((published, entry_id)) = newest {
+            if let Err(e) = self.db.set_feed_cursor(&f.id, published, &entry_id).await {
+                error!("couldn't store cursor for {}, {:?}", f.name, e);
+            }
+        }
+        // only remember validators once the body they describe is stored
+        if let Err(e) = self
+            .db
+            .set_feed_validators(&f.id, etag, last_modified, content_length)
+            .await
+        {
+            error!("couldn't store validators for {}, {:?}", f.name, e);
+        }
+        self.record_move(f, moved).await;
+        Ok((status, new_entries))
+    }
+
+    /// Gets a feed, following redirects by hand to tell permanent ones apart.
+    /// Along with the response comes where the feed's permanently moved to, when
+    /// the redirects start with one or more permanent ones.
+    async fn get_following(
+        &self,
+        f: &Feed,
+    ) -> Result<(reqwest::Response, Option<String>), FeedError> {
+        let mut url =
+            Url::parse(&f.feed_url).map_err(|_| FeedError::new(None, "feed URL isn't valid"))?;
+        let mut moved = None;
+        let mut permanent = true;
+        for _ in 0..=MAX_REDIRECTS {
+            let mut request = self.client.get(url.clone());
+            if let Some(etag) = &f.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &f.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(|_| FeedError::new(None, "couldn't get response"))?;
+            let Some(next) = redirect(&url, &response) else {
+                return Ok((response, moved));
+            };
+            permanent &= matches!(response.status().as_u16(), 301 | 308);
+            if permanent {
+                moved = Some(next.to_string());
+            }
+            url = next;
+        }
+        Err(FeedError::new(None, "too many redirects"))
+    }
+
+    /// Counts a fetch that was, or wasn't, permanently redirected, moving the
+    /// feed once enough in a row have gone to the same place.
+    async fn record_move(&self, f: &Feed, moved: Option<String>) {
+        let count = match self.db.record_feed_redirect(&f.id, moved.as_deref()).await {
+            Ok(count) => count,
+            Err(e) => {
+                error!("couldn't record redirect for {}, {:?}", f.name, e);
+                return;
+            }
+        };
+        let Some(url) = moved.filter(|_| count >= MOVED_AFTER) else {
+            return;
+        };
+        match self.db.move_feed(&f.id, &url).await {
+            Ok(true) => info!(
+                "{} moved permanently, changed its URL from {} to {}",
+                f.name, f.feed_url, url
+            ),
+            Ok(false) => info!(
+                "{} moved to {}, which is already another feed's URL",
+                f.name, url
+            ),
+            Err(e) => error!("couldn't move {} to {}, {:?}", f.name, url, e),
+        }
+    }
+}
+
+/// Where a response redirects to, if it's a redirect that says where.
+fn redirect(url: &Url, response: &reqwest::Response) -> Option<Url> {
+    if !matches!(response.status().as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    url.join(location).ok()
+}
+
+async fn trigger_refresh(State(AppState { refresh, .. }): State<AppState>) -> StatusCode {
+    refresh.trigger(request_id::current());
+    StatusCode::ACCEPTED
+}
+
+/// Fetches one feed ahead of any queued fetches, and alongside a pass if there's one.
+/// The body replaces the button that asked for it.
+async fn refresh_feed(
+    Path(feed_id): Path<String>,
+    State(AppState { db, jobs, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    match db.get_feed(&feed_id).await? {
+        Some(feed) if feed.push => {
+            Ok((StatusCode::CONFLICT, "pushed feeds aren't fetched").into_response())
+        }
+        Some(feed) if !feed.enabled => {
+            Ok((StatusCode::CONFLICT, "paused feeds aren't fetched").into_response())
+        }
+        Some(_) => {
+            jobs.fetch(&feed_id);
+            Ok((StatusCode::ACCEPTED, "Queued").into_response())
+        }
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "refresh_status.html")]
+struct RefreshStatusTemplate {
+    health: RefreshHealth,
+}
+
+/// A footer snippet showing whether the refresh loop is alive.
+async fn refresh_status(State(AppState { refresh, .. }): State<AppState>) -> RefreshStatusTemplate {
+    RefreshStatusTemplate {
+        health: refresh.health(),
+    }
+}
+
+async fn refresh_progress(
+    State(AppState { refresh, .. }): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = BroadcastStream::new(refresh.subscribe()).filter_map(|e| async move {
+        // a lagging client just misses some intermediate progress
+        let e = e.ok()?;
+        Event::default().event(e.name()).json_data(&e).ok().map(Ok)
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::user;
+
+    #[test]
+    fn health_tracks_passes() {
+        let mut health = RefreshHealth {
+            interval_secs: 60,
+            ..Default::default()
+        };
+        assert!(!health.stalled());
+
+        health.record(&RefreshEvent::Started { total: 2 });
+        health.record(&RefreshEvent::Parsed {
+            feed: "a".to_string(),
+            new_entries: 3,
+        });
+        assert_eq!(health.progress, Some((1, 2)));
+        health.record(&RefreshEvent::Finished {
+            new_entries: 3,
+            seconds: 1,
+        });
+        assert_eq!(health.progress, None);
+        assert_eq!(health.last_new_entries, 3);
+        assert!(health.last_finished.is_some());
+
+        health.last_tick = Some(UtcTime(Utc::now() - chrono::Duration::seconds(300)));
+        assert!(health.stalled());
+        let page = RefreshStatusTemplate { health }.render().unwrap();
+        assert!(page.contains("stalled"));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let interval = Duration::from_secs(180);
+        assert_eq!(backoff(interval, 1), interval);
+        assert_eq!(backoff(interval, 2), interval * 2);
+        assert_eq!(backoff(interval, 4), interval * 8);
+        assert_eq!(backoff(interval, 100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn stagger_offsets_are_stable_and_in_window() {
+        let window = Duration::from_secs(162);
+        let a = stagger_offset("feed-a", window);
+        assert_eq!(a, stagger_offset("feed-a", window));
+        assert!(a < window);
+        assert_ne!(a, stagger_offset("feed-b", window));
+        assert_eq!(
+            "staggered".parse::<Schedule>().unwrap(),
+            Schedule::Staggered
+        );
+    }
+
+    #[test]
+    fn skip_entries_before_cursor() {
+        let now = Utc::now();
+        let entry = |id: &str, hours: Option<i64>| {
+            Entry::new(
+                id,
+                id.to_string(),
+                "".to_string(),
+                "".to_string(),
+                hours.map(|h| UtcTime(now + chrono::Duration::hours(h))),
+            )
+        };
+        let entries = vec![
+            entry("old", Some(-1)),
+            entry("newest-seen", Some(0)),
+            entry("same-time", Some(0)),
+            entry("new", Some(1)),
+            entry("undated", None),
+        ];
+        let mut feed = Feed::default();
+        let none = HashSet::new();
+        assert_eq!(unseen_entries(&feed, entries.clone(), &none).len(), 5);
+
+        feed.last_entry_published = Some(UtcTime(now));
+        feed.last_entry_id = Some(entries[1].id.clone());
+        let titles: Vec<String> = unseen_entries(&feed, entries.clone(), &none)
+            .into_iter()
+            .map(|e| e.title)
+            .collect();
+        assert_eq!(titles, vec!["same-time", "new", "undated"]);
+
+        // stored ones might have been edited
+        let stored = HashSet::from([entries[0].id.clone()]);
+        let titles: Vec<String> = unseen_entries(&feed, entries, &stored)
+            .into_iter()
+            .map(|e| e.title)
+            .collect();
+        assert_eq!(titles, vec!["old", "same-time", "new", "undated"]);
+    }
+
+    #[tokio::test]
+    async fn keep_revisions_of_dated_entries() -> anyhow::Result<()> {
+        user::scope(user::OWNER, async {
+            let rss = |content: &str| {
+                format!(
+                    r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Blog</title>
+                    <item><guid>post-1</guid><title>Post</title><link>https://blog.com/1</link>
+                    <pubDate>Mon, 02 Jan 2023 10:00:00 GMT</pubDate>
+                    <description>{}</description></item></channel></rss>"#,
+                    content
+                )
+            };
+            let body = Arc::new(Mutex::new(rss("first draft")));
+            let served = body.clone();
+            let app = axum::Router::new().route(
+                "/feed.xml",
+                get(move || async move { served.lock().unwrap().clone() }),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let feed_url = format!("http://{}/feed.xml", listener.local_addr()?);
+            tokio::spawn(async move { axum::serve(listener, app).await });
+
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed {
+                id: base64::encode_config(&feed_url, base64::URL_SAFE),
+                name: "Blog".to_string(),
+                feed_url: feed_url.clone(),
+                ..Default::default()
+            };
+            db.add_feeds(std::iter::once(feed.clone())).await?;
+            let (refresher, _, _) =
+                Refresher::new(db.clone(), reqwest::Client::new(), Duration::from_secs(60));
+            assert_eq!(refresher.refresh_feed(&feed).await.unwrap().1, 1);
+
+            *body.lock().unwrap() = rss("corrected");
+            let feed = db.get_feed(&feed.id).await?.unwrap();
+            assert!(feed.last_entry_published.is_some());
+            assert_eq!(refresher.refresh_feed(&feed).await.unwrap().1, 0);
+
+            let id = parse::parse_entries(rss("").as_bytes(), &feed_url)?[0]
+                .id
+                .clone();
+            let entry = db.get_entry(&id).await?.unwrap();
+            assert_eq!(entry.content.as_deref(), Some("corrected"));
+            let revisions = db.get_entry_revisions(&id).await?;
+            assert_eq!(revisions.len(), 1);
+            assert_eq!(revisions[0].content, "first draft");
+            Ok(())
+        })
+        .await
+    }
+
+    #[test]
+    fn head_probe_compares_headers() {
+        let mut feed = Feed {
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            content_length: Some(1024),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert!(!unchanged_since(&feed, &headers));
+
+        headers.insert(CONTENT_LENGTH, "1024".parse().unwrap());
+        assert!(unchanged_since(&feed, &headers));
+        headers.insert(
+            LAST_MODIFIED,
+            "Thu, 22 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert!(!unchanged_since(&feed, &headers));
+
+        feed.last_modified = None;
+        headers.insert(CONTENT_LENGTH, "2048".parse().unwrap());
+        assert!(!unchanged_since(&feed, &headers));
+    }
+
+    #[tokio::test]
+    async fn retry_transient_writes() {
+        let mut calls = 0;
+        let result = with_retry(|| {
+            calls += 1;
+            let calls = calls;
+            async move {
+                if calls < WRITE_ATTEMPTS {
+                    Err(anyhow::anyhow!("database is locked"))
+                } else {
+                    Ok(calls)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), WRITE_ATTEMPTS);
+
+        let mut calls = 0;
+        let result: anyhow::Result<()> = with_retry(|| {
+            calls += 1;
+            async { Err(anyhow::anyhow!("gone")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, WRITE_ATTEMPTS);
+    }
+
+    /// Panics when told about a feed failing, like a buggy notifier would.
+    struct PanickingNotifier;
+
+    impl Notifier for PanickingNotifier {
+        fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, anyhow::Result<()>> {
+            panic!("can't send {}", notification.title)
+        }
+    }
+
+    #[tokio::test]
+    async fn pass_survives_a_panicking_feed() -> anyhow::Result<()> {
+        user::scope(user::OWNER, async {
+            let app = axum::Router::new()
+                .route(
+                    "/broken.xml",
+                    get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+                )
+                .route(
+                    "/feed.xml",
+                    get(|| async {
+                        r#"<?xml version="1.0"?><rss version="2.0"><channel><title>Blog</title>
+                        <item><guid>post-1</guid><title>Post</title><link>https://blog.com/1</link>
+                        </item></channel></rss>"#
+                    }),
+                );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let site = format!("http://{}", listener.local_addr()?);
+            tokio::spawn(async move { axum::serve(listener, app).await });
+
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            // sorted by name, so the broken one goes first
+            let feeds =
+                [("A broken", "broken.xml"), ("B working", "feed.xml")].map(|(name, path)| {
+                    Feed::new(
+                        name.to_string(),
+                        site.clone(),
+                        format!("{}/{}", site, path),
+                        "".to_string(),
+                    )
+                });
+            let broken = feeds[0].id.clone();
+            db.add_feeds(feeds.into_iter()).await?;
+            // it was fine last time, so failing now is news for the notifier
+            db.update_feed_status(broken, Some(200), None).await?;
+
+            let (refresher, handle, _) =
+                Refresher::new(db.clone(), reqwest::Client::new(), Duration::from_secs(60));
+            let refresher = refresher.with_failure_notifiers(vec![Arc::new(PanickingNotifier)]);
+            let mut events = handle.subscribe();
+            refresher.refresh_all(Some("test".to_string())).await;
+
+            let mut outcomes = vec![];
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    RefreshEvent::Parsed { feed, new_entries } => {
+                        outcomes.push(format!("{} parsed {}", feed, new_entries))
+                    }
+                    RefreshEvent::Error { feed, error } => {
+                        outcomes.push(format!("{} failed: {}", feed, error))
+                    }
+                    _ => {}
+                }
+            }
+            assert_eq!(
+                outcomes,
+                [
+                    "A broken failed: panicked while recording the refresh",
+                    "B working parsed 1"
+                ]
+            );
+            assert_eq!(handle.health().last_new_entries, 1);
+            Ok(())
+        })
+        .await
+    }
+}
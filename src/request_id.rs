@@ -0,0 +1,30 @@
+//! Makes the `X-Request-Id` of the current request available outside of its span,
+//! so errors and background work it kicks off can refer back to it.
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::request_id::RequestId;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs the rest of the request with its id in scope.
+pub async fn scope(request: Request, next: Next) -> Response {
+    let id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+    REQUEST_ID.scope(id, next.run(request)).await
+}
+
+/// The id of the request being handled, if there is one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub fn generate() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
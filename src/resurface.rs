@@ -0,0 +1,48 @@
+//! Brings a few old starred entries back each week, so saving something for later
+//! doesn't mean never seeing it again.
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::maintenance::ReadOnly;
+use crate::{db, user};
+
+/// Starred entries need to be at least this old to be picked.
+const MIN_AGE_DAYS: i64 = 30;
+
+/// An entry isn't picked again until this long after it was last shown.
+const REPEAT_AFTER_DAYS: i64 = 90;
+
+/// Picks `count` old starred entries for the rediscover page once a week.
+pub async fn run(db: db::DB, count: u32, read_only: ReadOnly) {
+    let mut interval = time::interval(Duration::from_secs(
+        db::RESURFACE_DAYS as u64 * 24 * 60 * 60,
+    ));
+    loop {
+        interval.tick().await;
+        if read_only.enabled() {
+            continue;
+        }
+        if let Err(e) = resurface(&db, count).await {
+            error!("couldn't resurface starred entries: {:?}", e);
+        }
+    }
+}
+
+/// Every user gets their own picks from what they starred.
+async fn resurface(db: &db::DB, count: u32) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let published_before = now - chrono::Duration::days(MIN_AGE_DAYS);
+    let not_since = now - chrono::Duration::days(REPEAT_AFTER_DAYS);
+    for u in db.get_users().await? {
+        let picked = user::scope(
+            u.id,
+            db.resurface_starred(count, published_before.into(), not_since.into()),
+        )
+        .await?;
+        info!("resurfaced {} starred entries for {}", picked, u.username);
+    }
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! Filter rules, applied to entries as they're stored: dropping ones never worth
+//! seeing, like sponsored posts, or marking them read or starred, optionally for
+//! a single feed. Rules are for the whole instance, like feeds.
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use askama_axum::Template;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Form, Router};
+use serde::Deserialize;
+
+use crate::{db, filters, AppError, AppState, Entry, Feed, FilterRule};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/rules.html", get(rules_page))
+        .route("/rules", post(add_rule))
+        .route("/rules/:rule_id", delete(remove_rule))
+}
+
+/// Where in an entry a rule's pattern is looked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleField {
+    Title,
+    Content,
+    Author,
+    Link,
+    /// The title, content or author
+    Any,
+}
+
+impl FromStr for RuleField {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<RuleField> {
+        match s {
+            "title" => Ok(RuleField::Title),
+            "content" => Ok(RuleField::Content),
+            "author" => Ok(RuleField::Author),
+            "link" => Ok(RuleField::Link),
+            "any" => Ok(RuleField::Any),
+            _ => Err(anyhow!("unknown rule field {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for RuleField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleField::Title => write!(f, "title"),
+            RuleField::Content => write!(f, "content"),
+            RuleField::Author => write!(f, "author"),
+            RuleField::Link => write!(f, "link"),
+            RuleField::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// What a rule does to an entry it matches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleAction {
+    /// The entry isn't stored at all
+    Drop,
+    MarkRead,
+    Star,
+}
+
+impl FromStr for RuleAction {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<RuleAction> {
+        match s {
+            "drop" => Ok(RuleAction::Drop),
+            "read" => Ok(RuleAction::MarkRead),
+            "star" => Ok(RuleAction::Star),
+            _ => Err(anyhow!("unknown rule action {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAction::Drop => write!(f, "drop"),
+            RuleAction::MarkRead => write!(f, "read"),
+            RuleAction::Star => write!(f, "star"),
+        }
+    }
+}
+
+/// What the rules matching an entry do to it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    pub drop: bool,
+    pub read: bool,
+    pub star: bool,
+}
+
+/// Runs every rule over a new entry. Rules with a field or action this build
+/// doesn't know, or for a feed that's gone, match nothing.
+pub fn apply(rules: &[FilterRule], entry: &Entry) -> Outcome {
+    let mut outcome = Outcome::default();
+    for rule in rules.iter().filter(|r| matches(r, entry)) {
+        match rule.action.parse() {
+            Ok(RuleAction::Drop) => outcome.drop = true,
+            Ok(RuleAction::MarkRead) => outcome.read = true,
+            Ok(RuleAction::Star) => outcome.star = true,
+            Err(_) => {}
+        }
+    }
+    outcome
+}
+
+fn matches(rule: &FilterRule, entry: &Entry) -> bool {
+    if rule.feed_id.is_some() && rule.feed.as_deref() != Some(entry.feed.as_str()) {
+        return false;
+    }
+    let pattern = rule.pattern.to_lowercase();
+    let contains = |text: Option<&str>| text.is_some_and(|t| t.to_lowercase().contains(&pattern));
+    let title = Some(entry.title.as_str());
+    let content = entry.content.as_deref();
+    let author = entry.author.as_deref();
+    match rule.field.parse() {
+        Ok(RuleField::Title) => contains(title),
+        Ok(RuleField::Content) => contains(content),
+        Ok(RuleField::Author) => contains(author),
+        Ok(RuleField::Link) => contains(Some(entry.content_link.as_str())),
+        Ok(RuleField::Any) => contains(title) || contains(content) || contains(author),
+        Err(_) => false,
+    }
+}
+
+/// How a rule reads on the rules page, e.g. "Drop entries with “sponsored” in
+/// the title from Blog".
+fn describe(rule: &FilterRule) -> String {
+    let action = match rule.action.parse() {
+        Ok(RuleAction::Drop) => "Drop",
+        Ok(RuleAction::MarkRead) => "Mark read",
+        Ok(RuleAction::Star) => "Star",
+        Err(_) => "Do nothing with",
+    };
+    let field = match rule.field.parse() {
+        Ok(RuleField::Any) => "the title, content or author".to_string(),
+        Ok(field) => format!("the {}", field),
+        Err(_) => rule.field.clone(),
+    };
+    let from = match (&rule.feed_id, &rule.feed) {
+        (None, _) => "any feed".to_string(),
+        (Some(_), Some(feed)) => feed.clone(),
+        (Some(_), None) => "a removed feed".to_string(),
+    };
+    format!(
+        "{} entries with “{}” in {} from {}",
+        action, rule.pattern, field, from
+    )
+}
+
+#[derive(Template)]
+#[template(path = "rules.html")]
+struct RulesTemplate {
+    rules: Vec<FilterRule>,
+    feeds: Vec<Feed>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RuleForm {
+    pattern: String,
+    field: String,
+    /// Empty for any
+    #[serde(default)]
+    feed_id: String,
+    action: String,
+}
+
+/// The page again after a change, with why it wasn't made if it wasn't. It's a
+/// success either way so htmx swaps the error in.
+async fn rules(db: &db::DB, error: Option<&str>) -> Result<Response, AppError> {
+    Ok(RulesTemplate {
+        rules: db.get_filter_rules().await?,
+        feeds: db.get_feeds().await?,
+        error: error.map(String::from),
+    }
+    .into_response())
+}
+
+async fn rules_page(State(AppState { db, .. }): State<AppState>) -> Result<Response, AppError> {
+    rules(&db, None).await
+}
+
+async fn add_rule(
+    State(AppState { db, .. }): State<AppState>,
+    Form(form): Form<RuleForm>,
+) -> Result<Response, AppError> {
+    let pattern = form.pattern.trim();
+    if pattern.is_empty() {
+        return rules(&db, Some("A rule needs something to look for")).await;
+    }
+    let (Ok(field), Ok(action)) = (
+        form.field.parse::<RuleField>(),
+        form.action.parse::<RuleAction>(),
+    ) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let rule = FilterRule {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        pattern: pattern.to_string(),
+        field: field.to_string(),
+        feed_id: Some(form.feed_id).filter(|f| !f.is_empty()),
+        action: action.to_string(),
+        ..Default::default()
+    };
+    db.add_filter_rule(&rule).await?;
+    rules(&db, None).await
+}
+
+async fn remove_rule(
+    Path(rule_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    if !db.remove_filter_rule(&rule_id).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    rules(&db, None).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply_matching_rules() {
+        let rule = |pattern: &str, field: &str, feed: Option<&str>, action: &str| FilterRule {
+            pattern: pattern.to_string(),
+            field: field.to_string(),
+            feed_id: feed.map(|_| "id".to_string()),
+            feed: feed.map(String::from),
+            action: action.to_string(),
+            ..Default::default()
+        };
+        let entry = Entry {
+            title: "This week in Rust".to_string(),
+            feed: "Blog".to_string(),
+            content: Some("<p>Brought to you by our SPONSOR</p>".to_string()),
+            ..Default::default()
+        };
+        let rules = [
+            rule("sponsor", "content", Some("News"), "drop"),
+            rule("rust", "any", None, "star"),
+            rule("sponsor", "title", None, "read"),
+        ];
+        assert_eq!(
+            apply(&rules, &entry),
+            Outcome {
+                star: true,
+                ..Default::default()
+            }
+        );
+        assert!(apply(&[rule("sponsor", "any", Some("Blog"), "drop")], &entry).drop);
+        assert_eq!(
+            describe(&rules[1]),
+            "Star entries with “rust” in the title, content or author from any feed"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Cleans feed HTML with ammonia before it's stored or rendered inside the reader,
+//! keeping formatting and dropping anything that could run script or restyle the page.
+//!
+//! What's kept can be changed with `FEED_HTML_ALLOW`, a comma separated list of
+//! elements to add, each with the attributes it may keep in brackets, or to take
+//! away with a leading `-`: `video[src poster controls], details, summary, -img`.
+//! Nothing that can run script can be let through that way.
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use ammonia::{Builder, UrlRelative};
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use reqwest::Url;
+
+/// Elements kept as they are, along with the attributes each may keep.
+const ALLOWED: &[(&str, &[&str])] = &[
+    ("a", &["href", "title"]),
+    ("abbr", &["title"]),
+    ("b", &[]),
+    ("blockquote", &["cite"]),
+    ("br", &[]),
+    ("cite", &[]),
+    ("code", &[]),
+    ("dd", &[]),
+    ("del", &[]),
+    ("div", &[]),
+    ("dl", &[]),
+    ("dt", &[]),
+    ("em", &[]),
+    ("figcaption", &[]),
+    ("figure", &[]),
+    ("h1", &[]),
+    ("h2", &[]),
+    ("h3", &[]),
+    ("h4", &[]),
+    ("h5", &[]),
+    ("h6", &[]),
+    ("hr", &[]),
+    ("i", &[]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("ins", &[]),
+    ("li", &[]),
+    ("mark", &[]),
+    ("ol", &["start"]),
+    ("p", &[]),
+    ("pre", &[]),
+    ("q", &["cite"]),
+    ("s", &[]),
+    ("small", &[]),
+    ("span", &[]),
+    ("strong", &[]),
+    ("sub", &[]),
+    ("sup", &[]),
+    ("table", &[]),
+    ("tbody", &[]),
+    ("td", &["colspan", "rowspan"]),
+    ("tfoot", &[]),
+    ("th", &["colspan", "rowspan"]),
+    ("thead", &[]),
+    ("time", &["datetime"]),
+    ("tr", &[]),
+    ("u", &[]),
+    ("ul", &[]),
+];
+
+/// Elements dropped along with everything inside them, which can't be allowed
+/// either. Anything else that isn't allowed is unwrapped, keeping its children.
+/// `base`, `meta` and `link` are here since they act on the whole page: retargeting
+/// every relative link, redirecting it or pulling in a stylesheet.
+const DROPPED: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "noscript", "template", "svg", "math", "form",
+    "textarea", "select", "button", "head", "title", "frame", "frameset", "base", "meta", "link",
+];
+
+/// Schemes links and sources may have; anything else loses the attribute.
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Attributes that can't be allowed, besides event handlers: they can run
+/// script, restyle the page or hold URLs that aren't checked.
+const FORBIDDEN_ATTRIBUTES: &[&str] = &[
+    "style",
+    "srcdoc",
+    "srcset",
+    "formaction",
+    "action",
+    "data",
+    "background",
+    "xmlns",
+];
+
+/// Elements kept and the attributes each keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allowlist(HashMap<String, Vec<String>>);
+
+impl Default for Allowlist {
+    fn default() -> Allowlist {
+        Allowlist(
+            ALLOWED
+                .iter()
+                .map(|(name, attributes)| {
+                    let attributes = attributes.iter().map(|a| a.to_string()).collect();
+                    (name.to_string(), attributes)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for Allowlist {
+    type Err = anyhow::Error;
+    /// The defaults with the changes in a `FEED_HTML_ALLOW` list.
+    fn from_str(s: &str) -> Result<Allowlist> {
+        let mut allowlist = Allowlist::default();
+        for change in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            if let Some(name) = change.strip_prefix('-') {
+                allowlist.0.remove(&name.trim().to_lowercase());
+                continue;
+            }
+            let (name, attributes) = match change.split_once('[') {
+                Some((name, rest)) => {
+                    let attributes = rest
+                        .strip_suffix(']')
+                        .ok_or_else(|| anyhow!("{} is missing a closing ]", change))?;
+                    (name, attributes.split_whitespace().collect())
+                }
+                None => (change, vec![]),
+            };
+            let name = name.trim().to_lowercase();
+            if !is_name(&name) {
+                return Err(anyhow!("{} isn't an element", name));
+            }
+            if DROPPED.contains(&name.as_str()) {
+                return Err(anyhow!("<{}> can't be allowed", name));
+            }
+            let kept = allowlist.0.entry(name.clone()).or_default();
+            for attribute in attributes {
+                let attribute = attribute.to_lowercase();
+                if !is_name(&attribute) {
+                    return Err(anyhow!("{} isn't an attribute", attribute));
+                }
+                if attribute.starts_with("on") || FORBIDDEN_ATTRIBUTES.contains(&attribute.as_str())
+                {
+                    return Err(anyhow!("{} on <{}> can't be allowed", attribute, name));
+                }
+                if !kept.contains(&attribute) {
+                    kept.push(attribute);
+                }
+            }
+        }
+        Ok(allowlist)
+    }
+}
+
+impl Allowlist {
+    /// An ammonia cleaner keeping what's allowed, with relative links resolved
+    /// against `base` or dropped when there's none.
+    fn builder(&self, base: Option<Url>) -> Builder<'_> {
+        let mut builder = Builder::empty();
+        builder
+            .tags(self.0.keys().map(String::as_str).collect())
+            .tag_attributes(
+                self.0
+                    .iter()
+                    .map(|(name, attributes)| {
+                        let attributes = attributes.iter().map(String::as_str).collect();
+                        (name.as_str(), attributes)
+                    })
+                    .collect(),
+            )
+            .clean_content_tags(DROPPED.iter().copied().collect())
+            .url_schemes(URL_SCHEMES.iter().copied().collect::<HashSet<_>>())
+            .url_relative(match base {
+                Some(base) => UrlRelative::RewriteWithBase(base),
+                None => UrlRelative::Deny,
+            })
+            .link_rel(None)
+            .strip_comments(true);
+        builder
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+static ALLOWLIST: OnceLock<Allowlist> = OnceLock::new();
+
+/// Sets what's kept for the rest of the process, once on startup before
+/// anything's sanitized.
+pub fn configure(allowlist: Allowlist) {
+    // a second call, like from a test, keeps the first
+    let _ = ALLOWLIST.set(allowlist);
+}
+
+/// Sanitizes an HTML fragment from a feed, resolving relative links against `base`.
+pub fn sanitize(html: &str, base: Option<&str>) -> String {
+    sanitize_with(ALLOWLIST.get_or_init(Allowlist::default), html, base)
+}
+
+fn sanitize_with(allowlist: &Allowlist, html: &str, base: Option<&str>) -> String {
+    let base = base.and_then(|b| Url::parse(b).ok());
+    allowlist.builder(base).clean(html).to_string()
+}
+
+/// What links off the site get: no `window.opener` or referrer for the page they
+/// open, and a new tab when that's preferred.
+fn leaving_attributes(new_tab: bool) -> &'static str {
+    if new_tab {
+        r#" rel="noopener noreferrer" target="_blank""#
+    } else {
+        r#" rel="noopener noreferrer""#
+    }
+}
+
+/// An `href` for `url` along with the attributes for leaving the reader.
+pub fn external_href(url: &str, new_tab: bool) -> String {
+    format!("href=\"{}\"{}", escape(url), leaving_attributes(new_tab))
+}
+
+/// Adds the attributes for leaving the reader to every absolute link in sanitized
+/// HTML, which has no `rel` or `target` of its own.
+pub fn external_links(html: &str, new_tab: bool) -> String {
+    lazy_static! {
+        static ref LINK: Regex = Regex::new(r#"<a\s[^>]*href="(?:https?:)?//[^>]*>"#).unwrap();
+    }
+    LINK.replace_all(html, |link: &Captures| {
+        let tag = &link[0];
+        format!("{}{}>", &tag[..tag.len() - 1], leaving_attributes(new_tab))
+    })
+    .into_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strip_hostile_markup() {
+        let html = r#"<p onclick="steal()">Hi <b>there</b><script>alert(1)</script></p>
+            <a href="javascript:alert(1)">bad</a> <a href="/post" target="_top">good</a>
+            <img src="cat.png" onerror="steal()"><font color="red">unwrapped</font>
+            <iframe src="https://evil.com"></iframe>"#;
+        let clean = sanitize(html, Some("https://blog.com/feed"));
+        assert_eq!(
+            clean,
+            r#"<p>Hi <b>there</b></p>
+            <a>bad</a> <a href="https://blog.com/post">good</a>
+            <img src="https://blog.com/cat.png">unwrapped
+            "#
+        );
+        assert_eq!(sanitize("1 &lt; 2", None), "1 &lt; 2");
+    }
+
+    #[test]
+    fn neutralize_malicious_payloads() {
+        let payloads = [
+            r#"<img src=x onerror=alert(1)>"#,
+            r#"<a href="JaVaScRiPt:alert(1)">x</a>"#,
+            r#"<a href="  javascript:alert(1)">x</a>"#,
+            r#"<a href="&#106;avascript:alert(1)">x</a>"#,
+            r#"<a href="java&#x09;script:alert(1)">x</a>"#,
+            r#"<a href="data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==">x</a>"#,
+            r#"<svg><script>alert(1)</script></svg>"#,
+            r#"<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>"#,
+            r#"<scr<script>ipt>alert(1)</script>"#,
+            r#"<noscript><p title="</noscript><img src=x onerror=alert(1)>">"#,
+            r#"<iframe srcdoc="<script>alert(1)</script>"></iframe>"#,
+            r#"<p style="background:url(javascript:alert(1))">x</p>"#,
+            r#"<form action="javascript:alert(1)"><button>x</button></form>"#,
+            r#"<!--<img src=x onerror=alert(1)>-->"#,
+            r#"<video poster="javascript:alert(1)" onloadstart="alert(1)"></video>"#,
+        ];
+        let allowlist: Allowlist = "video[src poster controls]".parse().unwrap();
+        for payload in payloads {
+            let clean = sanitize_with(&allowlist, payload, Some("https://blog.com/feed"));
+            for hostile in [
+                "<script",
+                "javascript:",
+                "onerror",
+                "onload",
+                "style=",
+                "data:",
+            ] {
+                assert!(
+                    !clean.to_lowercase().contains(hostile),
+                    "{} became {}",
+                    payload,
+                    clean
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn configure_allowlist() {
+        let allowlist: Allowlist = "video[src Controls], details, -img".parse().unwrap();
+        let html =
+            r#"<details><video src="clip.mp4" autoplay></video></details><img src="cat.png">"#;
+        assert_eq!(
+            sanitize_with(&allowlist, html, Some("https://blog.com/feed")),
+            r#"<details><video src="https://blog.com/clip.mp4"></video></details>"#
+        );
+        assert_eq!(
+            sanitize_with(&allowlist, "<video controls></video>", None),
+            r#"<video controls=""></video>"#
+        );
+        assert!("script".parse::<Allowlist>().is_err());
+        assert!("img[onerror]".parse::<Allowlist>().is_err());
+        assert!("p[style]".parse::<Allowlist>().is_err());
+        assert!("video[src".parse::<Allowlist>().is_err());
+        assert!("base[href]".parse::<Allowlist>().is_err());
+        assert!("meta[http-equiv content]".parse::<Allowlist>().is_err());
+        assert!("link[rel href]".parse::<Allowlist>().is_err());
+    }
+
+    #[test]
+    fn mark_links_leaving_the_reader() {
+        let html = sanitize(
+            r#"<p><a href="/post">post</a> <a href="mailto:me@blog.com">mail</a></p>"#,
+            Some("https://blog.com/feed"),
+        );
+        assert_eq!(
+            external_links(&html, true),
+            r#"<p><a href="https://blog.com/post" rel="noopener noreferrer" target="_blank">post</a> <a href="mailto:me@blog.com">mail</a></p>"#
+        );
+        assert_eq!(
+            external_href("https://blog.com/?a=1&b=\"2\"", false),
+            r#"href="https://blog.com/?a=1&amp;b=&quot;2&quot;" rel="noopener noreferrer""#
+        );
+    }
+}
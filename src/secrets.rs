@@ -0,0 +1,120 @@
+//! Encrypting credentials kept in the database, like a feed's login or an
+//! integration's API token, so a copy of the database alone doesn't give them
+//! away. They're sealed with AES-256-GCM under this instance's key from
+//! `FEED_SECRET_KEY`: 32 random bytes in base64, e.g. from `openssl rand -base64 32`.
+//!
+//! Each value records which key sealed it, so a value sealed with another key
+//! is reported as such rather than as corrupt, and `rotate-key` can move every
+//! stored credential onto a new key.
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Lowercase hex, for digests and fingerprints.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+/// The key credentials are encrypted with.
+pub struct SecretKey {
+    key: LessSafeKey,
+    id: String,
+}
+
+impl FromStr for SecretKey {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<SecretKey> {
+        let bytes = base64::decode(s.trim()).map_err(|_| anyhow!("secret key isn't base64"))?;
+        let unbound = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| anyhow!("secret key has to be 32 bytes, not {}", bytes.len()))?;
+        // a fingerprint to tell keys apart by, too short to say anything about the key
+        let id = hex(&digest(&SHA256, &bytes).as_ref()[..4]);
+        Ok(SecretKey {
+            key: LessSafeKey::new(unbound),
+            id,
+        })
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({})", self.id)
+    }
+}
+
+impl SecretKey {
+    /// Seals the credential stored under `name`, which is bound to it so a
+    /// sealed value can't be moved onto another credential.
+    pub fn encrypt(&self, name: &str, secret: &str) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("couldn't generate a nonce"))?;
+        let mut sealed = secret.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("couldn't encrypt {}", name))?;
+        let mut stored = nonce.to_vec();
+        stored.append(&mut sealed);
+        Ok(format!("{}:{}", self.id, base64::encode(stored)))
+    }
+
+    /// Opens a value `encrypt` sealed for `name`.
+    pub fn decrypt(&self, name: &str, value: &str) -> Result<String> {
+        let (id, sealed) = value
+            .split_once(':')
+            .ok_or_else(|| anyhow!("{} isn't an encrypted value", name))?;
+        if id != self.id {
+            return Err(anyhow!(
+                "{} was encrypted with key {}, not this one ({})",
+                name,
+                id,
+                self.id
+            ));
+        }
+        let mut sealed =
+            base64::decode(sealed).map_err(|_| anyhow!("{} isn't an encrypted value", name))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("{} isn't an encrypted value", name));
+        }
+        let mut ciphertext = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed)
+            .map_err(|_| anyhow!("{} isn't an encrypted value", name))?;
+        let secret = self
+            .key
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut ciphertext)
+            .map_err(|_| anyhow!("couldn't decrypt {}", name))?;
+        Ok(String::from_utf8(secret.to_vec())?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encrypt_credentials() {
+        let key: SecretKey = base64::encode([7u8; 32]).parse().unwrap();
+        let other: SecretKey = base64::encode([8u8; 32]).parse().unwrap();
+        let sealed = key.encrypt("feed:blog", "hunter2").unwrap();
+        assert!(!sealed.contains("hunter2"));
+        assert_ne!(sealed, key.encrypt("feed:blog", "hunter2").unwrap());
+        assert_eq!(key.decrypt("feed:blog", &sealed).unwrap(), "hunter2");
+
+        assert!(key.decrypt("feed:other", &sealed).is_err());
+        let wrong_key = other.decrypt("feed:blog", &sealed).unwrap_err();
+        assert!(wrong_key.to_string().contains("encrypted with key"));
+        assert!(base64::encode([7u8; 16]).parse::<SecretKey>().is_err());
+    }
+}
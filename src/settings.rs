@@ -0,0 +1,221 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::db::{self, EntryFilter, Ordering};
+
+/// What `/` shows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum LandingPage {
+    #[default]
+    Unread,
+    All,
+    Category(String),
+}
+
+impl LandingPage {
+    pub fn title(&self) -> String {
+        match self {
+            LandingPage::Unread => "Unread".to_string(),
+            LandingPage::All => "All".to_string(),
+            LandingPage::Category(c) => c.clone(),
+        }
+    }
+
+    /// The filter and ordering used to list this page's entries.
+    pub fn query(&self) -> (EntryFilter, Ordering) {
+        match self {
+            LandingPage::Unread => (EntryFilter::Unread, Ordering::Ascending),
+            LandingPage::All => (EntryFilter::All, Ordering::Descending),
+            LandingPage::Category(c) => (EntryFilter::Category(c.clone()), Ordering::Descending),
+        }
+    }
+}
+
+impl FromStr for LandingPage {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<LandingPage> {
+        if let Some(category) = s.strip_prefix("category:") {
+            return Ok(LandingPage::Category(category.to_string()));
+        }
+        Ok(match s {
+            "all" => LandingPage::All,
+            _ => LandingPage::Unread,
+        })
+    }
+}
+
+impl fmt::Display for LandingPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LandingPage::Unread => write!(f, "unread"),
+            LandingPage::All => write!(f, "all"),
+            LandingPage::Category(c) => write!(f, "category:{}", c),
+        }
+    }
+}
+
+/// User preferences stored in the settings table.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub landing_page: LandingPage,
+    /// Mark entries read as they scroll past on the landing page
+    pub auto_mark_read: bool,
+    /// Days after which unread entries are left off an unread landing page, so an
+    /// old backlog doesn't bury new entries. They're still in the history.
+    pub max_unread_age: Option<u32>,
+}
+
+impl Settings {
+    pub async fn load(db: &db::DB) -> Result<Settings> {
+        let mut settings = Settings::default();
+        if let Some(landing) = db.get_setting("landing_page").await? {
+            settings.landing_page = landing.parse()?;
+        }
+        if let Some(auto_mark_read) = db.get_setting("auto_mark_read").await? {
+            settings.auto_mark_read = auto_mark_read.parse()?;
+        }
+        if let Some(days) = db.get_setting("max_unread_age").await? {
+            settings.max_unread_age = parse_days(&days)?;
+        }
+        Ok(settings)
+    }
+
+    /// The filter and ordering the landing page lists entries with.
+    pub fn landing_query(&self) -> (EntryFilter, Ordering) {
+        match (self.landing_page.query(), self.max_unread_age) {
+            ((EntryFilter::Unread, ordering), Some(days)) => {
+                (EntryFilter::UnreadWithin(days), ordering)
+            }
+            (query, _) => query,
+        }
+    }
+
+    pub async fn save(&self, db: &db::DB) -> Result<()> {
+        db.set_setting("landing_page", &self.landing_page.to_string())
+            .await?;
+        db.set_setting("auto_mark_read", &self.auto_mark_read.to_string())
+            .await?;
+        let max_unread_age = self.max_unread_age.map_or(String::new(), |d| d.to_string());
+        db.set_setting("max_unread_age", &max_unread_age).await
+    }
+}
+
+/// Preferences each user sets for themselves, in the user settings table.
+#[derive(Debug, Clone, Default)]
+pub struct Preferences {
+    /// Open links to other sites in a new tab
+    pub new_tab_links: bool,
+}
+
+impl Preferences {
+    /// The current user's preferences.
+    pub async fn load(db: &db::DB) -> Result<Preferences> {
+        let mut preferences = Preferences::default();
+        if let Some(new_tab_links) = db.get_user_setting("new_tab_links").await? {
+            preferences.new_tab_links = new_tab_links.parse()?;
+        }
+        Ok(preferences)
+    }
+
+    pub async fn save(&self, db: &db::DB) -> Result<()> {
+        db.set_user_setting("new_tab_links", &self.new_tab_links.to_string())
+            .await
+    }
+}
+
+/// A number of days, where blank or 0 means no limit.
+pub fn parse_days(days: &str) -> Result<Option<u32>> {
+    match days.trim() {
+        "" => Ok(None),
+        days => Ok(Some(days.parse()?).filter(|&d| d > 0)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::user;
+
+    #[test]
+    fn landing_page_roundtrip() {
+        for page in [
+            LandingPage::Unread,
+            LandingPage::All,
+            LandingPage::Category("tech".to_string()),
+        ] {
+            let parsed: LandingPage = page.to_string().parse().unwrap();
+            assert_eq!(parsed, page);
+        }
+    }
+
+    #[tokio::test]
+    async fn save_load_settings() -> Result<(), anyhow::Error> {
+        let db = db::connect(db::ConnectionBacking::Memory).await?;
+        db.init().await?;
+        assert_eq!(Settings::load(&db).await?.landing_page, LandingPage::Unread);
+
+        let settings = Settings {
+            landing_page: LandingPage::Category("news".to_string()),
+            auto_mark_read: true,
+            max_unread_age: Some(30),
+        };
+        settings.save(&db).await?;
+        let loaded = Settings::load(&db).await?;
+        assert_eq!(
+            loaded.landing_page,
+            LandingPage::Category("news".to_string())
+        );
+        assert!(loaded.auto_mark_read);
+        assert_eq!(loaded.max_unread_age, Some(30));
+        assert!(matches!(loaded.landing_query().0, EntryFilter::Category(_)));
+
+        let settings = Settings {
+            max_unread_age: parse_days(" 0 ")?,
+            ..Settings::default()
+        };
+        settings.save(&db).await?;
+        assert_eq!(Settings::load(&db).await?.max_unread_age, None);
+        let week = Settings {
+            max_unread_age: parse_days("7")?,
+            ..Settings::default()
+        };
+        assert!(matches!(
+            week.landing_query(),
+            (EntryFilter::UnreadWithin(7), Ordering::Ascending)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn preferences_per_user() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let other = db.add_user("other", "").await?;
+            Preferences {
+                new_tab_links: true,
+            }
+            .save(&db)
+            .await?;
+            assert!(Preferences::load(&db).await?.new_tab_links);
+            let theirs = user::scope(other, Preferences::load(&db)).await?;
+            assert!(!theirs.new_tab_links);
+
+            user::scope(other, async {
+                Preferences {
+                    new_tab_links: true,
+                }
+                .save(&db)
+                .await?;
+                Preferences::default().save(&db).await
+            })
+            .await?;
+            assert!(Preferences::load(&db).await?.new_tab_links);
+            assert!(db.remove_user(other).await?);
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,63 @@
+//! Stopping the server without cutting work off partway: once a signal comes
+//! in, no new requests or refresh passes are started, and what's underway gets
+//! `GRACE` to finish before whatever's left is dropped.
+use std::future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// How long requests and the current refresh pass get to finish after a signal.
+pub const GRACE: Duration = Duration::from_secs(30);
+
+/// Whether the server's been asked to stop, for work that should wrap up rather
+/// than be dropped mid-way.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Default for Shutdown {
+    /// One that's never requested.
+    fn default() -> Shutdown {
+        Shutdown(watch::channel(false).1)
+    }
+}
+
+impl Shutdown {
+    /// A shutdown along with what requests it.
+    pub fn new() -> (watch::Sender<bool>, Shutdown) {
+        let (request, requested) = watch::channel(false);
+        (request, Shutdown(requested))
+    }
+
+    pub fn requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until shutdown is requested, forever when nothing can request it.
+    pub async fn wait(mut self) {
+        if self.0.wait_for(|requested| *requested).await.is_err() {
+            future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_for_request() {
+        let (request, shutdown) = Shutdown::new();
+        assert!(!shutdown.requested());
+        let waiting = tokio::spawn(shutdown.clone().wait());
+        request.send(true).unwrap();
+        waiting.await.unwrap();
+        assert!(shutdown.requested());
+
+        let never = Shutdown::default();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), never.wait())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Suggests feeds for the sites that keep turning up in what's been starred and
+//! read, through the same autodiscovery as adding a feed, leaving out sites
+//! already subscribed to.
+use std::collections::HashSet;
+
+use askama_axum::Template;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::Router;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::filters;
+use crate::parse::DiscoveredFeed;
+use crate::view::discover_site;
+use crate::{AppError, AppState, Feed};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/suggestions.html", get(suggestions))
+        .route("/suggestions/feeds", get(suggested_feeds))
+}
+
+/// How many of the most recently read entries are looked at.
+const RECENT_LINKS: u32 = 1000;
+
+/// A site needs at least this score to be suggested.
+const MIN_SCORE: u32 = 3;
+
+/// How much more a starred entry counts than one that was only read.
+const STARRED_WEIGHT: u32 = 3;
+
+/// At most this many sites are suggested at a time.
+const MAX_SUGGESTIONS: usize = 20;
+
+/// A site that isn't subscribed to but keeps coming up.
+#[derive(Debug, PartialEq)]
+struct SuggestedSite {
+    /// The host without any leading `www.`
+    domain: String,
+    /// Where to look for its feeds
+    origin: String,
+    starred: u32,
+    read: u32,
+}
+
+impl SuggestedSite {
+    fn score(&self) -> u32 {
+        self.starred * STARRED_WEIGHT + self.read
+    }
+}
+
+#[derive(Template)]
+#[template(path = "suggestions.html")]
+struct SuggestionsTemplate {
+    sites: Vec<SuggestedSite>,
+}
+
+#[derive(Template)]
+#[template(path = "suggested_feeds.html")]
+struct SuggestedFeedsTemplate {
+    site_url: String,
+    feeds: Vec<DiscoveredFeed>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SiteQuery {
+    site_url: String,
+}
+
+fn domain(url: &Url) -> Option<String> {
+    url.host_str()
+        .map(|h| h.trim_start_matches("www.").to_lowercase())
+}
+
+/// Tallies the sites `links` point at, best first, leaving out the ones `feeds`
+/// already cover.
+fn suggest_sites(links: &[(String, bool)], feeds: &[Feed]) -> Vec<SuggestedSite> {
+    let subscribed: HashSet<String> = feeds
+        .iter()
+        .flat_map(|f| [&f.site_url, &f.feed_url])
+        .filter_map(|u| Url::parse(u).ok())
+        .filter_map(|u| domain(&u))
+        .collect();
+    let mut sites: Vec<SuggestedSite> = vec![];
+    for (link, starred) in links {
+        let Ok(url) = Url::parse(link) else {
+            continue;
+        };
+        let Some(domain) = domain(&url).filter(|d| !subscribed.contains(d)) else {
+            continue;
+        };
+        let site = match sites.iter_mut().position(|s| s.domain == domain) {
+            Some(i) => &mut sites[i],
+            None => {
+                sites.push(SuggestedSite {
+                    domain,
+                    origin: format!("{}/", url.origin().ascii_serialization()),
+                    starred: 0,
+                    read: 0,
+                });
+                sites.last_mut().unwrap()
+            }
+        };
+        match starred {
+            true => site.starred += 1,
+            false => site.read += 1,
+        }
+    }
+    sites.retain(|s| s.score() >= MIN_SCORE);
+    sites.sort_by(|a, b| b.score().cmp(&a.score()).then(a.domain.cmp(&b.domain)));
+    sites.truncate(MAX_SUGGESTIONS);
+    sites
+}
+
+/// The sites worth a look, with their feeds found as the page loads.
+async fn suggestions(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<SuggestionsTemplate, AppError> {
+    let links = db.get_engaged_links(RECENT_LINKS).await?;
+    let feeds = db.get_feeds().await?;
+    Ok(SuggestionsTemplate {
+        sites: suggest_sites(&links, &feeds),
+    })
+}
+
+async fn suggested_feeds(
+    State(AppState { previews, .. }): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> SuggestedFeedsTemplate {
+    match discover_site(&previews, &query.site_url).await {
+        Ok(feeds) => SuggestedFeedsTemplate {
+            site_url: query.site_url,
+            feeds,
+            error: None,
+        },
+        Err(e) => SuggestedFeedsTemplate {
+            error: Some(format!("couldn't fetch {}: {}", query.site_url, e)),
+            site_url: query.site_url,
+            feeds: vec![],
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suggest_frequent_sites() {
+        let link = |url: &str, starred: bool| (url.to_string(), starred);
+        let links = vec![
+            link("https://www.often.com/a", false),
+            link("https://often.com/b", false),
+            link("https://often.com/c", false),
+            link("https://loved.com/a", true),
+            link("https://once.com/a", false),
+            link("https://subscribed.com/a", true),
+            link("not a link", true),
+        ];
+        let feeds = vec![Feed::new(
+            "Subscribed".to_string(),
+            "https://www.subscribed.com".to_string(),
+            "https://subscribed.com/feed.xml".to_string(),
+            String::new(),
+        )];
+        let sites = suggest_sites(&links, &feeds);
+        let domains: Vec<&str> = sites.iter().map(|s| s.domain.as_str()).collect();
+        assert_eq!(domains, ["loved.com", "often.com"]);
+        assert_eq!(sites[1].origin, "https://www.often.com/");
+        assert_eq!(sites[1].read, 3);
+    }
+}
@@ -0,0 +1,215 @@
+//! The people sharing a server. Feeds are shared, but each user has their own
+//! read and starred state, looked up for whoever the current request is for.
+use std::future::Future;
+use std::num::NonZeroU32;
+
+use anyhow::{anyhow, Result};
+use askama_axum::Template;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Form, Router};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{filters, AppError, AppState, User};
+
+/// The user from before there were others: who `FEED_USERNAME` logs in as, API
+/// tokens act for and background work is explicitly scoped to.
+pub const OWNER: i64 = 1;
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+tokio::task_local! {
+    static USER: i64;
+    static VISITING: bool;
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/users.html", get(users_page))
+        .route("/users", post(create_user))
+        .route("/users/:user_id", delete(remove_user))
+}
+
+/// Runs `f` on behalf of `user`.
+pub async fn scope<F: Future>(user: i64, f: F) -> F::Output {
+    USER.scope(user, f).await
+}
+
+/// Runs `f` for someone who hasn't logged in, who sees the owner's reader but
+/// mustn't change it, even by reading.
+pub async fn visit<F: Future>(f: F) -> F::Output {
+    USER.scope(OWNER, VISITING.scope(true, f)).await
+}
+
+/// Whether the current request is from someone who hasn't logged in, so views
+/// that mark things as they're shown should leave them be.
+pub fn visiting() -> bool {
+    VISITING.try_with(|visiting| *visiting).unwrap_or(false)
+}
+
+/// The user being acted for, an error outside of `scope`, so work that isn't
+/// for anyone in particular has to say whose it is.
+pub fn current() -> Result<i64> {
+    USER.try_with(|user| *user)
+        .map_err(|_| anyhow!("not acting for any user"))
+}
+
+/// Salted PBKDF2, stored as `pbkdf2-sha256$iterations$salt$hash`.
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| anyhow!("couldn't generate a salt"))?;
+    let mut hash = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    Ok(format!(
+        "pbkdf2-sha256${}${}${}",
+        PBKDF2_ITERATIONS,
+        base64::encode(salt),
+        base64::encode(hash)
+    ))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        base64::decode(salt),
+        base64::decode(hash),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    users: Vec<User>,
+    /// Whether anyone can log in at all
+    login: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UserForm {
+    username: String,
+    password: String,
+}
+
+async fn users_page(
+    State(AppState { db, login, .. }): State<AppState>,
+) -> Result<UsersTemplate, AppError> {
+    Ok(UsersTemplate {
+        users: db.get_users().await?,
+        login: login.is_some(),
+        error: None,
+    })
+}
+
+async fn create_user(
+    State(AppState { db, login, .. }): State<AppState>,
+    Form(form): Form<UserForm>,
+) -> Result<Response, AppError> {
+    let username = form.username.trim();
+    let taken = db.get_user_login(username).await?.is_some();
+    if username.is_empty() || form.password.is_empty() || taken {
+        let page = UsersTemplate {
+            users: db.get_users().await?,
+            login: login.is_some(),
+            error: Some(if taken {
+                format!("There's already a user called {}", username)
+            } else {
+                "A username and password are needed".to_string()
+            }),
+        };
+        return Ok((StatusCode::BAD_REQUEST, page).into_response());
+    }
+    let id = db
+        .add_user(username, &hash_password(&form.password)?)
+        .await?;
+    info!("added user {} ({})", username, id);
+    Ok(UsersTemplate {
+        users: db.get_users().await?,
+        login: login.is_some(),
+        error: None,
+    }
+    .into_response())
+}
+
+async fn remove_user(
+    Path(user_id): Path<i64>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    if user_id == OWNER {
+        return Ok(StatusCode::FORBIDDEN);
+    }
+    if db.remove_user(user_id).await? {
+        Ok(StatusCode::OK)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passwords() {
+        let stored = hash_password("hunter2").unwrap();
+        assert!(stored.starts_with("pbkdf2-sha256$100000$"));
+        assert!(verify_password("hunter2", &stored));
+        assert!(!verify_password("hunter", &stored));
+        assert!(!verify_password("hunter2", "hunter2"));
+        // salted, so the same password hashes differently
+        assert_ne!(stored, hash_password("hunter2").unwrap());
+    }
+
+    #[tokio::test]
+    async fn no_user_outside_a_scope() {
+        assert!(current().is_err());
+        assert_eq!(scope(7, async { current().unwrap() }).await, 7);
+        // spawned tasks don't inherit the scope, they have to be given it
+        let spawned = scope(7, async {
+            tokio::spawn(async { current().is_err() }).await
+        });
+        assert!(spawned.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn visitors_look_as_the_owner() {
+        assert!(!visiting());
+        assert!(!scope(7, async { visiting() }).await);
+        let (user, looking) = visit(async { (current().unwrap(), visiting()) }).await;
+        assert_eq!(user, OWNER);
+        assert!(looking);
+    }
+}
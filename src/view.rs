@@ -1,20 +1,34 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use askama_axum::{IntoResponse, Template};
 use axum::{
-    extract::{Path, State},
-    http::HeaderMap,
-    response::Redirect,
+    extract::{Multipart, Path, Query, Request, State},
+    http::{
+        header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    middleware::Next,
+    response::{Redirect, Response},
     routing::{delete, get, post},
-    Form, Router,
+    Extension, Form, Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
 
 use crate::{
-    db::{self, EntryFilter, Ordering},
-    AppError, AppState,
+    base_path,
+    db::{self, EntryFilter, FeedSort, Ordering, Page},
+    extract, filters, jsonfeed,
+    parse::{self, DiscoveredFeed},
+    preview, proxy, public_url, sanitize,
+    settings::{self, Preferences, Settings},
+    user, AppError, AppState, ErrorDetails,
 };
 
-use super::{Entry, Feed};
+use super::{
+    ArchiveMonth, CombinedView, Entry, Feed, FeedStats, FetchStatus, SavedFilter, UtcTime,
+};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -22,57 +36,371 @@ pub fn routes() -> Router<AppState> {
         .route("/history.html", get(history))
         .route("/feeds.html", get(get_feeds))
         .route("/starred.html", get(get_starred))
+        .route("/starred/export", get(export_starred))
+        .route("/rediscover.html", get(rediscover))
         .route("/add_feed.html", get(add_feed))
+        .route("/settings.html", get(get_settings))
+        .route("/settings", post(post_settings))
+        .route("/preferences.html", get(get_preferences))
+        .route("/preferences", post(post_preferences))
+        .route("/archive.html", get(archive))
+        .route("/archive/:year/:month", get(archive_month))
         .route("/feeds", post(post_feed))
-        .route("/feeds/:feed_url", delete(remove_feed))
+        .route("/discover", get(discover))
+        .route("/opml", post(import_opml))
+        .route("/feeds/:feed_url", delete(remove_feed).put(update_feed))
+        .route("/feeds/:feed_url/icon", get(feed_icon).post(set_feed_icon))
+        .route("/feeds/:feed_url/compact", post(set_feed_compact))
+        .route("/feeds/:feed_url/enabled", post(set_feed_enabled))
+        .route("/feeds/:feed_url/extract", post(set_feed_extract_mode))
+        .route("/read", post(mark_entries_read))
+        .route("/read_all", post(mark_all_read))
         .route("/read/:entry_id", post(mark_entry_read))
         .route("/starred/:entry_id", post(mark_entry_starred))
+        .route("/hidden.html", get(get_hidden))
+        .route("/hidden/:entry_id", post(hide_entry))
+        .route("/entry/:entry_id", get(entry))
+        .route("/entries/:entry_id/diff", get(entry_diff))
+        .route("/search", get(search))
+        .route("/feed/:feed_id/entries", get(feed_entries))
+        .route("/category/:category", get(category_entries))
+        .route("/views.html", get(combined_views))
+        .route("/views", post(add_combined_view))
+        .route("/views/:view_id", delete(remove_combined_view))
+        .route("/view/:view_id", get(combined_view_entries))
+        .route("/view/:view_id/feed.json", get(combined_view_feed))
+        .route("/filters", post(add_saved_filter))
+        .route("/filters/nav", get(saved_filter_nav))
+        .route("/filters/:filter_id", delete(remove_saved_filter))
+        .route("/filter/:filter_id", get(saved_filter_entries))
+        .route("/fragments/entries", get(entries_fragment))
+        .route("/fragments/entries/:entry_id", get(entry_fragment))
+        .route("/fragments/feeds/:feed_id", get(feed_fragment))
+        .route("/fragments/counts", get(counts_fragment))
 }
 
-pub fn display_some<T>(value: &Option<T>) -> String
-where
-    T: std::fmt::Display,
-{
-    match value {
-        Some(value) => value.to_string(),
-        None => String::new(),
+/// Renders failed requests as a page when a browser asked for HTML, leaving the
+/// JSON error body for everyone else.
+pub async fn error_pages(request: Request, next: Next) -> Response {
+    let wants_html = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("text/html"));
+    let response = next.run(request).await;
+    if !wants_html {
+        return response;
+    }
+    match response.extensions().get::<ErrorDetails>() {
+        Some(details) => {
+            let page = ErrorTemplate {
+                details: details.clone(),
+            };
+            (response.status(), page).into_response()
+        }
+        None => response,
     }
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
+    title: String,
+    hx_headers: String,
+    auto_mark_read: bool,
     entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    details: ErrorDetails,
+}
+
+#[derive(Template)]
+#[template(path = "entry.html")]
+struct EntryTemplate {
+    entry: Entry,
+    /// The stored content, sanitized for rendering as is
+    content: String,
+    new_tab: bool,
+}
+
+#[derive(Template)]
+#[template(path = "entry_diff.html")]
+struct EntryDiffTemplate {
+    entry: Entry,
+    changed_at: Option<UtcTime>,
+    lines: Vec<DiffLine>,
+    new_tab: bool,
+}
+
+/// A line of a content diff, `tag` being `+`, `-` or a space.
+struct DiffLine {
+    tag: char,
+    text: String,
+}
+
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: match change.tag() {
+                ChangeTag::Delete => '-',
+                ChangeTag::Insert => '+',
+                ChangeTag::Equal => ' ',
+            },
+            text: change.value().trim_end_matches('\n').to_string(),
+        })
+        .collect()
+}
+
+#[derive(Template)]
+#[template(path = "discovered_feeds.html")]
+struct DiscoveredFeedsTemplate {
+    feeds: Vec<DiscoveredFeed>,
+    error: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "history.html")]
 struct HistoryTemplate {
     entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+    query: String,
+    hx_headers: String,
+    entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "entry_list.html")]
 struct EntryListTemplate {
     entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+/// One entry of a list, swapped in by itself after it changes.
+#[derive(Template)]
+#[template(path = "entry_row.html")]
+struct EntryRowTemplate {
+    entry: Entry,
+    title_length: usize,
+    new_tab: bool,
 }
 
 #[derive(Template)]
 #[template(path = "feeds.html")]
 struct FeedsTemplate {
     feeds: Vec<Feed>,
+    sort: String,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+    unread: UnreadCounts,
+}
+
+impl FeedsTemplate {
+    fn sorted_by(&self, sort: &str) -> bool {
+        self.sort == sort
+    }
 }
 
+/// One feed of the list, swapped in by itself after it changes.
 #[derive(Template)]
-#[template(path = "feed_list.html")]
-struct FeedListTemplate {
-    feeds: Vec<Feed>,
+#[template(path = "feed_row.html")]
+struct FeedRowTemplate {
+    feed: Feed,
+    feed_unread: u32,
+    /// Why the last change to the feed wasn't made, empty when it was
+    feed_error: String,
+}
+
+#[derive(Template)]
+#[template(path = "unread_counts.html")]
+struct UnreadCountsTemplate {
+    unread: UnreadCounts,
+}
+
+/// Unread entries by feed and by category, for badges on the feeds page.
+#[derive(Default)]
+struct UnreadCounts {
+    /// By feed id
+    feeds: HashMap<String, u32>,
+    /// Every category with a feed in it, by name
+    categories: Vec<CategoryUnread>,
+    total: u32,
+}
+
+struct CategoryUnread {
+    name: String,
+    unread: u32,
+}
+
+impl UnreadCounts {
+    fn new(stats: Vec<FeedStats>) -> Self {
+        let mut categories: Vec<CategoryUnread> = vec![];
+        for s in stats.iter().filter(|s| !s.category.is_empty()) {
+            match categories.iter_mut().find(|c| c.name == s.category) {
+                Some(category) => category.unread += s.unread,
+                None => categories.push(CategoryUnread {
+                    name: s.category.clone(),
+                    unread: s.unread,
+                }),
+            }
+        }
+        categories.sort_by_key(|c| c.name.to_lowercase());
+        UnreadCounts {
+            total: stats.iter().map(|s| s.unread).sum(),
+            feeds: stats.into_iter().map(|s| (s.feed_id, s.unread)).collect(),
+            categories,
+        }
+    }
+
+    fn feed(&self, feed_id: &str) -> u32 {
+        self.feeds.get(feed_id).copied().unwrap_or_default()
+    }
 }
 
 #[derive(Template)]
 #[template(path = "starred.html")]
 struct StarredTemplate {
     entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "archive.html")]
+struct ArchiveTemplate {
+    months: Vec<ArchiveMonth>,
+}
+
+/// The entries of one feed or category.
+#[derive(Template)]
+#[template(path = "filtered.html")]
+struct FilteredTemplate {
+    title: String,
+    hx_headers: String,
+    /// Form values scoping mark all read to what's shown, when it can be
+    read_all: Option<String>,
+    entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "views.html")]
+struct CombinedViewsTemplate {
+    views: Vec<CombinedView>,
+    categories: Vec<String>,
+    filters: Vec<SavedFilter>,
+    feeds: Vec<Feed>,
+}
+
+impl CombinedViewsTemplate {
+    /// What a saved filter picks out, e.g. "unread, in tech, last 24 hours".
+    fn describe(&self, filter: &SavedFilter) -> String {
+        let mut parts = vec![];
+        if filter.unread {
+            parts.push("unread".to_string());
+        }
+        if filter.starred {
+            parts.push("starred".to_string());
+        }
+        if let Some(category) = &filter.category {
+            parts.push(format!("in {}", category));
+        }
+        if let Some(feed_id) = &filter.feed_id {
+            let feed = self.feeds.iter().find(|f| &f.id == feed_id);
+            parts.push(format!(
+                "from {}",
+                feed.map_or("a removed feed", |f| f.name.as_str())
+            ));
+        }
+        if let Some(hours) = filter.since_hours {
+            parts.push(format!("last {} hours", hours));
+        }
+        if parts.is_empty() {
+            return "everything".to_string();
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Template)]
+#[template(path = "filter_nav.html")]
+struct SavedFilterNavTemplate {
+    filters: Vec<SavedFilter>,
+}
+
+#[derive(Template)]
+#[template(path = "archive_month.html")]
+struct ArchiveMonthTemplate {
+    month: ArchiveMonth,
+    entries: Vec<Entry>,
+    title_length: usize,
+    new_tab: bool,
+    /// Query string of the next page, when there may be one
+    more: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct SettingsTemplate {
+    landing_page: String,
+    auto_mark_read: bool,
+    /// Blank when there's no limit
+    max_unread_age: String,
+    categories: Vec<String>,
+    /// Whether there's a login to log out of
+    login: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SettingsForm {
+    landing_page: String,
+    /// checkboxes are only submitted when checked
+    auto_mark_read: Option<String>,
+    #[serde(default)]
+    max_unread_age: String,
+}
+
+#[derive(Template)]
+#[template(path = "preferences.html")]
+struct PreferencesTemplate {
+    new_tab_links: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PreferencesForm {
+    new_tab_links: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MarkReadRequest {
+    ids: Vec<String>,
 }
 
 #[derive(Template)]
@@ -98,26 +426,713 @@ impl From<AddFeedForm> for Feed {
     }
 }
 
-async fn index(State(AppState { db }): State<AppState>) -> Result<IndexTemplate, AppError> {
-    let entries = db.get_unread_entries().await?;
-    Ok(IndexTemplate { entries })
+/// The `hx-headers` value that tells entry actions how to re-render the list they came from.
+fn hx_headers(filter: &EntryFilter, ordering: &Ordering) -> String {
+    serde_json::json!({
+        "entry_filter": filter.to_string(),
+        "ordering": ordering.to_string(),
+    })
+    .to_string()
+}
+
+/// The path listing a category's entries, with the name escaped as a path segment.
+pub(crate) fn category_path(category: &str) -> String {
+    let mut url = reqwest::Url::parse("http://localhost/category").unwrap();
+    url.path_segments_mut().unwrap().push(category);
+    url.path().to_string()
+}
+
+/// How many entries the views show at a time.
+pub(crate) const PAGE_SIZE: u32 = 50;
+
+#[derive(Deserialize)]
+pub(crate) struct PageQuery {
+    #[serde(default)]
+    offset: u32,
+}
+
+impl PageQuery {
+    pub(crate) fn page(&self) -> Page {
+        Page::new(self.offset, PAGE_SIZE)
+    }
+}
+
+/// The query string for the page after `page`, keeping `params` (like a search) as they were.
+pub(crate) fn more_link(page: Page, shown: usize, params: &[(&str, &str)]) -> Option<String> {
+    let offset = page.next_offset(shown)?.to_string();
+    let mut params = params.to_vec();
+    params.push(("offset", &offset));
+    serde_urlencoded::to_string(params)
+        .ok()
+        .map(|q| format!("?{}", q))
+}
+
+async fn index(
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<IndexTemplate, AppError> {
+    let settings = Settings::load(&db).await?;
+    let (filter, ordering) = settings.landing_query();
+    let landing = settings.landing_page;
+    let hx_headers = hx_headers(&filter, &ordering);
+    let page = query.page();
+    let entries = db.get_entries_page(filter, ordering, page).await?;
+    read_compact_entries(&db, &entries, read_only.enabled()).await?;
+    Ok(IndexTemplate {
+        title: landing.title(),
+        hx_headers,
+        auto_mark_read: settings.auto_mark_read,
+        new_tab: Preferences::load(&db).await?.new_tab_links,
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+    })
+}
+
+async fn history(
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<HistoryTemplate, AppError> {
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    let page = query.page();
+    let entries = db
+        .get_entries_page(db::EntryFilter::All, db::Ordering::Descending, page)
+        .await?;
+    read_compact_entries(&db, &entries, read_only.enabled()).await?;
+    Ok(HistoryTemplate {
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    offset: u32,
+}
+
+async fn search(
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<SearchTemplate, AppError> {
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    let page = Page::new(query.offset, PAGE_SIZE);
+    let entries = db.search_entries(&query.q, page).await?;
+    let hx_headers = hx_headers(&EntryFilter::Search(query.q.clone()), &Ordering::Descending);
+    Ok(SearchTemplate {
+        hx_headers,
+        more: more_link(page, entries.len(), &[("q", &query.q)]),
+        query: query.q,
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+/// Old starred entries picked to be read again this week.
+async fn rediscover(
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<FilteredTemplate, AppError> {
+    let title = "Rediscover".to_string();
+    filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        title,
+        EntryFilter::Resurfaced,
+        None,
+        query,
+    )
+    .await
+}
+
+async fn feed_entries(
+    Path(feed_id): Path<String>,
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Response, AppError> {
+    let Some(feed) = db.get_feed(&feed_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let read_all = Some(serde_json::json!({ "feed": feed.name }).to_string());
+    let filtered = filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        feed.name,
+        EntryFilter::Feed(feed_id),
+        read_all,
+        query,
+    )
+    .await?;
+    Ok(filtered.into_response())
+}
+
+async fn category_entries(
+    Path(category): Path<String>,
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<FilteredTemplate, AppError> {
+    let read_all = Some(serde_json::json!({ "category": category }).to_string());
+    let filter = EntryFilter::Category(category.clone());
+    filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        category,
+        filter,
+        read_all,
+        query,
+    )
+    .await
+}
+
+/// A page of entries matching `filter`, newest first.
+async fn filtered_entries(
+    db: &db::DB,
+    read_only: bool,
+    title_length: usize,
+    title: String,
+    filter: EntryFilter,
+    read_all: Option<String>,
+    query: PageQuery,
+) -> Result<FilteredTemplate, AppError> {
+    let new_tab = Preferences::load(db).await?.new_tab_links;
+    let hx_headers = hx_headers(&filter, &Ordering::Descending);
+    let page = query.page();
+    let entries = db
+        .get_entries_page(filter, Ordering::Descending, page)
+        .await?;
+    read_compact_entries(db, &entries, read_only).await?;
+    Ok(FilteredTemplate {
+        title,
+        hx_headers,
+        read_all,
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+async fn combined_views(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<CombinedViewsTemplate, AppError> {
+    Ok(CombinedViewsTemplate {
+        views: db.get_combined_views().await?,
+        categories: db.get_categories().await?,
+        filters: db.get_saved_filters().await?,
+        feeds: db.get_feeds().await?,
+    })
+}
+
+#[derive(Deserialize)]
+struct CombinedViewForm {
+    name: String,
+    /// Comma separated, every category when empty
+    #[serde(default)]
+    categories: String,
+    /// Comma separated
+    #[serde(default)]
+    excluded: String,
+}
+
+/// Splits a comma separated list of categories, dropping empty ones.
+fn split_categories(categories: &str) -> Vec<String> {
+    categories
+        .split(',')
+        .map(db::normalize_category)
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+async fn add_combined_view(
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<CombinedViewForm>,
+) -> Result<impl IntoResponse, AppError> {
+    let view = CombinedView {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        name: body.name.trim().to_string(),
+        categories: split_categories(&body.categories),
+        excluded: split_categories(&body.excluded),
+    };
+    db.add_combined_view(&view).await?;
+    Ok(Redirect::to(&base_path::to("/views.html")))
+}
+
+async fn remove_combined_view(
+    Path(view_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    Ok(match db.remove_combined_view(&view_id).await? {
+        true => StatusCode::OK,
+        false => StatusCode::NOT_FOUND,
+    })
+}
+
+async fn combined_view_entries(
+    Path(view_id): Path<String>,
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Response, AppError> {
+    let Some(view) = db.get_combined_view(&view_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let filter = EntryFilter::Combined(view.id);
+    let filtered = filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        view.name,
+        filter,
+        None,
+        query,
+    )
+    .await?;
+    Ok(filtered.into_response())
+}
+
+/// The newest entries of a combined view as a JSON Feed, for reading it elsewhere.
+async fn combined_view_feed(
+    Path(view_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Extension(client): Extension<proxy::Client>,
+) -> Result<Response, AppError> {
+    let Some(view) = db.get_combined_view(&view_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let entries = db
+        .get_entries_page(
+            EntryFilter::Combined(view.id),
+            Ordering::Descending,
+            Page::new(0, PAGE_SIZE),
+        )
+        .await?;
+    let mut feed = jsonfeed::JsonFeed::new(&view.name, &entries);
+    feed.home_page_url = public_url::absolute(&format!("/view/{}", view_id), Some(&client));
+    feed.feed_url = public_url::absolute(&format!("/view/{}/feed.json", view_id), Some(&client));
+    Ok(([(CONTENT_TYPE, "application/feed+json")], Json(feed)).into_response())
+}
+
+#[derive(Deserialize)]
+struct SavedFilterForm {
+    name: String,
+    /// Checkboxes, only sent when checked
+    unread: Option<String>,
+    starred: Option<String>,
+    /// Empty for any
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    feed_id: String,
+    #[serde(default)]
+    since_hours: String,
+}
+
+async fn add_saved_filter(
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<SavedFilterForm>,
+) -> Result<impl IntoResponse, AppError> {
+    let category = db::normalize_category(&body.category);
+    let since_hours = body.since_hours.trim();
+    let filter = SavedFilter {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        name: body.name.trim().to_string(),
+        unread: body.unread.is_some(),
+        starred: body.starred.is_some(),
+        category: Some(category).filter(|c| !c.is_empty()),
+        feed_id: Some(body.feed_id).filter(|f| !f.is_empty()),
+        since_hours: match since_hours {
+            "" => None,
+            hours => Some(hours.parse()?),
+        },
+    };
+    db.add_saved_filter(&filter).await?;
+    Ok(Redirect::to(&base_path::to("/views.html")))
+}
+
+async fn remove_saved_filter(
+    Path(filter_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    Ok(match db.remove_saved_filter(&filter_id).await? {
+        true => StatusCode::OK,
+        false => StatusCode::NOT_FOUND,
+    })
+}
+
+/// The saved filters as nav links, loaded into every page's header.
+async fn saved_filter_nav(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<SavedFilterNavTemplate, AppError> {
+    Ok(SavedFilterNavTemplate {
+        filters: db.get_saved_filters().await?,
+    })
+}
+
+/// Entries hidden from every other list, to bring back ones hidden by mistake.
+async fn get_hidden(
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<FilteredTemplate, AppError> {
+    filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        "Hidden".to_string(),
+        EntryFilter::Hidden,
+        None,
+        query,
+    )
+    .await
+}
+
+async fn saved_filter_entries(
+    Path(filter_id): Path<String>,
+    State(AppState {
+        db,
+        read_only,
+        title_length,
+        ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Response, AppError> {
+    let Some(filter) = db.get_saved_filter(&filter_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let filtered = filtered_entries(
+        &db,
+        read_only.enabled(),
+        title_length,
+        filter.name,
+        EntryFilter::Saved(filter.id),
+        None,
+        query,
+    )
+    .await?;
+    Ok(filtered.into_response())
+}
+
+/// Marks unread entries of compact feeds read, since showing them is all there is to reading them.
+/// Nothing is marked when `read_only` or for visitors who haven't logged in.
+async fn read_compact_entries(
+    db: &db::DB,
+    entries: &[Entry],
+    read_only: bool,
+) -> anyhow::Result<()> {
+    if read_only || user::visiting() {
+        return Ok(());
+    }
+    let ids: Vec<String> = entries
+        .iter()
+        .filter(|e| e.compact && !e.read)
+        .map(|e| e.id.clone())
+        .collect();
+    if !ids.is_empty() {
+        db.mark_entries_read(ids).await?;
+    }
+    Ok(())
+}
+
+/// Loads a page of feeds along with their recent fetch history for display.
+async fn feeds_with_statuses(db: &db::DB, sort: FeedSort, page: Page) -> anyhow::Result<Vec<Feed>> {
+    let mut feeds = db.get_feeds_page(sort, page).await?;
+    let mut statuses = db.get_feed_statuses().await?;
+    for f in feeds.iter_mut() {
+        set_statuses(f, statuses.remove(&f.id).unwrap_or_default());
+    }
+    Ok(feeds)
+}
+
+fn set_statuses(f: &mut Feed, statuses: Vec<FetchStatus>) {
+    f.statuses = statuses;
+    if let Some(last) = f.statuses.last() {
+        f.last_fetched = Some(last.created_at.clone());
+        f.fetch_error.clone_from(&last.fetch_error);
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedsQuery {
+    sort: Option<String>,
+    #[serde(default)]
+    offset: u32,
+}
+
+async fn get_feeds(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<FeedsQuery>,
+) -> Result<FeedsTemplate, AppError> {
+    let sort: FeedSort = match &query.sort {
+        Some(sort) => sort.parse()?,
+        None => FeedSort::default(),
+    };
+    let page = Page::new(query.offset, PAGE_SIZE);
+    let feeds = feeds_with_statuses(&db, sort, page).await?;
+    let sort = sort.to_string();
+    Ok(FeedsTemplate {
+        more: more_link(page, feeds.len(), &[("sort", &sort)]),
+        feeds,
+        sort,
+        unread: UnreadCounts::new(db.get_feed_stats().await?),
+    })
 }
 
-async fn history(State(AppState { db }): State<AppState>) -> Result<HistoryTemplate, AppError> {
+/// A feed's row again, after it changed or with why it couldn't be changed.
+async fn feed_row(db: &db::DB, feed_id: &str, error: Option<String>) -> Result<Response, AppError> {
+    let Some(mut feed) = db.get_feed(feed_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    set_statuses(&mut feed, db.get_feed_history(feed_id).await?);
+    let feed_unread = db.get_feed_entry_counts(&feed.name).await?.unread;
+    Ok(FeedRowTemplate {
+        feed,
+        feed_unread,
+        feed_error: error.unwrap_or_default(),
+    }
+    .into_response())
+}
+
+async fn feed_fragment(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    feed_row(&db, &feed_id, None).await
+}
+
+/// Unread entries overall and by category, reloaded when something sends a
+/// `counts` event, like a feed being removed.
+async fn counts_fragment(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<UnreadCountsTemplate, AppError> {
+    Ok(UnreadCountsTemplate {
+        unread: UnreadCounts::new(db.get_feed_stats().await?),
+    })
+}
+
+async fn get_starred(
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<StarredTemplate, AppError> {
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    let page = query.page();
     let entries = db
-        .get_entries(db::EntryFilter::All, db::Ordering::Descending)
+        .get_entries_page(EntryFilter::Starred, Ordering::Ascending, page)
         .await?;
-    Ok(HistoryTemplate { entries })
+    Ok(StarredTemplate {
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+#[derive(Deserialize)]
+struct StarredExportQuery {
+    /// `md` or `json`
+    #[serde(default = "markdown_format")]
+    format: String,
+    /// Whether entries' content is included, not just where to find them
+    #[serde(default)]
+    content: bool,
 }
 
-async fn get_feeds(State(AppState { db }): State<AppState>) -> Result<FeedsTemplate, AppError> {
-    let feeds = db.get_feeds().await?;
-    Ok(FeedsTemplate { feeds })
+fn markdown_format() -> String {
+    "md".to_string()
 }
 
-async fn get_starred(State(AppState { db }): State<AppState>) -> Result<StarredTemplate, AppError> {
+/// A starred entry as it's exported.
+#[derive(Serialize)]
+struct StarredExport {
+    title: String,
+    link: String,
+    published: Option<UtcTime>,
+    feed: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+/// Every starred entry as Markdown or JSON, for keeping them in notes.
+async fn export_starred(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<StarredExportQuery>,
+) -> Result<Response, AppError> {
     let entries = db.get_starred_entries().await?;
-    Ok(StarredTemplate { entries })
+    match query.format.as_str() {
+        "md" => Ok((
+            [
+                (CONTENT_TYPE, "text/markdown; charset=utf-8"),
+                (CONTENT_DISPOSITION, "attachment; filename=\"starred.md\""),
+            ],
+            starred_markdown(&entries, query.content),
+        )
+            .into_response()),
+        "json" => {
+            let entries: Vec<StarredExport> = entries
+                .into_iter()
+                .map(|e| StarredExport {
+                    title: e.title,
+                    link: e.content_link,
+                    published: e.published,
+                    feed: e.feed,
+                    content: e.content.filter(|_| query.content),
+                })
+                .collect();
+            Ok((
+                [(CONTENT_DISPOSITION, "attachment; filename=\"starred.json\"")],
+                Json(entries),
+            )
+                .into_response())
+        }
+        _ => Ok((StatusCode::BAD_REQUEST, "format has to be md or json").into_response()),
+    }
+}
+
+/// Starred entries as a Markdown list of links, each followed by its content
+/// as HTML, which Markdown passes through, when `with_content` is set.
+fn starred_markdown(entries: &[Entry], with_content: bool) -> String {
+    let mut markdown = "# Starred entries\n".to_string();
+    for entry in entries {
+        let title = entry.title.replace('[', "\\[").replace(']', "\\]");
+        let published = entry
+            .published
+            .as_ref()
+            .map(|p| format!(", {}", p.0.format("%Y-%m-%d")))
+            .unwrap_or_default();
+        markdown.push_str(&format!(
+            "\n## [{}](<{}>)\n\n{}{}\n",
+            title, entry.content_link, entry.feed, published
+        ));
+        if let Some(content) = entry.content.as_deref().filter(|_| with_content) {
+            markdown.push_str(&format!("\n{}\n", content.trim()));
+        }
+    }
+    markdown
+}
+
+async fn archive(State(AppState { db, .. }): State<AppState>) -> Result<ArchiveTemplate, AppError> {
+    let months = db.get_archive_months().await?;
+    Ok(ArchiveTemplate { months })
+}
+
+async fn archive_month(
+    Path((year, month)): Path<(i32, String)>,
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<ArchiveMonthTemplate, AppError> {
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    let month: u32 = month.trim_end_matches(".html").parse()?;
+    let page = query.page();
+    let entries = db
+        .get_entries_page(EntryFilter::Month(year, month), Ordering::Ascending, page)
+        .await?;
+    let count = db
+        .get_archive_months()
+        .await?
+        .into_iter()
+        .find(|m| m.year == year && m.month == month)
+        .map_or(0, |m| m.count);
+    Ok(ArchiveMonthTemplate {
+        month: ArchiveMonth { year, month, count },
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+async fn get_settings(
+    State(AppState { db, login, .. }): State<AppState>,
+) -> Result<SettingsTemplate, AppError> {
+    let settings = Settings::load(&db).await?;
+    let categories = db.get_categories().await?;
+    Ok(SettingsTemplate {
+        landing_page: settings.landing_page.to_string(),
+        auto_mark_read: settings.auto_mark_read,
+        max_unread_age: settings
+            .max_unread_age
+            .map_or(String::new(), |d| d.to_string()),
+        categories,
+        login: login.is_some(),
+    })
+}
+
+async fn post_settings(
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<SettingsForm>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut settings = Settings::load(&db).await?;
+    settings.landing_page = body.landing_page.parse()?;
+    settings.auto_mark_read = body.auto_mark_read.is_some();
+    settings.max_unread_age = settings::parse_days(&body.max_unread_age)?;
+    settings.save(&db).await?;
+    Ok(Redirect::to(&base_path::to("/settings.html")))
+}
+
+async fn get_preferences(
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<PreferencesTemplate, AppError> {
+    let preferences = Preferences::load(&db).await?;
+    Ok(PreferencesTemplate {
+        new_tab_links: preferences.new_tab_links,
+    })
+}
+
+async fn post_preferences(
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<PreferencesForm>,
+) -> Result<impl IntoResponse, AppError> {
+    let preferences = Preferences {
+        new_tab_links: body.new_tab_links.is_some(),
+    };
+    preferences.save(&db).await?;
+    Ok(Redirect::to(&base_path::to("/preferences.html")))
 }
 
 async fn add_feed() -> Result<AddFeedTemplate, AppError> {
@@ -125,26 +1140,284 @@ async fn add_feed() -> Result<AddFeedTemplate, AppError> {
 }
 
 async fn post_feed(
-    State(AppState { db }): State<AppState>,
+    State(AppState { db, jobs, .. }): State<AppState>,
     Form(body): Form<AddFeedForm>,
 ) -> Result<impl IntoResponse, AppError> {
-    db.add_feeds(vec![body.into()].into_iter()).await?;
-    Ok(Redirect::to("/feeds.html"))
+    let feed: Feed = body.into();
+    let id = feed.id.clone();
+    db.add_feeds(std::iter::once(feed)).await?;
+    jobs.first_fetch(&id);
+    Ok(Redirect::to(&base_path::to("/feeds.html")))
+}
+
+#[derive(Deserialize)]
+struct DiscoverQuery {
+    site_url: String,
+}
+
+/// The most of a site's page or feed read while looking for feeds.
+const MAX_DISCOVERY_BYTES: usize = 4 * 1024 * 1024;
+
+/// The feeds a site advertises, or the url itself if it's already a feed. Sites
+/// are fetched like link previews, so only public web pages are looked at.
+pub(crate) async fn discover_site(
+    fetcher: &preview::Fetcher,
+    site_url: &str,
+) -> anyhow::Result<Vec<DiscoveredFeed>> {
+    let url = preview::previewable(site_url)
+        .ok_or_else(|| anyhow!("only http and https sites can be looked at"))?;
+    let body = fetcher.fetch(&url, MAX_DISCOVERY_BYTES).await?;
+    let linked = parse::discover_feeds(&String::from_utf8_lossy(&body), site_url);
+    if linked.is_empty() && parse::parse_entries(&body, site_url).is_ok_and(|e| !e.is_empty()) {
+        return Ok(vec![DiscoveredFeed {
+            url: site_url.to_string(),
+            title: "This url is a feed".to_string(),
+            kind: "Feed",
+        }]);
+    }
+    Ok(linked)
 }
 
+/// Lists the feeds a site advertises, or the url itself if it's already a feed.
+async fn discover(
+    State(AppState { previews, .. }): State<AppState>,
+    Query(query): Query<DiscoverQuery>,
+) -> DiscoveredFeedsTemplate {
+    match discover_site(&previews, &query.site_url).await {
+        Ok(feeds) => DiscoveredFeedsTemplate { feeds, error: None },
+        Err(e) => DiscoveredFeedsTemplate {
+            feeds: vec![],
+            error: Some(format!("couldn't fetch {}: {}", query.site_url, e)),
+        },
+    }
+}
+
+/// Starts importing every feed from an uploaded OPML file, same as `FEED_OPML_FILE`
+/// at startup, and shows its progress.
+async fn import_opml(
+    State(AppState { db, imports, .. }): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("opml") {
+            continue;
+        }
+        let source = field.file_name().unwrap_or("uploaded opml").to_string();
+        let body = field.text().await?;
+        let document = opml::OPML::from_str(&body)?;
+        let id = imports.start(
+            db,
+            user::current()?,
+            source,
+            crate::parse_opml_outlines(&document),
+        );
+        return Ok(Redirect::to(&base_path::to(&format!("/import/{}", id))));
+    }
+    Ok(Redirect::to(&base_path::to("/feeds.html")))
+}
+
+/// Removes a feed, and its row with it, telling the counts to catch up.
 async fn remove_feed(
     Path(feed_url): Path<String>,
-    State(AppState { db }): State<AppState>,
-) -> Result<FeedListTemplate, AppError> {
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
     db.remove_feed(feed_url).await?;
-    let feeds = db.get_feeds().await?;
-    Ok(FeedListTemplate { feeds })
+    Ok(([("HX-Trigger", "counts")], "").into_response())
 }
 
-async fn mark_entry_read(
+/// Renames a feed or fixes its urls or category. Problems with the form are shown
+/// in the feed's row, which is swapped in either way.
+async fn update_feed(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<AddFeedForm>,
+) -> Result<Response, AppError> {
+    let mut feed: Feed = body.into();
+    feed.id.clone_from(&feed_id);
+    feed.name = feed.name.trim().to_string();
+    let error = if feed.name.is_empty() {
+        Some("A feed needs a name".to_string())
+    } else if [&feed.feed_url, &feed.site_url]
+        .iter()
+        .any(|u| reqwest::Url::parse(u).is_err())
+    {
+        Some("The feed and site urls need to be full links".to_string())
+    } else if db
+        .get_feeds()
+        .await?
+        .iter()
+        .any(|f| f.id != feed.id && f.name == feed.name)
+    {
+        // entries belong to feeds by name
+        Some(format!("There's already a feed called {}", feed.name))
+    } else if !db.update_feed(&feed).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    } else {
+        None
+    };
+    feed_row(&db, &feed_id, error).await
+}
+
+async fn feed_icon(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    match db.get_feed(&feed_id).await? {
+        Some(feed) => Ok(Redirect::temporary(&feed.icon()).into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedIconForm {
+    icon_url: String,
+}
+
+/// Overrides a feed's icon, an empty url going back to the site's favicon.
+async fn set_feed_icon(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<FeedIconForm>,
+) -> Result<Response, AppError> {
+    let icon_url = Some(body.icon_url.trim().to_string()).filter(|u| !u.is_empty());
+    db.set_feed_icon(&feed_id, icon_url).await?;
+    feed_row(&db, &feed_id, None).await
+}
+
+#[derive(Deserialize)]
+struct FeedCompactForm {
+    /// checkboxes are only submitted when checked
+    compact: Option<String>,
+}
+
+async fn set_feed_compact(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<FeedCompactForm>,
+) -> Result<Response, AppError> {
+    db.set_feed_compact(&feed_id, body.compact.is_some())
+        .await?;
+    feed_row(&db, &feed_id, None).await
+}
+
+#[derive(Deserialize)]
+struct FeedEnabledForm {
+    enabled: Option<String>,
+}
+
+async fn set_feed_enabled(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<FeedEnabledForm>,
+) -> Result<Response, AppError> {
+    db.set_feed_enabled(&feed_id, body.enabled.is_some())
+        .await?;
+    feed_row(&db, &feed_id, None).await
+}
+
+#[derive(Deserialize)]
+struct FeedExtractForm {
+    extract_mode: String,
+}
+
+async fn set_feed_extract_mode(
+    Path(feed_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+    Form(body): Form<FeedExtractForm>,
+) -> Result<Response, AppError> {
+    db.set_feed_extract_mode(&feed_id, body.extract_mode.parse()?)
+        .await?;
+    feed_row(&db, &feed_id, None).await
+}
+
+/// Reads an entry inside the reader, marking it read. Shows the full article
+/// when one was extracted, fetching it now if the feed extracts on view.
+async fn entry(
+    Path(entry_id): Path<String>,
+    State(AppState {
+        db,
+        client,
+        extractions,
+        read_only,
+        ..
+    }): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(entry) = db.get_entry(&entry_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    // it's only a GET, so it gets past `reject_writes` and `require_login` and has to
+    // leave writes out itself
+    let read_only = read_only.enabled() || user::visiting();
+    if !read_only {
+        db.mark_entries_read(vec![entry_id.clone()]).await?;
+    }
+    // the feed's own content is still worth showing if the page can't be read
+    let article = extract::article_for(&db, &client, &extractions, &entry, read_only).await?;
+    let content = match article {
+        Some(article) => article,
+        None => sanitize::sanitize(
+            entry.content.as_deref().unwrap_or_default(),
+            Some(&entry.content_link),
+        ),
+    };
+    let new_tab = Preferences::load(&db).await?.new_tab_links;
+    Ok(EntryTemplate {
+        entry,
+        content,
+        new_tab,
+    }
+    .into_response())
+}
+
+/// What changed in an entry's content since the version before it.
+async fn entry_diff(
     Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    let Some(entry) = db.get_entry(&entry_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let previous = db.get_entry_revisions(&entry_id).await?.pop();
+    let current = entry.content.clone().unwrap_or_default();
+    let (changed_at, lines) = match previous {
+        Some(previous) => (
+            Some(previous.created_at),
+            diff_lines(&previous.content, &current),
+        ),
+        None => (None, diff_lines(&current, &current)),
+    };
+    Ok(EntryDiffTemplate {
+        entry,
+        changed_at,
+        lines,
+        new_tab: Preferences::load(&db).await?.new_tab_links,
+    }
+    .into_response())
+}
+
+/// Marks a batch of entries read, e.g. ones that scrolled past the viewport.
+async fn mark_entries_read(
+    State(AppState { db, .. }): State<AppState>,
+    Json(body): Json<MarkReadRequest>,
+) -> Result<StatusCode, AppError> {
+    db.mark_entries_read(body.ids).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct ReadAllForm {
+    feed: Option<String>,
+    category: Option<String>,
+}
+
+/// Marks everything read, or just one feed or category, then re-renders the list
+/// the request came from.
+async fn mark_all_read(
     headers: HeaderMap,
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Form(body): Form<ReadAllForm>,
 ) -> Result<EntryListTemplate, AppError> {
     let entry_filter = headers
         .get("entry_filter")
@@ -156,29 +1429,111 @@ async fn mark_entry_read(
         .ok_or_else(|| anyhow!("missing ordering header"))?
         .to_str()?
         .parse::<Ordering>()?;
-    let entries = db.mark_entry_read(entry_id, entry_filter, ordering).await?;
-    Ok(EntryListTemplate { entries })
+    let feed = body.feed.filter(|f| !f.is_empty());
+    let category = body.category.filter(|c| !c.is_empty());
+    db.mark_all_read(feed.as_deref(), category.as_deref())
+        .await?;
+    entry_list(
+        &db,
+        title_length,
+        entry_filter,
+        ordering,
+        Page::new(0, PAGE_SIZE),
+    )
+    .await
+}
+
+async fn mark_entry_read(
+    Path(entry_id): Path<String>,
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+) -> Result<Response, AppError> {
+    db.toggle_entry_read(&entry_id).await?;
+    entry_row(&db, title_length, &entry_id).await
 }
 
 async fn mark_entry_starred(
     Path(entry_id): Path<String>,
-    headers: HeaderMap,
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+) -> Result<Response, AppError> {
+    db.toggle_entry_starred(&entry_id).await?;
+    entry_row(&db, title_length, &entry_id).await
+}
+
+/// Hides an entry, or unhides it on the hidden list, taking it out of the list
+/// it was on either way.
+async fn hide_entry(
+    Path(entry_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    db.toggle_entry_hidden(&entry_id).await?;
+    Ok(StatusCode::OK)
+}
+
+/// The first page of a list again, after one of its entries changed.
+async fn entry_list(
+    db: &db::DB,
+    title_length: usize,
+    filter: EntryFilter,
+    ordering: Ordering,
+    page: Page,
 ) -> Result<EntryListTemplate, AppError> {
-    let entry_filter = headers
-        .get("entry_filter")
-        .ok_or_else(|| anyhow!("missing entry_filter header"))?
-        .to_str()?
-        .parse::<EntryFilter>()?;
-    let ordering = headers
-        .get("ordering")
-        .ok_or_else(|| anyhow!("missing ordering header"))?
-        .to_str()?
-        .parse::<Ordering>()?;
-    let entries = db
-        .mark_entry_starred(entry_id, entry_filter, ordering)
-        .await?;
-    Ok(EntryListTemplate { entries })
+    let new_tab = Preferences::load(db).await?.new_tab_links;
+    let entries = db.get_entries_page(filter, ordering, page).await?;
+    Ok(EntryListTemplate {
+        more: more_link(page, entries.len(), &[]),
+        entries,
+        title_length,
+        new_tab,
+    })
+}
+
+/// An entry's row again, after it changed.
+async fn entry_row(db: &db::DB, title_length: usize, entry_id: &str) -> Result<Response, AppError> {
+    let Some(entry) = db.get_entry(entry_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    Ok(EntryRowTemplate {
+        entry,
+        title_length,
+        new_tab: Preferences::load(db).await?.new_tab_links,
+    }
+    .into_response())
+}
+
+async fn entry_fragment(
+    Path(entry_id): Path<String>,
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+) -> Result<Response, AppError> {
+    entry_row(&db, title_length, &entry_id).await
+}
+
+/// Which list `/fragments/entries` renders, named like the `hx-headers` of the
+/// page it's for.
+#[derive(Deserialize)]
+struct EntriesFragmentQuery {
+    entry_filter: String,
+    ordering: String,
+    #[serde(default)]
+    offset: u32,
+}
+
+/// A page of any entry list, without the page around it.
+async fn entries_fragment(
+    State(AppState {
+        db, title_length, ..
+    }): State<AppState>,
+    Query(query): Query<EntriesFragmentQuery>,
+) -> Result<EntryListTemplate, AppError> {
+    let filter: EntryFilter = query.entry_filter.parse()?;
+    let ordering: Ordering = query.ordering.parse()?;
+    let page = Page::new(query.offset, PAGE_SIZE);
+    entry_list(&db, title_length, filter, ordering, page).await
 }
 
 #[cfg(test)]
@@ -187,6 +1542,30 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn export_starred_markdown() {
+        let mut entry = Entry::new(
+            "starred",
+            "Rust [2024] recap".to_string(),
+            "https://blog.com/posts/rust recap".to_string(),
+            "".to_string(),
+            Some(UtcTime("2024-03-01T12:00:00Z".parse().unwrap())),
+        );
+        entry.feed = "Blog".to_string();
+        entry.content = Some("<p>What happened</p>".to_string());
+        assert_eq!(
+            starred_markdown(std::slice::from_ref(&entry), false),
+            "# Starred entries\n\n## [Rust \\[2024\\] recap](<https://blog.com/posts/rust recap>)\n\nBlog, 2024-03-01\n"
+        );
+        assert!(starred_markdown(&[entry], true).ends_with("\n\n<p>What happened</p>\n"));
+    }
+
+    #[test]
+    fn escape_category_path() {
+        assert_eq!(category_path("tech"), "/category/tech");
+        assert_eq!(category_path("Tech News/AI"), "/category/Tech%20News%2FAI");
+    }
+
     #[test]
     fn addfeedform_toform() {
         let form = AddFeedForm {
@@ -212,6 +1591,7 @@ mod test {
                 last_fetched: Some(Utc::now().into()),
                 fetch_error: None,
                 category: "tech".to_string(),
+                ..Default::default()
             },
             Feed {
                 id: base64::encode_config("Product Hunt", base64::URL_SAFE),
@@ -221,10 +1601,118 @@ mod test {
                 last_fetched: None,
                 fetch_error: None,
                 category: "tech".to_string(),
+                ..Default::default()
             },
         ];
-        let temp = FeedsTemplate { feeds };
+        let temp = FeedsTemplate {
+            feeds,
+            sort: FeedSort::Name.to_string(),
+            more: Some("?offset=50".to_string()),
+            unread: UnreadCounts::new(vec![FeedStats {
+                feed_id: "1".to_string(),
+                category: "tech".to_string(),
+                unread: 3,
+            }]),
+        };
 
         assert!(temp.render().is_ok(), "template failed to render");
     }
+
+    #[test]
+    fn diff_edited_content() {
+        let lines = diff_lines("one\ntwo\nthree\n", "one\n2\nthree\n");
+        let tags: String = lines.iter().map(|l| l.tag).collect();
+        assert_eq!(tags, " -+ ");
+        assert_eq!(lines[2].text, "2");
+    }
+
+    #[test]
+    fn render_row_fragments() {
+        let feed = Feed {
+            id: "blog".to_string(),
+            name: "Blog".to_string(),
+            ..Default::default()
+        };
+        let row = FeedRowTemplate {
+            feed,
+            feed_unread: 4,
+            feed_error: "There's already a feed called Blog".to_string(),
+        }
+        .render()
+        .unwrap();
+        assert!(row.starts_with("<article"));
+        assert!(row.contains("<mark title=\"Unread entries\">4</mark>"));
+        assert!(row.contains("There&#x27;s already a feed called Blog"));
+
+        let entry = Entry::new(
+            "row",
+            "Row".to_string(),
+            "https://blog.com/row".to_string(),
+            "".to_string(),
+            None,
+        );
+        let row = EntryRowTemplate {
+            entry,
+            title_length: 0,
+            new_tab: false,
+        }
+        .render()
+        .unwrap();
+        assert!(row.contains("hx-target=\"closest article\""));
+    }
+
+    #[test]
+    fn render_errortemplate() {
+        let temp = ErrorTemplate {
+            details: ErrorDetails {
+                error: "Something went wrong: no such table".to_string(),
+                request_id: "abc-123".to_string(),
+            },
+        };
+        let page = temp.render().unwrap();
+        assert!(page.contains("no such table"));
+        assert!(page.contains("abc-123"));
+    }
+
+    #[tokio::test]
+    async fn compact_entries_kept_unread_while_read_only() -> Result<(), anyhow::Error> {
+        user::scope(user::OWNER, async {
+            let db = db::connect(db::ConnectionBacking::Memory).await?;
+            db.init().await?;
+            let feed = Feed::new(
+                "Links".to_string(),
+                "https://links.com".to_string(),
+                "https://links.com/rss".to_string(),
+                "".to_string(),
+            );
+            db.add_feeds(std::iter::once(feed.clone())).await?;
+            db.set_feed_compact(&feed.id, true).await?;
+            let mut entry = Entry::new(
+                "link",
+                "Link".to_string(),
+                "https://links.com/1".to_string(),
+                "".to_string(),
+                None,
+            );
+            entry.feed = "Links".to_string();
+            db.add_entries(std::iter::once(entry)).await?;
+
+            let entries = db
+                .get_entries(EntryFilter::All, Ordering::Descending)
+                .await?;
+            assert!(entries[0].compact);
+            read_compact_entries(&db, &entries, true).await?;
+            let entries = db
+                .get_entries(EntryFilter::All, Ordering::Descending)
+                .await?;
+            assert!(!entries[0].read);
+            read_compact_entries(&db, &entries, false).await?;
+            let entries = db
+                .get_entries(EntryFilter::All, Ordering::Descending)
+                .await?;
+            assert!(entries[0].read);
+            Ok(())
+        })
+        .await
+    }
 }
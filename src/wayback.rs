@@ -0,0 +1,155 @@
+//! Keeps a copy of starred entries' pages in the Wayback Machine, storing where
+//! it is as the entry's robust link so there's something to read once the
+//! original goes away. An existing snapshot is used when there is one, and only
+//! pages without one are submitted to be saved.
+use std::time::Duration;
+
+use reqwest::header::CONTENT_LOCATION;
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::time;
+use tracing::{error, info};
+
+use crate::db;
+use crate::maintenance::ReadOnly;
+
+/// How many entries get a snapshot each pass, as saving pages is slow and rate limited.
+const PER_PASS: u32 = 20;
+
+/// Time between saving pages, well under the Wayback Machine's rate limit.
+const SAVE_SPACING: Duration = Duration::from_secs(10);
+
+const WAYBACK: &str = "https://web.archive.org";
+
+/// Looks for snapshots of starred entries missing one once per `period`.
+pub async fn run(db: db::DB, client: reqwest::Client, period: Duration, read_only: ReadOnly) {
+    let mut interval = time::interval(period);
+    loop {
+        interval.tick().await;
+        if read_only.enabled() {
+            continue;
+        }
+        if let Err(e) = archive_starred(&db, &client).await {
+            error!("couldn't archive starred links: {:?}", e);
+        }
+    }
+}
+
+async fn archive_starred(db: &db::DB, client: &reqwest::Client) -> anyhow::Result<()> {
+    let entries = db.get_entries_to_archive(PER_PASS).await?;
+    let mut archived = 0;
+    for entry in &entries {
+        match snapshot(client, &entry.content_link).await {
+            Ok(link) => {
+                db.set_robust_link(&entry.id, &link).await?;
+                archived += 1;
+            }
+            // left for the next pass
+            Err(e) => info!("couldn't archive {}: {:#}", entry.content_link, e),
+        }
+    }
+    info!("archived {} of {} starred links", archived, entries.len());
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Availability {
+    archived_snapshots: Snapshots,
+}
+
+#[derive(Deserialize)]
+struct Snapshots {
+    closest: Option<Snapshot>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    available: bool,
+    url: String,
+}
+
+/// Where a snapshot of `link` is, saving one first if there isn't one yet.
+async fn snapshot(client: &reqwest::Client, link: &str) -> anyhow::Result<String> {
+    let mut lookup = Url::parse("https://archive.org/wayback/available")?;
+    lookup.query_pairs_mut().append_pair("url", link);
+    let availability: Availability = client
+        .get(lookup)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(link) = existing_snapshot(availability) {
+        return Ok(link);
+    }
+
+    time::sleep(SAVE_SPACING).await;
+    let saved = client
+        .get(format!("{}/save/{}", WAYBACK, link))
+        .send()
+        .await?
+        .error_for_status()?;
+    let location = saved
+        .headers()
+        .get(CONTENT_LOCATION)
+        .and_then(|l| l.to_str().ok());
+    saved_snapshot(saved.url(), location)
+        .ok_or_else(|| anyhow::anyhow!("saving didn't say where the snapshot is"))
+}
+
+/// The snapshot the availability API found, over https.
+fn existing_snapshot(availability: Availability) -> Option<String> {
+    let closest = availability.archived_snapshots.closest?;
+    if !closest.available {
+        return None;
+    }
+    Some(match closest.url.strip_prefix("http://") {
+        Some(rest) => format!("https://{}", rest),
+        None => closest.url,
+    })
+}
+
+/// Where a save ended up, from the redirect to the snapshot or the
+/// `Content-Location` the Wayback Machine sends when it doesn't redirect.
+fn saved_snapshot(final_url: &Url, content_location: Option<&str>) -> Option<String> {
+    if final_url.path().starts_with("/web/") {
+        return Some(final_url.to_string());
+    }
+    content_location
+        .filter(|l| l.starts_with("/web/"))
+        .map(|l| format!("{}{}", WAYBACK, l))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_snapshots() {
+        let availability: Availability = serde_json::from_str(
+            r#"{"url": "example.com", "archived_snapshots": {"closest": {"status": "200", "available": true,
+                "url": "http://web.archive.org/web/20240101000000/https://example.com/", "timestamp": "20240101000000"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            existing_snapshot(availability).as_deref(),
+            Some("https://web.archive.org/web/20240101000000/https://example.com/")
+        );
+        let missing: Availability =
+            serde_json::from_str(r#"{"url": "example.com", "archived_snapshots": {}}"#).unwrap();
+        assert!(existing_snapshot(missing).is_none());
+
+        let save = Url::parse("https://web.archive.org/save/https://example.com/").unwrap();
+        assert_eq!(
+            saved_snapshot(&save, Some("/web/20240101000000/https://example.com/")).as_deref(),
+            Some("https://web.archive.org/web/20240101000000/https://example.com/")
+        );
+        assert!(saved_snapshot(&save, None).is_none());
+        let redirected =
+            Url::parse("https://web.archive.org/web/20240101000000/https://example.com/").unwrap();
+        assert_eq!(
+            saved_snapshot(&redirected, None),
+            Some(redirected.to_string())
+        );
+    }
+}
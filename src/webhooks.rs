@@ -0,0 +1,312 @@
+//! Posting new entries to webhooks as they're stored, to pass articles on to
+//! chat rooms and other automations. Each webhook is for every feed, or just
+//! one feed or category, and is sent one entry per request as JSON.
+//!
+//! Requests are signed with the webhook's secret: `X-Feedreader-Signature` is
+//! `sha256=` and the hex HMAC-SHA256 of the body, which the receiver can check
+//! with the same secret to know the entry came from here. Secrets are stored
+//! encrypted with `FEED_SECRET_KEY`, like other credentials, so webhooks need it set.
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use askama_axum::Template;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Form, Router};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::secrets::{self, SecretKey};
+use crate::{db, filters, AppError, AppState, Category, Entry, Feed, Webhook};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/webhooks.html", get(webhooks_page))
+        .route("/webhooks", post(add_webhook))
+        .route("/webhooks/:webhook_id", delete(remove_webhook))
+}
+
+/// How long a receiver gets to answer before the delivery is given up on.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Posts what `add_entries` stores from now on to the webhooks it's for.
+pub async fn run(db: db::DB, client: reqwest::Client, key: Option<Arc<SecretKey>>) {
+    let mut inserted = db.watch_inserted();
+    let mut delivered = match db.last_item_id().await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("couldn't start sending webhooks: {:?}", e);
+            return;
+        }
+    };
+    while inserted.changed().await.is_ok() {
+        let through = *inserted.borrow_and_update();
+        if through <= delivered {
+            continue;
+        }
+        if let Err(e) = deliver(&db, &client, key.as_deref(), delivered, through).await {
+            error!("couldn't send webhooks: {:?}", e);
+        }
+        delivered = through;
+    }
+}
+
+/// Sends the entries stored after row id `after` and up to `through`. A
+/// webhook that can't be reached misses them, rather than holding up the rest.
+async fn deliver(
+    db: &db::DB,
+    client: &reqwest::Client,
+    key: Option<&SecretKey>,
+    after: i64,
+    through: i64,
+) -> Result<()> {
+    let webhooks = db.get_webhooks().await?;
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let key = key.ok_or_else(|| anyhow!("set FEED_SECRET_KEY to sign webhook requests"))?;
+    let entries = db.get_new_entries(after, through).await?;
+    for webhook in &webhooks {
+        let secret = match db.get_webhook_secret(key, &webhook.id).await {
+            Ok(secret) => secret,
+            Err(e) => {
+                error!("couldn't open the secret for {}: {:#}", webhook.url, e);
+                continue;
+            }
+        };
+        let mut sent = 0;
+        for entry in entries.iter().filter(|e| matches(webhook, e)) {
+            match send(client, webhook, &secret, entry).await {
+                Ok(()) => sent += 1,
+                Err(e) => error!("couldn't post {} to {}: {:#}", entry.id, webhook.url, e),
+            }
+        }
+        if sent > 0 {
+            info!("posted {} new entries to {}", sent, webhook.url);
+        }
+    }
+    Ok(())
+}
+
+fn matches(webhook: &Webhook, entry: &Entry) -> bool {
+    if webhook.feed_id.is_some() && webhook.feed_id != entry.feed_id {
+        return false;
+    }
+    if webhook.category_id.is_some() && webhook.category != entry.category {
+        return false;
+    }
+    true
+}
+
+/// What's posted for an entry.
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: &'a str,
+    title: &'a str,
+    link: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    comments_link: &'a str,
+    published: Option<String>,
+    author: Option<&'a str>,
+    feed: &'a str,
+    feed_id: Option<&'a str>,
+    category: Option<&'a str>,
+    enclosure_url: Option<&'a str>,
+    enclosure_type: Option<&'a str>,
+    excerpt: Option<&'a str>,
+    content: Option<&'a str>,
+}
+
+fn payload(entry: &Entry) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&Payload {
+        id: &entry.id,
+        title: &entry.title,
+        link: &entry.content_link,
+        comments_link: &entry.comments_link,
+        published: entry.published.as_ref().map(|p| p.0.to_rfc3339()),
+        author: entry.author.as_deref(),
+        feed: &entry.feed,
+        feed_id: entry.feed_id.as_deref(),
+        category: entry.category.as_deref().filter(|c| !c.is_empty()),
+        enclosure_url: entry.enclosure_url.as_deref(),
+        enclosure_type: entry.enclosure_type.as_deref(),
+        excerpt: entry.excerpt.as_deref(),
+        content: entry.content.as_deref(),
+    })?)
+}
+
+/// The `X-Feedreader-Signature` for `body`.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, body);
+    format!("sha256={}", secrets::hex(tag.as_ref()))
+}
+
+async fn send(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    secret: &str,
+    entry: &Entry,
+) -> Result<()> {
+    let body = payload(entry)?;
+    client
+        .post(&webhook.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Feedreader-Signature", sign(secret, &body))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn generate_secret() -> Result<String> {
+    let mut secret = [0u8; 24];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| anyhow!("couldn't generate a secret"))?;
+    Ok(base64::encode_config(secret, base64::URL_SAFE_NO_PAD))
+}
+
+/// Which entries a webhook gets, as it reads on the webhooks page.
+fn describe(webhook: &Webhook) -> String {
+    match (&webhook.feed, &webhook.category) {
+        (Some(feed), _) => format!("New entries from {}", feed),
+        (None, Some(category)) => format!("New entries in {}", category),
+        (None, None) => "Every new entry".to_string(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "webhooks.html")]
+struct WebhooksTemplate {
+    webhooks: Vec<Webhook>,
+    feeds: Vec<Feed>,
+    categories: Vec<Category>,
+    error: Option<String>,
+    /// The secret of a webhook that was just added, the only time it's shown
+    created: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WebhookForm {
+    url: String,
+    /// Empty to have one generated
+    #[serde(default)]
+    secret: String,
+    /// `feed:<id>` or `category:<id>`, empty for every entry
+    #[serde(default)]
+    source: String,
+}
+
+/// The page again after a change, with why it wasn't made if it wasn't. It's a
+/// success either way so htmx swaps the error in.
+async fn webhooks(
+    db: &db::DB,
+    error: Option<&str>,
+    created: Option<String>,
+) -> Result<Response, AppError> {
+    Ok(WebhooksTemplate {
+        webhooks: db.get_webhooks().await?,
+        feeds: db.get_feeds().await?,
+        categories: db.get_category_list().await?,
+        error: error.map(String::from),
+        created,
+    }
+    .into_response())
+}
+
+async fn webhooks_page(State(AppState { db, .. }): State<AppState>) -> Result<Response, AppError> {
+    webhooks(&db, None, None).await
+}
+
+async fn add_webhook(
+    State(AppState { db, secret_key, .. }): State<AppState>,
+    Form(form): Form<WebhookForm>,
+) -> Result<Response, AppError> {
+    let Some(key) = secret_key else {
+        let error = "Set FEED_SECRET_KEY to add webhooks, their secrets are stored encrypted";
+        return webhooks(&db, Some(error), None).await;
+    };
+    let url = form.url.trim();
+    let valid = reqwest::Url::parse(url)
+        .is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.has_host());
+    if !valid {
+        return webhooks(&db, Some("A webhook needs an http or https URL"), None).await;
+    }
+    let secret = match form.secret.trim() {
+        "" => generate_secret()?,
+        secret => secret.to_string(),
+    };
+    let mut webhook = Webhook {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        url: url.to_string(),
+        ..Default::default()
+    };
+    match form.source.split_once(':') {
+        Some(("feed", id)) => webhook.feed_id = Some(id.to_string()),
+        Some(("category", id)) => match id.parse() {
+            Ok(id) => webhook.category_id = Some(id),
+            Err(_) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+        },
+        None if form.source.is_empty() => {}
+        _ => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    }
+    db.add_webhook(&key, &webhook, &secret).await?;
+    webhooks(&db, None, Some(secret)).await
+}
+
+async fn remove_webhook(
+    Path(webhook_id): Path<String>,
+    State(AppState { db, .. }): State<AppState>,
+) -> Result<Response, AppError> {
+    if !db.remove_webhook(&webhook_id).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    webhooks(&db, None, None).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_matching_entries() {
+        let entry = Entry {
+            id: "new".to_string(),
+            title: "This week in Rust".to_string(),
+            feed: "Blog".to_string(),
+            feed_id: Some("blog".to_string()),
+            category: Some("Tech".to_string()),
+            ..Default::default()
+        };
+        let webhook = |feed_id: Option<&str>, category: Option<&str>| Webhook {
+            feed_id: feed_id.map(String::from),
+            category_id: category.map(|_| 1),
+            category: category.map(String::from),
+            ..Default::default()
+        };
+        assert!(matches(&webhook(None, None), &entry));
+        assert!(matches(&webhook(Some("blog"), None), &entry));
+        assert!(!matches(&webhook(Some("news"), None), &entry));
+        assert!(matches(&webhook(None, Some("Tech")), &entry));
+        assert!(!matches(&webhook(None, Some("Home Lab")), &entry));
+
+        let body: serde_json::Value = serde_json::from_slice(&payload(&entry).unwrap()).unwrap();
+        assert_eq!(body["title"], "This week in Rust");
+        assert_eq!(body["category"], "Tech");
+        assert!(body.get("comments_link").is_none());
+
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}